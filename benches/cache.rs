@@ -0,0 +1,105 @@
+//! Benchmarks of the answer cache, alone and under contention.
+//!
+//! The cache is reached through an embedded [`Resolver`], filled by
+//! resolving the benchmark names against a mock upstream running on
+//! localhost; the lookups measured never leave the cache.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dns_resolver::{Config, Resolver, Type};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::runtime::Runtime;
+
+/// Names cached before measuring.
+const NAMES: usize = 1024;
+
+/// Threads sharing the cache in the contended benchmarks.
+const THREADS: usize = 8;
+
+/// Spawns a mock upstream answering every query with an A record.
+fn spawn_upstream() -> SocketAddr {
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = sock.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok((len, peer)) = sock.recv_from(&mut buf) {
+            let mut reply = buf[..len].to_vec();
+            reply[2] = 0x84;
+            reply[3] = 0;
+            reply[6..12].copy_from_slice(&[0, 1, 0, 0, 0, 0]);
+            let end = 12 + question_len(&reply);
+            reply.truncate(end);
+            reply.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0x0E, 0x10, 0, 4, 192, 0, 2, 1]);
+            let _ = sock.send_to(&reply, peer);
+        }
+    });
+    addr
+}
+
+/// Returns the length of the question section of a message.
+fn question_len(msg: &[u8]) -> usize {
+    let mut end = 12;
+    while msg[end] != 0 {
+        end += msg[end] as usize + 1;
+    }
+    end + 5 - 12
+}
+
+/// Creates a resolver whose cache holds an answer for each of the
+/// benchmark names.
+fn filled(runtime: &Runtime) -> Resolver {
+    let config   = Config { root: spawn_upstream(), outgoing_rate: 0, ..Config::default() };
+    let resolver = runtime.block_on(async { Resolver::from_config(&config) }).unwrap();
+    let outcomes = runtime.block_on(resolver.lookup_many((0..NAMES).map(|n| (name(n), Type::A)), Duration::from_secs(30)));
+    assert!(outcomes.iter().all(Result::is_ok));
+    resolver
+}
+
+fn name(n: usize) -> String {
+    format!("host{}.example.com", n)
+}
+
+/// Runs `op` `iters` times on each of the threads at once, returning the
+/// time they took together.
+fn contended(iters: u64, op: impl Fn(usize) + Sync) -> Duration {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for t in 0..THREADS {
+            let op = &op;
+            scope.spawn(move || {
+                for i in 0..iters as usize {
+                    op(t * 7919 + i);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn cache(c: &mut Criterion) {
+    let runtime  = Runtime::new().unwrap();
+    let resolver = filled(&runtime);
+    let mut n = 0;
+    c.bench_function("cache/get", |b| {
+        b.iter(|| {
+            n = (n + 1) % NAMES;
+            resolver.lookup_cached(black_box(&name(n)), Type::A, false).unwrap()
+        })
+    });
+    c.bench_function("cache/resolve", |b| {
+        b.iter(|| {
+            n = (n + 1) % NAMES;
+            runtime.block_on(resolver.resolve(black_box(&name(n)), Type::A)).unwrap()
+        })
+    });
+
+    c.bench_function("cache/get_contended", |b| {
+        b.iter_custom(|iters| contended(iters, |n| drop(resolver.lookup_cached(&name(n % NAMES), Type::A, false))))
+    });
+}
+
+criterion_group!(benches, cache);
+criterion_main!(benches);
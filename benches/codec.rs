@@ -0,0 +1,92 @@
+//! Benchmarks of the DNS message codec.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dns_resolver::{AnswerRecord, Dns, DnsReadBuffer, RData, Type};
+use std::net::Ipv4Addr;
+
+/// Builds a response to an A query for `name`, with the given sections.
+fn response(
+    name:        &str,
+    answers:     Vec<AnswerRecord>,
+    authorities: Vec<AnswerRecord>,
+    additionals: Vec<AnswerRecord>,
+) -> Vec<u8> {
+    let mut msg = Dns::new_question(name, Type::A, 0x1234);
+    msg.header.flags.qr = true;
+    msg.answers         = answers;
+    msg.authorities     = authorities;
+    msg.additionals     = additionals;
+    msg.set_edns(1232);
+    msg.encode().unwrap().into_inner()
+}
+
+/// A stub query, with an OPT record.
+fn query() -> Vec<u8> {
+    let mut msg = Dns::new_question("www.example.com", Type::A, 0x1234);
+    msg.header.flags.rd = true;
+    msg.set_edns(1232);
+    msg.encode().unwrap().into_inner()
+}
+
+/// An answer following an alias to a few addresses.
+fn answer() -> Vec<u8> {
+    let mut answers = vec![AnswerRecord::new("www.example.com".into(), RData::CNAME("cdn.example.net".into()))];
+    answers.extend((1..=4).map(|n| AnswerRecord::new("cdn.example.net".into(), RData::A(Ipv4Addr::new(192, 0, 2, n)))));
+    response("www.example.com", answers, Vec::new(), Vec::new())
+}
+
+/// A referral from the root to the servers of `com`, with their glue.
+fn referral() -> Vec<u8> {
+    let servers: Vec<String> = (b'a'..=b'm').map(|c| format!("{}.gtld-servers.net", c as char)).collect();
+    let authorities = servers
+        .iter()
+        .map(|server| AnswerRecord::new("com".into(), RData::NS(server.clone())))
+        .collect();
+    let additionals = servers
+        .iter()
+        .enumerate()
+        .map(|(n, server)| AnswerRecord::new(server.clone(), RData::A(Ipv4Addr::new(192, 5, 6, n as u8))))
+        .collect();
+    response("www.example.com", Vec::new(), authorities, additionals)
+}
+
+/// A signed answer, carrying an RRSIG record.
+fn signed() -> Vec<u8> {
+    let mut rrsig = vec![0, 1, 13, 3, 0, 0, 0x0E, 0x10, 0x68, 0, 0, 0, 0x67, 0, 0, 0, 0x12, 0x34];
+    rrsig.extend_from_slice(b"\x07example\x03com\x00");
+    rrsig.extend_from_slice(&[0xAB; 64]);
+    let answers = vec![
+        AnswerRecord::new("www.example.com".into(), RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+        AnswerRecord::new("www.example.com".into(), RData::Unknown { rtype: 46, data: rrsig }),
+    ];
+    response("www.example.com", answers, Vec::new(), Vec::new())
+}
+
+fn codec(c: &mut Criterion) {
+    let packets = [("query", query()), ("answer", answer()), ("referral", referral()), ("signed", signed())];
+
+    for (name, packet) in &packets {
+        c.bench_function(&format!("decode/{}", name), |b| {
+            b.iter(|| Dns::decode(&mut DnsReadBuffer::new(black_box(packet))).unwrap())
+        });
+
+        let msg = Dns::decode(&mut DnsReadBuffer::new(packet)).unwrap();
+        c.bench_function(&format!("encode/{}", name), |b| b.iter(|| black_box(&msg).encode().unwrap()));
+    }
+
+    // The name of the last glue record points to a name pointing further
+    // back, as in any referral
+    let packet = referral();
+    let msg    = Dns::decode(&mut DnsReadBuffer::new(&packet)).unwrap();
+    let start  = msg.additionals.last().and_then(|record| record.wire_span()).unwrap().start;
+    c.bench_function("decompress/name", |b| {
+        b.iter(|| {
+            let mut buf = DnsReadBuffer::new(black_box(&packet));
+            buf.set_index(start).unwrap();
+            buf.read_str().unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, codec);
+criterion_main!(benches);
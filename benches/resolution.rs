@@ -0,0 +1,114 @@
+//! Benchmarks of the whole resolution path: a stub query goes to the
+//! resolver process over UDP, which resolves it against a mock upstream
+//! running on localhost, as the integration tests do.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// A running resolver process, killed when dropped.
+struct Server {
+    child: Child,
+    addr:  SocketAddr,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Spawns a mock upstream answering every query with an A record.
+fn spawn_upstream() -> SocketAddr {
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = sock.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok((len, peer)) = sock.recv_from(&mut buf) {
+            let mut reply = buf[..len].to_vec();
+            reply[2] = 0x84;
+            reply[3] = 0;
+            reply[6..12].copy_from_slice(&[0, 1, 0, 0, 0, 0]);
+            let end = 12 + question_len(&reply);
+            reply.truncate(end);
+            reply.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0x0E, 0x10, 0, 4, 192, 0, 2, 1]);
+            let _ = sock.send_to(&reply, peer);
+        }
+    });
+    addr
+}
+
+/// Spawns the resolver on an ephemeral port, with `root` as the root
+/// server, and waits until it answers.
+fn spawn_server(root: SocketAddr) -> Server {
+    let addr = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let child = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .env("DNSR_LISTEN", addr.to_string())
+        .env("DNSR_ROOT", root.to_string())
+        .env("DNSR_OUTGOING_RATE", "0")
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let server = Server { child, addr };
+    exchange(&UdpSocket::bind("127.0.0.1:0").unwrap(), &server, &query("whoami.resolver.local", 16));
+    server
+}
+
+/// Returns the length of the question section of a message.
+fn question_len(msg: &[u8]) -> usize {
+    let mut end = 12;
+    while msg[end] != 0 {
+        end += msg[end] as usize + 1;
+    }
+    end + 5 - 12
+}
+
+/// Builds a recursive stub query for `name` and `qtype`.
+fn query(name: &str, qtype: u16) -> Vec<u8> {
+    let mut out = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.split('.') {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+    out.extend_from_slice(&qtype.to_be_bytes());
+    out.extend_from_slice(&[0, 1]);
+    out
+}
+
+/// Sends `packet` to the server and waits for its reply, sending it again
+/// if it gets lost.
+fn exchange(sock: &UdpSocket, server: &Server, packet: &[u8]) -> Vec<u8> {
+    sock.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    let mut buf = [0u8; 4096];
+    loop {
+        sock.send_to(packet, server.addr).unwrap();
+        if let Ok((len, _)) = sock.recv_from(&mut buf) {
+            return buf[..len].to_vec();
+        }
+    }
+}
+
+fn resolution(c: &mut Criterion) {
+    let server = spawn_server(spawn_upstream());
+    let sock   = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let cached = query("www.example.com", 1);
+    c.bench_function("resolution/cached", |b| b.iter(|| exchange(&sock, &server, &cached)));
+
+    let mut n = 0u64;
+    c.bench_function("resolution/upstream", |b| {
+        b.iter(|| {
+            n += 1;
+            exchange(&sock, &server, &query(&format!("host{}.example.com", n), 1))
+        })
+    });
+}
+
+criterion_group!(benches, resolution);
+criterion_main!(benches);
@@ -0,0 +1,196 @@
+use crate::{
+    http::{self, Request},
+    types::{AnswerRecord, RData, Type},
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+/// Label the DNS-01 challenges are published under (RFC 8555, section 8.4).
+const CHALLENGE_LABEL: &str = "_acme-challenge";
+
+/// TTL of the challenge records, short so that a retried validation sees
+/// the new token.
+const CHALLENGE_TTL: u32 = 10;
+
+/// How long a challenge is served when it is never cleaned up.
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Most challenges served at once.
+const MAX_CHALLENGES: usize = 256;
+
+/// How long a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request accepted, headers and body.
+const MAX_REQUEST: usize = 4096;
+
+/// Body of the `/present` and `/cleanup` requests.
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    /// Name the challenge is published at.
+    fqdn: String,
+    /// Key authorization digest to publish.
+    value: String,
+}
+
+/// TXT records of the pending DNS-01 challenges, managed over HTTP.
+#[derive(Debug)]
+pub struct Challenges {
+    token:   String,
+    zones:   Vec<String>,
+    records: Mutex<HashMap<String, Vec<(String, Instant)>>>,
+}
+
+impl Challenges {
+    /// Creates an empty set of challenges, for the names under `zones`,
+    /// managed by the clients presenting `token`.
+    pub fn new(token: &str, zones: &[String]) -> Self {
+        Challenges {
+            token:   token.to_string(),
+            zones:   zones.iter().map(|zone| normalize(zone)).collect(),
+            records: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Answers a question from the pending challenges, if the name has any.
+    /// A name with challenges has no records of other types.
+    pub fn answer(&self, qname: &str, qtype: Type) -> Option<Vec<AnswerRecord>> {
+        let now     = Instant::now();
+        let records = self.records.lock().unwrap();
+        let values  = records.get(&normalize(qname))?;
+        if values.iter().all(|(_, expires)| *expires <= now) {
+            return None;
+        }
+
+        Some(
+            values
+                .iter()
+                .filter(|(_, expires)| qtype == Type::TXT && *expires > now)
+                .map(|(value, _)| {
+                    let mut answer = AnswerRecord::new(qname.to_string(), RData::txt(value));
+                    answer.ttl = CHALLENGE_TTL;
+                    answer
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns whether challenges may be published at `name`: directly
+    /// under the challenge label, for a domain in one of the zones.
+    fn allowed(&self, name: &str) -> bool {
+        let Some(domain) = name.strip_prefix(CHALLENGE_LABEL).and_then(|rest| rest.strip_prefix('.')) else {
+            return false;
+        };
+        self.zones
+            .iter()
+            .any(|zone| domain == zone || domain.ends_with(&format!(".{}", zone)))
+    }
+
+    /// Publishes a challenge. Returns `false` when too many are pending.
+    fn present(&self, name: &str, value: &str) -> bool {
+        let now = Instant::now();
+        let mut records = self.records.lock().unwrap();
+        records.retain(|_, values| {
+            values.retain(|(_, expires)| *expires > now);
+            !values.is_empty()
+        });
+        if records.values().map(Vec::len).sum::<usize>() >= MAX_CHALLENGES {
+            return false;
+        }
+
+        let values = records.entry(name.to_string()).or_default();
+        values.retain(|(other, _)| other != value);
+        values.push((value.to_string(), now + CHALLENGE_LIFETIME));
+        true
+    }
+
+    /// Withdraws a challenge.
+    fn cleanup(&self, name: &str, value: &str) {
+        let mut records = self.records.lock().unwrap();
+        if let Some(values) = records.get_mut(name) {
+            values.retain(|(other, _)| other != value);
+            if values.is_empty() {
+                records.remove(name);
+            }
+        }
+    }
+
+    /// Serves the challenge API over HTTP on `addr`.
+    ///
+    /// `POST /present` publishes the challenge in the JSON body, as
+    /// `{"fqdn": "_acme-challenge.example.com.", "value": "..."}`, and
+    /// `POST /cleanup` withdraws it. Requests must carry the token as
+    /// `Authorization: Bearer <token>`.
+    pub async fn serve(self: Arc<Self>, addr: SocketAddr) {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!(addr = %addr, error = %e, "can't listen for ACME challenges");
+                return;
+            }
+        };
+
+        loop {
+            let Ok((stream, peer)) = listener.accept().await else {
+                continue;
+            };
+            let challenges = Arc::clone(&self);
+            tokio::spawn(async move {
+                let _ = time::timeout(REQUEST_TIMEOUT, challenges.respond(stream, peer)).await;
+            });
+        }
+    }
+
+    /// Answers a single HTTP request.
+    async fn respond(&self, mut stream: TcpStream, peer: SocketAddr) -> std::io::Result<()> {
+        let Some(request) = Request::read(&mut stream, MAX_REQUEST).await? else {
+            return Ok(());
+        };
+
+        let status = match (request.method(), request.path()) {
+            ("POST", path @ ("/present" | "/cleanup")) => {
+                if !request.bears(&self.token) {
+                    tracing::warn!(client = %peer, "unauthorized ACME request");
+                    "401 Unauthorized"
+                } else {
+                    match serde_json::from_slice::<Challenge>(&request.body) {
+                        Err(_) => "400 Bad Request",
+                        Ok(challenge) => {
+                            let name = normalize(&challenge.fqdn);
+                            if !self.allowed(&name) {
+                                "403 Forbidden"
+                            } else if path == "/cleanup" {
+                                self.cleanup(&name, &challenge.value);
+                                tracing::info!(name = %name, "ACME challenge withdrawn");
+                                "200 OK"
+                            } else if self.present(&name, &challenge.value) {
+                                tracing::info!(name = %name, "ACME challenge published");
+                                "200 OK"
+                            } else {
+                                "429 Too Many Requests"
+                            }
+                        }
+                    }
+                }
+            }
+            ("POST", _) => "404 Not Found",
+            _ => "405 Method Not Allowed",
+        };
+
+        http::respond(&mut stream, status, "").await
+    }
+}
+
+/// Lowercases a name and strips its trailing dot.
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
@@ -0,0 +1,144 @@
+use crate::{
+    cache::Cache,
+    http::{self, Request},
+    metrics::Metrics,
+    policy::{Policy, Source},
+    types::DnsError,
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+    time,
+};
+
+/// How long a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request accepted, headers and body.
+const MAX_REQUEST: usize = 4096;
+
+/// Request to reload the configuration, answered with the outcome.
+pub type Reload = oneshot::Sender<Result<(), DnsError>>;
+
+/// Administration API, changing the state of the running resolver.
+pub struct Admin {
+    token:   String,
+    cache:   Arc<Cache>,
+    policy:  Arc<Policy>,
+    sources: Vec<Source>,
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    metrics: Arc<Metrics>,
+    reloads: mpsc::Sender<Reload>,
+}
+
+impl Admin {
+    /// Creates the API, accepting the requests that carry `token`, over
+    /// the cache, the policy with the blocklists of `sources`, and the
+    /// counters. The configuration is reloaded through `reloads`.
+    pub fn new(
+        token:   String,
+        cache:   Arc<Cache>,
+        policy:  Arc<Policy>,
+        sources: Vec<Source>,
+        metrics: Arc<Metrics>,
+        reloads: mpsc::Sender<Reload>,
+    ) -> Self {
+        Admin { token, cache, policy, sources, metrics, reloads }
+    }
+
+    /// Serves the API over HTTP on `addr`.
+    ///
+    /// `GET /stats` returns the counters, `POST /cache/flush` empties the
+    /// cache and `POST /blocklists/reload` reloads the blocklists from
+    /// their sources. `PUT /blocklist/<name>` blocks a domain and its
+    /// subdomains, and `DELETE /blocklist/<name>` unblocks it, the name
+    /// being percent-encoded. `POST /reload` reloads the configuration,
+    /// then the blocklists. Requests must carry the token as
+    /// `Authorization: Bearer <token>`.
+    pub async fn serve(self: Arc<Self>, addr: SocketAddr) {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!(addr = %addr, error = %e, "can't listen for admin requests");
+                return;
+            }
+        };
+
+        loop {
+            let Ok((stream, peer)) = listener.accept().await else {
+                continue;
+            };
+            let admin = Arc::clone(&self);
+            tokio::spawn(async move {
+                let _ = time::timeout(REQUEST_TIMEOUT, admin.respond(stream, peer)).await;
+            });
+        }
+    }
+
+    /// Answers a single HTTP request.
+    async fn respond(&self, mut stream: TcpStream, peer: SocketAddr) -> std::io::Result<()> {
+        let Some(request) = Request::read(&mut stream, MAX_REQUEST).await? else {
+            return Ok(());
+        };
+        if !request.bears(&self.token) {
+            tracing::warn!(client = %peer, "unauthorized admin request");
+            return http::respond(&mut stream, "401 Unauthorized", "unauthorized\n").await;
+        }
+
+        let (status, body) = match (request.method(), request.path()) {
+            #[cfg(feature = "metrics")]
+            ("GET", "/stats") => ("200 OK", self.metrics.render()),
+            ("POST", "/cache/flush") => {
+                let count = self.cache.clear();
+                tracing::info!(client = %peer, entries = %count, "cache flushed");
+                ("200 OK", format!("flushed {} entries\n", count))
+            }
+            ("POST", "/blocklists/reload") => {
+                self.policy.load(&self.sources).await;
+                tracing::info!(client = %peer, "blocklists reloaded");
+                ("200 OK", "reloaded\n".to_string())
+            }
+            ("POST", "/reload") => match self.reload().await {
+                Ok(()) => {
+                    self.policy.load(&self.sources).await;
+                    tracing::info!(client = %peer, "configuration reloaded");
+                    ("200 OK", "reloaded\n".to_string())
+                }
+                Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+            },
+            (method @ ("PUT" | "DELETE"), path) if path.starts_with("/blocklist/") => {
+                let name = http::percent_decode(&path["/blocklist/".len()..]).unwrap_or_default();
+                let name = name.trim_end_matches('.');
+                match (method, name.is_empty()) {
+                    (_, true) => ("400 Bad Request", "invalid name\n".to_string()),
+                    ("PUT", _) => match self.policy.block(name) {
+                        Some(added) => {
+                            tracing::info!(client = %peer, name = %name, "domain blocked");
+                            (if added { "201 Created" } else { "200 OK" }, "blocked\n".to_string())
+                        }
+                        None => ("400 Bad Request", "invalid name\n".to_string()),
+                    },
+                    _ => match self.policy.unblock(name) {
+                        true => {
+                            tracing::info!(client = %peer, name = %name, "domain unblocked");
+                            ("200 OK", "unblocked\n".to_string())
+                        }
+                        false => ("404 Not Found", "not blocked\n".to_string()),
+                    },
+                }
+            }
+            (_, "/stats" | "/cache/flush" | "/blocklists/reload" | "/reload") => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+            _ => ("404 Not Found", "not found\n".to_string()),
+        };
+
+        http::respond(&mut stream, status, &body).await
+    }
+
+    /// Has the server reload its configuration, returning why it couldn't.
+    async fn reload(&self) -> Result<(), DnsError> {
+        let (reply, outcome) = oneshot::channel();
+        self.reloads.send(reply).await.map_err(|_| DnsError::IOError("the server is stopping".into()))?;
+        outcome.await.map_err(|_| DnsError::IOError("the server is stopping".into()))?
+    }
+}
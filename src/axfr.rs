@@ -0,0 +1,136 @@
+use crate::{
+    hosted::HostedZones,
+    network::Network,
+    types::{AnswerRecord, Dns, DnsError, DnsReadBuffer, Type},
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+/// How long a connection may stay idle between two queries.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Size the messages of a transfer are filled up to, well below the
+/// 65535 bytes a TCP message can carry.
+const MESSAGE_SIZE: usize = 16384;
+
+/// Binds the TCP socket the zone transfers are served on.
+pub async fn listen(addr: SocketAddr) -> Result<TcpListener, DnsError> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| DnsError::IOError(format!("can't bind {}: {}", addr, e)))
+}
+
+/// Serves the full transfers (AXFR, RFC 5936) of the hosted `zones`, in
+/// their current version, to the clients connecting to `listener`.
+///
+/// Messages are framed as over TCP, each preceded by its length (RFC 1035,
+/// section 4.2.2). Only the clients within the `allow` networks get a
+/// zone, the others are refused, as are the transfers of unknown zones;
+/// any other query isn't implemented. The queries of a connection are
+/// answered in turn, and connections idle for too long are closed.
+pub async fn serve(listener: TcpListener, zones: Arc<HostedZones>, allow: Vec<Network>) {
+    let allow = Arc::new(allow);
+    loop {
+        let Ok((stream, peer)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(connection(stream, peer, Arc::clone(&zones), Arc::clone(&allow)));
+    }
+}
+
+/// Answers the queries of a client, until it disconnects or stays idle.
+async fn connection(mut stream: TcpStream, peer: SocketAddr, zones: Arc<HostedZones>, allow: Arc<Vec<Network>>) {
+    let allowed = allow.iter().any(|network| network.contains(peer.ip()));
+    loop {
+        let Ok(Ok(length)) = time::timeout(IDLE_TIMEOUT, stream.read_u16()).await else {
+            return;
+        };
+
+        let mut query = vec![0; length as usize];
+        if stream.read_exact(&mut query).await.is_err() {
+            return;
+        }
+        let Ok(query) = Dns::decode(&mut DnsReadBuffer::new(&query)) else {
+            return;
+        };
+
+        let messages = match answer(&query, &zones, peer, allowed) {
+            Ok(messages) => messages,
+            Err(e)       => {
+                tracing::error!(client = %peer, error = %e, "zone transfer error");
+                return;
+            }
+        };
+
+        for message in messages {
+            let mut framed = Vec::with_capacity(message.len() + 2);
+            framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+            framed.extend_from_slice(&message);
+            if stream.write_all(&framed).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Answers the query of `peer` with the encoded messages of the transfer,
+/// or a single error reply.
+fn answer(query: &Dns, zones: &HostedZones, peer: SocketAddr, allowed: bool) -> Result<Vec<Vec<u8>>, DnsError> {
+    let mut reply = Dns::new_reply(query);
+    reply.header.flags.ra = false;
+
+    let question = query.questions.first().filter(|question| {
+        query.header.flags.opcode == 0 && query.questions.len() == 1 && question.qtype == Type::AXFR
+    });
+    let Some(question) = question else {
+        reply.set_rcode(4)?;
+        return Ok(vec![reply.encode()?.into_inner()]);
+    };
+
+    let name = question.qname.trim_end_matches('.');
+    let zone = zones.get(name);
+    let (Some(zone), true) = (zone, allowed) else {
+        tracing::warn!(client = %peer, zone = %name, "zone transfer refused");
+        reply.set_rcode(5)?;
+        return Ok(vec![reply.encode()?.into_inner()]);
+    };
+    reply.header.flags.aa = true;
+
+    // The zone starts and ends with its SOA record, the others in between
+    let soa = zone
+        .rrset(&zone.origin, Type::SOA)
+        .and_then(|rrset| rrset.records.first())
+        .ok_or(DnsError::InvalidField)?;
+    let records = zone
+        .rrsets
+        .iter()
+        .filter(|rrset| !(rrset.rtype == Type::SOA && rrset.name.eq_ignore_ascii_case(&zone.origin)))
+        .flat_map(|rrset| &rrset.records);
+
+    let mut messages = Vec::new();
+    let mut size     = 0;
+    for record in std::iter::once(soa).chain(records).chain(std::iter::once(soa)) {
+        let length = wire_length(record);
+        if size + length > MESSAGE_SIZE && !reply.answers.is_empty() {
+            messages.push(reply.encode()?.into_inner());
+            reply.questions.clear();
+            reply.answers.clear();
+            size = 0;
+        }
+        reply.answers.push(record.clone());
+        size += length;
+    }
+    messages.push(reply.encode()?.into_inner());
+
+    tracing::info!(client = %peer, zone = %zone.origin, messages = %messages.len(), "zone transferred");
+    Ok(messages)
+}
+
+/// Returns the size of a record on the wire, without name compression.
+fn wire_length(record: &AnswerRecord) -> usize {
+    record.aname.len() + 2 + 10 + record.rdata.len() as usize
+}
@@ -2,6 +2,24 @@ use std::str;
 
 use crate::types::{DnsBufferError, DnsReadBuffer, DnsWriteBuffer};
 
+/// Default ceiling on the memory taken by the data decoded from a single
+/// message, for the smaller messages.
+///
+/// Compression pointers let a small message expand into many copies of
+/// the same long name: a 64 KiB message could otherwise decode into
+/// megabytes.
+pub const MAX_DECODED_SIZE: usize = 256 * 1024;
+
+/// Memory the data decoded from a larger message may take, per byte of
+/// the message: a record whose owner is a two-byte pointer still takes a
+/// whole `AnswerRecord` and a copy of the name once decoded, so that a
+/// large zone transfer or TCP answer made of them decodes into about ten
+/// times its size.
+const DECODED_SIZE_FACTOR: usize = 32;
+
+/// Longest domain name, in wire format (RFC 1035, section 2.3.4).
+const MAX_NAME_LEN: usize = 255;
+
 impl<'a> DnsReadBuffer<'a> {
     /// Creates a new `DnsReadBuffer` to read from the given byte slice.
     ///
@@ -9,9 +27,26 @@ impl<'a> DnsReadBuffer<'a> {
     /// * `data` - The byte slice containing DNS message data.
     ///
     /// # Returns
-    /// A new `DnsReadBuffer` instance with read index set to 0.
+    /// A new `DnsReadBuffer` instance with read index set to 0, whose
+    /// decoded data may take `MAX_DECODED_SIZE` bytes, or 32 times the
+    /// size of the message if that's more.
     pub fn new(data: &'a [u8]) -> Self {
-        Self { data, index: 0 }
+        Self::with_budget(data, MAX_DECODED_SIZE.max(data.len() * DECODED_SIZE_FACTOR))
+    }
+
+    /// Creates a new `DnsReadBuffer` whose decoded data may take at most
+    /// `budget` bytes.
+    pub fn with_budget(data: &'a [u8], budget: usize) -> Self {
+        Self { data, index: 0, budget }
+    }
+
+    /// Accounts for `n` bytes of decoded data.
+    ///
+    /// # Errors
+    /// Returns `DnsBufferError::BudgetExceeded` if the budget runs out.
+    pub fn charge(&mut self, n: usize) -> Result<(), DnsBufferError> {
+        self.budget = self.budget.checked_sub(n).ok_or(DnsBufferError::BudgetExceeded)?;
+        Ok(())
     }
 
     /// Returns the current read index.
@@ -47,9 +82,8 @@ impl<'a> DnsReadBuffer<'a> {
             .get(self.index)
             .copied()
             .ok_or(DnsBufferError::EndOfBuffer)
-            .map(|b| {
+            .inspect(|_| {
                 self.index += 1;
-                b
             })
     }
 
@@ -98,41 +132,47 @@ impl<'a> DnsReadBuffer<'a> {
         self.data
             .get(self.index..self.index + n)
             .ok_or(DnsBufferError::EndOfBuffer)
-            .map(|b| {
+            .inspect(|_| {
                 self.index += n;
-                b
             })
     }
 
     /// Reads a DNS domain name from the buffer, supporting pointer compression.
     ///
     /// Returns the decoded domain name as a `String` and updates the read index.
+    /// The name is charged to the budget of the buffer.
     ///
     /// # Errors
     /// Returns `DnsBufferError::EndOfBuffer` if buffer ends unexpectedly.
     /// Returns `DnsBufferError::InvalidString` if invalid compression pointers or invalid UTF-8 are encountered.
+    /// Returns `DnsBufferError::BudgetExceeded` if the budget runs out.
     pub fn read_str(&mut self) -> Result<String, DnsBufferError> {
         let (name, new_index) = Self::read_name_at(self.data, self.index)?;
+        self.charge(name.len())?;
         self.index = new_index;
         Ok(name)
     }
 
     /// Internal helper function to read a DNS name at a given position in the buffer.
     ///
-    /// Recursively handles pointer-based compression.
+    /// Follows the compression pointers, which may only point backwards, to
+    /// a prior occurrence of the rest of the name: this rules out loops.
     ///
-    /// Returns a tuple of `(decoded_name, next_index_after_name)`.
+    /// Returns a tuple of `(decoded_name, next_index_after_name)`, the name
+    /// being in presentation format, with its unusual bytes escaped.
     ///
     /// # Arguments
     /// * `data` - The DNS message byte slice.
     /// * `idx` - The starting index to read the name from.
     ///
     /// # Errors
-    /// Returns errors if reading outside bounds, invalid pointers, or invalid UTF-8 occurs.
+    /// Returns errors if reading outside bounds, invalid pointers, or a
+    /// name longer than 255 bytes occurs.
     fn read_name_at(data: &'a [u8], mut idx: usize) -> Result<(String, usize), DnsBufferError> {
         let mut labels = Vec::new();
-        let mut jumped = false;
-        let mut jump_index = 0;
+        let mut length = 1;
+        let mut start  = idx;
+        let mut next   = None;
 
         loop {
             let len = *data.get(idx).ok_or(DnsBufferError::EndOfBuffer)?;
@@ -144,36 +184,36 @@ impl<'a> DnsReadBuffer<'a> {
                 idx += 1;
 
                 let pointer = (((len & 0b0011_1111) as usize) << 8) | (b2 as usize);
-
-                if pointer >= data.len() {
+                if pointer >= start {
                     return Err(DnsBufferError::InvalidString);
                 }
 
-                // Save current index if this is the first jump
-                if !jumped {
-                    jump_index = idx;
-                    jumped = true;
-                }
-
-                // Recursively read name at pointer location
-                let (name, _) = Self::read_name_at(data, pointer)?;
-                labels.push(name);
-                break;
+                // The name ends after the first pointer
+                next.get_or_insert(idx);
+                idx   = pointer;
+                start = pointer;
+                continue;
             }
 
             // Zero length indicates end of domain name
             if len == 0 {
                 break;
             }
+            if len > 63 {
+                return Err(DnsBufferError::LabelTooLong);
+            }
+
+            length += len as usize + 1;
+            if length > MAX_NAME_LEN {
+                return Err(DnsBufferError::InvalidString);
+            }
 
             // Read label of `len` bytes
             let end = idx + (len as usize);
             let label_bytes = data.get(idx..end).ok_or(DnsBufferError::EndOfBuffer)?;
             idx = end;
 
-            // Convert label bytes to UTF-8 string
-            let label = str::from_utf8(label_bytes).map_err(|_| DnsBufferError::InvalidString)?;
-            labels.push(label.to_string());
+            labels.push(escape_label(label_bytes));
         }
 
         Ok((
@@ -182,7 +222,7 @@ impl<'a> DnsReadBuffer<'a> {
             } else {
                 labels.join(".")
             },
-            if jumped { jump_index } else { idx },
+            next.unwrap_or(idx),
         ))
     }
 }
@@ -245,26 +285,97 @@ impl DnsWriteBuffer {
     /// Writes a DNS domain name to the buffer, without compression.
     ///
     /// Splits the name by `.` and writes each label preceded by its length,
-    /// followed by a zero-length byte to terminate the name.
+    /// followed by a zero-length byte to terminate the name. Escaped dots,
+    /// backslashes and `\DDD` bytes are unescaped first. A trailing dot
+    /// is ignored, so the root (`""` or `"."`) is written as a single zero
+    /// byte.
     ///
     /// # Arguments
     /// * `name` - The domain name string to write.
     ///
     /// # Errors
-    /// Returns `DnsBufferError::LabelTooLong` if any label exceeds 63 bytes.
+    /// Returns `DnsBufferError::LabelTooLong` if any label exceeds 63 bytes,
+    /// or `DnsBufferError::InvalidString` if a label is empty or an escape
+    /// is invalid.
     ///
     /// # Returns
     /// `Ok(&mut Self)` on success.
     pub fn write_str(&mut self, name: &str) -> Result<(), DnsBufferError> {
-        for label in name.split('.') {
+        let mut labels = unescape_name(name)?;
+        if labels.last().is_some_and(Vec::is_empty) {
+            labels.pop();
+        }
+        if let [label] = labels.as_slice()
+            && label.is_empty()
+        {
+            labels.clear();
+        }
+
+        for label in labels {
             let len = label.len();
+            if len == 0 {
+                return Err(DnsBufferError::InvalidString);
+            }
             if len > 63 {
                 return Err(DnsBufferError::LabelTooLong);
             }
             self.write_u8(len as u8);
-            self.write_bytes(label.as_bytes());
+            self.write_bytes(&label);
         }
         self.write_u8(0);
         Ok(())
     }
 }
+
+/// Turns a label into the presentation format of RFC 1035 (section 5.1):
+/// the dots and backslashes it contains are escaped by a backslash, and
+/// the bytes outside printable ASCII written as `\DDD`, so that a name
+/// never carries raw control bytes into the answers or the logs.
+pub fn escape_label(label: &[u8]) -> String {
+    let mut out = String::with_capacity(label.len());
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            0x21..=0x7E  => out.push(byte as char),
+            _            => out.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    out
+}
+
+/// Splits a name in presentation format into the bytes of its labels,
+/// undoing the escapes of [`escape_label`]. A trailing dot gives an empty
+/// last label.
+pub fn unescape_name(name: &str) -> Result<Vec<Vec<u8>>, DnsBufferError> {
+    let mut labels = vec![Vec::new()];
+    let mut bytes  = name.bytes();
+    while let Some(byte) = bytes.next() {
+        let byte = match byte {
+            b'.' => {
+                labels.push(Vec::new());
+                continue;
+            }
+            b'\\' => match bytes.next().ok_or(DnsBufferError::InvalidString)? {
+                digit @ b'0'..=b'9' => {
+                    let mut value = (digit - b'0') as u16;
+                    for _ in 0..2 {
+                        match bytes.next() {
+                            Some(digit @ b'0'..=b'9') => value = value * 10 + (digit - b'0') as u16,
+                            _ => return Err(DnsBufferError::InvalidString),
+                        }
+                    }
+                    u8::try_from(value).map_err(|_| DnsBufferError::InvalidString)?
+                }
+                other => other,
+            },
+            byte => byte,
+        };
+        if let Some(label) = labels.last_mut() {
+            label.push(byte);
+        }
+    }
+    Ok(labels)
+}
@@ -0,0 +1,358 @@
+use crate::{
+    clock::Clock,
+    infra::MAX_UDP_SIZE,
+    metrics::{CacheEvent, Metrics},
+    types::{AnswerRecord, Dns, DnsError, DnsReadBuffer, RData, Type},
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+/// Maximum number of entries kept in the cache.
+const MAX_ENTRIES: usize = 4096;
+
+/// TTL of the expired answers served when a name fails to resolve (RFC
+/// 8767, section 4).
+const STALE_TTL: u32 = 30;
+
+/// Key identifying a cached answer.
+///
+/// Answers fetched with the DNSSEC OK (DO) bit carry RRSIGs, while the
+/// ones fetched without it do not, so the two are cached separately.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// Queried domain name, lowercased.
+    pub qname: String,
+    /// Query type.
+    pub qtype: Type,
+    /// Whether the answer was fetched with the DO bit set.
+    pub dnssec_ok: bool,
+}
+
+impl CacheKey {
+    /// Creates a new key, normalizing the name case.
+    pub fn new(qname: &str, qtype: Type, dnssec_ok: bool) -> Self {
+        CacheKey {
+            qname: qname.to_ascii_lowercase(),
+            qtype,
+            dnssec_ok,
+        }
+    }
+}
+
+/// A cached answer, together with its expiration time.
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    answers:  Vec<AnswerRecord>,
+    /// For a negative answer, whether the name doesn't exist, rather than
+    /// having no records of the type.
+    negative: Option<bool>,
+    expires:  Instant,
+}
+
+impl CacheEntry {
+    /// Returns whether the entry carries answers.
+    fn is_positive(&self) -> bool {
+        self.negative.is_none()
+    }
+}
+
+/// Answer cache shared by all the request tasks.
+#[derive(Debug)]
+pub struct Cache {
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+    metrics: Arc<Metrics>,
+    clock:   Arc<dyn Clock>,
+    skew:    u32,
+    /// How long the expired entries are kept, to be served stale.
+    stale:   Duration,
+}
+
+impl Cache {
+    /// Creates a new empty cache, reporting its activity to `metrics`.
+    /// Signatures are considered valid `skew` before their inception and
+    /// after their expiration, to put up with a wall clock that is off.
+    /// Expired entries can still be served `stale` after their expiration.
+    pub fn new(metrics: Arc<Metrics>, clock: Arc<dyn Clock>, skew: Duration, stale: Duration) -> Self {
+        Cache {
+            entries: Mutex::new(HashMap::new()),
+            metrics,
+            clock,
+            skew: skew.as_secs().try_into().unwrap_or(u32::MAX),
+            stale,
+        }
+    }
+
+    /// Returns the counters the cache reports its activity to.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Drops every entry, returning how many there were.
+    #[cfg(feature = "admin")]
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+
+    /// Looks up the answers for the given question, or the negative
+    /// answer cached for it, as a `DnsError::Negative` error.
+    ///
+    /// A query without the DO bit can also be served from an answer fetched
+    /// with it, as that is a superset of what was asked. The returned
+    /// records have their TTL lowered by the time spent in the cache.
+    pub fn get(&self, qname: &str, qtype: Type, dnssec_ok: bool) -> Option<Result<Vec<AnswerRecord>, DnsError>> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = self.clock.now();
+
+        let mut keys = vec![CacheKey::new(qname, qtype, dnssec_ok)];
+        if !dnssec_ok {
+            keys.push(CacheKey::new(qname, qtype, true));
+        }
+
+        for key in keys {
+            let Some(entry) = entries.get(&key) else { continue };
+
+            // Drop the entry as soon as it is found expired, unless it may
+            // still be served stale
+            if entry.expires <= now {
+                if entry.expires + self.stale <= now || !entry.is_positive() {
+                    self.metrics.cache_event(qtype, entry.is_positive(), CacheEvent::Expired);
+                    entries.remove(&key);
+                }
+                continue;
+            }
+
+            self.metrics.cache_event(qtype, entry.is_positive(), CacheEvent::Hit);
+
+            let remaining = (entry.expires - now).as_secs() as u32;
+            if let Some(nxdomain) = entry.negative {
+                return Some(Err(DnsError::Negative { nxdomain, ttl: remaining }));
+            }
+            let answers = entry
+                .answers
+                .iter()
+                .cloned()
+                .map(|mut answer| {
+                    answer.ttl = answer.ttl.min(remaining);
+                    answer
+                })
+                .collect();
+            return Some(Ok(answers));
+        }
+
+        self.metrics.cache_event(qtype, true, CacheEvent::Miss);
+        None
+    }
+
+    /// Looks up the expired answers for the given question, to be served
+    /// when it fails to resolve (RFC 8767), as long as they expired less
+    /// than the stale period ago. The records have a TTL of 30 seconds.
+    /// Negative answers are never served stale.
+    pub fn get_stale(&self, qname: &str, qtype: Type, dnssec_ok: bool) -> Option<Vec<AnswerRecord>> {
+        let entries = self.entries.lock().unwrap();
+        let now = self.clock.now();
+
+        let mut keys = vec![CacheKey::new(qname, qtype, dnssec_ok)];
+        if !dnssec_ok {
+            keys.push(CacheKey::new(qname, qtype, true));
+        }
+
+        let entry = keys
+            .iter()
+            .filter_map(|key| entries.get(key))
+            .find(|entry| entry.is_positive() && entry.expires <= now && now < entry.expires + self.stale)?;
+        self.metrics.cache_event(qtype, true, CacheEvent::Stale);
+
+        let answers = entry
+            .answers
+            .iter()
+            .cloned()
+            .map(|mut answer| {
+                answer.ttl = STALE_TTL;
+                answer
+            })
+            .collect();
+        Some(answers)
+    }
+
+    /// Stores the answers for the given question.
+    ///
+    /// The entry lives as long as the smallest TTL among the answers, and
+    /// no longer than their signatures are valid: answers whose signatures
+    /// are outside of their validity window aren't cached. An answer
+    /// fetched with the DO bit supersedes the one fetched without it,
+    /// which is dropped.
+    pub fn insert(&self, qname: &str, qtype: Type, dnssec_ok: bool, answers: Vec<AnswerRecord>) {
+        let ttl = match answers.iter().map(|answer| answer.ttl).min() {
+            Some(ttl) if ttl > 0 => ttl,
+            _ => return,
+        };
+        let ttl = match signatures_lifetime(&answers, self.clock.unix() as u32, self.skew) {
+            Some(lifetime) if lifetime <= 0 => return,
+            Some(lifetime) => ttl.min(lifetime as u32),
+            None           => ttl,
+        };
+
+        let expires = self.clock.now() + Duration::from_secs(ttl as u64);
+        self.store(CacheKey::new(qname, qtype, dnssec_ok), CacheEntry { answers, negative: None, expires });
+    }
+
+    /// Stores the negative answer for the given question, NXDOMAIN or
+    /// NODATA, for `ttl` seconds, the TTL of the SOA record that came with
+    /// it capped by its minimum field (RFC 2308, section 5).
+    pub fn insert_negative(&self, qname: &str, qtype: Type, dnssec_ok: bool, nxdomain: bool, ttl: u32) {
+        if ttl == 0 {
+            return;
+        }
+        let expires = self.clock.now() + Duration::from_secs(ttl as u64);
+        self.store(
+            CacheKey::new(qname, qtype, dnssec_ok),
+            CacheEntry { answers: Vec::new(), negative: Some(nxdomain), expires },
+        );
+    }
+
+    /// Stores an entry, making room for it if the cache is full.
+    fn store(&self, key: CacheKey, entry: CacheEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        let now = self.clock.now();
+
+        if key.dnssec_ok {
+            entries.remove(&CacheKey::new(&key.qname, key.qtype, false));
+        }
+
+        // Make room for the new entry, dropping the expired ones first and
+        // the one closest to expiration if that is not enough
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|key, entry| {
+                let alive = entry.expires + self.stale > now;
+                if !alive {
+                    self.metrics.cache_event(key.qtype, entry.is_positive(), CacheEvent::Expired);
+                }
+                alive
+            });
+        }
+        if entries.len() >= MAX_ENTRIES
+            && let Some(key) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(key, _)| key.clone())
+            && let Some(evicted) = entries.remove(&key)
+        {
+            self.metrics.cache_event(key.qtype, evicted.is_positive(), CacheEvent::Evicted);
+        }
+
+        entries.insert(key, entry);
+    }
+
+    /// Writes the live positive entries to `path`, returning how many were
+    /// saved.
+    ///
+    /// Each entry is stored as its expiration time, in seconds since the
+    /// Unix epoch, followed by a length-prefixed DNS response carrying the
+    /// question and the answers. The file is replaced atomically.
+    pub fn save(&self, path: &Path) -> Result<usize, DnsError> {
+        let entries = self.entries.lock().unwrap();
+        let now     = self.clock.now();
+        let wall    = self.clock.wall();
+
+        let mut data  = Vec::new();
+        let mut saved = 0;
+        for (key, entry) in entries.iter().filter(|(_, entry)| entry.is_positive() && entry.expires > now) {
+            let expires = (wall + (entry.expires - now))
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| DnsError::InvalidField)?
+                .as_secs();
+
+            let mut msg = Dns::new_question(&key.qname, key.qtype, 0);
+            msg.header.flags.qr = true;
+            msg.answers = entry.answers.clone();
+            msg.set_edns(MAX_UDP_SIZE).dnssec_ok = key.dnssec_ok;
+            let Ok(enc) = msg.encode() else { continue };
+
+            data.extend_from_slice(&expires.to_be_bytes());
+            data.extend_from_slice(&(enc.data.len() as u32).to_be_bytes());
+            data.extend_from_slice(&enc.data);
+            saved += 1;
+        }
+        drop(entries);
+
+        let io = |e: std::io::Error| DnsError::IOError(format!("can't save the cache to {}: {}", path.display(), e));
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &data).map_err(io)?;
+        fs::rename(&tmp, path).map_err(io)?;
+        Ok(saved)
+    }
+
+    /// Loads the entries saved to `path` by [`Cache::save`], skipping the
+    /// ones that expired in the meantime, and returns how many were loaded.
+    ///
+    /// An entry never lives longer than the TTL of its answers, even if the
+    /// wall clock went back since it was saved.
+    pub fn load(&self, path: &Path) -> Result<usize, DnsError> {
+        let data = fs::read(path)
+            .map_err(|e| DnsError::IOError(format!("can't load the cache from {}: {}", path.display(), e)))?;
+        let now  = self.clock.now();
+        let wall = self.clock.unix();
+
+        let mut entries = self.entries.lock().unwrap();
+        let mut loaded  = 0;
+        let mut rest    = data.as_slice();
+        while !rest.is_empty() && entries.len() < MAX_ENTRIES {
+            let (expires, tail) = rest.split_first_chunk::<8>().ok_or(DnsError::InvalidField)?;
+            let (length, tail)  = tail.split_first_chunk::<4>().ok_or(DnsError::InvalidField)?;
+            let length = u32::from_be_bytes(*length) as usize;
+            let body   = tail.get(..length).ok_or(DnsError::InvalidField)?;
+            rest = &tail[length..];
+
+            let remaining = u64::from_be_bytes(*expires).saturating_sub(wall);
+            if remaining == 0 {
+                continue;
+            }
+            let msg = Dns::decode(&mut DnsReadBuffer::new(body))?;
+            let Some(question) = msg.questions.first() else { continue };
+            let Some(ttl) = msg.answers.iter().map(|answer| answer.ttl).min() else { continue };
+            let remaining = remaining.min(ttl.into());
+
+            entries.insert(
+                CacheKey::new(&question.qname, question.qtype, msg.dnssec_ok()),
+                CacheEntry {
+                    answers:  msg.answers.clone(),
+                    negative: None,
+                    expires:  now + Duration::from_secs(remaining),
+                },
+            );
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+}
+
+/// Returns how many seconds are left until the first of the signatures
+/// among `answers` expires, at `now` in seconds since the Unix epoch and
+/// allowing for `skew`, or `None` if there is no signature. A signature
+/// outside of its validity window leaves none.
+///
+/// Times are compared in serial number arithmetic (RFC 4034, section
+/// 3.1.5), as they wrap around in 2106.
+fn signatures_lifetime(answers: &[AnswerRecord], now: u32, skew: u32) -> Option<i64> {
+    answers
+        .iter()
+        .filter_map(|answer| match &answer.rdata {
+            RData::Unknown { rtype: 46, data } if data.len() >= 16 => {
+                let expiration = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+                let inception  = u32::from_be_bytes([data[12], data[13], data[14], data[15]]);
+                let started    = now.wrapping_sub(inception) as i32 as i64 + skew as i64;
+                let left       = expiration.wrapping_sub(now) as i32 as i64 + skew as i64;
+                Some(if started < 0 { 0 } else { left })
+            }
+            _ => None,
+        })
+        .min()
+}
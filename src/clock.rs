@@ -0,0 +1,54 @@
+use std::{
+    fmt::Debug,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Source of the current time.
+///
+/// Expiries run on the monotonic clock, which a bad real-time clock
+/// doesn't affect, while the wall clock is only read for the times
+/// exchanged with the outside world: the validity of signatures and the
+/// expiries of the saved cache. Going through this trait lets the time be
+/// shifted or faked.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the monotonic time.
+    fn now(&self) -> Instant;
+
+    /// Returns the wall clock time.
+    fn wall(&self) -> SystemTime;
+
+    /// Returns the wall clock time, in seconds since the Unix epoch.
+    fn unix(&self) -> u64 {
+        self.wall().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+    }
+}
+
+/// The clocks of the operating system, with the wall clock shifted by a
+/// fixed number of seconds.
+#[derive(Debug, Default)]
+pub struct SystemClock {
+    offset: i64,
+}
+
+impl SystemClock {
+    /// Creates a clock whose wall time is `offset` seconds ahead of the
+    /// system's, or behind it if negative.
+    pub fn new(offset: i64) -> Self {
+        SystemClock { offset }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall(&self) -> SystemTime {
+        let now   = SystemTime::now();
+        let shift = Duration::from_secs(self.offset.unsigned_abs());
+        match self.offset >= 0 {
+            true  => now + shift,
+            false => now.checked_sub(shift).unwrap_or(UNIX_EPOCH),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{
+    replay::{ask, summary},
+    rng::DnsRng,
+    types::{Dns, DnsError, Type},
+};
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    process,
+};
+use tokio::task::JoinSet;
+
+/// Questions asked to both targets at once.
+const CONCURRENCY: usize = 32;
+
+/// Options of the `compare` subcommand.
+#[derive(Debug)]
+struct Options {
+    /// File listing the names to resolve.
+    path:     PathBuf,
+    /// Instance whose answers are checked.
+    server:   SocketAddr,
+    /// Resolver whose answers are the reference.
+    baseline: SocketAddr,
+    /// Type asked for the names listed without one.
+    qtype:    Type,
+}
+
+impl Options {
+    /// Parses the arguments following `compare`.
+    fn parse(mut args: impl Iterator<Item = String>, server: SocketAddr) -> Result<Self, DnsError> {
+        let usage = || DnsError::IOError("usage: compare <names> --baseline addr [--server addr] [--type type]".into());
+        let mut path     = None;
+        let mut server   = server;
+        let mut baseline = None;
+        let mut qtype    = Type::A;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(usage);
+            match arg.as_str() {
+                "--server"   => server   = target(&value()?).ok_or_else(usage)?,
+                "--baseline" => baseline = Some(target(&value()?).ok_or_else(usage)?),
+                "--type"     => qtype    = value()?.parse().map_err(|_| usage())?,
+                _ if path.is_none() && !arg.starts_with("--") => path = Some(PathBuf::from(arg)),
+                _ => return Err(usage()),
+            }
+        }
+        Ok(Options {
+            path:     path.ok_or_else(usage)?,
+            server,
+            baseline: baseline.ok_or_else(usage)?,
+            qtype,
+        })
+    }
+}
+
+/// Runs the `compare` subcommand: resolves the names of a list with both
+/// `server` (the local instance unless told otherwise) and a baseline
+/// resolver, such as a public one, and reports the response codes and
+/// answer sets that differ, along with a summary.
+///
+/// The process exits with status 1 if any answer differs.
+pub async fn run(args: impl Iterator<Item = String>, server: SocketAddr) -> Result<(), DnsError> {
+    let options   = Options::parse(args, server)?;
+    let text      = fs::read_to_string(&options.path)
+        .map_err(|e| DnsError::IOError(format!("can't read {}: {}", options.path.display(), e)))?;
+    let questions = read_names(&text, options.qtype)?;
+
+    // Ask both targets each question, a few questions at a time
+    let rng         = DnsRng::from_entropy();
+    let mut tasks   = JoinSet::new();
+    let mut results = Vec::with_capacity(questions.len());
+    for (n, (name, qtype)) in questions.iter().enumerate() {
+        if tasks.len() >= CONCURRENCY && let Some(Ok(result)) = tasks.join_next().await {
+            results.push(result);
+        }
+        let mut query = Dns::new_question(name, *qtype, rng.query_id());
+        query.header.flags.rd = true;
+        let query    = query.encode()?.into_inner();
+        let server   = options.server;
+        let baseline = options.baseline;
+        tasks.spawn(async move {
+            let (answer, expected) = tokio::join!(ask(&query, server), ask(&query, baseline));
+            (n, answer, expected)
+        });
+    }
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok(result) = joined {
+            results.push(result);
+        }
+    }
+    results.sort_by_key(|(n, ..)| *n);
+
+    // Differing response codes are told apart from differing answers
+    let (mut same, mut rcodes, mut answers) = (0, 0, 0);
+    for (n, answer, expected) in results {
+        let (name, qtype) = &questions[n];
+        let expected = expected.as_deref().map_or("no answer".to_string(), summary);
+        let answer   = answer.as_deref().map_or("no answer".to_string(), summary);
+        if answer == expected {
+            same += 1;
+            continue;
+        }
+        match rcode(&answer) == rcode(&expected) {
+            true  => answers += 1,
+            false => rcodes += 1,
+        }
+        println!("{} {}: baseline {}, got {}", name, qtype, expected, answer);
+    }
+
+    println!(
+        "compared {} names: {} same, {} different response codes, {} different answers",
+        questions.len(), same, rcodes, answers,
+    );
+    if rcodes + answers > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Parses a target, whose port defaults to 53, such as `1.1.1.1`.
+pub fn target(s: &str) -> Option<SocketAddr> {
+    s.parse()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 53)))
+}
+
+/// Reads the questions of a list of names, one per line, optionally
+/// followed by the type asked, `qtype` otherwise. Blank lines and the
+/// comments starting with `#` are skipped.
+fn read_names(text: &str, qtype: Type) -> Result<Vec<(String, Type)>, DnsError> {
+    let mut questions = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let Some(name) = fields.next() else {
+            continue;
+        };
+        let invalid = || DnsError::IOError(format!("line {}: invalid entry: {}", number + 1, line.trim()));
+        let qtype = match fields.next() {
+            Some(qtype) => qtype.parse().map_err(|_| invalid())?,
+            None        => qtype,
+        };
+        if fields.next().is_some() {
+            return Err(invalid());
+        }
+        questions.push((name.trim_end_matches('.').to_string(), qtype));
+    }
+    Ok(questions)
+}
+
+/// Returns the response code of the summary of a response, or the whole
+/// summary when there was no valid response.
+fn rcode(summary: &str) -> &str {
+    summary.split(" [").next().unwrap_or(summary)
+}
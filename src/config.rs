@@ -0,0 +1,804 @@
+use crate::{
+    local::{LocalRecord, LocalZone},
+    logging::{LogFilter, LogFormat, LogTarget},
+    network::Network,
+    policy::HomographAction,
+    querylog::Rotation,
+    resolver::{ApexMode, NonRecursiveMode, UpstreamFamily},
+    routing::DomainRule,
+    timeouts::{RetryPolicy, TimeoutRule},
+    types::DnsError,
+    unix::PeerRule,
+    update::UpdateKey,
+    zone::{SecondaryZone, ZoneFile},
+};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    env, fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+/// Runtime configuration of the resolver.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Addresses the server listens on for client queries, over UDP.
+    pub listen: Vec<SocketAddr>,
+    /// Root server the iterative resolution starts from.
+    pub root: SocketAddr,
+    /// Root hints file listing the root servers, instead of `root`.
+    pub root_hints: Option<PathBuf>,
+    /// Maximum number of nested queries for a single resolution.
+    pub max_depth: usize,
+    /// Queries taking longer than this are written to the slow-query log.
+    pub slow_query_threshold: Duration,
+    /// File the slow-query log is appended to, stderr when unset.
+    pub slow_log: Option<PathBuf>,
+    /// File every query is logged to, if any.
+    pub query_log: Option<PathBuf>,
+    /// When the query log is rotated, and how many old files are kept.
+    pub query_log_rotation: Rotation,
+    /// Where the log messages are written to.
+    pub log_target: LogTarget,
+    /// Format of the log messages written to stderr.
+    pub log_format: LogFormat,
+    /// Levels of the log messages written, per module.
+    pub log_filter: LogFilter,
+    /// Unix domain socket of the collector the messages exchanged are
+    /// written to in the dnstap format.
+    pub dnstap: Option<PathBuf>,
+    /// Largest UDP payload the resolver sends or advertises upstream.
+    pub max_udp_size: u16,
+    /// Fixed seed of the random generator, for deterministic runs.
+    pub rng_seed: Option<u64>,
+    /// Seconds added to the wall clock, for devices whose clock is known
+    /// to be off and for tests.
+    pub clock_offset: i64,
+    /// How far the wall clock may be off from the signers' when checking
+    /// the validity of signatures.
+    pub signature_skew: Duration,
+    /// How long the expired answers may be served when their name fails to
+    /// resolve, zero for never.
+    pub serve_stale: Duration,
+    /// Whether to randomize the case of the outgoing query names (0x20).
+    pub use_0x20: bool,
+    /// Port the name servers found through referrals are queried on.
+    pub delegation_port: u16,
+    /// Name servers of a zone queried at once, the first answer winning.
+    pub fanout: usize,
+    /// Address families the name servers are contacted over.
+    pub upstream_family: UpstreamFamily,
+    /// Addresses dropped from the answers found in the public DNS.
+    pub bogus_addresses: Vec<IpAddr>,
+    /// Queries per second sent to each upstream server, 0 for no limit.
+    pub outgoing_rate: u32,
+    /// Queries that can be sent to a server at once before pacing kicks in.
+    pub outgoing_burst: u32,
+    /// Largest random delay added to the paced queries.
+    pub outgoing_jitter: Duration,
+    /// Sockets the upstream queries share, per address family, 0 for a
+    /// socket per query.
+    pub outgoing_sockets: usize,
+    /// How long a shared socket is used before moving to another port.
+    pub socket_lifetime: Duration,
+    /// Whether nothing identifying the clients may leave with the
+    /// upstream queries.
+    pub privacy: bool,
+    /// Largest random delay the upstream queries are held back for, in
+    /// privacy mode.
+    pub privacy_delay: Duration,
+    /// Static records answered locally.
+    pub local_records: Vec<LocalRecord>,
+    /// Hosts file whose records are served along with the static ones.
+    pub hosts_file: Option<PathBuf>,
+    /// Domains answered from the local records only, never resolved.
+    pub local_zones: Vec<LocalZone>,
+    /// Whether to derive PTR records from the local A/AAAA records.
+    pub synthesize_ptr: bool,
+    /// Domains that clients are not allowed to resolve.
+    pub blocklist: Vec<String>,
+    /// Lists of blocked domains fetched over HTTP on startup.
+    pub blocklist_urls: Vec<String>,
+    /// Lists of blocked domains read from local files.
+    pub blocklist_files: Vec<PathBuf>,
+    /// How often the lists of blocked domains are reloaded, zero for never.
+    pub blocklist_refresh: Duration,
+    /// Names whose lookalikes in other scripts are flagged.
+    pub protected_names: Vec<String>,
+    /// What to do with the lookalikes of the protected names.
+    pub homograph_action: HomographAction,
+    /// Addresses the blocked names resolve to, instead of not existing.
+    pub sinkhole: Vec<IpAddr>,
+    /// Address the page explaining the blocks is served on, over HTTP.
+    pub sinkhole_listen: Option<SocketAddr>,
+    /// How the queries for the root and the top-level domains are handled.
+    pub apex_queries: ApexMode,
+    /// How the queries that don't ask for recursion are handled.
+    pub non_recursive: NonRecursiveMode,
+    /// Whether names under `.local` are left to Multicast DNS.
+    pub mdns: bool,
+    /// Whether positive answers leave out the records the client didn't
+    /// ask for.
+    pub minimal_responses: bool,
+    /// Upstream servers the client queries are relayed to, as they are,
+    /// instead of being resolved, in order of preference.
+    pub proxy: Vec<SocketAddr>,
+    /// How the names of specific domains are resolved.
+    pub domain_rules: Vec<DomainRule>,
+    /// Retry policies of the queries of specific zones or upstreams.
+    pub timeout_rules: Vec<TimeoutRule>,
+    /// Retry policy of the plain UDP queries without a timeout rule.
+    pub query_policy: RetryPolicy,
+    /// Whether the service names of the Kubernetes cluster are forwarded
+    /// to the cluster DNS.
+    pub kubernetes: bool,
+    /// resolv.conf the cluster DNS is learned from.
+    pub resolv_conf: PathBuf,
+    /// Consul agent whose catalog is served as a dynamic zone.
+    pub consul: Option<String>,
+    /// Domain the services of the Consul catalog are served under.
+    pub consul_domain: String,
+    /// ACL token sent to the Consul agent.
+    pub consul_token: Option<String>,
+    /// Address the ACME DNS-01 challenge API is served on, over HTTP.
+    pub acme_listen: Option<SocketAddr>,
+    /// Token the clients of the challenge API must present.
+    pub acme_token: Option<String>,
+    /// Domains challenges may be published for, with their subdomains.
+    pub acme_zones: Vec<String>,
+    /// Instances the new cache entries are sent to.
+    pub peers: Vec<SocketAddr>,
+    /// Address the cache entries of the peers are received on.
+    pub peer_listen: Option<SocketAddr>,
+    /// Key attesting the origin of the cache entries exchanged with peers.
+    pub peer_key: Option<String>,
+    /// File the cache is saved to on shutdown and loaded from on startup.
+    pub cache_file: Option<PathBuf>,
+    /// Address the health check endpoints are served on, over HTTP.
+    pub health_listen: Option<SocketAddr>,
+    /// Address the administration API is served on, over HTTP.
+    pub admin_listen: Option<SocketAddr>,
+    /// Bearer token the administration requests must carry.
+    pub admin_token: Option<String>,
+    /// Path of the unix domain socket queries are also accepted on.
+    pub unix_listen: Option<PathBuf>,
+    /// Clients admitted on the unix domain socket, all if empty.
+    pub unix_allow: Vec<PeerRule>,
+    /// Addresses queries are also accepted on over TLS (DoT).
+    pub tls_listen: Vec<SocketAddr>,
+    /// Addresses queries are also accepted on over QUIC (DoQ).
+    pub quic_listen: Vec<SocketAddr>,
+    /// PEM file of the certificate chain presented to the TLS and QUIC
+    /// clients.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM file of the private key of the certificate.
+    pub tls_key: Option<PathBuf>,
+    /// PEM file of the certificate authorities the DoT servers of the
+    /// domain rules are checked against, instead of the web PKI roots.
+    pub upstream_ca: Option<PathBuf>,
+    /// Zones read from master files, served to the secondaries.
+    pub zones: Vec<ZoneFile>,
+    /// Address the zone transfers are served on, over TCP.
+    pub transfer_listen: Option<SocketAddr>,
+    /// Networks allowed to transfer the zones.
+    pub allow_transfer: Vec<Network>,
+    /// Zones transferred from their primary, and kept in sync with it.
+    pub secondary_zones: Vec<SecondaryZone>,
+    /// Secondaries sent a NOTIFY when a zone changes.
+    pub notify: Vec<SocketAddr>,
+    /// Networks allowed to update the zones of master files.
+    pub allow_update: Vec<Network>,
+    /// Keys the updates of the zones may be signed with, from anywhere.
+    pub update_keys: Vec<UpdateKey>,
+    /// Policy plugins the queries are checked with, in order.
+    pub plugins: Vec<PathBuf>,
+    /// Reverse lookups resolved per second, 0 for no limit.
+    pub ptr_rate: u32,
+    /// How long failed and empty reverse lookups are cached.
+    pub ptr_negative_ttl: Duration,
+    /// Responses per second sent over UDP to a client subnet for the same
+    /// name and type, 0 for no limit.
+    pub rrl_rate: u32,
+    /// One in how many responses over the limit is sent truncated rather
+    /// than dropped, 0 to drop them all.
+    pub rrl_slip: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen:               vec!["127.0.0.1:53".parse().unwrap()],
+            root:                 "198.41.0.4:53".parse().unwrap(),
+            root_hints:           None,
+            max_depth:            20,
+            slow_query_threshold: Duration::from_millis(1000),
+            slow_log:             None,
+            query_log:            None,
+            query_log_rotation:   Rotation {
+                size:     100 * 1024 * 1024,
+                interval: Duration::from_secs(86400),
+                keep:     7,
+            },
+            log_target:           LogTarget::Stderr,
+            log_format:           LogFormat::Text,
+            log_filter:           "info".parse().unwrap(),
+            dnstap:               None,
+            max_udp_size:         1232,
+            rng_seed:             None,
+            clock_offset:         0,
+            signature_skew:       Duration::from_secs(3600),
+            serve_stale:          Duration::ZERO,
+            use_0x20:             false,
+            delegation_port:      53,
+            fanout:               1,
+            upstream_family:      UpstreamFamily::Ipv4,
+            bogus_addresses:      vec![
+                Ipv4Addr::UNSPECIFIED.into(),
+                Ipv4Addr::BROADCAST.into(),
+                Ipv6Addr::UNSPECIFIED.into(),
+            ],
+            outgoing_rate:        50,
+            outgoing_burst:       20,
+            outgoing_jitter:      Duration::from_millis(20),
+            outgoing_sockets:     16,
+            socket_lifetime:      Duration::from_secs(60),
+            privacy:              false,
+            privacy_delay:        Duration::ZERO,
+            local_records:        Vec::new(),
+            hosts_file:           None,
+            local_zones:          Vec::new(),
+            synthesize_ptr:       false,
+            blocklist:            Vec::new(),
+            blocklist_urls:       Vec::new(),
+            blocklist_files:      Vec::new(),
+            blocklist_refresh:    Duration::from_secs(86400),
+            protected_names:      Vec::new(),
+            homograph_action:     HomographAction::Block,
+            sinkhole:             Vec::new(),
+            sinkhole_listen:      None,
+            apex_queries:         ApexMode::Root,
+            non_recursive:        NonRecursiveMode::Cache,
+            mdns:                 false,
+            minimal_responses:    false,
+            proxy:                Vec::new(),
+            domain_rules:         Vec::new(),
+            timeout_rules:        Vec::new(),
+            query_policy:         RetryPolicy::new(Duration::from_secs(2)),
+            kubernetes:           false,
+            resolv_conf:          "/etc/resolv.conf".into(),
+            consul:               None,
+            consul_domain:        "consul".to_string(),
+            consul_token:         None,
+            acme_listen:          None,
+            acme_token:           None,
+            acme_zones:           Vec::new(),
+            peers:                Vec::new(),
+            peer_listen:          None,
+            peer_key:             None,
+            cache_file:           None,
+            health_listen:        None,
+            admin_listen:         None,
+            admin_token:          None,
+            unix_listen:          None,
+            unix_allow:           Vec::new(),
+            tls_listen:           Vec::new(),
+            quic_listen:          Vec::new(),
+            tls_cert:             None,
+            tls_key:              None,
+            upstream_ca:          None,
+            zones:                Vec::new(),
+            transfer_listen:      None,
+            allow_transfer:       Vec::new(),
+            secondary_zones:      Vec::new(),
+            notify:               Vec::new(),
+            allow_update:         Vec::new(),
+            update_keys:          Vec::new(),
+            plugins:              Vec::new(),
+            ptr_rate:             20,
+            ptr_negative_ttl:     Duration::from_secs(60),
+            rrl_rate:             0,
+            rrl_slip:             2,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the defaults, overridden by the
+    /// options of the `DNSR_CONFIG` file, if any, then by the `DNSR_*`
+    /// environment variables that are set, themselves overridden by the
+    /// command line flags `args`.
+    pub fn load(args: &[String]) -> Result<Self, DnsError> {
+        let options    = Options::parse(args)?;
+        let mut config = Config::default();
+
+        if let Some(listen) = options.list("DNSR_LISTEN")? {
+            config.listen = listen;
+        }
+        if let Some(root) = options.value("DNSR_ROOT")? {
+            config.root = root;
+        }
+        if let Some(path) = options.value("DNSR_ROOT_HINTS")? {
+            config.root_hints = Some(path);
+        }
+        if let Some(depth) = options.value("DNSR_MAX_DEPTH")? {
+            config.max_depth = depth;
+        }
+        if let Some(millis) = options.value("DNSR_SLOW_QUERY_MS")? {
+            config.slow_query_threshold = Duration::from_millis(millis);
+        }
+        if let Some(path) = options.value("DNSR_SLOW_LOG")? {
+            config.slow_log = Some(path);
+        }
+        if let Some(path) = options.value("DNSR_QUERY_LOG")? {
+            config.query_log = Some(path);
+        }
+        if let Some(size) = options.value("DNSR_QUERY_LOG_SIZE")? {
+            config.query_log_rotation.size = size;
+        }
+        if let Some(secs) = options.value("DNSR_QUERY_LOG_ROTATE")? {
+            config.query_log_rotation.interval = Duration::from_secs(secs);
+        }
+        if let Some(keep) = options.value("DNSR_QUERY_LOG_KEEP")? {
+            config.query_log_rotation.keep = keep;
+        }
+        if let Some(target) = options.value("DNSR_LOG_TARGET")? {
+            config.log_target = target;
+        }
+        if let Some(format) = options.value("DNSR_LOG_FORMAT")? {
+            config.log_format = format;
+        }
+        if let Some(filter) = options.value("DNSR_LOG_FILTER")? {
+            config.log_filter = filter;
+        }
+        if let Some(path) = options.value("DNSR_DNSTAP")? {
+            config.dnstap = Some(path);
+        }
+        if let Some(size) = options.value("DNSR_MAX_UDP_SIZE")? {
+            config.max_udp_size = size;
+        }
+        if let Some(seed) = options.value("DNSR_RNG_SEED")? {
+            config.rng_seed = Some(seed);
+        }
+        if let Some(secs) = options.value("DNSR_CLOCK_OFFSET")? {
+            config.clock_offset = secs;
+        }
+        if let Some(secs) = options.value("DNSR_SIGNATURE_SKEW")? {
+            config.signature_skew = Duration::from_secs(secs);
+        }
+        if let Some(secs) = options.value("DNSR_SERVE_STALE")? {
+            config.serve_stale = Duration::from_secs(secs);
+        }
+        if let Some(enabled) = options.value("DNSR_USE_0X20")? {
+            config.use_0x20 = enabled;
+        }
+        if let Some(port) = options.value("DNSR_DELEGATION_PORT")? {
+            config.delegation_port = port;
+        }
+        if let Some(count) = options.value("DNSR_FANOUT")? {
+            config.fanout = count;
+        }
+        if let Some(family) = options.value("DNSR_UPSTREAM_FAMILY")? {
+            config.upstream_family = family;
+        }
+        if let Some(addresses) = options.list("DNSR_BOGUS_ADDRESSES")? {
+            config.bogus_addresses = addresses;
+        }
+        if let Some(rate) = options.value("DNSR_OUTGOING_RATE")? {
+            config.outgoing_rate = rate;
+        }
+        if let Some(burst) = options.value("DNSR_OUTGOING_BURST")? {
+            config.outgoing_burst = burst;
+        }
+        if let Some(millis) = options.value("DNSR_OUTGOING_JITTER_MS")? {
+            config.outgoing_jitter = Duration::from_millis(millis);
+        }
+        if let Some(count) = options.value("DNSR_OUTGOING_SOCKETS")? {
+            config.outgoing_sockets = count;
+        }
+        if let Some(secs) = options.value("DNSR_OUTGOING_SOCKET_LIFETIME")? {
+            config.socket_lifetime = Duration::from_secs(secs);
+        }
+        if let Some(enabled) = options.value("DNSR_PRIVACY")? {
+            config.privacy = enabled;
+        }
+        if let Some(millis) = options.value("DNSR_PRIVACY_DELAY_MS")? {
+            config.privacy_delay = Duration::from_millis(millis);
+        }
+        if let Some(records) = options.list("DNSR_LOCAL_RECORDS")? {
+            config.local_records = records;
+        }
+        if let Some(path) = options.value("DNSR_HOSTS_FILE")? {
+            config.hosts_file = Some(path);
+        }
+        if let Some(zones) = options.list("DNSR_LOCAL_ZONES")? {
+            config.local_zones = zones;
+        }
+        if let Some(enabled) = options.value("DNSR_SYNTHESIZE_PTR")? {
+            config.synthesize_ptr = enabled;
+        }
+        if let Some(names) = options.list("DNSR_BLOCKLIST")? {
+            config.blocklist = names;
+        }
+        if let Some(urls) = options.list("DNSR_BLOCKLIST_URLS")? {
+            config.blocklist_urls = urls;
+        }
+        if let Some(paths) = options.list("DNSR_BLOCKLIST_FILES")? {
+            config.blocklist_files = paths;
+        }
+        if let Some(secs) = options.value("DNSR_BLOCKLIST_REFRESH")? {
+            config.blocklist_refresh = Duration::from_secs(secs);
+        }
+        if let Some(names) = options.list("DNSR_PROTECTED_NAMES")? {
+            config.protected_names = names;
+        }
+        if let Some(action) = options.value("DNSR_HOMOGRAPH_ACTION")? {
+            config.homograph_action = action;
+        }
+        if let Some(addresses) = options.list("DNSR_SINKHOLE")? {
+            config.sinkhole = addresses;
+        }
+        if let Some(addr) = options.value("DNSR_SINKHOLE_LISTEN")? {
+            config.sinkhole_listen = Some(addr);
+        }
+        if let Some(mode) = options.value("DNSR_APEX_QUERIES")? {
+            config.apex_queries = mode;
+        }
+        if let Some(mode) = options.value("DNSR_NON_RECURSIVE")? {
+            config.non_recursive = mode;
+        }
+        if let Some(enabled) = options.value("DNSR_MDNS")? {
+            config.mdns = enabled;
+        }
+        if let Some(enabled) = options.value("DNSR_MINIMAL_RESPONSES")? {
+            config.minimal_responses = enabled;
+        }
+        if let Some(upstreams) = options.list("DNSR_PROXY")? {
+            config.proxy = upstreams;
+        }
+        if let Some(rules) = options.list("DNSR_DOMAIN_RULES")? {
+            config.domain_rules = rules;
+        }
+        if let Some(rules) = options.list("DNSR_TIMEOUT_RULES")? {
+            config.timeout_rules = rules;
+        }
+        if let Some(policy) = options.value("DNSR_QUERY_TIMEOUT")? {
+            config.query_policy = policy;
+        }
+        if let Some(enabled) = options.value("DNSR_KUBERNETES")? {
+            config.kubernetes = enabled;
+        }
+        if let Some(path) = options.value("DNSR_RESOLV_CONF")? {
+            config.resolv_conf = path;
+        }
+        if let Some(url) = options.value("DNSR_CONSUL")? {
+            config.consul = Some(url);
+        }
+        if let Some(domain) = options.value("DNSR_CONSUL_DOMAIN")? {
+            config.consul_domain = domain;
+        }
+        if let Some(token) = options.value("DNSR_CONSUL_TOKEN")? {
+            config.consul_token = Some(token);
+        }
+        if let Some(addr) = options.value("DNSR_ACME_LISTEN")? {
+            config.acme_listen = Some(addr);
+        }
+        if let Some(token) = options.value("DNSR_ACME_TOKEN")? {
+            config.acme_token = Some(token);
+        }
+        if let Some(zones) = options.list("DNSR_ACME_ZONES")? {
+            config.acme_zones = zones;
+        }
+        if let Some(peers) = options.list("DNSR_PEERS")? {
+            config.peers = peers;
+        }
+        if let Some(addr) = options.value("DNSR_PEER_LISTEN")? {
+            config.peer_listen = Some(addr);
+        }
+        if let Some(key) = options.value("DNSR_PEER_KEY")? {
+            config.peer_key = Some(key);
+        }
+        if let Some(path) = options.value("DNSR_CACHE_FILE")? {
+            config.cache_file = Some(path);
+        }
+        if let Some(addr) = options.value("DNSR_HEALTH_LISTEN")? {
+            config.health_listen = Some(addr);
+        }
+        if let Some(addr) = options.value("DNSR_ADMIN_LISTEN")? {
+            config.admin_listen = Some(addr);
+        }
+        if let Some(token) = options.value("DNSR_ADMIN_TOKEN")? {
+            config.admin_token = Some(token);
+        }
+        if let Some(path) = options.value("DNSR_UNIX_LISTEN")? {
+            config.unix_listen = Some(path);
+        }
+        if let Some(rules) = options.list("DNSR_UNIX_ALLOW")? {
+            config.unix_allow = rules;
+        }
+        if let Some(addrs) = options.list("DNSR_TLS_LISTEN")? {
+            config.tls_listen = addrs;
+        }
+        if let Some(addrs) = options.list("DNSR_QUIC_LISTEN")? {
+            config.quic_listen = addrs;
+        }
+        if let Some(path) = options.value("DNSR_TLS_CERT")? {
+            config.tls_cert = Some(path);
+        }
+        if let Some(path) = options.value("DNSR_TLS_KEY")? {
+            config.tls_key = Some(path);
+        }
+        if let Some(path) = options.value("DNSR_UPSTREAM_CA")? {
+            config.upstream_ca = Some(path);
+        }
+        if let Some(zones) = options.list("DNSR_ZONES")? {
+            config.zones = zones;
+        }
+        if let Some(addr) = options.value("DNSR_TRANSFER_LISTEN")? {
+            config.transfer_listen = Some(addr);
+        }
+        if let Some(networks) = options.list("DNSR_ALLOW_TRANSFER")? {
+            config.allow_transfer = networks;
+        }
+        if let Some(zones) = options.list("DNSR_SECONDARY_ZONES")? {
+            config.secondary_zones = zones;
+        }
+        if let Some(addrs) = options.list("DNSR_NOTIFY")? {
+            config.notify = addrs;
+        }
+        if let Some(networks) = options.list("DNSR_ALLOW_UPDATE")? {
+            config.allow_update = networks;
+        }
+        if let Some(keys) = options.list("DNSR_UPDATE_KEYS")? {
+            config.update_keys = keys;
+        }
+        if let Some(paths) = options.list("DNSR_PLUGINS")? {
+            config.plugins = paths;
+        }
+        if let Some(rate) = options.value("DNSR_PTR_RATE")? {
+            config.ptr_rate = rate;
+        }
+        if let Some(secs) = options.value("DNSR_PTR_NEGATIVE_TTL")? {
+            config.ptr_negative_ttl = Duration::from_secs(secs);
+        }
+        if let Some(rate) = options.value("DNSR_RRL_RATE")? {
+            config.rrl_rate = rate;
+        }
+        if let Some(slip) = options.value("DNSR_RRL_SLIP")? {
+            config.rrl_slip = slip;
+        }
+
+        // The mode only checks that the proxy is set as expected, so that
+        // the upstream isn't relayed to by mistake
+        match (options.value("DNSR_MODE")?, config.proxy.is_empty()) {
+            (Some(Mode::Recursive), false) => config.proxy.clear(),
+            (Some(Mode::Proxy), true) => {
+                return Err(DnsError::IOError("the proxy mode requires DNSR_PROXY".into()));
+            }
+            _ => {}
+        }
+
+        if let Some(flag) = options.unknown() {
+            return Err(DnsError::IOError(format!("unknown option: {}", flag)));
+        }
+
+        // The options of the subsystems left out of the build are errors,
+        // rather than being silently ignored
+        let unsupported = [
+            ("DNSR_CONSUL",          config.consul.is_some() && !cfg!(feature = "consul")),
+            ("DNSR_ACME_LISTEN",     config.acme_listen.is_some() && !cfg!(feature = "acme")),
+            ("DNSR_BLOCKLIST_URLS",  !config.blocklist_urls.is_empty() && !cfg!(feature = "blocklist-urls")),
+            ("DNSR_PLUGINS",         !config.plugins.is_empty() && !cfg!(feature = "wasm-plugins")),
+            ("DNSR_UPDATE_KEYS",     !config.update_keys.is_empty() && !cfg!(feature = "sig0")),
+            ("DNSR_TLS_LISTEN",      !config.tls_listen.is_empty() && !cfg!(feature = "dot")),
+            ("DNSR_QUIC_LISTEN",     !config.quic_listen.is_empty() && !cfg!(feature = "doq")),
+            ("DNSR_UPSTREAM_CA",     config.upstream_ca.is_some() && !cfg!(feature = "dot")),
+            ("DNSR_ADMIN_LISTEN",    config.admin_listen.is_some() && !cfg!(feature = "admin")),
+            ("DNSR_HEALTH_LISTEN",   config.health_listen.is_some() && !cfg!(feature = "health")),
+            ("DNSR_TRANSFER_LISTEN", config.transfer_listen.is_some() && !cfg!(feature = "zone-transfers")),
+            ("DNSR_SECONDARY_ZONES", !config.secondary_zones.is_empty() && !cfg!(feature = "zone-transfers")),
+            ("DNSR_NOTIFY",          !config.notify.is_empty() && !cfg!(feature = "zone-transfers")),
+        ];
+        if let Some((key, _)) = unsupported.iter().find(|(_, unsupported)| *unsupported) {
+            return Err(DnsError::IOError(format!("{} is not supported by this build", key)));
+        }
+
+        // The query sockets are the one listener that can't be left out
+        if config.listen.is_empty() {
+            return Err(DnsError::IOError("DNSR_LISTEN requires an address".into()));
+        }
+
+        // In privacy mode, the queries of all the clients leave from the
+        // same shared sockets
+        if config.privacy && config.outgoing_sockets == 0 {
+            return Err(DnsError::IOError("DNSR_PRIVACY requires DNSR_OUTGOING_SOCKETS".into()));
+        }
+
+        // The challenge and administration APIs must never be open to
+        // anyone
+        if config.acme_listen.is_some() && config.acme_token.as_deref().unwrap_or_default().is_empty() {
+            return Err(DnsError::IOError("DNSR_ACME_LISTEN requires DNSR_ACME_TOKEN".into()));
+        }
+        if config.admin_listen.is_some() && config.admin_token.as_deref().unwrap_or_default().is_empty() {
+            return Err(DnsError::IOError("DNSR_ADMIN_LISTEN requires DNSR_ADMIN_TOKEN".into()));
+        }
+
+        // Clients over TLS and QUIC are presented with a certificate
+        let certified = config.tls_cert.is_some() && config.tls_key.is_some();
+        if !config.tls_listen.is_empty() && !certified {
+            return Err(DnsError::IOError("DNSR_TLS_LISTEN requires DNSR_TLS_CERT and DNSR_TLS_KEY".into()));
+        }
+        if !config.quic_listen.is_empty() && !certified {
+            return Err(DnsError::IOError("DNSR_QUIC_LISTEN requires DNSR_TLS_CERT and DNSR_TLS_KEY".into()));
+        }
+
+        // Zones are only transferred to the networks listed explicitly
+        let hosted = !config.zones.is_empty() || !config.secondary_zones.is_empty();
+        if config.transfer_listen.is_some() && (!hosted || config.allow_transfer.is_empty()) {
+            return Err(DnsError::IOError(
+                "DNSR_TRANSFER_LISTEN requires DNSR_ZONES or DNSR_SECONDARY_ZONES, and DNSR_ALLOW_TRANSFER".into(),
+            ));
+        }
+        if config.transfer_listen.is_some_and(|addr| config.listen.contains(&addr)) {
+            return Err(DnsError::IOError("DNSR_TRANSFER_LISTEN must differ from DNSR_LISTEN, served over TCP too".into()));
+        }
+        if !config.notify.is_empty() && !hosted {
+            return Err(DnsError::IOError("DNSR_NOTIFY requires DNSR_ZONES or DNSR_SECONDARY_ZONES".into()));
+        }
+        if !config.allow_update.is_empty() && config.zones.is_empty() {
+            return Err(DnsError::IOError("DNSR_ALLOW_UPDATE requires DNSR_ZONES".into()));
+        }
+        if !config.update_keys.is_empty() && config.zones.is_empty() {
+            return Err(DnsError::IOError("DNSR_UPDATE_KEYS requires DNSR_ZONES".into()));
+        }
+
+        Ok(config)
+    }
+}
+
+/// How the client queries are answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Resolved iteratively, from the root.
+    Recursive,
+    /// Relayed to the upstream servers of `DNSR_PROXY`.
+    Proxy,
+}
+
+impl FromStr for Mode {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "recursive" => Ok(Mode::Recursive),
+            "proxy"     => Ok(Mode::Proxy),
+            _           => Err(DnsError::InvalidField),
+        }
+    }
+}
+
+/// The options set on the command line, in the environment and in the
+/// configuration file.
+///
+/// Every `DNSR_*` variable has a flag named after it, `--max-depth` for
+/// `DNSR_MAX_DEPTH`, whose value wins over the variable's. Flags are
+/// followed by their value, or joined to it by `=`; a flag without a
+/// value is `true`. The file of `DNSR_CONFIG` sets the variables too, one
+/// `DNSR_*=value` per line, below those of the environment.
+#[derive(Debug, Default)]
+struct Options {
+    flags: HashMap<String, String>,
+    file:  HashMap<String, String>,
+    path:  Option<String>,
+    read:  RefCell<HashSet<String>>,
+}
+
+impl Options {
+    /// Parses the command line flags, and reads the configuration file
+    /// they or the environment name.
+    fn parse(args: &[String]) -> Result<Self, DnsError> {
+        let mut flags = HashMap::new();
+        let mut args  = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--").filter(|flag| !flag.is_empty()) else {
+                return Err(DnsError::IOError(format!("unexpected argument: {}", arg)));
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name, value.to_string()),
+                None => match args.next_if(|next| !next.starts_with("--")) {
+                    Some(value) => (flag, value.clone()),
+                    None        => (flag, "true".to_string()),
+                },
+            };
+            flags.insert(format!("DNSR_{}", name.replace('-', "_").to_ascii_uppercase()), value);
+        }
+
+        let mut options = Options { flags, ..Options::default() };
+        if let Some((path, _)) = options.get("DNSR_CONFIG") {
+            let text = fs::read_to_string(&path)
+                .map_err(|e| DnsError::IOError(format!("can't read the configuration file {}: {}", path, e)))?;
+            options.file = parse_file(&text).map_err(|line| {
+                DnsError::IOError(format!("invalid line {} in the configuration file {}", line, path))
+            })?;
+            options.path = Some(path);
+        }
+        Ok(options)
+    }
+
+    /// Returns the value of `key`, from its flag, its environment variable
+    /// or the configuration file, along with where it comes from.
+    fn get(&self, key: &str) -> Option<(String, String)> {
+        self.read.borrow_mut().insert(key.to_string());
+        if let Some(value) = self.flags.get(key) {
+            return Some((value.clone(), flag_name(key)));
+        }
+        env::var(key).ok().map(|value| (value, key.to_string())).or_else(|| {
+            let path = self.path.as_deref()?;
+            self.file.get(key).map(|value| (value.clone(), format!("{} of {}", key, path)))
+        })
+    }
+
+    /// Reads and parses an option, if set.
+    fn value<T: FromStr>(&self, key: &str) -> Result<Option<T>, DnsError> {
+        match self.get(key) {
+            Some((value, origin)) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| DnsError::IOError(format!("invalid value in {}", origin))),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads and parses a comma separated list from an option, if set.
+    fn list<T: FromStr>(&self, key: &str) -> Result<Option<Vec<T>>, DnsError> {
+        match self.get(key) {
+            Some((value, origin)) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| item.parse())
+                .collect::<Result<Vec<T>, _>>()
+                .map(Some)
+                .map_err(|_| DnsError::IOError(format!("invalid value in {}", origin))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a flag, or a variable of the configuration file, that
+    /// doesn't match any option, if any.
+    fn unknown(&self) -> Option<String> {
+        let read = self.read.borrow();
+        let flag = self.flags.keys().find(|key| !read.contains(*key)).map(|key| flag_name(key));
+        flag.or_else(|| {
+            let path = self.path.as_deref().unwrap_or_default();
+            self.file.keys().find(|key| !read.contains(*key)).map(|key| format!("{} in {}", key, path))
+        })
+    }
+}
+
+/// Parses the text of a configuration file: a `DNSR_*=value` variable per
+/// line, blank lines and comments starting with `#` aside. Returns the
+/// number of the first invalid line, if any.
+fn parse_file(text: &str) -> Result<HashMap<String, String>, usize> {
+    let mut vars = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) if key.trim_end().starts_with("DNSR_") => {
+                vars.insert(key.trim_end().to_string(), value.trim_start().to_string());
+            }
+            _ => return Err(n + 1),
+        }
+    }
+    Ok(vars)
+}
+
+/// Returns the command line flag of an environment variable.
+fn flag_name(key: &str) -> String {
+    format!("--{}", key.trim_start_matches("DNSR_").replace('_', "-").to_ascii_lowercase())
+}
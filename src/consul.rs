@@ -0,0 +1,190 @@
+use crate::{
+    dynamic::ZoneSource,
+    types::{AnswerRecord, DnsError, RData},
+};
+use serde::Deserialize;
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+
+/// TTL of the records materialized from the catalog, short as the services
+/// come and go.
+const SERVICE_TTL: u32 = 5;
+
+/// Longest time a blocking query waits for the catalog to change.
+const WATCH_WAIT: &str = "5m";
+
+/// How long to wait for the response to a blocking query: a bit more than
+/// [`WATCH_WAIT`], as Consul adds some jitter to it.
+const WATCH_TIMEOUT: Duration = Duration::from_secs(6 * 60);
+
+/// An instance of a service, as listed by the catalog.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Instance {
+    /// Name of the node the instance runs on.
+    node: String,
+    /// Address of the node.
+    address: String,
+    /// Address of the instance, empty when it is the node's.
+    #[serde(default)]
+    service_address: String,
+    /// Port the instance listens on.
+    service_port: u16,
+}
+
+/// Services registered in the catalog of a Consul agent.
+///
+/// Every instance of a service gets, under the domain of the source:
+/// * A/AAAA records at `<service>.service` and `<node>.node`;
+/// * SRV records at `<service>.service` and `_<service>._tcp.service`,
+///   targeting `<node>.node`, or `<hex address>.addr` when the instance
+///   has an address of its own, as the Consul DNS interface does.
+#[derive(Debug)]
+pub struct Consul {
+    http:   reqwest::Client,
+    url:    String,
+    domain: String,
+    token:  Option<String>,
+}
+
+impl Consul {
+    /// Creates a source reading the catalog of the agent at `url`, whose
+    /// records are placed under `domain`.
+    pub fn new(url: &str, domain: &str, token: Option<String>) -> Result<Self, DnsError> {
+        let http = reqwest::Client::builder()
+            .timeout(WATCH_TIMEOUT)
+            .build()
+            .map_err(|e| DnsError::IOError(format!("can't create the HTTP client: {}", e)))?;
+        Ok(Consul {
+            http,
+            url:    url.trim_end_matches('/').to_string(),
+            domain: domain.trim_end_matches('.').to_ascii_lowercase(),
+            token,
+        })
+    }
+
+    /// Sends a GET request for `path` to the agent, returning the body of
+    /// the response and its `X-Consul-Index`.
+    async fn get(&self, path: &str) -> Result<(Vec<u8>, u64), DnsError> {
+        let url = format!("{}{}", self.url, path);
+        let mut req = self.http.get(&url);
+        if let Some(token) = &self.token {
+            req = req.header("X-Consul-Token", token);
+        }
+
+        let res = req
+            .send()
+            .await
+            .map_err(|e| DnsError::IOError(format!("can't reach {}: {}", url, e)))?;
+        if !res.status().is_success() {
+            return Err(DnsError::IOError(format!("{} answered with status {}", url, res.status())));
+        }
+
+        let index = res
+            .headers()
+            .get("X-Consul-Index")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+        let body = res
+            .bytes()
+            .await
+            .map_err(|e| DnsError::IOError(format!("can't read the response of {}: {}", url, e)))?;
+        Ok((body.to_vec(), index))
+    }
+
+    /// Turns the instances of `service` into records.
+    fn materialize(&self, service: &str, instances: &[Instance], records: &mut Vec<AnswerRecord>) {
+        let service = service.to_ascii_lowercase();
+        for instance in instances {
+            let node = format!("{}.node.{}", instance.node.to_ascii_lowercase(), self.domain);
+            let Ok(node_addr) = instance.address.parse::<IpAddr>() else { continue };
+
+            let (addr, target) = match instance.service_address.parse::<IpAddr>() {
+                Ok(addr) if addr != node_addr => (addr, format!("{}.addr.{}", hex(addr), self.domain)),
+                _ => (node_addr, node.clone()),
+            };
+
+            records.push(record(&node, address(node_addr)));
+            if target != node {
+                records.push(record(&target, address(addr)));
+            }
+            records.push(record(&format!("{}.service.{}", service, self.domain), address(addr)));
+
+            for owner in [
+                format!("{}.service.{}", service, self.domain),
+                format!("_{}._tcp.service.{}", service, self.domain),
+            ] {
+                records.push(record(&owner, RData::SRV {
+                    priority: 1,
+                    weight:   1,
+                    port:     instance.service_port,
+                    target:   target.clone(),
+                }));
+            }
+        }
+    }
+}
+
+impl ZoneSource for Consul {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    /// Watches the list of services with a blocking query, then reads the
+    /// instances of each of them.
+    async fn watch(&self, version: u64) -> Result<(Vec<AnswerRecord>, u64), DnsError> {
+        let invalid = |e: serde_json::Error| DnsError::IOError(format!("invalid catalog from {}: {}", self.url, e));
+
+        let path = format!("/v1/catalog/services?index={}&wait={}", version, WATCH_WAIT);
+        let (body, index) = self.get(&path).await?;
+        let services: HashMap<String, Vec<String>> = serde_json::from_slice(&body).map_err(invalid)?;
+
+        let mut records = Vec::new();
+        for service in services.keys() {
+            let (body, _) = self.get(&format!("/v1/catalog/service/{}", service)).await?;
+            let instances: Vec<Instance> = serde_json::from_slice(&body).map_err(invalid)?;
+            self.materialize(service, &instances, &mut records);
+        }
+
+        // The index must grow: start over when it doesn't (Consul docs,
+        // "Blocking Queries")
+        let index = if index < version { 0 } else { index };
+        Ok((dedup(records), index))
+    }
+}
+
+/// Creates a record of the catalog.
+fn record(name: &str, rdata: RData) -> AnswerRecord {
+    let mut record = AnswerRecord::new(name.to_string(), rdata);
+    record.ttl = SERVICE_TTL;
+    record
+}
+
+/// Returns the A or AAAA data of an address.
+fn address(addr: IpAddr) -> RData {
+    match addr {
+        IpAddr::V4(ip) => RData::A(ip),
+        IpAddr::V6(ip) => RData::AAAA(ip),
+    }
+}
+
+/// Writes an address in hexadecimal, as a single label.
+fn hex(addr: IpAddr) -> String {
+    let octets = match addr {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    octets.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Drops the duplicate records, such as the address of a node running
+/// several services.
+fn dedup(records: Vec<AnswerRecord>) -> Vec<AnswerRecord> {
+    let mut unique: Vec<AnswerRecord> = Vec::with_capacity(records.len());
+    for record in records {
+        if !unique.iter().any(|other| other.aname == record.aname && other.rdata == record.rdata) {
+            unique.push(record);
+        }
+    }
+    unique
+}
@@ -1,32 +1,186 @@
-use crate::types::DnsError;
-use std::net::SocketAddr;
-use tokio::net::UdpSocket;
+use crate::{
+    dnstap::{Kind, Message, Protocol},
+    sockets::SocketPool,
+    types::DnsError,
+};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::{self, Instant},
+};
 
+/// Sends a query to `addr` and waits for its response.
+///
+/// The query goes out from a socket of the pool, or from a socket of its
+/// own, on a random port, when the pool can't take it.
+///
+/// Only a response from `addr` carrying the ID and the question of the
+/// query is accepted: anything else reaching the socket, such as the
+/// packets of an off-path attacker guessing the port, is dropped, and the
+/// wait goes on until the timeout.
+///
+/// The privacy profile of the pool applies to every query sent here, and
+/// the queries and their responses go to its dnstap output.
 pub async fn contact<'a>(
     dns:     &[u8],           // The packet to be sent
-    address: &str,            // The remote server address
+    addr:    SocketAddr,      // The remote server address
     buffer:  &'a mut [u8],    // The buffer where store the result
+    timeout: Duration,        // How long to wait for the response
+    pool:    &SocketPool,     // The sockets to send the packet from
 ) -> Result<&'a [u8], DnsError> {
+    let dns = &*pool.privacy().scrub(dns)?;
+    pool.privacy().wait(pool.rng()).await;
+    let deadline = Instant::now() + timeout;
 
-    // Parse the address into a SocketAddr
-    let addr: SocketAddr = address.parse().map_err(|_| DnsError::SocketError)?;
+    // Share a socket of the pool, which hands us the packets from the
+    // server carrying our ID
+    let id = dns.get(..2).map_or(0, |id| u16::from_be_bytes([id[0], id[1]]));
+    if let Some(mut pending) = pool.register(addr, id) {
+        let sent = SystemTime::now();
+        pending.send(dns).await?;
+        tap(pool, addr, Protocol::Udp, (dns, sent), None);
+        loop {
+            let packet = time::timeout_at(deadline, pending.recv())
+                .await
+                .map_err(|_| DnsError::Timeout)?
+                .ok_or(DnsError::SocketError)?;
+            if is_response(dns, &packet) {
+                tap(pool, addr, Protocol::Udp, (dns, sent), Some(&packet));
+                let size = packet.len().min(buffer.len());
+                buffer[..size].copy_from_slice(&packet[..size]);
+                return Ok(&buffer[..size]);
+            }
+        }
+    }
 
-    // Create a socket binding on a random available local port
-    let sock = UdpSocket::bind("0.0.0.0:0")
-        .await
-        .map_err(|_| DnsError::SocketError)?;
+    // Otherwise create a socket binding on the requested local port, falling back
+    // to any available port if that one is taken
+    let local = match addr {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, pool.rng().source_port())),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, pool.rng().source_port())),
+    };
+    let sock = match UdpSocket::bind(local).await {
+        Ok(sock) => sock,
+        Err(_) => UdpSocket::bind(SocketAddr::new(local.ip(), 0))
+            .await
+            .map_err(|_| DnsError::SocketError)?,
+    };
 
     // Send the message
-    sock.send_to(dns, &addr)
+    let sent = SystemTime::now();
+    sock.send_to(dns, addr)
         .await
         .map_err(|_| DnsError::IOError("can't send DNS packet".into()))?;
+    tap(pool, addr, Protocol::Udp, (dns, sent), None);
 
-    // Read the message
-    let (size, _) = sock
-        .recv_from(buffer)
-        .await
-        .map_err(|_| DnsError::IOError("can't read DNS packet".into()))?;
+    // Read the messages until the response comes, giving up if it takes
+    // too long
+    let size = loop {
+        let (size, from) = time::timeout_at(deadline, sock.recv_from(buffer))
+            .await
+            .map_err(|_| DnsError::Timeout)?
+            .map_err(|_| DnsError::IOError("can't read DNS packet".into()))?;
+        if from == addr && is_response(dns, &buffer[..size]) {
+            break size;
+        }
+    };
 
     // Return the portion of the buffer that contains the DNS response
+    tap(pool, addr, Protocol::Udp, (dns, sent), Some(&buffer[..size]));
     Ok(&buffer[..size])
 }
+
+/// Sends a query to `addr` over TCP and waits for its response, for the
+/// queries whose response was truncated over UDP.
+///
+/// Messages are framed with their length (RFC 1035, section 4.2.2). As
+/// over UDP, only a response carrying the ID and the question of the query
+/// is accepted, and the privacy profile and the dnstap output of the pool
+/// apply.
+pub async fn contact_tcp(
+    dns:     &[u8],           // The packet to be sent
+    addr:    SocketAddr,      // The remote server address
+    timeout: Duration,        // How long to wait for the response
+    pool:    &SocketPool,     // The pool whose profile and output apply
+) -> Result<Vec<u8>, DnsError> {
+    let dns = &*pool.privacy().scrub(dns)?;
+    let length = u16::try_from(dns.len()).map_err(|_| DnsError::InvalidField)?;
+    pool.privacy().wait(pool.rng()).await;
+
+    time::timeout(timeout, async {
+        let mut stream = TcpStream::connect(addr)
+            .await
+            .map_err(|e| DnsError::IOError(format!("can't reach {} over TCP: {}", addr, e)))?;
+
+        let mut framed = Vec::with_capacity(dns.len() + 2);
+        framed.extend_from_slice(&length.to_be_bytes());
+        framed.extend_from_slice(dns);
+        let sent = SystemTime::now();
+        stream.write_all(&framed).await.map_err(|_| DnsError::SocketError)?;
+        tap(pool, addr, Protocol::Tcp, (dns, sent), None);
+
+        let length = stream.read_u16().await.map_err(|_| DnsError::SocketError)?;
+        let mut response = vec![0; length as usize];
+        stream.read_exact(&mut response).await.map_err(|_| DnsError::SocketError)?;
+        if !is_response(dns, &response) {
+            return Err(DnsError::IOError(format!("mismatched response from {} over TCP", addr)));
+        }
+        tap(pool, addr, Protocol::Tcp, (dns, sent), Some(&response));
+        Ok(response)
+    })
+    .await
+    .map_err(|_| DnsError::Timeout)?
+}
+
+/// Writes a query sent to `addr` over `protocol` to the dnstap output of
+/// the pool, if any, or its response once received.
+fn tap(
+    pool:     &SocketPool,
+    addr:     SocketAddr,
+    protocol: Protocol,
+    query:    (&[u8], SystemTime),
+    response: Option<&[u8]>,
+) {
+    if let Some(dnstap) = pool.dnstap() {
+        dnstap.record(Message {
+            kind:     if response.is_some() { Kind::ResolverResponse } else { Kind::ResolverQuery },
+            protocol,
+            peer:     addr,
+            query,
+            response: response.map(|response| (response, SystemTime::now())),
+        });
+    }
+}
+
+/// Returns whether `res` is a response to `req`: it has the QR flag, the
+/// same ID and the same question, the name compared case-insensitively.
+fn is_response(req: &[u8], res: &[u8]) -> bool {
+    res.len() >= 12
+        && res[2] & 0x80 != 0
+        && res[..2] == req[..2]
+        && match (question(req), question(res)) {
+            (Some((qname, qtail)), Some((name, tail))) => qname.eq_ignore_ascii_case(name) && qtail == tail,
+            (None, None) => true,
+            _ => false,
+        }
+}
+
+/// Splits the first question of a message into its name and its type and
+/// class, or returns `None` if the message has no question.
+///
+/// The name of the first question can't be compressed, as nothing comes
+/// before it that it could point to.
+fn question(msg: &[u8]) -> Option<(&[u8], &[u8])> {
+    if msg.get(4..6)? == [0, 0] {
+        return None;
+    }
+    let mut end = 12;
+    while *msg.get(end)? != 0 {
+        end += *msg.get(end)? as usize + 1;
+    }
+    Some((msg.get(12..end)?, msg.get(end + 1..end + 5)?))
+}
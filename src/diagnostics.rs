@@ -0,0 +1,56 @@
+use crate::types::{AnswerRecord, RData, Type};
+use std::net::{IpAddr, SocketAddr};
+
+/// Name answering with the client's address as seen by the server.
+const WHOAMI: &str = "whoami.resolver.local";
+
+/// Name whose queries make the request task panic, in debug builds only.
+#[cfg(debug_assertions)]
+const PANIC: &str = "panic.resolver.local";
+
+/// Answers the built-in diagnostic names, if `qname` is one of them.
+///
+/// `whoami.resolver.local` returns TXT records with the source address,
+/// port and transport of the client, and its address for A/AAAA queries
+/// of the matching family. The records have a zero TTL, as they depend on
+/// who is asking.
+///
+/// In debug builds, `panic.resolver.local` makes the task handling the
+/// query panic, so that the tests can check how the supervisor copes.
+pub fn answer(
+    qname:     &str,
+    qtype:     Type,
+    client:    SocketAddr,
+    transport: &str,
+) -> Option<Vec<AnswerRecord>> {
+    #[cfg(debug_assertions)]
+    if qname.trim_end_matches('.').eq_ignore_ascii_case(PANIC) {
+        panic!("{} was asked for", PANIC);
+    }
+
+    if !qname.trim_end_matches('.').eq_ignore_ascii_case(WHOAMI) {
+        return None;
+    }
+
+    let rdata = match (qtype, client.ip()) {
+        (Type::TXT, _) => vec![
+            RData::txt(&format!("addr={}", client.ip())),
+            RData::txt(&format!("port={}", client.port())),
+            RData::txt(&format!("transport={}", transport)),
+        ],
+        (Type::A, IpAddr::V4(ip))    => vec![RData::A(ip)],
+        (Type::AAAA, IpAddr::V6(ip)) => vec![RData::AAAA(ip)],
+        _ => Vec::new(),
+    };
+
+    Some(
+        rdata
+            .into_iter()
+            .map(|rdata| {
+                let mut answer = AnswerRecord::new(qname.to_string(), rdata);
+                answer.ttl = 0;
+                answer
+            })
+            .collect(),
+    )
+}
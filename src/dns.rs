@@ -5,24 +5,34 @@ use crate::types::{
     DnsReadBuffer, 
     DnsWriteBuffer, 
     Flags, 
+    EdnsOption,
     Header, 
+    OptRecord,
     QueryRecord, 
     RData, 
+    Soa,
+    SvcParam,
+    Svcb,
     Type,
 };
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+    ops::Range,
+    str::FromStr,
+};
 
 impl Dns {
     /// Encodes DNS flags into a 16-bit integer.
     fn encode_flags(flags: &Flags) -> u16 {
         ((flags.qr as u16) << 15)
-            | ((flags.opcode as u16) << 11)
+            | (((flags.opcode & 0x0F) as u16) << 11)
             | ((flags.aa as u16) << 10)
             | ((flags.tc as u16) << 9)
             | ((flags.rd as u16) << 8)
             | ((flags.ra as u16) << 7)
-            | ((flags.z as u16) << 4)
-            | (flags.rcode as u16)
+            | (((flags.z & 0x07) as u16) << 4)
+            | ((flags.rcode & 0x0F) as u16)
     }
 
     /// Decodes a 16-bit integer into DNS flags.
@@ -39,14 +49,33 @@ impl Dns {
         }
     }
 
+    /// Decodes the header of a message.
+    pub fn decode_header(buf: &mut DnsReadBuffer) -> Result<Header, DnsError> {
+        let id        = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+        let flags_raw = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+        let qd_count  = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+        let an_count  = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+        let ns_count  = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+        let ar_count  = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+
+        Ok(Header {
+            id,
+            flags: Self::decode_flags(flags_raw),
+            qd_count,
+            an_count,
+            ns_count,
+            ar_count,
+        })
+    }
+
     /// Decodes a resource data section based on type and length.
     fn decode_rdata(
         buf:    &mut DnsReadBuffer, 
-        atype:  u16, 
+        atype:  Type, 
         length: u16) 
     -> Result<RData, DnsError> {
         match atype {
-            1 => {
+            Type::A => {
                 let raw = buf.read_n_bytes(length as usize).map_err(|_| DnsError::InvalidField)?;
                 if raw.len() != 4 {
                     return Err(DnsError::InvalidRData);
@@ -57,7 +86,7 @@ impl Dns {
                     raw[2], 
                     raw[3])))
             }
-            28 => {
+            Type::AAAA => {
                 let raw = buf.read_n_bytes(length as usize).map_err(|_| DnsError::InvalidField)?;
                 if raw.len() != 16 {
                     return Err(DnsError::InvalidRData);
@@ -76,7 +105,7 @@ impl Dns {
                     parts[7],
                 )))
             }
-            2 | 5 => {
+            Type::NS | Type::CNAME | Type::PTR => {
                 let stat = buf.get_index();
                 let name = buf.read_str().map_err(|_| DnsError::InvalidField)?;
                 if buf.get_index() > stat + length as usize {
@@ -86,24 +115,144 @@ impl Dns {
                     buf.read_u8().map_err(|_| DnsError::InvalidField)?;
                 }
                 match atype {
-                    2 => Ok(RData::NS(name)),
-                    5 => Ok(RData::CNAME(name)),
+                    Type::NS    => Ok(RData::NS(name)),
+                    Type::CNAME => Ok(RData::CNAME(name)),
+                    Type::PTR   => Ok(RData::PTR(name)),
                     _ => unreachable!(),
                 }
             }
-            _ => Ok(RData::EMPTY([])),
+            Type::MX => {
+                let stat       = buf.get_index();
+                let preference = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+                let exchange   = buf.read_str().map_err(|_| DnsError::InvalidField)?;
+                if buf.get_index() != stat + length as usize {
+                    return Err(DnsError::InvalidRData);
+                }
+                Ok(RData::MX { preference, exchange })
+            }
+            Type::SOA => {
+                let stat = buf.get_index();
+                let read = |buf: &mut DnsReadBuffer| buf.read_u32().map_err(|_| DnsError::InvalidField);
+                let soa  = Soa {
+                    mname:   buf.read_str().map_err(|_| DnsError::InvalidField)?,
+                    rname:   buf.read_str().map_err(|_| DnsError::InvalidField)?,
+                    serial:  read(buf)?,
+                    refresh: read(buf)?,
+                    retry:   read(buf)?,
+                    expire:  read(buf)?,
+                    minimum: read(buf)?,
+                };
+                if buf.get_index() != stat + length as usize {
+                    return Err(DnsError::InvalidRData);
+                }
+                Ok(RData::SOA(soa))
+            }
+            Type::SRV => {
+                let stat     = buf.get_index();
+                let priority = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+                let weight   = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+                let port     = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+                let target   = buf.read_str().map_err(|_| DnsError::InvalidField)?;
+                if buf.get_index() != stat + length as usize {
+                    return Err(DnsError::InvalidRData);
+                }
+                Ok(RData::SRV { priority, weight, port, target })
+            }
+            Type::SVCB | Type::HTTPS => {
+                let stat     = buf.get_index();
+                let priority = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+                let target   = buf.read_str().map_err(|_| DnsError::InvalidField)?;
+                let rest     = (stat + length as usize)
+                    .checked_sub(buf.get_index())
+                    .ok_or(DnsError::InvalidRData)?;
+                let raw = buf.read_n_bytes(rest).map_err(|_| DnsError::InvalidField)?;
+                buf.charge(raw.len()).map_err(|_| DnsError::InvalidField)?;
+
+                let svcb = Svcb { priority, target, params: Self::decode_svc_params(raw)? };
+                match atype {
+                    Type::SVCB => Ok(RData::SVCB(svcb)),
+                    _          => Ok(RData::HTTPS(svcb)),
+                }
+            }
+            Type::TXT => {
+                let raw = buf.read_n_bytes(length as usize).map_err(|_| DnsError::InvalidField)?;
+                buf.charge(raw.len()).map_err(|_| DnsError::InvalidField)?;
+                let mut strings = Vec::new();
+                let mut rest    = raw;
+                while let Some((&len, tail)) = rest.split_first() {
+                    let string = tail.get(..len as usize).ok_or(DnsError::InvalidRData)?;
+                    strings.push(string.to_vec());
+                    rest = &tail[len as usize..];
+                }
+                Ok(RData::TXT(strings))
+            }
+            _ => {
+                let raw = buf.read_n_bytes(length as usize).map_err(|_| DnsError::InvalidField)?;
+                buf.charge(raw.len()).map_err(|_| DnsError::InvalidField)?;
+                Ok(RData::Unknown { rtype: atype.into(), data: raw.to_vec() })
+            }
         }
     }
 
+    /// Decodes the parameters of an SVCB or HTTPS record, whose keys must
+    /// come in increasing order (RFC 9460, section 2.2).
+    fn decode_svc_params(mut raw: &[u8]) -> Result<Vec<SvcParam>, DnsError> {
+        let mut params = Vec::new();
+        let mut last   = None;
+        while !raw.is_empty() {
+            let (key, tail)    = raw.split_first_chunk::<2>().ok_or(DnsError::InvalidRData)?;
+            let (length, tail) = tail.split_first_chunk::<2>().ok_or(DnsError::InvalidRData)?;
+            let key    = u16::from_be_bytes(*key);
+            let length = u16::from_be_bytes(*length) as usize;
+            let value  = tail.get(..length).ok_or(DnsError::InvalidRData)?;
+            raw = &tail[length..];
+
+            if last.is_some_and(|last| key <= last) {
+                return Err(DnsError::InvalidRData);
+            }
+            last = Some(key);
+
+            let param = match key {
+                0 if length.is_multiple_of(2) => SvcParam::Mandatory(
+                    value.chunks(2).map(|k| u16::from_be_bytes([k[0], k[1]])).collect(),
+                ),
+                1 => {
+                    let mut ids  = Vec::new();
+                    let mut rest = value;
+                    while let Some((&len, tail)) = rest.split_first() {
+                        let id = tail.get(..len as usize).ok_or(DnsError::InvalidRData)?;
+                        ids.push(String::from_utf8_lossy(id).into_owned());
+                        rest = &tail[len as usize..];
+                    }
+                    SvcParam::Alpn(ids)
+                }
+                2 if length == 0 => SvcParam::NoDefaultAlpn,
+                3 if length == 2 => SvcParam::Port(u16::from_be_bytes([value[0], value[1]])),
+                4 if length.is_multiple_of(4) => SvcParam::Ipv4Hint(
+                    value.chunks(4).map(|ip| Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3])).collect(),
+                ),
+                5 => SvcParam::Ech(value.to_vec()),
+                6 if length.is_multiple_of(16) => SvcParam::Ipv6Hint(
+                    value.chunks(16).map(|ip| Ipv6Addr::from(<[u8; 16]>::try_from(ip).unwrap())).collect(),
+                ),
+                0..=6 => return Err(DnsError::InvalidRData),
+                _ => SvcParam::Unknown(key, value.to_vec()),
+            };
+            params.push(param);
+        }
+        Ok(params)
+    }
+
     /// Decodes a list of query records from the buffer.
-    fn decode_questions(
+    pub fn decode_questions(
         buf:   &mut DnsReadBuffer, 
         count: u16) 
     -> Result<Vec<QueryRecord>, DnsError> {
-        let mut records = Vec::with_capacity(count as usize);
+        let mut records = Vec::new();
         for _ in 0..count {
+            buf.charge(size_of::<QueryRecord>()).map_err(|_| DnsError::InvalidField)?;
             let qname  = buf.read_str().map_err(|_| DnsError::InvalidField)?;
-            let qtype     = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+            let qtype     = Type::from(buf.read_u16().map_err(|_| DnsError::InvalidField)?);
             let qclass    = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
             records.push(QueryRecord { qname, qtype, qclass });
         }
@@ -115,20 +264,83 @@ impl Dns {
         buf:   &mut DnsReadBuffer, 
         count: u16) 
     -> Result<Vec<AnswerRecord>, DnsError> {
-        let mut records = Vec::with_capacity(count as usize);
+        (0..count).map(|_| Self::decode_record(buf)).collect()
+    }
+
+    /// Decodes the additional section, setting the OPT record apart.
+    fn decode_additionals(
+        buf:   &mut DnsReadBuffer,
+        count: u16)
+    -> Result<(Vec<AnswerRecord>, Option<OptRecord>), DnsError> {
+        let mut additionals = Vec::new();
+        let mut opt         = None;
         for _ in 0..count {
-            let aname  = buf.read_str().map_err(|_| DnsError::InvalidField)?;
-            let atype     = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
-            let aclass    = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
-            let ttl       = buf.read_u32().map_err(|_| DnsError::InvalidField)?;
-            let length    = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
-            let rdata   = Self::decode_rdata(buf, atype, length)?;
-
-            records.push(AnswerRecord {
-                aname, atype, aclass, ttl, length, rdata,
+            let start = buf.get_index();
+            buf.read_str().map_err(|_| DnsError::InvalidField)?;
+            let atype = Type::from(buf.read_u16().map_err(|_| DnsError::InvalidField)?);
+
+            if atype == Type::OPT && opt.is_none() {
+                opt = Some(Self::decode_opt(buf)?);
+            } else {
+                buf.set_index(start).map_err(|_| DnsError::InvalidField)?;
+                additionals.push(Self::decode_record(buf)?);
+            }
+        }
+        Ok((additionals, opt))
+    }
+
+    /// Decodes the fields of an OPT record following its type.
+    fn decode_opt(buf: &mut DnsReadBuffer) -> Result<OptRecord, DnsError> {
+        let udp_size = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+        let ttl      = buf.read_u32().map_err(|_| DnsError::InvalidField)?;
+        let length   = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+        let mut rest = buf.read_n_bytes(length as usize).map_err(|_| DnsError::InvalidField)?;
+        buf.charge(rest.len()).map_err(|_| DnsError::InvalidField)?;
+
+        let mut options = Vec::new();
+        while !rest.is_empty() {
+            let (code, tail) = rest.split_first_chunk::<2>().ok_or(DnsError::InvalidRData)?;
+            let (len, tail)  = tail.split_first_chunk::<2>().ok_or(DnsError::InvalidRData)?;
+            let len  = u16::from_be_bytes(*len) as usize;
+            let data = tail.get(..len).ok_or(DnsError::InvalidRData)?;
+            options.push(EdnsOption {
+                code: u16::from_be_bytes(*code),
+                data: data.to_vec(),
             });
+            rest = &tail[len..];
         }
-        Ok(records)
+
+        Ok(OptRecord {
+            udp_size,
+            extended_rcode: (ttl >> 24) as u8,
+            version:        (ttl >> 16) as u8,
+            dnssec_ok:      ttl & 0x8000 != 0,
+            options,
+        })
+    }
+
+    /// Decodes a single answer, authority or additional record.
+    pub fn decode_record(buf: &mut DnsReadBuffer) -> Result<AnswerRecord, DnsError> {
+        let start  = buf.get_index();
+        buf.charge(size_of::<AnswerRecord>()).map_err(|_| DnsError::InvalidField)?;
+        let aname  = buf.read_str().map_err(|_| DnsError::InvalidField)?;
+        let atype     = Type::from(buf.read_u16().map_err(|_| DnsError::InvalidField)?);
+        let aclass    = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+        let ttl       = buf.read_u32().map_err(|_| DnsError::InvalidField)?;
+        let length    = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
+
+        // The prerequisites and updates of an UPDATE message of class ANY
+        // or NONE may carry no data, whatever their type (RFC 2136,
+        // section 2.4)
+        let rdata = match (length, aclass) {
+            (0, 254 | 255) => RData::Unknown { rtype: atype.into(), data: Vec::new() },
+            _              => Self::decode_rdata(buf, atype, length)?,
+        };
+
+        Ok(AnswerRecord {
+            aname, atype, aclass, ttl, length, rdata,
+            span: Some(start..buf.get_index()),
+        })
     }
 
     /// Encodes a list of answer, authority, or additional records.
@@ -137,12 +349,8 @@ impl Dns {
         answers: &[AnswerRecord],
     ) -> Result<(), DnsError> {
         for a in answers {
-            if a.atype == 41 {
-                buffer.write_u8(0);
-            } else {
-                buffer.write_str(&a.aname);
-            }
-            buffer.write_u16(a.atype);
+            buffer.write_str(&a.aname).map_err(|_| DnsError::InvalidField)?;
+            buffer.write_u16(a.atype.into());
             buffer.write_u16(a.aclass);
             buffer.write_u32(a.ttl);
 
@@ -153,6 +361,24 @@ impl Dns {
         Ok(())
     }
 
+    /// Encodes the OPT record.
+    fn encode_opt(buffer: &mut DnsWriteBuffer, opt: &OptRecord) {
+        let ttl = ((opt.extended_rcode as u32) << 24)
+            | ((opt.version as u32) << 16)
+            | ((opt.dnssec_ok as u32) << 15);
+
+        buffer.write_u8(0);
+        buffer.write_u16(Type::OPT.into());
+        buffer.write_u16(opt.udp_size);
+        buffer.write_u32(ttl);
+        buffer.write_u16(opt.options.iter().map(|o| 4 + o.data.len() as u16).sum());
+        for option in &opt.options {
+            buffer.write_u16(option.code);
+            buffer.write_u16(option.data.len() as u16);
+            buffer.write_bytes(&option.data);
+        }
+    }
+
     /// Encodes a single RData into bytes for writing.
     fn encode_rdata(rdata: &RData) -> Result<Vec<u8>, DnsError> {
         let mut buf = DnsWriteBuffer { data: Vec::new() };
@@ -166,43 +392,75 @@ impl Dns {
                     buf.write_u16(seg);
                 }
             }
-            RData::NS(name) | RData::CNAME(name)  => {
-            // RData::NS(name) | RData::CNAME(name) | RData::PTR(name) => {
-                buf.write_str(name);
+            RData::NS(name) | RData::CNAME(name) | RData::PTR(name) => {
+                buf.write_str(name).map_err(|_| DnsError::InvalidField)?;
+            }
+            RData::TXT(strings) => {
+                // The character-strings are written as they are, and the
+                // data holds at least one
+                if strings.is_empty() {
+                    buf.write_u8(0);
+                }
+                for string in strings {
+                    let len = u8::try_from(string.len()).map_err(|_| DnsError::InvalidField)?;
+                    buf.write_u8(len);
+                    buf.write_bytes(string);
+                }
+            }
+            RData::MX {
+                preference,
+                exchange,
+            } => {
+                buf.write_u16(*preference);
+                buf.write_str(exchange).map_err(|_| DnsError::InvalidField)?;
+            }
+            RData::SOA(soa) => {
+                buf.write_str(&soa.mname).map_err(|_| DnsError::InvalidField)?;
+                buf.write_str(&soa.rname).map_err(|_| DnsError::InvalidField)?;
+                buf.write_u32(soa.serial);
+                buf.write_u32(soa.refresh);
+                buf.write_u32(soa.retry);
+                buf.write_u32(soa.expire);
+                buf.write_u32(soa.minimum);
+            }
+            RData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                buf.write_u16(*priority);
+                buf.write_u16(*weight);
+                buf.write_u16(*port);
+                buf.write_str(target).map_err(|_| DnsError::InvalidField)?;
+            }
+            RData::SVCB(svcb) | RData::HTTPS(svcb) => {
+                buf.write_u16(svcb.priority);
+                buf.write_str(&svcb.target).map_err(|_| DnsError::InvalidField)?;
+
+                // The parameters go out in increasing key order
+                let mut params: Vec<&SvcParam> = svcb.params.iter().collect();
+                params.sort_by_key(|param| param.key());
+                for param in params {
+                    buf.write_u16(param.key());
+                    buf.write_u16(param.len());
+                    match param {
+                        SvcParam::Mandatory(keys) => keys.iter().for_each(|key| buf.write_u16(*key)),
+                        SvcParam::Alpn(ids) => {
+                            for id in ids {
+                                buf.write_u8(id.len() as u8);
+                                buf.write_bytes(id.as_bytes());
+                            }
+                        }
+                        SvcParam::NoDefaultAlpn => {}
+                        SvcParam::Port(port) => buf.write_u16(*port),
+                        SvcParam::Ipv4Hint(ips) => ips.iter().for_each(|ip| buf.write_bytes(&ip.octets())),
+                        SvcParam::Ipv6Hint(ips) => ips.iter().for_each(|ip| buf.write_bytes(&ip.octets())),
+                        SvcParam::Ech(data) | SvcParam::Unknown(_, data) => buf.write_bytes(data),
+                    }
+                }
             }
-            // RData::TXT(text) => {
-            //     let bytes = text.as_bytes();
-            //     if bytes.len() > 255 {
-            //         return Err(DnsError::InvalidField);
-            //     }
-            //     buf.write_u8(bytes.len() as u8);
-            //     buf.write_bytes(bytes);
-            // }
-            // RData::MX {
-            //     preference,
-            //     exchange,
-            // } => {
-            //     buf.write_u16(*preference);
-            //     buf.write_str(exchange);
-            // }
-            // RData::SOA {
-            //     mname,
-            //     rname,
-            //     serial,
-            //     refresh,
-            //     retry,
-            //     expire,
-            //     minimum,
-            // } => {
-            //     buf.write_str(mname);
-            //     buf.write_str(rname);
-            //     buf.write_u32(*serial);
-            //     buf.write_u32(*refresh);
-            //     buf.write_u32(*retry);
-            //     buf.write_u32(*expire);
-            //     buf.write_u32(*minimum);
-            // }
-            RData::EMPTY(data) => {
+            RData::Unknown { data, .. } => {
                 buf.write_bytes(data);
             }
         }
@@ -212,69 +470,60 @@ impl Dns {
 
     /// Decodes a full DNS message from the given buffer.
     pub fn decode(buf: &mut DnsReadBuffer) -> Result<Dns, DnsError> {
-        let id        = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
-        let flags_raw = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
-        let qd_count  = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
-        let an_count  = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
-        let ns_count  = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
-        let ar_count  = buf.read_u16().map_err(|_| DnsError::InvalidField)?;
-
-        let flags                   = Self::decode_flags(flags_raw);
-        let questions    = Self::decode_questions(buf, qd_count)?;
-        let answers     = Self::decode_answers(buf, an_count)?;
-        let authorities = Self::decode_answers(buf, ns_count)?;
-        let additionals = Self::decode_answers(buf, ar_count)?;
+        let header      = Self::decode_header(buf)?;
+        let questions    = Self::decode_questions(buf, header.qd_count)?;
+        let answers     = Self::decode_answers(buf, header.an_count)?;
+        let authorities = Self::decode_answers(buf, header.ns_count)?;
+        let (additionals, opt) = Self::decode_additionals(buf, header.ar_count)?;
 
         Ok(Dns {
-            header: Header {
-                id,
-                flags,
-                qd_count,
-                an_count,
-                ns_count,
-                ar_count,
-            },
+            header,
             questions,
             answers,
             authorities,
             additionals,
+            opt,
         })
     }
 
     /// Encodes this DNS struct into a buffer suitable for transmission.
+    ///
+    /// The section counts are derived from the sections themselves, the
+    /// ones in the header are ignored.
     pub fn encode(&self) -> Result<DnsWriteBuffer, DnsError> {
         let mut buffer = DnsWriteBuffer::new();
 
         let flags = Self::encode_flags(&self.header.flags);
+        let count = |len: usize| u16::try_from(len).map_err(|_| DnsError::InvalidField);
 
         buffer.write_u16(self.header.id);
         buffer.write_u16(flags);
-        buffer.write_u16(self.header.qd_count);
-        buffer.write_u16(self.header.an_count);
-        buffer.write_u16(self.header.ns_count);
-        buffer.write_u16(self.header.ar_count);
+        buffer.write_u16(count(self.questions.len())?);
+        buffer.write_u16(count(self.answers.len())?);
+        buffer.write_u16(count(self.authorities.len())?);
+        buffer.write_u16(count(self.additionals.len() + self.opt.is_some() as usize)?);
 
         for q in &self.questions {
-            buffer.write_str(&q.qname);
-            buffer.write_u16(q.qtype);
+            buffer.write_str(&q.qname).map_err(|_| DnsError::InvalidField)?;
+            buffer.write_u16(q.qtype.into());
             buffer.write_u16(q.qclass);
         }
 
         Self::encode_answers(&mut buffer, &self.answers)?;
         Self::encode_answers(&mut buffer, &self.authorities)?;
         Self::encode_answers(&mut buffer, &self.additionals)?;
+        if let Some(opt) = &self.opt {
+            Self::encode_opt(&mut buffer, opt);
+        }
 
         Ok(buffer)
     }
 
-    /// Constructs a new `Dns` instance from all components.
+    /// Constructs a new `Dns` instance from all components, counting the
+    /// records of each section.
     pub fn new(
-        id:    u16,
-        flags: Flags,
-        qd_count: u16,
-        an_count: u16,
-        ns_count: u16,
-        ar_count: u16,
+        id:          u16,
+        flags:       Flags,
         questions:   Vec<QueryRecord>,
         answers:     Vec<AnswerRecord>,
         authorities: Vec<AnswerRecord>,
@@ -284,20 +533,26 @@ impl Dns {
             header: Header {
                 id,
                 flags,
-                qd_count,
-                an_count,
-                ns_count,
-                ar_count,
+                qd_count: questions.len() as u16,
+                an_count: answers.len() as u16,
+                ns_count: authorities.len() as u16,
+                ar_count: additionals.len() as u16,
             },
             questions,
             answers,
             authorities,
             additionals,
+            opt: None,
         }
     }
 
     /// Creates a new DNS IPv4 query for the given domain and ID.
     pub fn new_a_question(domain: &str, id: u16) -> Self {
+        Self::new_question(domain, Type::A, id)
+    }
+
+    /// Creates a new DNS query for the records of the given type.
+    pub fn new_question(domain: &str, qtype: Type, id: u16) -> Self {
         let flags = Flags {
             qr:     false,
             opcode: 0,
@@ -309,11 +564,7 @@ impl Dns {
             rcode:  0,
         };
 
-        let qd_count     = 1;
-        let an_count     = 0;
-        let ns_count     = 0;
-        let ar_count     = 0;
-        let questions     = vec![QueryRecord::new(domain.to_string(), 1, 1)];
+        let questions    = vec![QueryRecord::new(domain.to_string(), qtype, 1)];
         let answers      = Vec::new();
         let authorities  = Vec::new();
         let additionals  = Vec::new();
@@ -321,10 +572,6 @@ impl Dns {
         Dns::new(
             id,
             flags,
-            qd_count,
-            an_count,
-            ns_count,
-            ar_count,
             questions,
             answers,
             authorities,
@@ -332,11 +579,152 @@ impl Dns {
         )
     }
 
+    /// Returns whether the message carries an OPT record with the DNSSEC
+    /// OK (DO) bit set.
+    pub fn dnssec_ok(&self) -> bool {
+        self.opt.as_ref().is_some_and(|opt| opt.dnssec_ok)
+    }
+
+    /// Returns the UDP payload size advertised in the OPT record, if any.
+    pub fn udp_payload_size(&self) -> Option<u16> {
+        self.opt.as_ref().map(|opt| opt.udp_size)
+    }
+
+    /// Adds an OPT record advertising the given UDP payload size, and
+    /// returns it for the other fields to be set.
+    pub fn set_edns(&mut self, udp_size: u16) -> &mut OptRecord {
+        self.opt.insert(OptRecord::new(udp_size))
+    }
+
+    /// Returns the response code, extended by the OPT record if any.
+    pub fn rcode(&self) -> u16 {
+        let extended = self.opt.as_ref().map_or(0, |opt| opt.extended_rcode);
+        ((extended as u16) << 4) | self.header.flags.rcode as u16
+    }
+
+    /// Sets the response code. Codes above 15 spill into the extended
+    /// response code of the OPT record (RFC 6891, section 6.1.3), so they
+    /// can only be set on messages that carry one.
+    pub fn set_rcode(&mut self, rcode: u16) -> Result<(), DnsError> {
+        if rcode > 0xFFF {
+            return Err(DnsError::InvalidField);
+        }
+        let extended = (rcode >> 4) as u8;
+        match &mut self.opt {
+            Some(opt)             => opt.extended_rcode = extended,
+            None if extended != 0 => return Err(DnsError::InvalidField),
+            None                  => {}
+        }
+        self.header.flags.rcode = (rcode & 0xF) as u8;
+        Ok(())
+    }
+
+    /// Leaves out of a positive answer what the client didn't ask for: the
+    /// authority and additional sections, and the answers of other types
+    /// than the question's, but for the aliases leading to them and their
+    /// signatures. The OPT record is kept.
+    ///
+    /// Negative answers are left as they are, since their authority
+    /// section tells how long they can be cached.
+    pub fn minimize(&mut self) {
+        if self.header.flags.rcode != 0 || self.answers.is_empty() {
+            return;
+        }
+        if let Some(qtype) = self.questions.first().map(|question| question.qtype)
+            && qtype != Type::ANY
+        {
+            self.answers
+                .retain(|answer| matches!(answer.atype, Type::CNAME | Type::RRSIG) || answer.atype == qtype);
+        }
+        self.authorities.clear();
+        self.additionals.clear();
+    }
+
+    /// Creates a SERVFAIL response to a raw client query.
+    ///
+    /// Only the header of the query is read, so that this can be used when
+    /// the query itself could not be processed. Returns `None` if the query
+    /// is too short to carry a header.
+    pub fn new_servfail(query: &[u8]) -> Option<Vec<u8>> {
+        let mut buf = DnsReadBuffer::new(query);
+        let id      = buf.read_u16().ok()?;
+        let raw     = buf.read_u16().ok()?;
+        buf.read_n_bytes(8).ok()?;
+
+        let query_flags = Self::decode_flags(raw);
+        let flags = Flags {
+            qr:     true,
+            opcode: query_flags.opcode,
+            aa:     false,
+            tc:     false,
+            rd:     query_flags.rd,
+            ra:     true,
+            z:      0,
+            rcode:  2,
+        };
+
+        let res = Dns::new(id, flags, Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        res.encode().ok().map(DnsWriteBuffer::into_inner)
+    }
+
+    /// Creates an empty response to the given client query.
+    ///
+    /// The transaction ID and the question section are always copied from
+    /// the query, so a reply can never carry the ID of an upstream message.
+    pub fn new_reply(query: &Dns) -> Self {
+        let flags = Flags {
+            qr:     true,
+            opcode: query.header.flags.opcode,
+            aa:     false,
+            tc:     false,
+            rd:     query.header.flags.rd,
+            ra:     true,
+            z:      0,
+            rcode:  0,
+        };
+
+        let questions = query.questions.clone();
+
+        Dns::new(
+            query.header.id,
+            flags,
+            questions,
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+        )
+    }
+
+}
+
+impl OptRecord {
+    /// Creates an EDNS(0) OPT record advertising the given UDP payload
+    /// size, with no options.
+    pub fn new(udp_size: u16) -> Self {
+        OptRecord {
+            udp_size,
+            extended_rcode: 0,
+            version:        0,
+            dnssec_ok:      false,
+            options:        Vec::new(),
+        }
+    }
+}
+
+impl Flags {
+    /// Sets the operation code, which must fit in 4 bits.
+    pub fn set_opcode(&mut self, opcode: u8) -> Result<(), DnsError> {
+        if opcode > 0x0F {
+            return Err(DnsError::InvalidField);
+        }
+        self.opcode = opcode;
+        Ok(())
+    }
 }
 
 impl QueryRecord {
     /// Creates a new query record with the given name, type, and class.
-    pub fn new(qname: String, qtype: u16, qclass: u16) -> Self {
+    pub fn new(qname: String, qtype: Type, qclass: u16) -> Self {
         QueryRecord { qname, qtype, qclass }
     }
 }
@@ -344,25 +732,419 @@ impl QueryRecord {
 impl AnswerRecord {
     /// Creates a new answer record from rdata
     pub fn new(name: String, rdata: RData) -> Self {
-        let atype = match &rdata {
-            RData::A(_)     => Type::A     as u16,
-            RData::AAAA(_)  => Type::AAAA  as u16,
-            RData::CNAME(_) => Type::CNAME as u16,
-            RData::NS(_)    => Type::NS  as u16,
-            // RData::TXT(_)   => Type::TXT as u16,
-            // RData::MX {..}  => Type::MX  as u16,
-            // RData::SOA {..} => Type::SOA as u16,
-            // RData::PTR(_)   => Type::PTR as u16,
-            RData::EMPTY(_) => 0, // or some fallback
-        };
+        let atype = rdata.rtype();
 
         AnswerRecord { 
             aname:  name,
-            atype:  atype,
+            atype,
             aclass: 1,        // 1 = IN (Internet)
             ttl:    300,      // Default TTL
             length: rdata.len(),
-            rdata:  rdata,
+            rdata,
+            span:   None,
         } 
     }
-}
\ No newline at end of file
+
+    /// Returns the byte range of the record within the message it was
+    /// decoded from, or `None` if it was not decoded from a message.
+    ///
+    /// Compressed names in the record point outside of this range, so the
+    /// raw bytes can only be interpreted along with the whole message.
+    pub fn wire_span(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+}
+
+impl FromStr for AnswerRecord {
+    type Err = DnsError;
+
+    /// Parses a record from its presentation format (RFC 1035, section
+    /// 5.1), such as `www.example.com. 300 IN A 192.0.2.1`.
+    ///
+    /// The TTL and the class are optional and may come in either order;
+    /// they default to 300 seconds and `IN`. TXT data is given as one or
+    /// more character-strings, quoted when they hold spaces, with `\DDD`
+    /// escapes for the bytes that aren't printable ASCII. The other types
+    /// take the generic `\# length hex` form (RFC 3597).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::IOError(format!("invalid record: {}", s));
+        let fields = split_fields(s).ok_or_else(invalid)?;
+        let mut fields = fields.into_iter();
+
+        let name = fields.next().ok_or_else(invalid)?;
+        let aname = match name.as_str() {
+            "." => name,
+            _   => name.trim_end_matches('.').to_string(),
+        };
+
+        let mut ttl    = None;
+        let mut aclass = None;
+        let rtype = loop {
+            let field = fields.next().ok_or_else(invalid)?;
+            if ttl.is_none() && let Ok(value) = field.parse::<u32>() {
+                ttl = Some(value);
+                continue;
+            }
+            if aclass.is_none() && let Some(class) = parse_class(&field) {
+                aclass = Some(class);
+                continue;
+            }
+            break field.parse::<Type>()?;
+        };
+
+        let data: Vec<String> = fields.collect();
+
+        let single = || match data.as_slice() {
+            [value] => Ok(value.as_str()),
+            _       => Err(invalid()),
+        };
+        let rdata = match rtype {
+            // Any type may take the generic syntax (RFC 3597, section 5)
+            _ if data.first().is_some_and(|marker| marker == "\\#") => {
+                generic_rdata(rtype, &data).ok_or_else(invalid)?
+            }
+            Type::A     => RData::A(single()?.parse().map_err(|_| invalid())?),
+            Type::AAAA  => RData::AAAA(single()?.parse().map_err(|_| invalid())?),
+            Type::NS    => RData::NS(single()?.trim_end_matches('.').to_string()),
+            Type::CNAME => RData::CNAME(single()?.trim_end_matches('.').to_string()),
+            Type::PTR   => RData::PTR(single()?.trim_end_matches('.').to_string()),
+            Type::TXT if !data.is_empty() => RData::TXT(
+                data.iter()
+                    .map(|string| unescape_string(string).filter(|bytes| bytes.len() <= 255))
+                    .collect::<Option<_>>()
+                    .ok_or_else(invalid)?,
+            ),
+            Type::MX => match data.as_slice() {
+                [preference, exchange] => RData::MX {
+                    preference: preference.parse().map_err(|_| invalid())?,
+                    exchange:   exchange.trim_end_matches('.').to_string(),
+                },
+                _ => return Err(invalid()),
+            },
+            Type::SOA => match data.as_slice() {
+                [mname, rname, serial, refresh, retry, expire, minimum] => {
+                    let counter = |s: &String| s.parse::<u32>().map_err(|_| invalid());
+                    RData::SOA(Soa {
+                        mname:   mname.trim_end_matches('.').to_string(),
+                        rname:   rname.trim_end_matches('.').to_string(),
+                        serial:  counter(serial)?,
+                        refresh: counter(refresh)?,
+                        retry:   counter(retry)?,
+                        expire:  counter(expire)?,
+                        minimum: counter(minimum)?,
+                    })
+                }
+                _ => return Err(invalid()),
+            },
+            Type::SRV => match data.as_slice() {
+                [priority, weight, port, target] => RData::SRV {
+                    priority: priority.parse().map_err(|_| invalid())?,
+                    weight:   weight.parse().map_err(|_| invalid())?,
+                    port:     port.parse().map_err(|_| invalid())?,
+                    target:   target.trim_end_matches('.').to_string(),
+                },
+                _ => return Err(invalid()),
+            },
+            Type::SVCB | Type::HTTPS => match data.as_slice() {
+                [priority, target, params @ ..] => {
+                    let svcb = Svcb {
+                        priority: priority.parse().map_err(|_| invalid())?,
+                        target:   match target.as_str() {
+                            "." => target.clone(),
+                            _   => target.trim_end_matches('.').to_string(),
+                        },
+                        params:   params
+                            .iter()
+                            .map(|param| parse_svc_param(param))
+                            .collect::<Option<_>>()
+                            .ok_or_else(invalid)?,
+                    };
+                    match rtype {
+                        Type::SVCB => RData::SVCB(svcb),
+                        _          => RData::HTTPS(svcb),
+                    }
+                }
+                _ => return Err(invalid()),
+            },
+            // Types without a syntax of their own only take the generic one
+            _ => return Err(invalid()),
+        };
+
+        Ok(AnswerRecord {
+            aname,
+            atype:  rtype,
+            aclass: aclass.unwrap_or(1),
+            ttl:    ttl.unwrap_or(300),
+            length: rdata.len(),
+            rdata,
+            span:   None,
+        })
+    }
+}
+
+impl fmt::Display for AnswerRecord {
+    /// Writes the record in its presentation format, with absolute names,
+    /// as parsed back by `from_str`. The types without a syntax of their
+    /// own, SVCB and HTTPS included, take the generic one.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let absolute = |name: &str| match name.trim_end_matches('.') {
+            ""   => ".".to_string(),
+            name => format!("{}.", name),
+        };
+        write!(f, "{} {} {} {} ", absolute(&self.aname), self.ttl, class_name(self.aclass), self.atype)?;
+
+        match &self.rdata {
+            RData::A(ip)    => write!(f, "{}", ip),
+            RData::AAAA(ip) => write!(f, "{}", ip),
+            RData::NS(name) | RData::CNAME(name) | RData::PTR(name) => write!(f, "{}", absolute(name)),
+            RData::TXT(strings) => {
+                let quoted: Vec<String> = strings.iter().map(|string| format!("\"{}\"", escape_string(string))).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+            RData::MX { preference, exchange } => write!(f, "{} {}", preference, absolute(exchange)),
+            RData::SOA(soa) => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                absolute(&soa.mname), absolute(&soa.rname), soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum,
+            ),
+            RData::SRV { priority, weight, port, target } => {
+                write!(f, "{} {} {} {}", priority, weight, port, absolute(target))
+            }
+            rdata => {
+                let raw = Dns::encode_rdata(rdata).map_err(|_| fmt::Error)?;
+                write!(f, "\\# {}", raw.len())?;
+                if !raw.is_empty() {
+                    let hex: String = raw.iter().map(|byte| format!("{:02x}", byte)).collect();
+                    write!(f, " {}", hex)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl TryFrom<&str> for AnswerRecord {
+    type Error = DnsError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Splits a presentation line into its fields, unquoting the quoted ones
+/// but keeping their escapes, as names carry them. Returns `None` on an
+/// unterminated quote.
+fn split_fields(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut chars  = line.trim().chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut field = String::new();
+        if c == '"' {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '"'  => break,
+                    '\\' => {
+                        field.push('\\');
+                        field.push(chars.next()?);
+                    }
+                    c    => field.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() && !c.is_whitespace() {
+                field.push(c);
+                chars.next();
+            }
+        }
+        fields.push(field);
+    }
+
+    Some(fields)
+}
+
+/// Writes a character-string in presentation format, escaping the quotes
+/// and backslashes by a backslash and the bytes outside printable ASCII
+/// as `\DDD`.
+fn escape_string(string: &[u8]) -> String {
+    let mut out = String::with_capacity(string.len());
+    for &byte in string {
+        match byte {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            0x20..=0x7E  => out.push(byte as char),
+            _            => out.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    out
+}
+
+/// Reads a character-string in presentation format back into its bytes,
+/// undoing the escapes of [`escape_string`]. Returns `None` on a broken
+/// escape.
+fn unescape_string(string: &str) -> Option<Vec<u8>> {
+    let mut out   = Vec::with_capacity(string.len());
+    let mut bytes = string.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            out.push(byte);
+            continue;
+        }
+        match bytes.next()? {
+            digit @ b'0'..=b'9' => {
+                let mut value = (digit - b'0') as u16;
+                for _ in 0..2 {
+                    let digit = bytes.next().filter(u8::is_ascii_digit)?;
+                    value = value * 10 + (digit - b'0') as u16;
+                }
+                out.push(u8::try_from(value).ok()?);
+            }
+            other => out.push(other),
+        }
+    }
+    Some(out)
+}
+
+/// Parses data in the generic syntax (RFC 3597, section 5): `\#` followed
+/// by the length and the hex data, in the wire format of `rtype`.
+fn generic_rdata(rtype: Type, data: &[String]) -> Option<RData> {
+    let [_, length, hex @ ..] = data else {
+        return None;
+    };
+    let raw = hex_decode(&hex.concat())?;
+    if length.parse::<usize>().ok()? != raw.len() {
+        return None;
+    }
+
+    let mut buf = DnsReadBuffer::new(&raw);
+    let rdata   = Dns::decode_rdata(&mut buf, rtype, raw.len() as u16).ok()?;
+    (buf.get_index() == raw.len()).then_some(rdata)
+}
+
+/// Parses the mnemonic of a class, or its `CLASSnn` generic form.
+fn parse_class(s: &str) -> Option<u16> {
+    let upper = s.to_ascii_uppercase();
+    match upper.as_str() {
+        "IN" => Some(1),
+        "CH" => Some(3),
+        "HS" => Some(4),
+        _    => upper.strip_prefix("CLASS")?.parse().ok(),
+    }
+}
+
+/// Writes the mnemonic of a class, or its `CLASSnn` generic form.
+pub fn class_name(class: u16) -> String {
+    match class {
+        1 => "IN".to_string(),
+        3 => "CH".to_string(),
+        4 => "HS".to_string(),
+        n => format!("CLASS{}", n),
+    }
+}
+
+/// Writes the mnemonic of a response code, or its `RCODEnn` generic form.
+pub fn rcode_name(rcode: u16) -> String {
+    match rcode {
+        0  => "NOERROR".to_string(),
+        1  => "FORMERR".to_string(),
+        2  => "SERVFAIL".to_string(),
+        3  => "NXDOMAIN".to_string(),
+        4  => "NOTIMP".to_string(),
+        5  => "REFUSED".to_string(),
+        6  => "YXDOMAIN".to_string(),
+        7  => "YXRRSET".to_string(),
+        8  => "NXRRSET".to_string(),
+        9  => "NOTAUTH".to_string(),
+        10 => "NOTZONE".to_string(),
+        16 => "BADVERS".to_string(),
+        n  => format!("RCODE{}", n),
+    }
+}
+
+/// Parses an SVCB parameter from its presentation format, such as
+/// `alpn=h2,h3` or `port=443` (RFC 9460, section 2.1).
+fn parse_svc_param(s: &str) -> Option<SvcParam> {
+    let (key, value) = s.split_once('=').unwrap_or((s, ""));
+    let list = || value.split(',').filter(|item| !item.is_empty());
+
+    match key {
+        "mandatory" => list()
+            .map(svc_key)
+            .collect::<Option<_>>()
+            .map(SvcParam::Mandatory),
+        "alpn" => Some(SvcParam::Alpn(list().map(str::to_string).collect())),
+        "no-default-alpn" if value.is_empty() => Some(SvcParam::NoDefaultAlpn),
+        "port" => value.parse().ok().map(SvcParam::Port),
+        "ipv4hint" => list()
+            .map(|ip| ip.parse().ok())
+            .collect::<Option<_>>()
+            .map(SvcParam::Ipv4Hint),
+        "ech" => base64_decode(value).map(SvcParam::Ech),
+        "ipv6hint" => list()
+            .map(|ip| ip.parse().ok())
+            .collect::<Option<_>>()
+            .map(SvcParam::Ipv6Hint),
+        _ => match svc_key(key)? {
+            0..=6 => None,
+            key   => Some(SvcParam::Unknown(key, value.as_bytes().to_vec())),
+        },
+    }
+}
+
+/// Parses the name of an SVCB parameter key, or its generic `keyNNNNN`
+/// form.
+fn svc_key(s: &str) -> Option<u16> {
+    match s {
+        "mandatory"       => Some(0),
+        "alpn"            => Some(1),
+        "no-default-alpn" => Some(2),
+        "port"            => Some(3),
+        "ipv4hint"        => Some(4),
+        "ech"             => Some(5),
+        "ipv6hint"        => Some(6),
+        _ => s.strip_prefix("key")?.parse().ok(),
+    }
+}
+
+/// Decodes hexadecimal data, in either case.
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.bytes().all(|c| c.is_ascii_hexdigit()) || !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Decodes standard base64, with or without padding.
+pub fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out  = Vec::with_capacity(s.len() * 3 / 4);
+    let mut acc  = 0u32;
+    let mut bits = 0;
+    for c in s.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+'        => 62,
+            b'/'        => 63,
+            _ => return None,
+        };
+        acc   = (acc << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
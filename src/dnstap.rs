@@ -0,0 +1,262 @@
+use crate::types::DnsError;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+
+#[cfg(unix)]
+use std::{path::PathBuf, time::Duration};
+#[cfg(unix)]
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
+    net::UnixStream,
+    time,
+};
+
+/// Frames waiting to be written to the collector. Beyond that, the
+/// messages are dropped rather than slowing the queries down.
+const QUEUE_LENGTH: usize = 4096;
+
+/// How long to wait before connecting again to the collector.
+#[cfg(unix)]
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long the collector may take to accept the stream.
+#[cfg(unix)]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Content type of the frames, as agreed with the collector.
+#[cfg(unix)]
+const CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+
+/// Control frame types of the Frame Streams protocol.
+#[cfg(unix)]
+const CONTROL_ACCEPT: u32 = 0x01;
+#[cfg(unix)]
+const CONTROL_START: u32 = 0x02;
+#[cfg(unix)]
+const CONTROL_STOP: u32 = 0x03;
+#[cfg(unix)]
+const CONTROL_READY: u32 = 0x04;
+
+/// Control frame field carrying the content type.
+#[cfg(unix)]
+const FIELD_CONTENT_TYPE: u32 = 0x01;
+
+/// Type of a dnstap message, numbered as in `dnstap.proto`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A query sent to a name server.
+    ResolverQuery    = 3,
+    /// The response of a name server.
+    ResolverResponse = 4,
+    /// A query received from a client.
+    ClientQuery      = 5,
+    /// The reply sent to a client.
+    ClientResponse   = 6,
+}
+
+/// Transport of a message, numbered as in `dnstap.proto`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Udp = 1,
+    Tcp = 2,
+    #[cfg(feature = "dot")]
+    Dot = 3,
+    #[cfg(feature = "doq")]
+    Doq = 7,
+}
+
+/// A message exchanged with a client or a name server.
+pub struct Message<'a> {
+    pub kind:     Kind,
+    pub protocol: Protocol,
+    /// The client, or the name server.
+    pub peer:     SocketAddr,
+    /// The query, and when it was received or sent.
+    pub query:    (&'a [u8], SystemTime),
+    /// The response, and when it was sent or received.
+    pub response: Option<(&'a [u8], SystemTime)>,
+}
+
+/// Output of the messages exchanged with the clients and the name servers
+/// in the dnstap format, as Frame Streams over a unix domain socket.
+///
+/// The messages are queued and written by a task of their own, which
+/// connects to the collector and connects again when it goes away: the
+/// messages are dropped while it is away, or when the queue is full.
+#[derive(Debug)]
+pub struct Dnstap {
+    frames: mpsc::Sender<Vec<u8>>,
+}
+
+impl Dnstap {
+    /// Starts writing the messages to the collector listening at `path`.
+    #[cfg(unix)]
+    pub fn open(path: &Path) -> Result<Self, DnsError> {
+        let (frames, queue) = mpsc::channel(QUEUE_LENGTH);
+        tokio::spawn(write(path.to_path_buf(), queue));
+        Ok(Dnstap { frames })
+    }
+
+    /// Starts writing the messages to the collector listening at `path`.
+    #[cfg(not(unix))]
+    pub fn open(path: &Path) -> Result<Self, DnsError> {
+        let _ = path;
+        Err(DnsError::IOError("dnstap needs unix domain sockets".into()))
+    }
+
+    /// Queues a message for the collector.
+    pub fn record(&self, message: Message) {
+        let _ = self.frames.try_send(encode(&message));
+    }
+}
+
+/// Writes the queued frames to the collector at `path`, connecting again
+/// whenever the stream breaks, until the queue is closed.
+#[cfg(unix)]
+async fn write(path: PathBuf, mut queue: mpsc::Receiver<Vec<u8>>) {
+    loop {
+        let mut stream = match time::timeout(HANDSHAKE_TIMEOUT, connect(&path)).await {
+            Ok(Ok(stream)) => BufWriter::new(stream),
+            Ok(Err(e)) => {
+                tracing::warn!(path = %path.display(), error = %e, "can't connect to the dnstap collector");
+                time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+            Err(_) => {
+                tracing::warn!(path = %path.display(), "dnstap handshake timed out");
+                time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+
+        // Write whatever is queued at once, flushing when nothing is left
+        loop {
+            let Some(frame) = queue.recv().await else {
+                let _ = stream.write_all(&control(CONTROL_STOP, false)).await;
+                let _ = stream.flush().await;
+                return;
+            };
+            let mut written = stream.write_all(&frame).await;
+            while written.is_ok()
+                && let Ok(frame) = queue.try_recv()
+            {
+                written = stream.write_all(&frame).await;
+            }
+            if let Err(e) = written.and(stream.flush().await) {
+                tracing::warn!(path = %path.display(), error = %e, "dnstap collector went away");
+                break;
+            }
+        }
+    }
+}
+
+/// Connects to the collector at `path` and opens a bidirectional stream:
+/// the collector has to accept the content type before it starts.
+#[cfg(unix)]
+async fn connect(path: &Path) -> std::io::Result<UnixStream> {
+    let mut stream = UnixStream::connect(path).await?;
+    stream.write_all(&control(CONTROL_READY, true)).await?;
+
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header).await?;
+    let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let mut frame = vec![0u8; length];
+    stream.read_exact(&mut frame).await?;
+    if header[..4] != [0; 4] || frame.get(..4) != Some(&CONTROL_ACCEPT.to_be_bytes()[..]) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "stream not accepted"));
+    }
+
+    stream.write_all(&control(CONTROL_START, true)).await?;
+    Ok(stream)
+}
+
+/// Builds a control frame, carrying the content type if `typed`.
+#[cfg(unix)]
+fn control(kind: u32, typed: bool) -> Vec<u8> {
+    let mut body = kind.to_be_bytes().to_vec();
+    if typed {
+        body.extend_from_slice(&FIELD_CONTENT_TYPE.to_be_bytes());
+        body.extend_from_slice(&(CONTENT_TYPE.len() as u32).to_be_bytes());
+        body.extend_from_slice(CONTENT_TYPE);
+    }
+
+    // An empty data frame escapes the control frames
+    let mut frame = vec![0; 4];
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend(body);
+    frame
+}
+
+/// Encodes a message as a data frame carrying a `Dnstap` protobuf.
+fn encode(message: &Message) -> Vec<u8> {
+    let ip = message.peer.ip().to_canonical();
+    let (family, address) = match ip {
+        IpAddr::V4(ip) => (1, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (2, ip.octets().to_vec()),
+    };
+
+    // The client sent the query, the name server the response
+    let (address_field, port_field) = match message.kind {
+        Kind::ClientQuery | Kind::ClientResponse     => (4, 6),
+        Kind::ResolverQuery | Kind::ResolverResponse => (5, 7),
+    };
+
+    let mut msg = Vec::new();
+    varint_field(&mut msg, 1, message.kind as u64);
+    varint_field(&mut msg, 2, family);
+    varint_field(&mut msg, 3, message.protocol as u64);
+    bytes_field(&mut msg, address_field, &address);
+    varint_field(&mut msg, port_field, message.peer.port() as u64);
+
+    let (query, sent) = message.query;
+    time_fields(&mut msg, 8, sent);
+    bytes_field(&mut msg, 10, query);
+    if let Some((response, received)) = message.response {
+        time_fields(&mut msg, 12, received);
+        bytes_field(&mut msg, 14, response);
+    }
+
+    let mut tap = Vec::new();
+    bytes_field(&mut tap, 2, concat!("dns-resolver ", env!("CARGO_PKG_VERSION")).as_bytes());
+    bytes_field(&mut tap, 14, &msg);
+    varint_field(&mut tap, 15, 1);
+
+    let mut frame = (tap.len() as u32).to_be_bytes().to_vec();
+    frame.extend(tap);
+    frame
+}
+
+/// Appends a time as its seconds, in field `field`, and nanoseconds, in
+/// the next one.
+fn time_fields(out: &mut Vec<u8>, field: u32, at: SystemTime) {
+    let since = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    varint_field(out, field, since.as_secs());
+    varint(out, ((field as u64 + 1) << 3) | 5);
+    out.extend_from_slice(&since.subsec_nanos().to_le_bytes());
+}
+
+/// Appends a varint field.
+fn varint_field(out: &mut Vec<u8>, field: u32, value: u64) {
+    varint(out, (field as u64) << 3);
+    varint(out, value);
+}
+
+/// Appends a length-delimited field.
+fn bytes_field(out: &mut Vec<u8>, field: u32, value: &[u8]) {
+    varint(out, ((field as u64) << 3) | 2);
+    varint(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+/// Appends a protobuf varint, seven bits at a time.
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
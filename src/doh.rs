@@ -0,0 +1,60 @@
+use crate::types::{Dns, DnsError, DnsReadBuffer};
+use std::time::Duration;
+
+/// Media type of the DNS messages carried over HTTPS (RFC 8484).
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// How long to wait for the response of a DoH server, unless told
+/// otherwise.
+pub const DOH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Client of the DNS-over-HTTPS servers, keeping their connections open
+/// across queries.
+#[derive(Debug, Clone)]
+pub struct DohClient {
+    http: reqwest::Client,
+}
+
+impl DohClient {
+    /// Creates a new client.
+    pub fn new() -> Result<Self, DnsError> {
+        let http = reqwest::Client::builder()
+            .timeout(DOH_TIMEOUT)
+            .build()
+            .map_err(|e| DnsError::IOError(format!("can't create the HTTPS client: {}", e)))?;
+        Ok(DohClient { http })
+    }
+
+    /// Sends `req` to the DoH server at `url` with a POST request and
+    /// decodes its response, waiting for it up to `timeout`.
+    pub async fn exchange(&self, url: &str, req: &Dns, timeout: Duration) -> Result<Dns, DnsError> {
+        let body = req.encode()?.data;
+
+        let res = self
+            .http
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, DNS_MESSAGE)
+            .header(reqwest::header::ACCEPT, DNS_MESSAGE)
+            .body(body)
+            .timeout(timeout)
+            .send()
+            .await
+            .map_err(|e| {
+                if e.is_timeout() {
+                    DnsError::Timeout
+                } else {
+                    DnsError::IOError(format!("can't reach {}: {}", url, e))
+                }
+            })?;
+
+        if !res.status().is_success() {
+            return Err(DnsError::IOError(format!("{} answered with status {}", url, res.status())));
+        }
+
+        let data = res
+            .bytes()
+            .await
+            .map_err(|e| DnsError::IOError(format!("can't read the response of {}: {}", url, e)))?;
+        Dns::decode(&mut DnsReadBuffer::new(&data))
+    }
+}
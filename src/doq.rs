@@ -0,0 +1,283 @@
+use crate::{
+    sockets,
+    types::{Dns, DnsError, DnsReadBuffer},
+};
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    ClientConfig, Connection, Endpoint, EndpointConfig, IdleTimeout, RecvStream, SendStream, ServerConfig, TokioRuntime,
+    TransportConfig, VarInt,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::time;
+
+/// ALPN token of DNS over QUIC (RFC 9250, section 4.1.1).
+pub const DOQ_ALPN: &[u8] = b"doq";
+
+/// How long to wait for the response of a DoQ server, connection included,
+/// unless told otherwise.
+pub const DOQ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval of the keep-alive probes on idle connections, below the 30
+/// seconds after which many NATs forget a UDP mapping.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Shortest time between two rebinds of the local socket.
+const REBIND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Largest DNS message, plus its length prefix.
+const MAX_RESPONSE: usize = 65535 + 2;
+
+/// How long a client connection may stay idle, as on the TLS listener.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Error closing the connection of a client breaking the protocol (RFC
+/// 9250, section 4.3).
+const DOQ_PROTOCOL_ERROR: u32 = 0x2;
+
+/// Error resetting the stream of a query left unanswered.
+const DOQ_REQUEST_CANCELLED: u32 = 0x3;
+
+/// Client of the DNS-over-QUIC servers (RFC 9250).
+///
+/// A connection is kept open to every server and each query is sent on a
+/// stream of its own, so that a slow response doesn't hold back the others.
+///
+/// All the connections share a long-lived local socket, whose NAT mapping
+/// could expire while idle: keep-alive probes keep it open, and when the
+/// responses stop arriving anyway the socket is replaced by a new one, to
+/// which QUIC migrates the connections.
+#[derive(Debug)]
+pub struct DoqClient {
+    endpoint:    Endpoint,
+    connections: Mutex<HashMap<SocketAddr, Connection>>,
+    last_rebind: Mutex<Option<Instant>>,
+}
+
+impl DoqClient {
+    /// Creates a new client, trusting the usual web PKI roots.
+    pub fn new() -> Result<Self, DnsError> {
+        let roots = rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let mut tls = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|e| DnsError::IOError(format!("can't configure TLS: {}", e)))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        tls.alpn_protocols = vec![DOQ_ALPN.to_vec()];
+
+        let quic = QuicClientConfig::try_from(tls)
+            .map_err(|e| DnsError::IOError(format!("can't configure QUIC: {}", e)))?;
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(Some(KEEP_ALIVE));
+        let mut client = ClientConfig::new(Arc::new(quic));
+        client.transport_config(Arc::new(transport));
+
+        let mut endpoint = bind()
+            .and_then(|sock| Endpoint::new(EndpointConfig::default(), None, sock, Arc::new(TokioRuntime)))
+            .map_err(|_| DnsError::SocketError)?;
+        endpoint.set_default_client_config(client);
+
+        Ok(DoqClient {
+            endpoint,
+            connections: Mutex::new(HashMap::new()),
+            last_rebind: Mutex::new(None),
+        })
+    }
+
+    /// Returns whether a connection to the server at `addr` is open.
+    fn is_open(&self, addr: SocketAddr) -> bool {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(&addr)
+            .is_some_and(|conn| conn.close_reason().is_none())
+    }
+
+    /// Returns an open connection to the server at `addr`, whose
+    /// certificate must be valid for `name`.
+    async fn connection(&self, addr: SocketAddr, name: &str) -> Result<Connection, DnsError> {
+        if let Some(conn) = self.connections.lock().unwrap().get(&addr)
+            && conn.close_reason().is_none()
+        {
+            return Ok(conn.clone());
+        }
+
+        let conn = self
+            .endpoint
+            .connect(addr, name)
+            .map_err(|e| DnsError::IOError(format!("can't connect to {}: {}", addr, e)))?
+            .await
+            .map_err(|e| DnsError::IOError(format!("can't connect to {}: {}", addr, e)))?;
+
+        self.connections.lock().unwrap().insert(addr, conn.clone());
+        Ok(conn)
+    }
+
+    /// Moves the connections to a new local socket, unless that was just
+    /// done. Returns whether the socket was replaced.
+    fn rebind(&self) -> bool {
+        let mut last = self.last_rebind.lock().unwrap();
+        if last.is_some_and(|last| last.elapsed() < REBIND_INTERVAL) {
+            return false;
+        }
+
+        let sock = match bind() {
+            Ok(sock) => sock,
+            Err(e) => {
+                tracing::warn!(error = %e, "can't bind a new QUIC socket");
+                return false;
+            }
+        };
+        if let Err(e) = self.endpoint.rebind(sock) {
+            tracing::warn!(error = %e, "can't rebind the QUIC endpoint");
+            return false;
+        }
+        *last = Some(Instant::now());
+        true
+    }
+
+    /// Sends `req` to the DoQ server at `addr` on a new stream and decodes
+    /// its response, waiting for it up to `timeout`. The query ID must be
+    /// zero (RFC 9250, section 4.2.1).
+    ///
+    /// A query timing out on a connection that was already open is retried
+    /// once from a new local socket, in case a NAT on the way forgot the
+    /// mapping of the old one.
+    pub async fn exchange(&self, addr: SocketAddr, name: &str, req: &Dns, timeout: Duration) -> Result<Dns, DnsError> {
+        let data = req.encode()?.data;
+
+        let open = self.is_open(addr);
+        let res  = match self.send(addr, name, &data, timeout).await {
+            Err(DnsError::Timeout) if open && self.rebind() => {
+                tracing::warn!(server = %addr, "no response on the QUIC socket, rebound it");
+                self.send(addr, name, &data, timeout).await?
+            }
+            res => res?,
+        };
+
+        let length = res
+            .get(..2)
+            .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+            .ok_or(DnsError::InvalidField)?;
+        let body = res.get(2..2 + length).ok_or(DnsError::InvalidField)?;
+        Dns::decode(&mut DnsReadBuffer::new(body))
+    }
+
+    /// Sends an encoded query on a new stream, returning the raw response.
+    async fn send(&self, addr: SocketAddr, name: &str, data: &[u8], timeout: Duration) -> Result<Vec<u8>, DnsError> {
+        let exchange = async {
+            let conn = self.connection(addr, name).await?;
+            let (mut send, mut recv) = conn
+                .open_bi()
+                .await
+                .map_err(|e| DnsError::IOError(format!("can't open a stream to {}: {}", addr, e)))?;
+
+            let mut msg = (data.len() as u16).to_be_bytes().to_vec();
+            msg.extend_from_slice(data);
+            send.write_all(&msg)
+                .await
+                .map_err(|e| DnsError::IOError(format!("can't send to {}: {}", addr, e)))?;
+            send.finish().map_err(|_| DnsError::SocketError)?;
+
+            recv.read_to_end(MAX_RESPONSE)
+                .await
+                .map_err(|e| DnsError::IOError(format!("can't read from {}: {}", addr, e)))
+        };
+
+        time::timeout(timeout, exchange)
+            .await
+            .map_err(|_| DnsError::Timeout)?
+    }
+}
+
+/// Binds the local socket of the client, dual stack so that the servers
+/// of both families are reached, or IPv4 only on hosts without IPv6.
+fn bind() -> std::io::Result<UdpSocket> {
+    sockets::listen_udp(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))
+        .or_else(|_| UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))))
+}
+
+/// Binds the DNS-over-QUIC listener, presenting the certificate of `tls`.
+pub fn listen(addr: SocketAddr, tls: rustls::ServerConfig) -> Result<Endpoint, DnsError> {
+    let quic = QuicServerConfig::try_from(tls)
+        .map_err(|e| DnsError::IOError(format!("can't configure QUIC: {}", e)))?;
+    let mut transport = TransportConfig::default();
+    transport.max_idle_timeout(IdleTimeout::try_from(IDLE_TIMEOUT).ok());
+    let mut server = ServerConfig::with_crypto(Arc::new(quic));
+    server.transport_config(Arc::new(transport));
+
+    let cant_bind = |e: std::io::Error| DnsError::IOError(format!("can't bind {}: {}", addr, e));
+    let sock      = sockets::listen_udp(addr).map_err(cant_bind)?;
+    Endpoint::new(EndpointConfig::default(), Some(server), sock, Arc::new(TokioRuntime)).map_err(cant_bind)
+}
+
+/// Serves the clients connecting over QUIC (RFC 9250), answering their
+/// queries with `answer`.
+///
+/// Every query comes on a bidirectional stream of its own, preceded by its
+/// length, and its response is sent back on the same stream before it is
+/// closed. The streams of a connection are answered concurrently, so that
+/// a slow resolution doesn't hold back the others.
+pub async fn serve<F, Fut>(endpoint: Endpoint, answer: F)
+where
+    F:   Fn(SocketAddr, Vec<u8>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Option<Vec<u8>>> + Send + 'static,
+{
+    while let Some(incoming) = endpoint.accept().await {
+        let answer = answer.clone();
+        tokio::spawn(async move {
+            let addr = incoming.remote_address();
+            let conn = match incoming.await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::warn!(client = %addr, error = %e, "QUIC handshake failed");
+                    return;
+                }
+            };
+
+            while let Ok((send, recv)) = conn.accept_bi().await {
+                tokio::spawn(stream(conn.clone(), send, recv, addr, answer.clone()));
+            }
+        });
+    }
+}
+
+/// Answers the query sent on a stream.
+async fn stream<F, Fut>(conn: Connection, mut send: SendStream, mut recv: RecvStream, addr: SocketAddr, answer: F)
+where
+    F:   Fn(SocketAddr, Vec<u8>) -> Fut,
+    Fut: Future<Output = Option<Vec<u8>>>,
+{
+    let Ok(Ok(data)) = time::timeout(IDLE_TIMEOUT, recv.read_to_end(MAX_RESPONSE)).await else {
+        let _ = send.reset(VarInt::from_u32(DOQ_REQUEST_CANCELLED));
+        return;
+    };
+
+    // A query is the whole stream, and its ID must be zero (RFC 9250,
+    // section 4.2.1)
+    let length = data.get(..2).map(|len| u16::from_be_bytes([len[0], len[1]]) as usize);
+    let query  = match length {
+        Some(length) if data.len() == length + 2 && data.get(2..4) == Some(&[0, 0]) => data[2..].to_vec(),
+        _ => {
+            conn.close(VarInt::from_u32(DOQ_PROTOCOL_ERROR), b"malformed query");
+            return;
+        }
+    };
+
+    let reply = answer(addr, query).await.filter(|reply| reply.len() <= u16::MAX as usize);
+    let Some(reply) = reply else {
+        let _ = send.reset(VarInt::from_u32(DOQ_REQUEST_CANCELLED));
+        return;
+    };
+
+    let mut framed = (reply.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(&reply);
+    if send.write_all(&framed).await.is_ok() {
+        let _ = send.finish();
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{
+    sockets, tls,
+    types::{Dns, DnsError, DnsReadBuffer},
+};
+use rustls::{pki_types::ServerName, ServerConfig};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+use tokio_rustls::{client, server::TlsStream, TlsAcceptor, TlsConnector};
+
+/// ALPN token of DNS over TLS (RFC 7858, section 3.2).
+pub const DOT_ALPN: &[u8] = b"dot";
+
+/// How long to wait for the response of a DoT server, connection included,
+/// unless told otherwise.
+pub const DOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connections kept open to every server, once their queries are
+/// answered.
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+/// How long a client may take to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a connection may stay idle between two queries. Mobile
+/// clients keep their connections open to save the handshakes, so it is
+/// longer than on the unix domain socket.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Client of the DNS-over-TLS servers (RFC 7858).
+///
+/// Connections are kept open once their query is answered and reused by
+/// the next queries to the same server, which saves the handshakes
+/// (RFC 7858, section 3.4). A connection carries one query at a time, and
+/// one the server closed while idle is replaced by a new one.
+pub struct DotClient {
+    connector: TlsConnector,
+    idle:      Mutex<HashMap<SocketAddr, Vec<client::TlsStream<TcpStream>>>>,
+}
+
+impl fmt::Debug for DotClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DotClient").finish_non_exhaustive()
+    }
+}
+
+impl DotClient {
+    /// Creates a new client, trusting the certificate authorities of the
+    /// PEM file `roots`, or else the usual web PKI roots.
+    pub fn new(roots: Option<&Path>) -> Result<Self, DnsError> {
+        let tls = tls::client_config(roots, DOT_ALPN)?;
+        Ok(DotClient {
+            connector: TlsConnector::from(Arc::new(tls)),
+            idle:      Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Sends `req` to the DoT server at `addr`, whose certificate must be
+    /// valid for `name`, and decodes its response, waiting for it up to
+    /// `timeout`.
+    pub async fn exchange(&self, addr: SocketAddr, name: &str, req: &Dns, timeout: Duration) -> Result<Dns, DnsError> {
+        let data     = req.encode()?.data;
+        let exchange = async {
+            let reused = self.idle.lock().unwrap().get_mut(&addr).and_then(Vec::pop);
+            let outcome = match reused {
+                Some(stream) => send(stream, &data).await.ok(),
+                None         => None,
+            };
+            let (stream, res) = match outcome {
+                Some(outcome) => outcome,
+                None          => send(self.connect(addr, name).await?, &data).await?,
+            };
+
+            let mut idle = self.idle.lock().unwrap();
+            let streams  = idle.entry(addr).or_default();
+            if streams.len() < MAX_IDLE_CONNECTIONS {
+                streams.push(stream);
+            }
+            Ok::<_, DnsError>(res)
+        };
+
+        let res = time::timeout(timeout, exchange).await.map_err(|_| DnsError::Timeout)??;
+        Dns::decode(&mut DnsReadBuffer::new(&res))
+    }
+
+    /// Opens a new connection to the server at `addr`, whose certificate
+    /// must be valid for `name`.
+    async fn connect(&self, addr: SocketAddr, name: &str) -> Result<client::TlsStream<TcpStream>, DnsError> {
+        let cant_connect = |e: &dyn fmt::Display| DnsError::IOError(format!("can't connect to {}: {}", addr, e));
+        let name = ServerName::try_from(name.to_string()).map_err(|e| cant_connect(&e))?;
+        let sock = TcpStream::connect(addr).await.map_err(|e| cant_connect(&e))?;
+        self.connector.connect(name, sock).await.map_err(|e| cant_connect(&e))
+    }
+}
+
+/// Sends an encoded query over a connection, preceded by its length,
+/// returning the connection and the raw response.
+async fn send(
+    mut stream: client::TlsStream<TcpStream>,
+    data:       &[u8],
+) -> Result<(client::TlsStream<TcpStream>, Vec<u8>), DnsError> {
+    let mut framed = Vec::with_capacity(data.len() + 2);
+    framed.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    framed.extend_from_slice(data);
+    stream.write_all(&framed).await.map_err(|_| DnsError::SocketError)?;
+    stream.flush().await.map_err(|_| DnsError::SocketError)?;
+
+    let length  = stream.read_u16().await.map_err(|_| DnsError::SocketError)?;
+    let mut res = vec![0; length as usize];
+    stream.read_exact(&mut res).await.map_err(|_| DnsError::SocketError)?;
+    Ok((stream, res))
+}
+
+/// Binds the DNS-over-TLS listener.
+pub fn listen(addr: SocketAddr) -> Result<TcpListener, DnsError> {
+    sockets::listen_tcp(addr)
+        .and_then(TcpListener::from_std)
+        .map_err(|e| DnsError::IOError(format!("can't bind {}: {}", addr, e)))
+}
+
+/// Serves the clients connecting over TLS (RFC 7858), answering their
+/// queries with `answer`.
+///
+/// Once the handshake is done, messages are framed as over TCP, each
+/// preceded by its length (RFC 1035, section 4.2.2). The queries of a
+/// connection are answered in turn, and connections idle for too long are
+/// closed.
+pub async fn serve<F, Fut>(listener: TcpListener, tls: ServerConfig, answer: F)
+where
+    F:   Fn(SocketAddr, Vec<u8>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Option<Vec<u8>>> + Send + 'static,
+{
+    let acceptor = TlsAcceptor::from(Arc::new(tls));
+    loop {
+        let Ok((stream, addr)) = listener.accept().await else {
+            continue;
+        };
+
+        let acceptor = acceptor.clone();
+        let answer   = answer.clone();
+        tokio::spawn(async move {
+            match time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => connection(stream, addr, answer).await,
+                Ok(Err(e))     => tracing::warn!(client = %addr, error = %e, "TLS handshake failed"),
+                Err(_)         => tracing::warn!(client = %addr, "TLS handshake timed out"),
+            }
+        });
+    }
+}
+
+/// Answers the queries of a client, until it disconnects or stays idle.
+async fn connection<F, Fut>(mut stream: TlsStream<TcpStream>, addr: SocketAddr, answer: F)
+where
+    F:   Fn(SocketAddr, Vec<u8>) -> Fut,
+    Fut: Future<Output = Option<Vec<u8>>>,
+{
+    loop {
+        let Ok(Ok(length)) = time::timeout(IDLE_TIMEOUT, stream.read_u16()).await else {
+            break;
+        };
+
+        let mut query = vec![0; length as usize];
+        if stream.read_exact(&mut query).await.is_err() {
+            return;
+        }
+
+        let Some(reply) = answer(addr, query).await else {
+            continue;
+        };
+        let Ok(length) = u16::try_from(reply.len()) else {
+            continue;
+        };
+
+        let mut framed = Vec::with_capacity(reply.len() + 2);
+        framed.extend_from_slice(&length.to_be_bytes());
+        framed.extend_from_slice(&reply);
+        if stream.write_all(&framed).await.is_err() || stream.flush().await.is_err() {
+            return;
+        }
+    }
+
+    // Say goodbye with a close_notify, so that the client tells the end of
+    // the connection from a truncation
+    let _ = stream.shutdown().await;
+}
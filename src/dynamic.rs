@@ -0,0 +1,99 @@
+use crate::types::{AnswerRecord, DnsError, Type};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::time;
+
+/// How long to wait before watching a source again after a failure.
+#[cfg_attr(not(feature = "consul"), allow(dead_code))]
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A source of records that change at runtime, such as the catalog of a
+/// service registry. Only the Consul catalog is built in, behind the
+/// `consul` feature.
+#[cfg_attr(not(feature = "consul"), allow(dead_code))]
+pub trait ZoneSource: Send + Sync + 'static {
+    /// Name of the source, for the logs.
+    fn name(&self) -> &str;
+
+    /// Waits for the records to change past `version`, then returns all of
+    /// them along with their new version. Version 0 returns at once.
+    fn watch(&self, version: u64) -> impl Future<Output = Result<(Vec<AnswerRecord>, u64), DnsError>> + Send;
+}
+
+/// Records materialized from a [`ZoneSource`], answered locally.
+#[derive(Debug, Default)]
+pub struct DynamicZone {
+    /// Records of each name, lowercased.
+    records: RwLock<HashMap<String, Vec<AnswerRecord>>>,
+}
+
+impl DynamicZone {
+    /// Creates an empty zone.
+    pub fn new() -> Self {
+        DynamicZone::default()
+    }
+
+    /// Answers a question from the zone, if the name is in it. A name that
+    /// exists but has no records of the requested type gets an empty answer.
+    pub fn answer(&self, qname: &str, qtype: Type) -> Option<Vec<AnswerRecord>> {
+        let name    = qname.trim_end_matches('.').to_ascii_lowercase();
+        let records = self.records.read().unwrap();
+        let records = records.get(&name)?;
+
+        Some(
+            records
+                .iter()
+                .filter(|record| record.atype == qtype)
+                .cloned()
+                .map(|mut record| {
+                    record.aname = qname.to_string();
+                    record
+                })
+                .collect(),
+        )
+    }
+
+    /// Replaces the content of the zone.
+    #[cfg_attr(not(feature = "consul"), allow(dead_code))]
+    fn replace(&self, records: Vec<AnswerRecord>) {
+        let mut names: HashMap<String, Vec<AnswerRecord>> = HashMap::new();
+        for record in records {
+            names.entry(record.aname.to_ascii_lowercase()).or_default().push(record);
+        }
+        *self.records.write().unwrap() = names;
+    }
+
+    /// Keeps the zone in sync with `source`, forever.
+    ///
+    /// A failed watch is retried with the last version, so that the zone
+    /// keeps serving the records it has while the source is unreachable.
+    #[cfg_attr(not(feature = "consul"), allow(dead_code))]
+    pub async fn follow<S: ZoneSource>(self: Arc<Self>, source: S) {
+        let mut version = 0;
+        loop {
+            match source.watch(version).await {
+                Ok((records, next)) => {
+                    tracing::info!(
+                        source  = %source.name(),
+                        records = %records.len(),
+                        "dynamic zone updated",
+                    );
+                    self.replace(records);
+                    version = next;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        source = %source.name(),
+                        error  = %e,
+                        "can't watch the dynamic zone source",
+                    );
+                    time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{lookup::Resolver, types::Type};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+/// How long a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request accepted.
+const MAX_REQUEST: usize = 1024;
+
+/// Delay between two priming attempts.
+const PRIMING_RETRY: Duration = Duration::from_secs(5);
+
+/// Startup progress of the resolver, as reported by `/readyz`.
+#[derive(Debug, Default)]
+pub struct Health {
+    listening: AtomicBool,
+    cache:     AtomicBool,
+    primed:    AtomicBool,
+}
+
+impl Health {
+    /// Creates the state of a resolver that is still starting.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that the query socket is bound.
+    pub fn set_listening(&self) {
+        self.listening.store(true, Ordering::Relaxed);
+    }
+
+    /// Records that the saved cache, if any, was loaded.
+    pub fn set_cache_loaded(&self) {
+        self.cache.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns what the resolver is still waiting for, empty once ready.
+    fn pending(&self) -> Vec<&'static str> {
+        [
+            (&self.listening, "listener"),
+            (&self.cache,     "cache"),
+            (&self.primed,    "priming"),
+        ]
+        .into_iter()
+        .filter(|(done, _)| !done.load(Ordering::Relaxed))
+        .map(|(_, what)| what)
+        .collect()
+    }
+
+    /// Primes the resolver by asking the root for its servers, until it
+    /// succeeds.
+    pub async fn prime(self: Arc<Self>, resolver: Resolver) {
+        loop {
+            let ctx = resolver.context();
+            match resolver.lookup(".", Type::NS, false, &ctx).await {
+                Ok(_) => {
+                    self.primed.store(true, Ordering::Relaxed);
+                    tracing::info!("root priming done");
+                    return;
+                }
+                Err(e) => tracing::warn!(error = %e, "root priming failed"),
+            }
+            time::sleep(PRIMING_RETRY).await;
+        }
+    }
+
+    /// Serves the `/healthz` and `/readyz` endpoints over HTTP on `addr`.
+    ///
+    /// `/healthz` succeeds as long as the process runs, while `/readyz`
+    /// only does once the query socket is bound, the saved cache loaded
+    /// and the root primed.
+    pub async fn serve(self: Arc<Self>, addr: SocketAddr) {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!(addr = %addr, error = %e, "can't listen for health checks");
+                return;
+            }
+        };
+
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let health = Arc::clone(&self);
+            tokio::spawn(async move {
+                let _ = time::timeout(REQUEST_TIMEOUT, health.respond(stream)).await;
+            });
+        }
+    }
+
+    /// Answers a single HTTP request.
+    async fn respond(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut request = Vec::new();
+        let mut buf     = [0u8; 256];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+
+        let line = request.split(|&b| b == b'\r').next().unwrap_or_default();
+        let mut parts = line.split(|&b| b == b' ');
+        let (method, path) = (parts.next(), parts.next());
+
+        let (status, body) = match (method, path) {
+            (Some(b"GET"), Some(b"/healthz")) => ("200 OK", "ok\n".to_string()),
+            (Some(b"GET"), Some(b"/readyz")) => match self.pending() {
+                pending if pending.is_empty() => ("200 OK", "ready\n".to_string()),
+                pending => ("503 Service Unavailable", format!("waiting for: {}\n", pending.join(", "))),
+            },
+            (Some(b"GET"), _) => ("404 Not Found", "not found\n".to_string()),
+            _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+        };
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body,
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
@@ -0,0 +1,49 @@
+use crate::types::DnsError;
+use std::{collections::HashSet, fs, net::IpAddr, path::Path};
+
+/// Reads the addresses of the root servers from a root hints file.
+///
+/// The file is in the zone file format of `named.root`, as published by
+/// IANA: the NS records of the root name the servers, whose A and AAAA
+/// records give their addresses. Fails if no address is found.
+pub fn load(path: &Path) -> Result<Vec<IpAddr>, DnsError> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| DnsError::IOError(format!("can't read the root hints {}: {}", path.display(), e)))?;
+
+    let addresses = parse(&text);
+    if addresses.is_empty() {
+        return Err(DnsError::IOError(format!("no root server address in {}", path.display())));
+    }
+    Ok(addresses)
+}
+
+/// Extracts the addresses of the root servers from the text of a root
+/// hints file. The address records of names that aren't root servers are
+/// ignored.
+fn parse(text: &str) -> Vec<IpAddr> {
+    let records: Vec<(String, String, String)> = text
+        .lines()
+        .map(|line| line.split(';').next().unwrap_or_default())
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (rtype, data) = match fields.as_slice() {
+                [.., rtype, data] => (rtype, data),
+                _ => return None,
+            };
+            let name = fields[0].trim_end_matches('.').to_ascii_lowercase();
+            Some((name, rtype.to_ascii_uppercase(), data.trim_end_matches('.').to_ascii_lowercase()))
+        })
+        .collect();
+
+    let servers: HashSet<&str> = records
+        .iter()
+        .filter(|(name, rtype, _)| name.is_empty() && rtype == "NS")
+        .map(|(_, _, server)| server.as_str())
+        .collect();
+
+    records
+        .iter()
+        .filter(|(name, rtype, _)| (rtype == "A" || rtype == "AAAA") && servers.contains(name.as_str()))
+        .filter_map(|(_, _, address)| address.parse().ok())
+        .collect()
+}
@@ -0,0 +1,349 @@
+use crate::{
+    local::LocalRecord,
+    types::{Dns, DnsError, RData, Type},
+    update::{self, FORMERR, NOTAUTH, SERVFAIL},
+    zone::{SecondaryZone, Zone, ZoneFile},
+};
+#[cfg(feature = "zone-transfers")]
+use crate::{rng::DnsRng, types::DnsReadBuffer, zone::is_newer};
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+#[cfg(feature = "zone-transfers")]
+use tokio::net::UdpSocket;
+use tokio::{
+    sync::{watch, Notify},
+    task::JoinSet,
+    time,
+};
+
+/// How often the master files of the zones are checked for changes.
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shortest wait between two refreshes of a secondary zone, whatever its
+/// SOA record says.
+#[cfg(feature = "zone-transfers")]
+const MIN_REFRESH: Duration = Duration::from_secs(5);
+
+/// How long a NOTIFY waits for its response before being sent again.
+#[cfg(feature = "zone-transfers")]
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Times a NOTIFY is sent before giving up on the secondary.
+#[cfg(feature = "zone-transfers")]
+const NOTIFY_ATTEMPTS: usize = 3;
+
+/// Operation code of the NOTIFY messages (RFC 1996).
+pub const OPCODE_NOTIFY: u8 = 4;
+
+/// A secondary zone, and how to wake up its refresh.
+#[cfg_attr(not(feature = "zone-transfers"), allow(dead_code))]
+struct Secondary {
+    primary: SocketAddr,
+    wake:    Notify,
+}
+
+/// The zones the resolver is authoritative for: those read from master
+/// files, which it is the primary of, and those it keeps in sync with
+/// their primary, as a secondary.
+///
+/// The master files are reloaded when they change, and the secondary
+/// zones refreshed when their SOA record says so, or right away when
+/// their primary sends a NOTIFY (RFC 1996). The zones of master files
+/// may also be changed by dynamic updates (RFC 2136), saved back to their
+/// files. Every change of a zone is notified to the secondaries of the
+/// resolver in turn.
+pub struct HostedZones {
+    /// Current version of each zone, by apex, in lower case.
+    zones:       RwLock<HashMap<String, Arc<Zone>>>,
+    /// Zones read from master files, with the time the files were last
+    /// modified when read.
+    files:       Vec<(ZoneFile, Option<SystemTime>)>,
+    /// Zones transferred from their primary, by apex, in lower case.
+    #[cfg_attr(not(feature = "zone-transfers"), allow(dead_code))]
+    secondaries: HashMap<String, Secondary>,
+    /// Secondaries told about the changes of the zones.
+    #[cfg_attr(not(feature = "zone-transfers"), allow(dead_code))]
+    notify:      Vec<SocketAddr>,
+    /// Held while a dynamic update is applied, so that the updates of a
+    /// zone don't overwrite each other.
+    updating:    Mutex<()>,
+    /// Number of changes of the zones so far, watched by those deriving
+    /// data from them.
+    changes:     watch::Sender<u64>,
+}
+
+impl HostedZones {
+    /// Reads the zones of the master `files`, and prepares the
+    /// `secondaries` zones, transferred once maintained. The changes are
+    /// notified to the `notify` servers.
+    pub fn load(files: &[ZoneFile], secondaries: &[SecondaryZone], notify: Vec<SocketAddr>) -> Result<Self, DnsError> {
+        let mut zones    = HashMap::new();
+        let mut modified = Vec::with_capacity(files.len());
+        for file in files {
+            modified.push((file.clone(), modified_at(file)));
+            let zone = Zone::load(&file.path, &file.origin)?;
+            zones.insert(key(&zone.origin), Arc::new(zone));
+        }
+
+        let secondaries = secondaries
+            .iter()
+            .map(|zone| (key(&zone.origin), Secondary { primary: zone.primary, wake: Notify::new() }))
+            .collect();
+
+        Ok(HostedZones {
+            zones:    RwLock::new(zones),
+            files:    modified,
+            secondaries,
+            notify,
+            updating: Mutex::new(()),
+            changes:  watch::Sender::new(0),
+        })
+    }
+
+    /// Returns the current version of the zone whose apex is `name`, if
+    /// it is hosted and, for a secondary zone, transferred already.
+    pub fn get(&self, name: &str) -> Option<Arc<Zone>> {
+        let zones = self.zones.read().unwrap_or_else(|e| e.into_inner());
+        zones.get(&key(name)).cloned()
+    }
+
+    /// Returns the addresses of the A/AAAA records of the hosted zones, in
+    /// their current version.
+    pub fn addresses(&self) -> Vec<LocalRecord> {
+        let zones = self.zones.read().unwrap_or_else(|e| e.into_inner());
+        zones
+            .values()
+            .flat_map(|zone| &zone.rrsets)
+            .flat_map(|rrset| &rrset.records)
+            .filter_map(|record| {
+                let addr = match record.rdata {
+                    RData::A(ip)    => IpAddr::V4(ip),
+                    RData::AAAA(ip) => IpAddr::V6(ip),
+                    _               => return None,
+                };
+                Some(LocalRecord { name: record.aname.trim_end_matches('.').to_ascii_lowercase(), addr })
+            })
+            .collect()
+    }
+
+    /// Watches the changes of the zones, whether their master file changed,
+    /// they were updated or transferred again.
+    pub fn changes(&self) -> watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    /// Handles a NOTIFY for the zone whose apex is `name`, sent from
+    /// `from`: the zone is refreshed right away if it is a secondary zone
+    /// of that primary. Returns whether it is.
+    #[cfg(feature = "zone-transfers")]
+    pub fn notified(&self, name: &str, from: IpAddr) -> bool {
+        match self.secondaries.get(&key(name)) {
+            Some(secondary) if secondary.primary.ip() == from.to_canonical() => {
+                secondary.wake.notify_one();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Applies a dynamic update to the zone of a master file it names,
+    /// saving the zone to its file and notifying the secondaries when it
+    /// changes. Returns the response code telling why the update failed.
+    ///
+    /// Secondary zones can only be updated on their primary.
+    pub fn update(&self, update: &Dns) -> Result<(), u8> {
+        let [question] = update.questions.as_slice() else {
+            return Err(FORMERR);
+        };
+        if question.qtype != Type::SOA {
+            return Err(FORMERR);
+        }
+        let origin = key(&question.qname);
+        let Some((file, _)) = self.files.iter().find(|(file, _)| key(&file.origin) == origin) else {
+            return Err(NOTAUTH);
+        };
+
+        let _updating = self.updating.lock().unwrap_or_else(|e| e.into_inner());
+        let zone    = self.get(&origin).ok_or(NOTAUTH)?;
+        let updated = update::apply(&zone, update)?;
+        if updated == *zone {
+            return Ok(());
+        }
+        if let Err(e) = updated.save(&file.path) {
+            tracing::error!(zone = %zone.origin, error = %e, "can't save the updated zone");
+            return Err(SERVFAIL);
+        }
+        self.replace(updated);
+        Ok(())
+    }
+
+    /// Keeps the zones up to date, for as long as the server runs.
+    pub async fn maintain(self: Arc<Self>) {
+        let mut tasks = JoinSet::new();
+        for (file, modified) in self.files.clone() {
+            tasks.spawn(Arc::clone(&self).watch(file, modified));
+        }
+        #[cfg(feature = "zone-transfers")]
+        for origin in self.secondaries.keys().cloned() {
+            tasks.spawn(Arc::clone(&self).follow(origin));
+        }
+        while tasks.join_next().await.is_some() {}
+    }
+
+    /// Reloads the zone of a master file every time the file changes from
+    /// its `last` modification. A file that can't be read, or is invalid,
+    /// leaves the zone as it was.
+    async fn watch(self: Arc<Self>, file: ZoneFile, mut last: Option<SystemTime>) {
+        loop {
+            time::sleep(FILE_CHECK_INTERVAL).await;
+            let current = modified_at(&file);
+            if current == last {
+                continue;
+            }
+            last = current;
+
+            match Zone::load(&file.path, &file.origin) {
+                Ok(zone) => self.replace(zone),
+                Err(e)   => tracing::warn!(zone = %file.origin, error = %e, "can't reload the zone"),
+            }
+        }
+    }
+
+    /// Keeps a secondary zone in sync with its primary: it is transferred
+    /// whenever the primary has a newer serial, checked every time the
+    /// refresh interval of the zone elapses, the retry interval after a
+    /// failure, or as soon as the primary notifies a change.
+    #[cfg(feature = "zone-transfers")]
+    async fn follow(self: Arc<Self>, origin: String) {
+        let Some(secondary) = self.secondaries.get(&origin) else {
+            return;
+        };
+
+        let mut wait = Duration::ZERO;
+        loop {
+            tokio::select! {
+                _ = time::sleep(wait)          => {}
+                _ = secondary.wake.notified() => {}
+            }
+
+            let current = self.get(&origin);
+            wait = match self.refresh(&origin, secondary.primary, current.as_deref()).await {
+                Ok(zone) => Duration::from_secs(zone.soa().map_or(0, |soa| soa.refresh).into()),
+                Err(e)   => {
+                    tracing::warn!(
+                        zone    = %origin,
+                        primary = %secondary.primary,
+                        error   = %e,
+                        "can't refresh the zone",
+                    );
+                    Duration::from_secs(current.as_ref().and_then(|zone| zone.soa()).map_or(0, |soa| soa.retry).into())
+                }
+            };
+            wait = wait.max(MIN_REFRESH);
+        }
+    }
+
+    /// Transfers a secondary zone from its `primary` unless `current` is
+    /// as recent, returning the zone now hosted.
+    #[cfg(feature = "zone-transfers")]
+    async fn refresh(&self, origin: &str, primary: SocketAddr, current: Option<&Zone>) -> Result<Arc<Zone>, DnsError> {
+        if let Some(serial) = current.and_then(Zone::soa).map(|soa| soa.serial) {
+            let latest = Zone::serial_at(primary, origin).await?;
+            if !is_newer(latest, serial) {
+                return self.get(origin).ok_or(DnsError::InvalidField);
+            }
+        }
+
+        let zone = Zone::transfer(primary, origin).await?;
+        self.replace(zone);
+        self.get(origin).ok_or(DnsError::InvalidField)
+    }
+
+    /// Hosts a new version of a zone, notifying the secondaries if it
+    /// changed.
+    fn replace(&self, zone: Zone) {
+        let origin = key(&zone.origin);
+        let serial = zone.soa().map(|soa| soa.serial).unwrap_or_default();
+        {
+            let mut zones = self.zones.write().unwrap_or_else(|e| e.into_inner());
+            if zones.get(&origin).is_some_and(|current| **current == zone) {
+                return;
+            }
+            zones.insert(origin, Arc::new(zone.clone()));
+        }
+
+        self.changes.send_modify(|changes| *changes += 1);
+        tracing::info!(zone = %zone.origin, serial = %serial, "zone updated");
+        #[cfg(feature = "zone-transfers")]
+        for &secondary in &self.notify {
+            tokio::spawn(notify(secondary, zone.origin.clone()));
+        }
+    }
+}
+
+/// Returns the key of a zone: its apex, in lower case, without its
+/// trailing dot unless it is the root.
+fn key(origin: &str) -> String {
+    match origin.trim_end_matches('.') {
+        ""     => ".".to_string(),
+        origin => origin.to_ascii_lowercase(),
+    }
+}
+
+/// Returns when the master file of a zone was last modified, if known.
+fn modified_at(file: &ZoneFile) -> Option<SystemTime> {
+    fs::metadata(&file.path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Tells a secondary that the zone whose apex is `origin` changed, sending
+/// the NOTIFY again until it responds.
+#[cfg(feature = "zone-transfers")]
+async fn notify(secondary: SocketAddr, origin: String) {
+    let id  = DnsRng::from_entropy().query_id();
+    let mut msg = Dns::new_question(&origin, Type::SOA, id);
+    msg.header.flags.opcode = OPCODE_NOTIFY;
+    msg.header.flags.aa     = true;
+    let Ok(msg) = msg.encode() else {
+        return;
+    };
+
+    let local = match secondary {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+    let Ok(sock) = UdpSocket::bind(local).await else {
+        return;
+    };
+    if sock.connect(secondary).await.is_err() {
+        return;
+    }
+
+    let mut buf = [0u8; 512];
+    for _ in 0..NOTIFY_ATTEMPTS {
+        if sock.send(&msg.data).await.is_err() {
+            break;
+        }
+        let answered = time::timeout(NOTIFY_TIMEOUT, async {
+            loop {
+                let Ok(size) = sock.recv(&mut buf).await else {
+                    return false;
+                };
+                if let Ok(res) = Dns::decode(&mut DnsReadBuffer::new(&buf[..size]))
+                    && res.header.id == id
+                    && res.header.flags.qr
+                {
+                    return true;
+                }
+            }
+        });
+        if answered.await.unwrap_or(false) {
+            return;
+        }
+    }
+    tracing::warn!(zone = %origin, secondary = %secondary, "secondary didn't acknowledge the NOTIFY");
+}
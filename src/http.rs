@@ -0,0 +1,112 @@
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// An HTTP request of the local APIs, read whole.
+#[derive(Debug)]
+pub struct Request {
+    /// Request line and headers.
+    head: String,
+    /// Body, as long as the `Content-Length` header says.
+    #[cfg_attr(not(feature = "acme"), allow(dead_code))]
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads a request from `stream`: the headers, then as much of the
+    /// body as they announce. Returns `None` if the connection is closed
+    /// first, or if the request is larger than `max` bytes.
+    pub async fn read(stream: &mut TcpStream, max: usize) -> std::io::Result<Option<Self>> {
+        let mut request = Vec::new();
+        let mut buf     = [0u8; 512];
+
+        let head_len = loop {
+            if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+            let n = stream.read(&mut buf).await?;
+            if n == 0 || request.len() + n > max {
+                return Ok(None);
+            }
+            request.extend_from_slice(&buf[..n]);
+        };
+        let head = String::from_utf8_lossy(&request[..head_len]).to_string();
+        let body_len = header(&head, "content-length")
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(0);
+        if head_len + body_len > max {
+            return Ok(None);
+        }
+        while request.len() < head_len + body_len {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+
+        let body = request[head_len..head_len + body_len].to_vec();
+        Ok(Some(Request { head, body }))
+    }
+
+    /// Returns the method, such as `GET`.
+    pub fn method(&self) -> &str {
+        self.head.split_whitespace().next().unwrap_or_default()
+    }
+
+    /// Returns the path of the target.
+    pub fn path(&self) -> &str {
+        self.head.split_whitespace().nth(1).unwrap_or_default()
+    }
+
+    /// Returns whether the request carries `token` as
+    /// `Authorization: Bearer <token>`.
+    pub fn bears(&self, token: &str) -> bool {
+        header(&self.head, "authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| same(given.trim().as_bytes(), token.as_bytes()))
+    }
+}
+
+/// Writes a response with a plain text body and closes the connection.
+pub async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Decodes the `%XX` escapes of a path segment. Returns `None` on a broken
+/// escape, or if the bytes decoded aren't UTF-8.
+pub fn percent_decode(segment: &str) -> Option<String> {
+    let mut out   = Vec::with_capacity(segment.len());
+    let mut bytes = segment.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            byte => out.push(byte),
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Returns the value of the header `name` of an HTTP request head.
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Compares two secrets in constant time.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,189 @@
+use crate::buffer::{escape_label, unescape_name};
+
+/// Prefix of the labels holding a Punycode encoded Unicode label.
+const ACE_PREFIX: &str = "xn--";
+
+// Parameters of the Punycode bootstring (RFC 3492, section 5)
+const BASE:         u32 = 36;
+const TMIN:         u32 = 1;
+const TMAX:         u32 = 26;
+const SKEW:         u32 = 38;
+const DAMP:         u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N:    u32 = 128;
+
+/// Converts a domain name to its ASCII form, lowercased and with every
+/// Unicode label replaced by its A-label (`xn--...`). The name is in
+/// presentation format: its `\DDD` escapes are resolved first, so that
+/// the UTF-8 bytes of a decoded query name are read as Unicode. Returns
+/// `None` if a label isn't UTF-8 or can't be encoded.
+pub fn to_ascii(name: &str) -> Option<String> {
+    let mut labels = unescape_name(name).ok()?;
+    if labels.len() > 1 && labels.last().is_some_and(Vec::is_empty) {
+        labels.pop();
+    }
+
+    let labels: Option<Vec<String>> = labels
+        .into_iter()
+        .map(|label| {
+            let label = String::from_utf8(label).ok()?.to_lowercase();
+            if label.is_ascii() {
+                Some(escape_label(label.as_bytes()))
+            } else {
+                let chars: Vec<char> = label.chars().collect();
+                encode(&chars).map(|encoded| format!("{}{}", ACE_PREFIX, encoded))
+            }
+        })
+        .collect();
+    labels.map(|labels| labels.join("."))
+}
+
+/// Converts a domain name to its Unicode form, decoding the A-labels.
+/// Labels that are not valid Punycode are kept as they are.
+pub fn to_unicode(name: &str) -> String {
+    name.trim_end_matches('.')
+        .split('.')
+        .map(|label| {
+            let lower = label.to_ascii_lowercase();
+            lower
+                .strip_prefix(ACE_PREFIX)
+                .and_then(decode)
+                .map(|chars| chars.into_iter().collect())
+                .unwrap_or(lower)
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// Returns the threshold of the digit at position `k`.
+fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        TMIN
+    } else if k >= bias + TMAX {
+        TMAX
+    } else {
+        k - bias
+    }
+}
+
+/// Adapts the bias after a code point is encoded or decoded.
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+/// Returns the character of a Punycode digit.
+fn digit(d: u32) -> char {
+    match d {
+        0..=25 => (b'a' + d as u8) as char,
+        _      => (b'0' + (d - 26) as u8) as char,
+    }
+}
+
+/// Returns the value of a Punycode digit.
+fn value(c: char) -> Option<u32> {
+    match c {
+        'a'..='z' => Some(c as u32 - 'a' as u32),
+        'A'..='Z' => Some(c as u32 - 'A' as u32),
+        '0'..='9' => Some(c as u32 - '0' as u32 + 26),
+        _         => None,
+    }
+}
+
+/// Encodes a Unicode label with Punycode, without the ACE prefix.
+fn encode(input: &[char]) -> Option<String> {
+    let mut output: String = input.iter().filter(|c| c.is_ascii()).collect();
+    let basic = output.len() as u32;
+    let mut handled = basic;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n     = INITIAL_N;
+    let mut delta = 0u32;
+    let mut bias  = INITIAL_BIAS;
+
+    while (handled as usize) < input.len() {
+        let m = input.iter().map(|&c| c as u32).filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+
+        for &c in input {
+            let c = c as u32;
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+
+        delta = delta.checked_add(1)?;
+        n += 1;
+    }
+
+    Some(output)
+}
+
+/// Decodes a Punycode label, without the ACE prefix.
+fn decode(input: &str) -> Option<Vec<char>> {
+    let (basic, extended) = match input.rfind('-') {
+        Some(i) => (&input[..i], &input[i + 1..]),
+        None    => ("", input),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut n    = INITIAL_N;
+    let mut i    = 0u32;
+    let mut bias = INITIAL_BIAS;
+    let mut digits = extended.chars().peekable();
+
+    while digits.peek().is_some() {
+        let old = i;
+        let mut w = 1u32;
+        let mut k = BASE;
+        loop {
+            let d = value(digits.next()?)?;
+            i = i.checked_add(d.checked_mul(w)?)?;
+            let t = threshold(k, bias);
+            if d < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old, len, old == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+
+    Some(output)
+}
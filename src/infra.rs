@@ -0,0 +1,208 @@
+use crate::metrics::Metrics;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Smallest UDP payload size every server must support.
+pub const MIN_UDP_SIZE: u16 = 512;
+
+/// Largest UDP payload size the resolver is able to receive.
+pub const MAX_UDP_SIZE: u16 = 4096;
+
+/// How long a server is queried with a reduced payload size, or without
+/// EDNS, before the configured size is probed again.
+const PROBE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Number of consecutive failures after which a server is held down.
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// Hold-down time once the failure threshold is reached.
+const BASE_HOLD_DOWN: Duration = Duration::from_secs(5);
+
+/// Longest hold-down time of a failing server.
+const MAX_HOLD_DOWN: Duration = Duration::from_secs(900);
+
+/// What the resolver learned about a single upstream server.
+#[derive(Debug, Clone, Copy)]
+struct ServerInfo {
+    /// UDP payload size to advertise to the server.
+    udp_size: u16,
+    /// Whether the server handles EDNS.
+    edns: bool,
+    /// When the payload size was last reduced, or EDNS disabled.
+    downgraded: Instant,
+}
+
+/// Penalty of a server that failed consecutive queries.
+#[derive(Debug, Clone, Copy)]
+struct Penalty {
+    /// Number of consecutive failures.
+    failures: u32,
+    /// The server is not queried until then.
+    until: Instant,
+}
+
+/// Infrastructure cache: knowledge about the upstream servers, shared
+/// across resolutions.
+#[derive(Debug)]
+pub struct InfraCache {
+    max_udp_size: u16,
+    servers:      Mutex<HashMap<IpAddr, ServerInfo>>,
+    /// Penalties of the servers, for every zone (failures to answer) or
+    /// for a single one (lame and error responses).
+    penalties:    Mutex<HashMap<(IpAddr, Option<String>), Penalty>>,
+    metrics:      Arc<Metrics>,
+}
+
+impl InfraCache {
+    /// Creates a new cache, advertising at most `max_udp_size` bytes.
+    pub fn new(max_udp_size: u16, metrics: Arc<Metrics>) -> Self {
+        InfraCache {
+            max_udp_size: max_udp_size.clamp(MIN_UDP_SIZE, MAX_UDP_SIZE),
+            servers:      Mutex::new(HashMap::new()),
+            penalties:    Mutex::new(HashMap::new()),
+            metrics,
+        }
+    }
+
+    /// Returns the largest UDP payload size allowed by the configuration.
+    pub fn max_udp_size(&self) -> u16 {
+        self.max_udp_size
+    }
+
+    /// Returns the UDP payload size to advertise to `server`.
+    ///
+    /// Once the probe interval has elapsed since the last downgrade, the
+    /// configured size is tried again.
+    pub fn udp_size(&self, server: IpAddr) -> u16 {
+        let mut servers = self.servers.lock().unwrap();
+        match servers.get(&server) {
+            Some(info) if info.downgraded.elapsed() < PROBE_INTERVAL => info.udp_size,
+            Some(_) => {
+                servers.remove(&server);
+                self.max_udp_size
+            }
+            None => self.max_udp_size,
+        }
+    }
+
+    /// Returns whether to send EDNS queries to `server`.
+    ///
+    /// Like the payload size, EDNS is probed again once the probe interval
+    /// has elapsed since it was found broken.
+    pub fn edns(&self, server: IpAddr) -> bool {
+        self.servers
+            .lock()
+            .unwrap()
+            .get(&server)
+            .is_none_or(|info| info.edns || info.downgraded.elapsed() >= PROBE_INTERVAL)
+    }
+
+    /// Records that `server` mishandles EDNS, so that it is queried without
+    /// it from now on.
+    pub fn disable_edns(&self, server: IpAddr) {
+        self.servers.lock().unwrap().insert(
+            server,
+            ServerInfo {
+                udp_size:   MIN_UDP_SIZE,
+                edns:       false,
+                downgraded: Instant::now(),
+            },
+        );
+    }
+
+    /// Records that a query advertising `udp_size` bytes to `server` timed
+    /// out, which often means that large fragmented responses are dropped
+    /// along the path. Returns the reduced size to retry with, if any.
+    pub fn downgrade(&self, server: IpAddr, udp_size: u16) -> Option<u16> {
+        if udp_size <= MIN_UDP_SIZE {
+            return None;
+        }
+
+        let reduced = (udp_size / 2).max(MIN_UDP_SIZE);
+        self.servers.lock().unwrap().insert(
+            server,
+            ServerInfo {
+                udp_size:   reduced,
+                edns:       true,
+                downgraded: Instant::now(),
+            },
+        );
+        Some(reduced)
+    }
+
+    /// Returns whether `server` is in the penalty box, for every zone or
+    /// for `zone`.
+    ///
+    /// Once the hold-down time has elapsed the server can be queried again;
+    /// the outcome of that query decides whether it is rehabilitated.
+    pub fn is_held_down(&self, server: IpAddr, zone: &str) -> bool {
+        let penalties = self.penalties.lock().unwrap();
+        let now = Instant::now();
+        [None, Some(zone_key(zone))]
+            .into_iter()
+            .filter_map(|zone| penalties.get(&(server, zone)))
+            .any(|penalty| penalty.until > now)
+    }
+
+    /// Records a failure of `server` to answer at all (a timeout or a
+    /// malformed response), which holds it down for every zone.
+    pub fn record_failure(&self, server: IpAddr) {
+        self.penalize((server, None));
+    }
+
+    /// Records a lame or error response of `server` for `zone`, which only
+    /// holds it down for that zone: it may serve others just fine.
+    pub fn record_lame(&self, server: IpAddr, zone: &str) {
+        self.penalize((server, Some(zone_key(zone))));
+    }
+
+    /// Counts a consecutive failure under `key`. The server is held down
+    /// once the failures reach the threshold, for a time doubling at every
+    /// further one.
+    fn penalize(&self, key: (IpAddr, Option<String>)) {
+        let mut penalties = self.penalties.lock().unwrap();
+        let now = Instant::now();
+
+        // Forget the servers whose penalty expired long ago
+        if penalties.len() > 4096 {
+            penalties.retain(|_, penalty| penalty.until + MAX_HOLD_DOWN > now);
+        }
+
+        let penalty = penalties.entry(key).or_insert(Penalty {
+            failures: 0,
+            until:    now,
+        });
+        penalty.failures += 1;
+
+        if let Some(excess) = penalty.failures.checked_sub(FAILURE_THRESHOLD) {
+            let factor = 1u32 << excess.min(16);
+            penalty.until = now + BASE_HOLD_DOWN.saturating_mul(factor).min(MAX_HOLD_DOWN);
+        }
+    }
+
+    /// Records that `count` queries raced against one answered first were
+    /// sent for nothing.
+    pub fn record_wasted(&self, count: usize) {
+        if count > 0 {
+            self.metrics.record_wasted_queries(count as u64);
+        }
+    }
+
+    /// Records a successful query to `server` for `zone`, clearing its
+    /// penalties for every zone and for that one.
+    pub fn record_success(&self, server: IpAddr, zone: &str) {
+        let mut penalties = self.penalties.lock().unwrap();
+        penalties.remove(&(server, None));
+        penalties.remove(&(server, Some(zone_key(zone))));
+    }
+}
+
+/// Returns the form of `zone` the penalties are kept under: lowercase,
+/// without the trailing dot.
+fn zone_key(zone: &str) -> String {
+    zone.trim_end_matches('.').to_ascii_lowercase()
+}
@@ -0,0 +1,55 @@
+use crate::{
+    routing::{DomainRule, Route},
+    types::DnsError,
+};
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+
+/// Cluster domain assumed when the search list doesn't reveal it.
+const DEFAULT_CLUSTER_DOMAIN: &str = "cluster.local";
+
+/// Builds the rule forwarding the service names of the cluster to the
+/// cluster DNS, learned from the resolv.conf of the pod at `path`.
+///
+/// The cluster DNS is the first `nameserver` of the file, and the cluster
+/// domain follows the `svc.` entry of its `search` list, as written by the
+/// kubelet (`default.svc.cluster.local svc.cluster.local cluster.local`).
+pub fn service_rule(path: &Path) -> Result<DomainRule, DnsError> {
+    let conf = fs::read_to_string(path)
+        .map_err(|e| DnsError::IOError(format!("can't read {}: {}", path.display(), e)))?;
+
+    let mut server = None;
+    let mut domain = None;
+    for line in conf.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("nameserver") if server.is_none() => server = words.next().and_then(parse_server),
+            Some("search") => {
+                domain = words
+                    .find_map(|name| name.trim_end_matches('.').strip_prefix("svc."))
+                    .map(str::to_ascii_lowercase)
+                    .or(domain);
+            }
+            _ => (),
+        }
+    }
+
+    let server = server
+        .ok_or_else(|| DnsError::IOError(format!("no cluster DNS found in {}", path.display())))?;
+    let domain = domain.as_deref().unwrap_or(DEFAULT_CLUSTER_DOMAIN);
+    Ok(DomainRule {
+        domain: format!("svc.{}", domain),
+        route:  Route::Udp(server),
+    })
+}
+
+/// Parses the address of a name server, on port 53 unless it comes with
+/// one of its own.
+fn parse_server(s: &str) -> Option<SocketAddr> {
+    s.parse()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 53)))
+}
@@ -0,0 +1,88 @@
+//! A recursive DNS resolver, as a library.
+//!
+//! The `dns-resolver` binary is a thin wrapper over [`run`]. Other programs
+//! can embed the resolver instead, looking names up through a [`Resolver`]
+//! built from a [`Config`], and encode or decode messages with [`Dns`] and
+//! its buffers.
+
+#![allow(clippy::upper_case_acronyms)]
+
+#[cfg(feature = "acme")]
+mod acme;
+#[cfg(feature = "admin")]
+mod admin;
+#[cfg(feature = "zone-transfers")]
+mod axfr;
+mod buffer;
+mod cache;
+mod clock;
+mod compare;
+mod config;
+#[cfg(feature = "consul")]
+mod consul;
+mod contact;
+mod diagnostics;
+mod dns;
+mod dnstap;
+#[cfg(feature = "doh")]
+mod doh;
+#[cfg(feature = "doq")]
+mod doq;
+#[cfg(feature = "dot")]
+mod dot;
+mod dynamic;
+#[cfg(feature = "health")]
+mod health;
+mod hints;
+mod hosted;
+#[cfg(any(feature = "admin", feature = "acme"))]
+mod http;
+mod idna;
+mod infra;
+mod kubernetes;
+mod local;
+mod logging;
+mod lookup;
+mod metrics;
+mod network;
+mod pacer;
+mod peer;
+#[cfg(feature = "wasm-plugins")]
+mod plugins;
+mod policy;
+mod privacy;
+mod proxy;
+mod ptr;
+mod query;
+mod querylog;
+mod replay;
+mod resolver;
+mod rng;
+mod routing;
+mod rrl;
+mod server;
+#[cfg(feature = "sig0")]
+mod sig0;
+mod sinkhole;
+mod slowlog;
+mod sockets;
+mod special;
+#[cfg(feature = "zone-transfers")]
+mod stream;
+mod supervisor;
+mod tcp;
+mod timeouts;
+#[cfg(any(feature = "dot", feature = "doq"))]
+mod tls;
+mod types;
+mod unix;
+mod update;
+mod zone;
+
+pub use config::Config;
+pub use lookup::{RecordWatch, Resolver};
+pub use server::run;
+pub use types::{
+    AnswerRecord, Dns, DnsBufferError, DnsError, DnsReadBuffer, DnsWriteBuffer, EdnsOption, Flags, Header, OptRecord,
+    QueryRecord, RData, Soa, SvcParam, Svcb, Type,
+};
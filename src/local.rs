@@ -0,0 +1,270 @@
+use crate::types::{AnswerRecord, DnsError, RData, Type};
+use std::{
+    collections::HashMap,
+    fs,
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+};
+
+/// TTL of the locally served records.
+const LOCAL_TTL: u32 = 300;
+
+/// A static record from the configuration, written as `name=address`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalRecord {
+    /// Owner name of the record.
+    pub name: String,
+    /// Address the name resolves to.
+    pub addr: IpAddr,
+}
+
+impl FromStr for LocalRecord {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::IOError(format!("invalid local record: {}", s));
+        let (name, addr) = s.split_once('=').ok_or_else(invalid)?;
+        Ok(LocalRecord {
+            name: normalize(name),
+            addr: addr.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// How the names of a local zone without local records are answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalZoneType {
+    /// They don't exist.
+    Static,
+    /// They are refused.
+    Refuse,
+    /// No name exists, not even those with local records.
+    Nxdomain,
+    /// Every name gets the local records of the apex.
+    Redirect,
+}
+
+impl FromStr for LocalZoneType {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "static"   => Ok(LocalZoneType::Static),
+            "refuse"   => Ok(LocalZoneType::Refuse),
+            "nxdomain" => Ok(LocalZoneType::Nxdomain),
+            "redirect" => Ok(LocalZoneType::Redirect),
+            _ => Err(DnsError::IOError(format!("invalid local zone type: {}", s))),
+        }
+    }
+}
+
+/// A domain answered locally along with its subdomains, never resolved,
+/// written as `name=type`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalZone {
+    /// Apex of the zone.
+    pub name: String,
+    /// How the names of the zone are answered.
+    pub ztype: LocalZoneType,
+}
+
+impl FromStr for LocalZone {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::IOError(format!("invalid local zone: {}", s));
+        let (name, ztype) = s.split_once('=').ok_or_else(invalid)?;
+        Ok(LocalZone {
+            name:  normalize(name),
+            ztype: ztype.trim().parse()?,
+        })
+    }
+}
+
+/// Records served locally, without touching the network.
+#[derive(Debug, Default, Clone)]
+pub struct LocalData {
+    /// Addresses of each name.
+    forward:        HashMap<String, Vec<IpAddr>>,
+    /// Names of each reverse lookup name, when synthesized.
+    reverse:        HashMap<String, Vec<String>>,
+    /// Names of each reverse lookup name, synthesized from the addresses
+    /// of the hosted zones.
+    hosted:         HashMap<String, Vec<String>>,
+    /// Type of each local zone, by apex.
+    zones:          HashMap<String, LocalZoneType>,
+    /// Whether the PTR records are synthesized.
+    synthesize_ptr: bool,
+}
+
+impl LocalData {
+    /// Builds the local data from the static records.
+    ///
+    /// With `synthesize_ptr`, a PTR record is derived from every A/AAAA
+    /// record, so that forward and reverse lookups stay consistent.
+    pub fn new(records: &[LocalRecord], zones: &[LocalZone], synthesize_ptr: bool) -> Self {
+        let mut data = LocalData { synthesize_ptr, ..LocalData::default() };
+        for record in records {
+            data.insert(record, synthesize_ptr);
+        }
+        data.zones = zones.iter().map(|zone| (zone.name.clone(), zone.ztype)).collect();
+        data
+    }
+
+    /// Returns a copy of the local data whose PTR records synthesized from
+    /// the hosted zones are those of `addresses`, the A/AAAA records of
+    /// the zones. Their forward records aren't served locally.
+    ///
+    /// Nothing is synthesized unless the local data was built with
+    /// `synthesize_ptr`.
+    pub fn with_zone_addresses(&self, addresses: &[LocalRecord]) -> Self {
+        let mut hosted: HashMap<String, Vec<String>> = HashMap::new();
+        for record in addresses.iter().filter(|_| self.synthesize_ptr) {
+            let names = hosted.entry(reverse_name(record.addr)).or_default();
+            if !names.contains(&record.name) {
+                names.push(record.name.clone());
+            }
+        }
+        LocalData { hosted, ..self.clone() }
+    }
+
+    /// Returns the innermost local zone holding `name`, with its type.
+    fn zone<'a>(&self, name: &'a str) -> Option<(&'a str, LocalZoneType)> {
+        std::iter::successors(Some(name), |name| name.split_once('.').map(|(_, parent)| parent))
+            .find_map(|apex| self.zones.get(apex).map(|ztype| (apex, *ztype)))
+    }
+
+    /// Returns the response code of a name without local records, when it
+    /// is within a local zone: the name doesn't exist, or is refused.
+    ///
+    /// A name of a static zone above names with local records exists, and
+    /// gets an empty answer instead.
+    pub fn rcode(&self, qname: &str) -> Option<u16> {
+        let name = normalize(qname);
+        let (_, ztype) = self.zone(&name)?;
+        let suffix = format!(".{}", name);
+        let parent = self
+            .forward
+            .keys()
+            .chain(self.reverse.keys())
+            .chain(self.hosted.keys())
+            .any(|owned| owned.ends_with(&suffix));
+        match ztype {
+            LocalZoneType::Refuse           => Some(5),
+            LocalZoneType::Static if parent => Some(0),
+            _                               => Some(3),
+        }
+    }
+
+    /// Adds a record to the local data.
+    fn insert(&mut self, record: &LocalRecord, synthesize_ptr: bool) {
+        let addrs = self.forward.entry(record.name.clone()).or_default();
+        if !addrs.contains(&record.addr) {
+            addrs.push(record.addr);
+        }
+
+        if synthesize_ptr {
+            let names = self.reverse.entry(reverse_name(record.addr)).or_default();
+            if !names.contains(&record.name) {
+                names.push(record.name.clone());
+            }
+        }
+    }
+
+    /// Answers a question from the local data, if the name is served
+    /// locally. A name that exists but has no records of the requested
+    /// type gets an empty answer.
+    ///
+    /// The names of redirect zones get the records of their apex, and those
+    /// of nxdomain zones none.
+    pub fn answer(&self, qname: &str, qtype: Type) -> Option<Vec<AnswerRecord>> {
+        let name = normalize(qname);
+        let name = match self.zone(&name) {
+            Some((_, LocalZoneType::Nxdomain))    => return None,
+            Some((apex, LocalZoneType::Redirect)) => apex.to_string(),
+            _                                     => name.clone(),
+        };
+
+        let rdata: Vec<RData> = if let Some(addrs) = self.forward.get(&name) {
+            addrs
+                .iter()
+                .filter_map(|addr| match (qtype, addr) {
+                    (Type::A, IpAddr::V4(ip))    => Some(RData::A(*ip)),
+                    (Type::AAAA, IpAddr::V6(ip)) => Some(RData::AAAA(*ip)),
+                    _ => None,
+                })
+                .collect()
+        } else if self.reverse.contains_key(&name) || self.hosted.contains_key(&name) {
+            let names = self.reverse.get(&name).into_iter().chain(self.hosted.get(&name)).flatten();
+            match qtype {
+                Type::PTR => names.map(|name| RData::PTR(name.clone())).collect(),
+                _ => Vec::new(),
+            }
+        } else {
+            return None;
+        };
+
+        Some(
+            rdata
+                .into_iter()
+                .map(|rdata| {
+                    let mut answer = AnswerRecord::new(qname.to_string(), rdata);
+                    answer.ttl = LOCAL_TTL;
+                    answer
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Reads the static records of a hosts file, such as `/etc/hosts`.
+///
+/// Each line gives an address followed by the names resolving to it,
+/// comments starting with `#`. Lines whose address can't be parsed are
+/// skipped.
+pub fn load_hosts(path: &Path) -> Result<Vec<LocalRecord>, DnsError> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| DnsError::IOError(format!("can't read the hosts file {}: {}", path.display(), e)))?;
+    Ok(parse_hosts(&text))
+}
+
+/// Extracts the static records of the text of a hosts file.
+fn parse_hosts(text: &str) -> Vec<LocalRecord> {
+    let mut records = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.split('#').next().unwrap_or_default().split_whitespace();
+        let Some(addr) = fields.next().and_then(|addr| addr.parse().ok()) else {
+            continue;
+        };
+        records.extend(fields.map(|name| LocalRecord { name: normalize(name), addr }));
+    }
+    records
+}
+
+/// Returns the name used for reverse lookups of an address, under
+/// `in-addr.arpa` for IPv4 and `ip6.arpa` (one label per nibble) for IPv6.
+pub fn reverse_name(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let mut labels: Vec<String> = ip
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|byte| [byte & 0x0f, byte >> 4])
+                .map(|nibble| format!("{:x}", nibble))
+                .collect();
+            labels.push("ip6.arpa".to_string());
+            labels.join(".")
+        }
+    }
+}
+
+/// Lowercases a name and strips its trailing dot.
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
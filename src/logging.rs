@@ -0,0 +1,447 @@
+use crate::types::DnsError;
+use std::{
+    fmt,
+    fs,
+    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
+    process,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    EnvFilter, Layer, Registry,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+/// Name the resolver identifies itself with in the logs.
+const APP_NAME: &str = "dns-resolver";
+
+/// Socket the local syslog daemon listens on.
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Socket of the journald native protocol.
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Private enterprise number used for the RFC 5424 structured data ID.
+const SD_ID: &str = "dnsr@32473";
+
+/// Severity of a log message, numbered as in RFC 5424.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error   = 3,
+    Warning = 4,
+    Info    = 6,
+    Debug   = 7,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Error   => write!(f, "ERROR"),
+            Level::Warning => write!(f, "WARN"),
+            Level::Info    => write!(f, "INFO"),
+            Level::Debug   => write!(f, "DEBUG"),
+        }
+    }
+}
+
+impl From<tracing::Level> for Level {
+    fn from(level: tracing::Level) -> Self {
+        match level {
+            tracing::Level::ERROR => Level::Error,
+            tracing::Level::WARN  => Level::Warning,
+            tracing::Level::INFO  => Level::Info,
+            _                     => Level::Debug,
+        }
+    }
+}
+
+/// Where the syslog messages are sent to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogAddr {
+    /// Local datagram socket, usually `/dev/log`.
+    Unix(PathBuf),
+    /// Remote collector over UDP.
+    Udp(SocketAddr),
+}
+
+/// Output target of the log messages.
+///
+/// Parsed from `stderr`, `journald`, `syslog` (local socket),
+/// `syslog:<path>` or `syslog:udp:<addr>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogTarget {
+    Stderr,
+    Syslog(SyslogAddr),
+    Journald,
+}
+
+impl FromStr for LogTarget {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::IOError(format!("invalid log target: {}", s));
+        match s {
+            "stderr"   => Ok(LogTarget::Stderr),
+            "journald" => Ok(LogTarget::Journald),
+            "syslog"   => Ok(LogTarget::Syslog(SyslogAddr::Unix(SYSLOG_SOCKET.into()))),
+            _ => match s.strip_prefix("syslog:") {
+                Some(rest) => match rest.strip_prefix("udp:") {
+                    Some(addr) => addr
+                        .parse()
+                        .map(|addr| LogTarget::Syslog(SyslogAddr::Udp(addr)))
+                        .map_err(|_| invalid()),
+                    None => Ok(LogTarget::Syslog(SyslogAddr::Unix(rest.into()))),
+                },
+                None => Err(invalid()),
+            },
+        }
+    }
+}
+
+/// Format of the messages written to stderr.
+///
+/// Parsed from `text`, one human readable line per message, or `json`,
+/// one JSON object per line for the log collectors of container platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(DnsError::IOError(format!("invalid log format: {}", s))),
+        }
+    }
+}
+
+/// Filter of the log messages, in the syntax of `RUST_LOG`: a default
+/// level and the levels of some modules, such as
+/// `warn,dns_resolver::query=debug`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter(String);
+
+impl FromStr for LogFilter {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EnvFilter::builder()
+            .parse(s)
+            .map(|_| LogFilter(s.to_string()))
+            .map_err(|_| DnsError::IOError(format!("invalid log filter: {}", s)))
+    }
+}
+
+/// Socket the messages are written to.
+enum Sink {
+    Stderr,
+    Udp(UdpSocket, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixDatagram, PathBuf),
+}
+
+/// Layer writing the events to the configured target, with the fields of
+/// the spans they happen in.
+struct Logger {
+    target:   LogTarget,
+    format:   LogFormat,
+    sink:     Sink,
+    hostname: String,
+}
+
+/// Installs the global subscriber, logging the messages `filter` lets
+/// through. Until then, nothing is logged.
+pub fn init(target: &LogTarget, format: LogFormat, filter: &LogFilter) -> Result<(), DnsError> {
+    let sink = match target {
+        LogTarget::Stderr => Sink::Stderr,
+        LogTarget::Syslog(SyslogAddr::Udp(addr)) => {
+            let bind = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+            let sock = UdpSocket::bind(bind).map_err(|_| DnsError::SocketError)?;
+            Sink::Udp(sock, *addr)
+        }
+        #[cfg(unix)]
+        LogTarget::Syslog(SyslogAddr::Unix(path)) => {
+            Sink::Unix(UnixDatagram::unbound().map_err(|_| DnsError::SocketError)?, path.clone())
+        }
+        #[cfg(unix)]
+        LogTarget::Journald => {
+            Sink::Unix(UnixDatagram::unbound().map_err(|_| DnsError::SocketError)?, JOURNALD_SOCKET.into())
+        }
+        #[cfg(not(unix))]
+        _ => return Err(DnsError::IOError("log target not supported on this platform".into())),
+    };
+
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_string());
+
+    let logger = Logger { target: target.clone(), format, sink, hostname };
+    let filter = EnvFilter::builder()
+        .parse(&filter.0)
+        .map_err(|_| DnsError::IOError(format!("invalid log filter: {}", filter.0)))?;
+    tracing::subscriber::set_global_default(Registry::default().with(filter).with(logger))
+        .map_err(|_| DnsError::IOError("logger already initialized".into()))
+}
+
+impl Logger {
+    /// Writes a message with some structured fields attached.
+    fn write(&self, level: Level, message: &str, fields: &[(&str, String)]) {
+        let data = match self.target {
+            LogTarget::Stderr    => match self.format {
+                LogFormat::Text => format_stderr(level, message, fields).into_bytes(),
+                LogFormat::Json => format_json(level, message, fields).into_bytes(),
+            },
+            LogTarget::Syslog(_) => format_syslog(level, message, fields, &self.hostname).into_bytes(),
+            LogTarget::Journald  => format_journald(level, message, fields),
+        };
+
+        // Logging must never take the server down: fall back to stderr
+        let sent = match &self.sink {
+            Sink::Stderr => {
+                eprintln!("{}", String::from_utf8_lossy(&data));
+                true
+            }
+            Sink::Udp(sock, addr) => sock.send_to(&data, addr).is_ok(),
+            #[cfg(unix)]
+            Sink::Unix(sock, path) => sock.send_to(&data, path).is_ok(),
+        };
+        if !sent {
+            eprintln!("{}", format_stderr(level, message, fields));
+        }
+    }
+}
+
+impl<S> Layer<S> for Logger
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id)
+            && let Some(fields) = span.extensions_mut().get_mut::<Fields>()
+        {
+            values.record(fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut own = Fields::default();
+        event.record(&mut own);
+
+        // The fields of the spans come first, from the outermost one, unless
+        // the event sets them itself
+        let mut fields = Vec::new();
+        for span in ctx.event_scope(event).into_iter().flat_map(|scope| scope.from_root()) {
+            if let Some(outer) = span.extensions().get::<Fields>() {
+                fields.extend(outer.values.iter().filter(|(key, _)| !own.contains(key)).cloned());
+            }
+        }
+        fields.extend(own.values);
+
+        self.write(Level::from(*event.metadata().level()), &own.message, &fields);
+    }
+}
+
+/// Message and fields of an event or a span, formatted.
+#[derive(Default)]
+struct Fields {
+    message: String,
+    values:  Vec<(&'static str, String)>,
+}
+
+impl Fields {
+    /// Whether a field is set.
+    fn contains(&self, key: &str) -> bool {
+        self.values.iter().any(|(name, _)| *name == key)
+    }
+
+    /// Sets a field, replacing its previous value if any.
+    fn set(&mut self, field: &Field, value: String) {
+        if field.name() == "message" {
+            self.message = value;
+        } else if let Some(slot) = self.values.iter_mut().find(|(name, _)| *name == field.name()) {
+            slot.1 = value;
+        } else {
+            self.values.push((field.name(), value));
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field, value.to_string())
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.set(field, format!("{:?}", value))
+    }
+}
+
+/// Formats a message as a single human readable line.
+fn format_stderr(level: Level, message: &str, fields: &[(&str, String)]) -> String {
+    let mut line = format!("{} {}", level, message);
+    for (key, value) in fields {
+        line.push_str(&format!(" {}={}", key, value));
+    }
+    line
+}
+
+/// Formats a message as a single line JSON object, with the fields next
+/// to the timestamp, the level and the message.
+fn format_json(level: Level, message: &str, fields: &[(&str, String)]) -> String {
+    let mut line = format!(
+        "{{\"time\":\"{}\",\"level\":\"{}\",\"message\":\"{}\"",
+        rfc3339_now(),
+        level,
+        escape_json(message),
+    );
+    for (key, value) in fields {
+        line.push_str(&format!(",\"{}\":\"{}\"", escape_json(key), escape_json(value)));
+    }
+    line.push('}');
+    line
+}
+
+/// Escapes a string to be placed between quotes in a JSON document.
+fn escape_json(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"'  => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Formats a message as an RFC 5424 syslog line, using the daemon facility
+/// and carrying the fields as structured data.
+fn format_syslog(
+    level:    Level,
+    message:  &str,
+    fields:   &[(&str, String)],
+    hostname: &str,
+) -> String {
+    let priority = 3 * 8 + level as u8;
+
+    let data = if fields.is_empty() {
+        "-".to_string()
+    } else {
+        let params: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape_sd(value)))
+            .collect();
+        format!("[{} {}]", SD_ID, params.join(" "))
+    };
+
+    format!(
+        "<{}>1 {} {} {} {} - {} {}",
+        priority,
+        rfc3339_now(),
+        hostname,
+        APP_NAME,
+        process::id(),
+        data,
+        message,
+    )
+}
+
+/// Escapes a structured data parameter value as required by RFC 5424.
+fn escape_sd(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Formats a message for the journald native protocol, turning each
+/// field into an uppercase journal field.
+fn format_journald(level: Level, message: &str, fields: &[(&str, String)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    push_journal_field(&mut out, "MESSAGE", message);
+    push_journal_field(&mut out, "PRIORITY", &(level as u8).to_string());
+    push_journal_field(&mut out, "SYSLOG_IDENTIFIER", APP_NAME);
+    for (key, value) in fields {
+        let key: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        push_journal_field(&mut out, &key, value);
+    }
+    out
+}
+
+/// Appends a field in the journald wire format, switching to the binary
+/// safe encoding when the value spans multiple lines.
+fn push_journal_field(out: &mut Vec<u8>, key: &str, value: &str) {
+    out.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        out.push(b'\n');
+        out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        out.push(b'=');
+    }
+    out.extend_from_slice(value.as_bytes());
+    out.push(b'\n');
+}
+
+/// Returns the current UTC time in the RFC 3339 format.
+pub fn rfc3339_now() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // Convert the days since the epoch into a civil date
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        now.subsec_micros(),
+    )
+}
@@ -0,0 +1,582 @@
+use crate::{
+    cache::{Cache, CacheKey},
+    clock::{Clock, SystemClock},
+    config::Config,
+    hints,
+    infra::InfraCache,
+    kubernetes,
+    metrics::Metrics,
+    pacer::Pacer,
+    peer::Gossip,
+    privacy::Privacy,
+    ptr::ReversePath,
+    resolver::{forward, is_apex, resolve, resolve_apex, Context, UpstreamFamily},
+    rng::DnsRng,
+    routing::{Route, Routes},
+    sockets::SocketPool,
+    special,
+    timeouts::Timeouts,
+    types::{AnswerRecord, DnsError, RData, Type},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+#[cfg(feature = "doh")]
+use crate::{doh::DohClient, resolver::forward_https};
+#[cfg(feature = "doq")]
+use crate::{doq::DoqClient, resolver::forward_quic};
+#[cfg(feature = "dot")]
+use crate::{dot::DotClient, resolver::forward_tls};
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinSet,
+    time,
+};
+
+/// Shortest delay between two refreshes of a watched record set.
+const WATCH_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Delay before refreshing a watched record set that failed to resolve or
+/// has no records, which leaves no TTL to go by.
+const WATCH_RETRY: Duration = Duration::from_secs(30);
+
+/// How long a name that failed to resolve is answered from its expired
+/// records, while it is refreshed in the background, before it is resolved
+/// again for a client (RFC 8767, section 4).
+const STALE_RECHECK: Duration = Duration::from_secs(30);
+
+/// Delay between the attempts to refresh a name answered stale.
+const STALE_RETRY: Duration = Duration::from_secs(5);
+
+/// Handle resolving names through the caches shared with the server.
+///
+/// Cloning it is cheap: the clones share the answer cache and everything
+/// learned about the upstream servers.
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    roots:           Arc<Vec<SocketAddr>>,
+    max_depth:       usize,
+    use_0x20:        bool,
+    delegation_port: u16,
+    fanout:          usize,
+    family:          UpstreamFamily,
+    bogus:           Arc<Vec<IpAddr>>,
+    cache:           Arc<Cache>,
+    infra:           Arc<InfraCache>,
+    pacer:           Arc<Pacer>,
+    /// Domain rules and timeouts, replaced when the configuration is
+    /// reloaded.
+    routes:          Arc<RwLock<Arc<Routes>>>,
+    timeouts:        Arc<RwLock<Arc<Timeouts>>>,
+    sockets:         Arc<SocketPool>,
+    #[cfg(feature = "doh")]
+    https:           DohClient,
+    #[cfg(feature = "doq")]
+    quic:            Arc<DoqClient>,
+    #[cfg(feature = "dot")]
+    tls:             Arc<DotClient>,
+    gossip:          Option<Arc<Gossip>>,
+    reverse:         Arc<ReversePath>,
+    /// Names answered stale while they are refreshed.
+    refreshing:      Arc<Mutex<HashSet<CacheKey>>>,
+    /// Outcome of the resolutions in progress, awaited by the identical
+    /// queries arriving meanwhile.
+    inflight:        Arc<Mutex<HashMap<CacheKey, Outcome>>>,
+}
+
+/// Outcome of a resolution, once it is over.
+type Outcome = watch::Receiver<Option<Result<Vec<AnswerRecord>, DnsError>>>;
+
+/// A resolution in progress, forgotten when it is over or abandoned.
+struct Flight {
+    key:      CacheKey,
+    inflight: Arc<Mutex<HashMap<CacheKey, Outcome>>>,
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        self.inflight.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl Resolver {
+    /// Creates a resolver from the configuration and the shared state.
+    pub(crate) fn new(
+        config:  &Config,
+        cache:   Arc<Cache>,
+        infra:   Arc<InfraCache>,
+        pacer:   Arc<Pacer>,
+        sockets: Arc<SocketPool>,
+        gossip:  Option<Arc<Gossip>>,
+    ) -> Result<Self, DnsError> {
+        // The root hints replace the single root server, with the addresses
+        // of the families the servers are contacted over
+        let roots = match &config.root_hints {
+            Some(path) => {
+                let roots: Vec<SocketAddr> = hints::load(path)?
+                    .into_iter()
+                    .filter(|ip| config.upstream_family.allows(*ip))
+                    .map(|ip| SocketAddr::from((ip, config.delegation_port)))
+                    .collect();
+                if roots.is_empty() {
+                    return Err(DnsError::IOError(format!(
+                        "no root server address of the upstream family in {}",
+                        path.display()
+                    )));
+                }
+                roots
+            }
+            None => vec![config.root],
+        };
+
+        let reverse = ReversePath::new(config.ptr_rate, config.ptr_negative_ttl, cache.metrics());
+        Ok(Resolver {
+            roots:           Arc::new(roots),
+            max_depth:       config.max_depth,
+            use_0x20:        config.use_0x20,
+            delegation_port: config.delegation_port,
+            fanout:          config.fanout,
+            family:          config.upstream_family,
+            bogus:           Arc::new(config.bogus_addresses.clone()),
+            cache,
+            infra,
+            pacer,
+            routes:          Arc::new(RwLock::new(Arc::new(routes(config)?))),
+            timeouts:        Arc::new(RwLock::new(Arc::new(Timeouts::new(&config.timeout_rules, config.query_policy)))),
+            sockets,
+            #[cfg(feature = "doh")]
+            https:           DohClient::new()?,
+            #[cfg(feature = "doq")]
+            quic:            Arc::new(DoqClient::new()?),
+            #[cfg(feature = "dot")]
+            tls:             Arc::new(DotClient::new(config.upstream_ca.as_deref())?),
+            gossip,
+            reverse:         Arc::new(reverse),
+            refreshing:      Arc::new(Mutex::new(HashSet::new())),
+            inflight:        Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Creates a resolver of its own, for the programs embedding it: the
+    /// cache, the upstream sockets and the rest of the shared state are
+    /// set up from the configuration as the server does, without peers or
+    /// dnstap output.
+    pub fn from_config(config: &Config) -> Result<Self, DnsError> {
+        let metrics = Arc::new(Metrics::new());
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock::new(config.clock_offset));
+        let cache   = Arc::new(Cache::new(Arc::clone(&metrics), clock, config.signature_skew, config.serve_stale));
+        let infra   = Arc::new(InfraCache::new(config.max_udp_size, metrics));
+        let pacer   = Arc::new(Pacer::new(config.outgoing_rate, config.outgoing_burst, config.outgoing_jitter));
+        let rng     = Arc::new(match config.rng_seed {
+            Some(seed) => DnsRng::from_seed(seed),
+            None       => DnsRng::from_entropy(),
+        });
+        let sockets = Arc::new(SocketPool::new(
+            config.outgoing_sockets,
+            config.socket_lifetime,
+            rng,
+            Privacy::new(config.privacy, config.privacy_delay),
+            None,
+        ));
+        Resolver::new(config, cache, infra, pacer, sockets, None)
+    }
+
+    /// Returns the knowledge about the upstream servers.
+    pub(crate) fn infra(&self) -> &InfraCache {
+        &self.infra
+    }
+
+    /// Replaces the domain rules and the timeouts with those of a
+    /// reloaded configuration, for the resolutions starting from now on.
+    #[cfg(feature = "admin")]
+    pub(crate) fn reconfigure(&self, config: &Config) -> Result<(), DnsError> {
+        let routes = routes(config)?;
+        *self.routes.write().unwrap()   = Arc::new(routes);
+        *self.timeouts.write().unwrap() = Arc::new(Timeouts::new(&config.timeout_rules, config.query_policy));
+        Ok(())
+    }
+
+    /// Returns how `name` is resolved.
+    pub(crate) fn route(&self, name: &str) -> Route {
+        self.routes.read().unwrap().route(name)
+    }
+
+    /// Returns the root servers the resolutions start from.
+    pub fn roots(&self) -> &[SocketAddr] {
+        &self.roots
+    }
+
+    /// Creates the context of a new resolution, starting from a random
+    /// root server among those that aren't held down.
+    pub(crate) fn context(&self) -> Context {
+        let mut roots: Vec<SocketAddr> = self
+            .roots
+            .iter()
+            .copied()
+            .filter(|root| !self.infra.is_held_down(root.ip(), ""))
+            .collect();
+        if roots.is_empty() {
+            roots = self.roots.to_vec();
+        }
+
+        Context::new(
+            roots[self.sockets.rng().index(roots.len())],
+            Arc::clone(&self.infra),
+            self.use_0x20,
+            self.delegation_port,
+            self.fanout,
+            self.family,
+            Arc::clone(&self.pacer),
+            Arc::clone(&self.timeouts.read().unwrap()),
+            Arc::clone(&self.sockets),
+        )
+    }
+
+    /// Looks up the `qtype` records of `name`, from the cache or with a
+    /// full resolution whose answers are then cached, and sent to the
+    /// peers if any. Reverse lookups take a path of their own.
+    ///
+    /// Identical lookups made while a name is being resolved share that
+    /// resolution rather than starting their own.
+    ///
+    /// A name that fails to resolve gets its expired records, if the cache
+    /// still has them, and is refreshed in the background; it gets them
+    /// without being resolved while it is.
+    pub(crate) async fn lookup(
+        &self,
+        name:      &str,
+        qtype:     Type,
+        dnssec_ok: bool,
+        ctx:       &Context,
+    ) -> Result<Vec<AnswerRecord>, DnsError> {
+        if qtype == Type::PTR && special::is_reverse(name) {
+            return self.lookup_reverse(name, ctx).await;
+        }
+
+        if let Some(outcome) = self.cache.get(name, qtype, dnssec_ok) {
+            return outcome;
+        }
+
+        let key        = CacheKey::new(name, qtype, dnssec_ok);
+        let refreshing = self.refreshing.lock().unwrap().contains(&key);
+        if refreshing && let Some(answers) = self.cache.get_stale(name, qtype, dnssec_ok) {
+            return Ok(answers);
+        }
+
+        // Wait for the identical resolution in progress, if any
+        let joined = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(&key) {
+                Some(outcome) => Err(outcome.clone()),
+                None          => {
+                    let (sender, outcome) = watch::channel(None);
+                    inflight.insert(key.clone(), outcome);
+                    Ok(sender)
+                }
+            }
+        };
+        let sender = match joined {
+            Ok(sender)       => sender,
+            Err(mut outcome) => {
+                return match outcome.wait_for(Option::is_some).await {
+                    Ok(outcome) => outcome.clone().unwrap_or(Err(DnsError::Timeout)),
+                    Err(_)      => Err(DnsError::IOError(format!("resolution of {} abandoned", name))),
+                };
+            }
+        };
+        let flight = Flight { key: key.clone(), inflight: Arc::clone(&self.inflight) };
+
+        let outcome = self.resolve_cached(name, qtype, dnssec_ok, key, ctx).await;
+        sender.send_replace(Some(outcome.clone()));
+        drop(flight);
+        outcome
+    }
+
+    /// Resolves the `qtype` records of `name` the way the clients of the
+    /// server get them: from the cache, or with a resolution starting from
+    /// the root, or along the route of the name.
+    pub async fn resolve(&self, name: &str, qtype: Type) -> Result<Vec<AnswerRecord>, DnsError> {
+        self.lookup(name, qtype, false, &self.context()).await
+    }
+
+    /// Resolves the `qtype` records of `name` and caches them, or the
+    /// negative answer, or returns its expired records if it fails.
+    async fn resolve_cached(
+        &self,
+        name:      &str,
+        qtype:     Type,
+        dnssec_ok: bool,
+        key:       CacheKey,
+        ctx:       &Context,
+    ) -> Result<Vec<AnswerRecord>, DnsError> {
+        let answers = match self.resolve_route(name, qtype, self.route(name), ctx).await {
+            Ok(answers) => answers,
+            Err(e @ DnsError::Negative { nxdomain, ttl }) => {
+                self.cache.insert_negative(name, qtype, dnssec_ok, nxdomain, ttl);
+                return Err(e);
+            }
+            Err(e)      => {
+                let Some(answers) = self.cache.get_stale(name, qtype, dnssec_ok) else {
+                    return Err(e);
+                };
+                tracing::warn!(qname = %name, qtype = %qtype, error = %e, "serving stale answer");
+                self.refresh(key);
+                return Ok(answers);
+            }
+        };
+
+        self.cache.insert(name, qtype, dnssec_ok, answers.clone());
+        if let Some(gossip) = &self.gossip {
+            gossip.publish(name, qtype, dnssec_ok, &answers).await;
+        }
+        Ok(answers)
+    }
+
+    /// Resolves a name answered stale again in the background, every few
+    /// seconds until it succeeds, caching its answers, or until the recheck
+    /// delay runs out.
+    fn refresh(&self, key: CacheKey) {
+        if !self.refreshing.lock().unwrap().insert(key.clone()) {
+            return;
+        }
+
+        let resolver = self.clone();
+        tokio::spawn(async move {
+            let deadline = Instant::now() + STALE_RECHECK;
+            while Instant::now() < deadline {
+                time::sleep(STALE_RETRY).await;
+                let ctx   = resolver.context();
+                let route = resolver.route(&key.qname);
+                if let Ok(answers) = resolver.resolve_route(&key.qname, key.qtype, route, &ctx).await {
+                    resolver.cache.insert(&key.qname, key.qtype, key.dnssec_ok, answers);
+                    break;
+                }
+            }
+            resolver.refreshing.lock().unwrap().remove(&key);
+        });
+    }
+
+    /// Looks up the PTR records of `name`, caching failures and empty
+    /// answers too, and rate limiting the resolutions.
+    async fn lookup_reverse(&self, name: &str, ctx: &Context) -> Result<Vec<AnswerRecord>, DnsError> {
+        if let Some(outcome) = self.reverse.get(name) {
+            return outcome;
+        }
+        if !self.reverse.admit() {
+            return Err(DnsError::IOError(format!("reverse lookup rate limit exceeded for {}", name)));
+        }
+
+        let outcome = self.resolve_route(name, Type::PTR, self.route(name), ctx).await;
+        self.reverse.insert(name, &outcome);
+        outcome
+    }
+
+    /// Looks up the `qtype` records of `name` along the given route rather
+    /// than its own, such as the one a policy plugin chose for a client.
+    /// The answers bypass the cache, which holds those of the name's own
+    /// route.
+    #[cfg_attr(not(feature = "wasm-plugins"), allow(dead_code))]
+    pub(crate) async fn lookup_via(
+        &self,
+        name:  &str,
+        qtype: Type,
+        route: Route,
+        ctx:   &Context,
+    ) -> Result<Vec<AnswerRecord>, DnsError> {
+        self.resolve_route(name, qtype, route, ctx).await
+    }
+
+    /// Resolves the `qtype` records of `name` along `route`.
+    ///
+    /// The duplicate records are dropped, and so are the bogus addresses
+    /// found in the public DNS: the resolvers the queries are forwarded to
+    /// may answer them on purpose, to block names.
+    async fn resolve_route(&self, name: &str, qtype: Type, route: Route, ctx: &Context) -> Result<Vec<AnswerRecord>, DnsError> {
+        let records = match route {
+            Route::Never => {
+                return Err(DnsError::IOError(format!("resolution of {} is not allowed", name)));
+            }
+            Route::Udp(upstream) => forward(name, qtype, upstream, ctx).await?,
+            #[cfg(feature = "doh")]
+            Route::Https(url)    => forward_https(name, qtype, &url, &self.https, ctx).await?,
+            #[cfg(feature = "doq")]
+            Route::Quic(upstream, server) => forward_quic(name, qtype, upstream, &server, &self.quic, ctx).await?,
+            #[cfg(feature = "dot")]
+            Route::Tls(upstream, server)  => forward_tls(name, qtype, upstream, &server, &self.tls, ctx).await?,
+            #[cfg(not(all(feature = "doh", feature = "doq", feature = "dot")))]
+            #[allow(unreachable_patterns)]
+            Route::Https(_) | Route::Quic(..) | Route::Tls(..) => {
+                return Err(DnsError::IOError(format!("route of {} not supported by this build", name)));
+            }
+            Route::Iterate if is_apex(name) => resolve_apex(name, qtype, self.max_depth, ctx).await?,
+            Route::Iterate => {
+                let mut records = resolve(name, qtype, ctx.root(), self.max_depth, ctx).await?;
+                records.retain(|rdata| !is_bogus(rdata, &self.bogus));
+                records
+            }
+        };
+
+        let mut unique: Vec<RData> = Vec::with_capacity(records.len());
+        for rdata in records {
+            if !unique.contains(&rdata) {
+                unique.push(rdata);
+            }
+        }
+
+        Ok(unique
+            .into_iter()
+            .map(|rdata| AnswerRecord::new(name.to_string(), rdata))
+            .collect())
+    }
+
+    /// Returns the cached answers for `name`, without resolving it.
+    pub fn lookup_cached(&self, name: &str, qtype: Type, dnssec_ok: bool) -> Option<Vec<AnswerRecord>> {
+        if qtype == Type::PTR && special::is_reverse(name) {
+            return self.reverse.get(name).and_then(Result::ok);
+        }
+        self.cache.get(name, qtype, dnssec_ok).and_then(Result::ok)
+    }
+
+    /// Looks up a batch of `(name, qtype)` questions concurrently, returning
+    /// the outcome of each in the same order.
+    ///
+    /// Duplicate questions are resolved only once. The whole batch shares
+    /// a single time `budget`: the lookups still running when it runs out
+    /// fail with a timeout.
+    pub async fn lookup_many<I>(
+        &self,
+        questions: I,
+        budget:    Duration,
+    ) -> Vec<Result<Vec<AnswerRecord>, DnsError>>
+    where
+        I: IntoIterator<Item = (String, Type)>,
+    {
+        // Map every question to its first occurrence in the batch
+        let mut unique: Vec<(String, Type)> = Vec::new();
+        let slots: Vec<usize> = questions
+            .into_iter()
+            .map(|(name, qtype)| {
+                let key = (name.trim_end_matches('.').to_ascii_lowercase(), qtype);
+                unique.iter().position(|seen| *seen == key).unwrap_or_else(|| {
+                    unique.push(key);
+                    unique.len() - 1
+                })
+            })
+            .collect();
+
+        let deadline = time::Instant::now() + budget;
+        let mut tasks = JoinSet::new();
+        for (slot, (name, qtype)) in unique.iter().cloned().enumerate() {
+            let resolver = self.clone();
+            tasks.spawn(async move {
+                let ctx = resolver.context();
+                let res = time::timeout_at(deadline, resolver.lookup(&name, qtype, false, &ctx))
+                    .await
+                    .unwrap_or(Err(DnsError::Timeout));
+                (slot, res)
+            });
+        }
+
+        let mut results = vec![Err(DnsError::Timeout); unique.len()];
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok((slot, res)) => results[slot] = res,
+                Err(_) => continue,
+            }
+        }
+
+        slots.into_iter().map(|slot| results[slot].clone()).collect()
+    }
+
+    /// Watches the `rtype` records of `name`, re-resolving them whenever
+    /// their TTL expires.
+    ///
+    /// The watch first yields the current records, then the new ones each
+    /// time a refresh finds a set differing from the last. Failed refreshes
+    /// are retried without ending the watch. Dropping it stops refreshing.
+    pub fn watch(&self, name: &str, rtype: Type) -> RecordWatch {
+        let (sender, changes) = mpsc::channel(1);
+        let resolver = self.clone();
+        let name     = name.to_string();
+
+        tokio::spawn(async move {
+            let mut current: Option<Vec<AnswerRecord>> = None;
+            loop {
+                let ctx  = resolver.context();
+                let wait = match resolver.lookup(&name, rtype, false, &ctx).await {
+                    Ok(answers) => {
+                        let wait = answers
+                            .iter()
+                            .map(|answer| Duration::from_secs(answer.ttl.into()))
+                            .min()
+                            .map_or(WATCH_RETRY, |ttl| ttl.max(WATCH_MIN_INTERVAL));
+                        if !current.as_ref().is_some_and(|current| same_records(current, &answers)) {
+                            if sender.send(answers.clone()).await.is_err() {
+                                return;
+                            }
+                            current = Some(answers);
+                        }
+                        wait
+                    }
+                    Err(_) => WATCH_RETRY,
+                };
+
+                tokio::select! {
+                    _ = time::sleep(wait) => {}
+                    _ = sender.closed()   => return,
+                }
+            }
+        });
+
+        RecordWatch { changes }
+    }
+}
+
+/// Successive versions of a watched record set, see [`Resolver::watch`].
+#[derive(Debug)]
+pub struct RecordWatch {
+    changes: mpsc::Receiver<Vec<AnswerRecord>>,
+}
+
+impl RecordWatch {
+    /// Waits for the next version of the records.
+    pub async fn next(&mut self) -> Option<Vec<AnswerRecord>> {
+        self.changes.recv().await
+    }
+}
+
+/// Returns the domain rules of the configuration, with the one sending
+/// the cluster services to the cluster DNS unless they are routed
+/// explicitly.
+fn routes(config: &Config) -> Result<Routes, DnsError> {
+    let mut rules = config.domain_rules.clone();
+    if config.kubernetes {
+        let rule = kubernetes::service_rule(&config.resolv_conf)?;
+        if !rules.iter().any(|r| r.domain == rule.domain) {
+            rules.push(rule);
+        }
+    }
+    Ok(Routes::new(&rules))
+}
+
+/// Whether two answers hold the same records, regardless of their order
+/// and TTLs.
+fn same_records(a: &[AnswerRecord], b: &[AnswerRecord]) -> bool {
+    let contains = |set: &[AnswerRecord], record: &AnswerRecord| {
+        set.iter().any(|r| r.aname.eq_ignore_ascii_case(&record.aname) && r.rdata == record.rdata)
+    };
+    a.len() == b.len() && a.iter().all(|r| contains(b, r)) && b.iter().all(|r| contains(a, r))
+}
+
+/// Returns whether `rdata` is an address among the `bogus` ones, which no
+/// public name can resolve to.
+fn is_bogus(rdata: &RData, bogus: &[IpAddr]) -> bool {
+    match rdata {
+        RData::A(ip)    => bogus.contains(&IpAddr::V4(*ip)),
+        RData::AAAA(ip) => bogus.contains(&IpAddr::V6(*ip)),
+        _               => false,
+    }
+}
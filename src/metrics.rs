@@ -0,0 +1,202 @@
+use crate::types::Type;
+#[cfg(feature = "metrics")]
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use std::time::Duration;
+
+/// Number of recent query durations the latency quantiles are computed on.
+#[cfg(feature = "metrics")]
+const LATENCY_WINDOW: usize = 1024;
+
+/// Outcome of a cache lookup or maintenance operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheEvent {
+    /// The answer was served from the cache.
+    Hit,
+    /// No entry was found for the question.
+    Miss,
+    /// An entry was found but its TTL had elapsed.
+    Expired,
+    /// An entry was dropped to make room for a new one.
+    Evicted,
+    /// An expired entry was served, the name failing to resolve.
+    Stale,
+}
+
+/// Counters for a single (qtype, polarity) pair.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default, Clone, Copy)]
+struct CacheCounters {
+    hits:    u64,
+    misses:  u64,
+    expired: u64,
+    evicted: u64,
+    stale:   u64,
+}
+
+/// Accessor extracting one counter out of a `CacheCounters`.
+#[cfg(feature = "metrics")]
+type CounterFn = fn(&CacheCounters) -> u64;
+
+/// Runtime counters of the resolver.
+///
+/// Cache counters are broken down by query type and by whether the entry
+/// was positive (it carried answers) or negative (NXDOMAIN or NODATA).
+///
+/// Query durations are kept over a rolling window of the most recent
+/// queries, from which the p50/p95/p99 latencies are derived.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+pub struct Metrics {
+    cache:     Mutex<BTreeMap<(u16, bool), CacheCounters>>,
+    latencies: Mutex<VecDeque<Duration>>,
+    panics:    AtomicU64,
+    wasted:    AtomicU64,
+    slipped:   AtomicU64,
+    dropped:   AtomicU64,
+}
+
+/// Runtime counters of the resolver, left out of the build: nothing is
+/// counted.
+#[cfg(not(feature = "metrics"))]
+#[derive(Debug, Default)]
+pub struct Metrics;
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    /// Creates a new set of zeroed counters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a cache event for the given query type.
+    ///
+    /// Misses have no entry to look at, and are accounted as positive.
+    pub fn cache_event(&self, qtype: Type, positive: bool, event: CacheEvent) {
+        let mut cache = self.cache.lock().unwrap();
+        let counters = cache.entry((qtype.into(), positive)).or_default();
+        match event {
+            CacheEvent::Hit     => counters.hits    += 1,
+            CacheEvent::Miss    => counters.misses  += 1,
+            CacheEvent::Expired => counters.expired += 1,
+            CacheEvent::Evicted => counters.evicted += 1,
+            CacheEvent::Stale   => counters.stale   += 1,
+        }
+    }
+
+    /// Records a request task that panicked.
+    pub fn record_panic(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records upstream queries whose responses were no longer needed,
+    /// another query of the same race having been answered first.
+    pub fn record_wasted_queries(&self, count: u64) {
+        self.wasted.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Records a response over the rate limit, sent truncated if
+    /// `slipped`, or else dropped.
+    pub fn record_limited(&self, slipped: bool) {
+        match slipped {
+            true  => self.slipped.fetch_add(1, Ordering::Relaxed),
+            false => self.dropped.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Records the time spent answering a query.
+    pub fn observe_latency(&self, duration: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() == LATENCY_WINDOW {
+            latencies.pop_front();
+        }
+        latencies.push_back(duration);
+    }
+
+    /// Returns the given quantile of the recent query durations.
+    pub fn latency_quantile(&self, quantile: f64) -> Option<Duration> {
+        let mut sorted: Vec<Duration> = self.latencies.lock().unwrap().iter().copied().collect();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_unstable();
+        let rank = ((sorted.len() - 1) as f64 * quantile).round() as usize;
+        Some(sorted[rank])
+    }
+
+    /// Renders all the counters in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# TYPE dns_request_panics_total counter");
+        let _ = writeln!(out, "dns_request_panics_total {}", self.panics.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# TYPE dns_upstream_queries_wasted_total counter");
+        let _ = writeln!(out, "dns_upstream_queries_wasted_total {}", self.wasted.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# TYPE dns_responses_limited_total counter");
+        let _ = writeln!(out, "dns_responses_limited_total{{action=\"slip\"}} {}", self.slipped.load(Ordering::Relaxed));
+        let _ = writeln!(out, "dns_responses_limited_total{{action=\"drop\"}} {}", self.dropped.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# TYPE dns_query_duration_seconds summary");
+        for quantile in [0.5, 0.95, 0.99] {
+            if let Some(latency) = self.latency_quantile(quantile) {
+                let _ = writeln!(
+                    out,
+                    "dns_query_duration_seconds{{quantile=\"{}\"}} {}",
+                    quantile,
+                    latency.as_secs_f64(),
+                );
+            }
+        }
+
+        let cache = self.cache.lock().unwrap();
+
+        let series: [(&str, CounterFn); 5] = [
+            ("dns_cache_hits_total",    |c| c.hits),
+            ("dns_cache_misses_total",  |c| c.misses),
+            ("dns_cache_expired_total", |c| c.expired),
+            ("dns_cache_evicted_total", |c| c.evicted),
+            ("dns_cache_stale_total",   |c| c.stale),
+        ];
+
+        for (name, value) in series {
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for ((qtype, positive), counters) in cache.iter() {
+                let _ = writeln!(
+                    out,
+                    "{}{{qtype=\"{}\",kind=\"{}\"}} {}",
+                    name,
+                    Type::from(*qtype),
+                    if *positive { "positive" } else { "negative" },
+                    value(counters),
+                );
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics
+    }
+
+    pub fn cache_event(&self, _qtype: Type, _positive: bool, _event: CacheEvent) {}
+
+    pub fn record_panic(&self) {}
+
+    pub fn record_wasted_queries(&self, _count: u64) {}
+
+    pub fn record_limited(&self, _slipped: bool) {}
+
+    pub fn observe_latency(&self, _duration: Duration) {}
+}
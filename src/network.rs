@@ -0,0 +1,48 @@
+use crate::types::DnsError;
+use std::{net::IpAddr, str::FromStr};
+
+/// A network of clients, such as those allowed to transfer or update the
+/// zones, written as an address or as `<address>/<prefix length>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    addr:   IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Network {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::IOError(format!("invalid network: {}", s));
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None                 => (s, None),
+        };
+
+        let addr: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+        let width = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|&len| len <= width).ok_or_else(invalid)?,
+            None         => width,
+        };
+        Ok(Network { addr, prefix })
+    }
+}
+
+impl Network {
+    /// Tells whether the network holds `ip`. IPv4 addresses mapped to IPv6
+    /// belong to the IPv4 networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
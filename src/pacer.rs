@@ -0,0 +1,81 @@
+use crate::{rng::DnsRng, types::DnsError};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::time;
+
+/// Longest a query may wait for its turn before being refused.
+const MAX_WAIT: Duration = Duration::from_secs(1);
+
+/// Pacing of the outgoing queries, per destination server.
+///
+/// Each server gets at most `rate` queries per second, with bursts of up
+/// to `burst` queries. Queries over the rate are delayed to their slot,
+/// plus a random jitter so that they do not leave in lockstep, and refused
+/// if the wait would be too long.
+#[derive(Debug)]
+pub struct Pacer {
+    interval:  Duration,
+    tolerance: Duration,
+    jitter:    Duration,
+    /// Theoretical arrival time of the next query, per server.
+    servers:   Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl Pacer {
+    /// Creates a new pacer. A zero `rate` disables pacing.
+    pub fn new(rate: u32, burst: u32, jitter: Duration) -> Self {
+        let interval = if rate == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(1) / rate
+        };
+
+        Pacer {
+            interval,
+            tolerance: interval * burst.saturating_sub(1),
+            jitter,
+            servers:   Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits until a query can be sent to `server`.
+    pub async fn wait(&self, server: IpAddr, rng: &DnsRng) -> Result<(), DnsError> {
+        if self.interval.is_zero() {
+            return Ok(());
+        }
+
+        let delay = {
+            let mut servers = self.servers.lock().unwrap();
+            let now = Instant::now();
+
+            // Forget the servers that have been idle long enough
+            if servers.len() > 1024 {
+                servers.retain(|_, tat| *tat > now);
+            }
+
+            let tat = servers.entry(server).or_insert(now);
+            if *tat < now {
+                *tat = now;
+            }
+
+            let slot  = tat.checked_sub(self.tolerance).unwrap_or(now);
+            let delay = slot.saturating_duration_since(now);
+            if delay > MAX_WAIT {
+                return Err(DnsError::IOError(format!("outgoing rate limit exceeded for {}", server)));
+            }
+
+            *tat += self.interval;
+            delay
+        };
+
+        if !delay.is_zero() {
+            time::sleep(delay + rng.jitter(self.jitter)).await;
+        }
+
+        Ok(())
+    }
+}
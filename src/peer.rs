@@ -0,0 +1,142 @@
+use crate::{
+    cache::Cache,
+    infra::MAX_UDP_SIZE,
+    sockets,
+    types::{AnswerRecord, Dns, DnsError, DnsReadBuffer, Type},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::UdpSocket;
+
+/// Length of the attestation tag appended to the messages.
+const TAG_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Cache synchronization between resolver instances.
+///
+/// Every positive answer inserted into the cache after a resolution is
+/// sent to the peers as a DNS response carrying the question and the
+/// answers, so that a standby instance is already warm when it takes over.
+/// Entries are only accepted from the configured peers. With a shared key,
+/// each message also carries an HMAC-SHA256 tag attesting its origin.
+#[derive(Debug)]
+pub struct Gossip {
+    sock:  UdpSocket,
+    peers: Vec<SocketAddr>,
+    key:   Option<Vec<u8>>,
+}
+
+impl Gossip {
+    /// Creates the sending side, towards `peers`.
+    ///
+    /// With IPv6 peers, the entries are sent from a dual-stack socket, and
+    /// to the IPv4-mapped addresses of the IPv4 peers.
+    pub async fn new(peers: Vec<SocketAddr>, key: Option<Vec<u8>>) -> Result<Self, DnsError> {
+        let (sock, peers) = if peers.iter().any(SocketAddr::is_ipv6) {
+            let sock  = sockets::listen_udp(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))
+                .and_then(UdpSocket::from_std)
+                .map_err(|_| DnsError::SocketError)?;
+            let peers = peers
+                .into_iter()
+                .map(|peer| match peer {
+                    SocketAddr::V4(v4) => SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port()),
+                    v6                 => v6,
+                })
+                .collect();
+            (sock, peers)
+        } else {
+            let sock = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+                .await
+                .map_err(|_| DnsError::SocketError)?;
+            (sock, peers)
+        };
+        Ok(Gossip { sock, peers, key })
+    }
+
+    /// Sends a new cache entry to the peers.
+    pub async fn publish(&self, qname: &str, qtype: Type, dnssec_ok: bool, answers: &[AnswerRecord]) {
+        if answers.is_empty() {
+            return;
+        }
+
+        let mut msg = Dns::new_question(qname, qtype, 0);
+        msg.header.flags.qr = true;
+        msg.answers = answers.to_vec();
+        msg.set_edns(MAX_UDP_SIZE).dnssec_ok = dnssec_ok;
+
+        let Ok(enc) = msg.encode() else {
+            return;
+        };
+        let mut data = enc.data;
+        if let Some(key) = &self.key {
+            let tag = sign(key, &data);
+            data.extend_from_slice(&tag);
+        }
+
+        for peer in &self.peers {
+            let _ = self.sock.send_to(&data, peer).await;
+        }
+    }
+
+    /// Receives the entries sent by the peers on `addr` and inserts them
+    /// into the cache.
+    pub async fn listen(addr: SocketAddr, peers: Vec<SocketAddr>, key: Option<Vec<u8>>, cache: Arc<Cache>) {
+        let sock = match sockets::listen_udp(addr).and_then(UdpSocket::from_std) {
+            Ok(sock) => sock,
+            Err(e) => {
+                tracing::error!(addr = %addr, error = %e, "can't listen for peer updates");
+                return;
+            }
+        };
+
+        let mut buf = [0u8; 65535];
+        loop {
+            let Ok((length, from)) = sock.recv_from(&mut buf).await else {
+                continue;
+            };
+            if !peers.iter().any(|peer| peer.ip() == from.ip().to_canonical()) {
+                tracing::warn!(peer = %from, "dropping update from unknown peer");
+                continue;
+            }
+
+            let mut data = &buf[..length];
+            if let Some(key) = &key {
+                let Some(split) = length.checked_sub(TAG_LEN) else {
+                    continue;
+                };
+                let (msg, tag) = data.split_at(split);
+                if !verify(key, msg, tag) {
+                    tracing::warn!(peer = %from, "dropping update with a bad attestation");
+                    continue;
+                }
+                data = msg;
+            }
+
+            let Ok(msg) = Dns::decode(&mut DnsReadBuffer::new(data)) else {
+                continue;
+            };
+            if let Some(question) = msg.questions.first() && !msg.answers.is_empty() {
+                cache.insert(&question.qname, question.qtype, msg.dnssec_ok(), msg.answers.clone());
+            }
+        }
+    }
+}
+
+/// Computes the attestation tag of a message.
+fn sign(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Checks the attestation tag of a message, in constant time.
+fn verify(key: &[u8], data: &[u8], tag: &[u8]) -> bool {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.verify_slice(tag).is_ok()
+}
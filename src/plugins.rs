@@ -0,0 +1,184 @@
+use crate::{
+    policy::PluginVerdict,
+    types::{DnsError, Type},
+};
+use std::{net::IpAddr, path::PathBuf};
+use wasmtime::{Caller, Engine, InstancePre, Linker, Module, Store};
+
+/// Module the host functions are imported from.
+const HOST_MODULE: &str = "dnsr";
+
+/// Instructions a plugin may run for a single query, so that a plugin
+/// stuck in a loop can't hold the query up.
+const FUEL: u64 = 1_000_000;
+
+/// What a plugin sees of the query being checked, and what it answers.
+struct Host {
+    /// Name asked, without its trailing dot.
+    qname:  String,
+    /// Type asked.
+    qtype:  Type,
+    /// Address of the client, in text form.
+    client: String,
+    /// Target of the verdict, set by the plugin.
+    target: Option<String>,
+}
+
+/// A plugin, compiled and linked, ready to be instantiated.
+struct Plugin {
+    /// Name of the plugin, after its file.
+    name: String,
+    pre:  InstancePre<Host>,
+}
+
+/// Policy plugins, compiled to WebAssembly and run in a sandbox for every
+/// query the policy lets through.
+///
+/// A plugin exports its linear memory as `memory`, and a `check` function
+/// taking nothing and returning its verdict: 0 to allow the query, 1 to
+/// block it, 2 to rewrite it to the addresses of its target, separated by
+/// commas, and 3 to forward it to its target, a route written as in the
+/// domain rules. It learns about the query from the functions of the
+/// `dnsr` module:
+///
+/// - `query_name(ptr, len) -> len` and `client(ptr, len) -> len` copy the
+///   name asked and the address of the client to its memory, as much as
+///   fits in `len` bytes, and return their full length;
+/// - `query_type() -> type` returns the type asked;
+/// - `set_target(ptr, len)` sets the target of the verdict.
+///
+/// Every query gets a fresh instance, which runs with a bounded budget of
+/// instructions. The plugins are run in order, until one of them doesn't
+/// allow the query.
+pub struct Plugins {
+    plugins: Vec<Plugin>,
+}
+
+impl Plugins {
+    /// Compiles the plugins at `paths`, in the binary or the text format.
+    pub fn load(paths: &[PathBuf]) -> Result<Self, DnsError> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)
+            .map_err(|e| DnsError::IOError(format!("can't create the plugin runtime: {}", e)))?;
+        let linker = linker(&engine)?;
+
+        let mut plugins = Vec::with_capacity(paths.len());
+        for path in paths {
+            let invalid = |e: wasmtime::Error| DnsError::IOError(format!("invalid plugin {}: {}", path.display(), e));
+            let module  = Module::from_file(&engine, path).map_err(invalid)?;
+            let pre     = linker.instantiate_pre(&module).map_err(invalid)?;
+            let name    = path
+                .file_stem()
+                .map_or_else(|| path.display().to_string(), |stem| stem.to_string_lossy().into_owned());
+            plugins.push(Plugin { name, pre });
+        }
+        Ok(Plugins { plugins })
+    }
+
+    /// Checks the `qtype` query for `qname` by `client` with the plugins,
+    /// returning the first verdict that doesn't allow it, along with the
+    /// plugin it comes from.
+    ///
+    /// A plugin that fails, or gives an invalid verdict, is logged and
+    /// allows the query.
+    pub fn check(&self, qname: &str, qtype: Type, client: IpAddr) -> Option<(String, PluginVerdict)> {
+        let qname = qname.trim_end_matches('.');
+        self.plugins.iter().find_map(|plugin| {
+            match run(plugin, qname, qtype, client) {
+                Ok(PluginVerdict::Allow) => None,
+                Ok(verdict)              => Some((plugin.name.clone(), verdict)),
+                Err(e)                   => {
+                    tracing::warn!(
+                        plugin = %plugin.name,
+                        qname  = %qname,
+                        error  = %e,
+                        "policy plugin failed",
+                    );
+                    None
+                }
+            }
+        })
+    }
+}
+
+/// Runs a plugin on a query, returning its verdict.
+fn run(plugin: &Plugin, qname: &str, qtype: Type, client: IpAddr) -> Result<PluginVerdict, DnsError> {
+    let failed = |e: wasmtime::Error| DnsError::IOError(e.root_cause().to_string());
+    let host   = Host { qname: qname.to_string(), qtype, client: client.to_string(), target: None };
+
+    let mut store = Store::new(plugin.pre.module().engine(), host);
+    store.set_fuel(FUEL).map_err(failed)?;
+    let instance = plugin.pre.instantiate(&mut store).map_err(failed)?;
+    let check    = instance.get_typed_func::<(), i32>(&mut store, "check").map_err(failed)?;
+    let verdict  = check.call(&mut store, ()).map_err(failed)?;
+
+    let target  = store.into_data().target;
+    let invalid = || DnsError::IOError(format!("invalid verdict {} with target {:?}", verdict, target));
+    match (verdict, &target) {
+        (0, _)            => Ok(PluginVerdict::Allow),
+        (1, _)            => Ok(PluginVerdict::Block),
+        (2, Some(target)) => target
+            .split(',')
+            .map(|addr| addr.trim().parse())
+            .collect::<Result<Vec<IpAddr>, _>>()
+            .map(PluginVerdict::Rewrite)
+            .map_err(|_| invalid()),
+        (3, Some(target)) => target.parse().map(PluginVerdict::Forward).map_err(|_| invalid()),
+        _                 => Err(invalid()),
+    }
+}
+
+/// Links the host functions the plugins may import.
+fn linker(engine: &Engine) -> Result<Linker<Host>, DnsError> {
+    let mut linker = Linker::new(engine);
+    let failed = |e: wasmtime::Error| DnsError::IOError(format!("can't link the plugin functions: {}", e));
+
+    linker
+        .func_wrap(HOST_MODULE, "query_name", |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            let qname = caller.data().qname.clone();
+            write(&mut caller, ptr, len, qname.as_bytes())
+        })
+        .map_err(failed)?;
+    linker
+        .func_wrap(HOST_MODULE, "query_type", |caller: Caller<'_, Host>| -> i32 {
+            u16::from(caller.data().qtype).into()
+        })
+        .map_err(failed)?;
+    linker
+        .func_wrap(HOST_MODULE, "client", |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            let client = caller.data().client.clone();
+            write(&mut caller, ptr, len, client.as_bytes())
+        })
+        .map_err(failed)?;
+    linker
+        .func_wrap(HOST_MODULE, "set_target", |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            let memory = memory(&mut caller)?;
+            let target = memory
+                .data(&caller)
+                .get(ptr as u32 as usize..)
+                .and_then(|data| data.get(..len.max(0) as usize))
+                .ok_or_else(|| wasmtime::Error::msg("the target is out of the plugin's memory"))?;
+            let target = String::from_utf8(target.to_vec())?;
+            caller.data_mut().target = Some(target);
+            Ok(())
+        })
+        .map_err(failed)?;
+    Ok(linker)
+}
+
+/// Copies as much of `data` as fits in the `len` bytes at `ptr` of the
+/// plugin's memory, returning the full length of `data`.
+fn write(caller: &mut Caller<'_, Host>, ptr: i32, len: i32, data: &[u8]) -> wasmtime::Result<i32> {
+    let size = data.len().min(len.max(0) as usize);
+    memory(caller)?.write(&mut *caller, ptr as u32 as usize, &data[..size])?;
+    Ok(data.len() as i32)
+}
+
+/// Returns the memory exported by the plugin.
+fn memory(caller: &mut Caller<'_, Host>) -> wasmtime::Result<wasmtime::Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| wasmtime::Error::msg("the plugin exports no memory"))
+}
@@ -0,0 +1,400 @@
+use crate::{
+    idna,
+    routing::Route,
+    types::{AnswerRecord, Dns, DnsError, EdnsOption, QueryRecord, RData, Type},
+};
+use std::{
+    collections::HashSet,
+    fmt,
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::time;
+
+/// How long to wait for a blocklist to be downloaded.
+#[cfg(feature = "blocklist-urls")]
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// EDNS option code of the extended DNS errors (RFC 8914).
+const EDE_OPTION: u16 = 15;
+
+/// Extended DNS error of the names blocked by the policy ("Blocked").
+const EDE_BLOCKED: u16 = 15;
+
+/// TTL of the sinkhole addresses, of the addresses rewritten by plugins and
+/// of the record explaining a block.
+const BLOCK_TTL: u32 = 60;
+
+/// Latin lookalikes of Cyrillic and Greek letters, commonly used to spoof
+/// well-known names.
+const CONFUSABLES: &[(char, char)] = &[
+    // Cyrillic
+    ('а', 'a'), ('в', 'b'), ('с', 'c'), ('ԁ', 'd'), ('е', 'e'), ('һ', 'h'),
+    ('і', 'i'), ('ј', 'j'), ('к', 'k'), ('ӏ', 'l'), ('м', 'm'), ('п', 'n'),
+    ('о', 'o'), ('р', 'p'), ('ԛ', 'q'), ('г', 'r'), ('ѕ', 's'), ('т', 't'),
+    ('ц', 'u'), ('ѵ', 'v'), ('ԝ', 'w'), ('х', 'x'), ('у', 'y'),
+    // Greek
+    ('α', 'a'), ('β', 'b'), ('ε', 'e'), ('η', 'n'), ('ι', 'i'), ('κ', 'k'),
+    ('ν', 'v'), ('ο', 'o'), ('ρ', 'p'), ('τ', 't'), ('υ', 'u'), ('χ', 'x'),
+];
+
+/// What to do with the lookalikes of the protected names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HomographAction {
+    /// Only log a warning.
+    Log,
+    /// Log a warning and refuse to resolve the name.
+    Block,
+}
+
+impl FromStr for HomographAction {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log"   => Ok(HomographAction::Log),
+            "block" => Ok(HomographAction::Block),
+            _ => Err(DnsError::IOError(format!("invalid homograph action: {}", s))),
+        }
+    }
+}
+
+/// Downloads the blocklist at `url` and returns the domains it lists.
+///
+/// The list holds a domain per line, or is in the hosts file format, with
+/// the address in front of the domains. Comments, blank lines and names
+/// without a dot, like `localhost`, are skipped.
+#[cfg(feature = "blocklist-urls")]
+async fn fetch_blocklist(url: &str) -> Result<Vec<String>, DnsError> {
+    let http = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .map_err(|e| DnsError::IOError(format!("can't create the HTTP client: {}", e)))?;
+
+    let res = http
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| DnsError::IOError(format!("can't reach {}: {}", url, e)))?;
+    if !res.status().is_success() {
+        return Err(DnsError::IOError(format!("{} answered with status {}", url, res.status())));
+    }
+    let body = res
+        .text()
+        .await
+        .map_err(|e| DnsError::IOError(format!("can't read the blocklist at {}: {}", url, e)))?;
+
+    Ok(parse_blocklist(&body))
+}
+
+/// Extracts the domains from the body of a blocklist.
+fn parse_blocklist(body: &str) -> Vec<String> {
+    body.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| {
+            let mut words = line.split_whitespace().peekable();
+            if words.peek().is_some_and(|word| word.parse::<IpAddr>().is_ok()) {
+                words.next();
+            }
+            words
+        })
+        .map(|name| name.trim_end_matches('.').to_ascii_lowercase())
+        .filter(|name| name.contains('.'))
+        .collect()
+}
+
+/// Where a blocklist is loaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A local file.
+    File(PathBuf),
+    /// A URL it is downloaded from.
+    #[cfg(feature = "blocklist-urls")]
+    Url(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "{}", path.display()),
+            #[cfg(feature = "blocklist-urls")]
+            Source::Url(url)   => write!(f, "{}", url),
+        }
+    }
+}
+
+impl Source {
+    /// Loads the blocklist and returns the domains it lists, in the same
+    /// formats as the downloaded ones.
+    pub async fn load(&self) -> Result<Vec<String>, DnsError> {
+        match self {
+            Source::File(path) => {
+                let body = tokio::fs::read_to_string(path)
+                    .await
+                    .map_err(|e| DnsError::IOError(format!("can't read the blocklist at {}: {}", path.display(), e)))?;
+                Ok(parse_blocklist(&body))
+            }
+            #[cfg(feature = "blocklist-urls")]
+            Source::Url(url) => fetch_blocklist(url).await,
+        }
+    }
+}
+
+/// Outcome of the policy check of a query name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The name can be resolved.
+    Allow,
+    /// The name, or one of its parents, is on the blocklist, under the
+    /// given entry.
+    Blocked(String),
+    /// The name is a lookalike of the given protected name.
+    Homograph(String),
+    /// The name can't be read as Unicode, so it can't be checked.
+    Unreadable,
+}
+
+/// Verdict of a policy plugin on a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(not(feature = "wasm-plugins"), allow(dead_code))]
+pub enum PluginVerdict {
+    /// The query goes on as usual.
+    Allow,
+    /// The name is blocked, as if it were on the blocklist.
+    Block,
+    /// The name resolves to the given addresses instead.
+    Rewrite(Vec<IpAddr>),
+    /// The name is resolved by the given route instead of its own.
+    Forward(Route),
+}
+
+impl PluginVerdict {
+    /// Returns the answers of a rewritten name to the `qtype` query: its
+    /// addresses of that family, if any.
+    pub fn answers(&self, qname: &str, qtype: Type) -> Option<Vec<AnswerRecord>> {
+        let PluginVerdict::Rewrite(addrs) = self else {
+            return None;
+        };
+        let answers = addrs
+            .iter()
+            .filter_map(|ip| match (ip, qtype) {
+                (IpAddr::V4(ip), Type::A)    => Some(RData::A(*ip)),
+                (IpAddr::V6(ip), Type::AAAA) => Some(RData::AAAA(*ip)),
+                _ => None,
+            })
+            .map(|rdata| AnswerRecord { ttl: BLOCK_TTL, ..AnswerRecord::new(qname.to_string(), rdata) })
+            .collect();
+        Some(answers)
+    }
+
+    /// Returns the route a forwarded name is resolved by.
+    pub fn route(&self) -> Option<&Route> {
+        match self {
+            PluginVerdict::Forward(route) => Some(route),
+            _                             => None,
+        }
+    }
+}
+
+/// Why a name was blocked, as told to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// What the name was blocked for.
+    pub reason: &'static str,
+    /// The rule that blocked it: the blocklist entry, or the protected
+    /// name it looks like.
+    pub rule: String,
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "blocked: {} (rule {})", self.reason, self.rule)
+    }
+}
+
+impl Block {
+    /// Creates the block of a name on the blocklist under `entry`.
+    pub fn blocklist(entry: String) -> Self {
+        Block { reason: "blocklist", rule: entry }
+    }
+
+    /// Creates the block of a lookalike of the protected name `target`.
+    pub fn homograph(target: String) -> Self {
+        Block { reason: "lookalike of a protected name", rule: target }
+    }
+
+    /// Creates the block of a name that can't be checked, `qname`.
+    pub fn unreadable(qname: String) -> Self {
+        Block { reason: "unreadable name", rule: qname }
+    }
+
+    /// Creates the block of a name by the policy plugin `plugin`.
+    #[cfg_attr(not(feature = "wasm-plugins"), allow(dead_code))]
+    pub fn plugin(plugin: String) -> Self {
+        Block { reason: "plugin", rule: plugin }
+    }
+
+    /// Fills `res`, the reply to the blocked `question`.
+    ///
+    /// Without sinkhole addresses the name doesn't exist. Otherwise, the
+    /// A and AAAA queries get the `sinkhole` addresses of their family,
+    /// and every reply a TXT record with the reason of the block in the
+    /// additional section. Replies carrying an OPT record also get an
+    /// extended DNS error.
+    pub fn answer(&self, res: &mut Dns, question: &QueryRecord, sinkhole: &[IpAddr]) -> Result<(), DnsError> {
+        if let Some(opt) = &mut res.opt {
+            let mut data = EDE_BLOCKED.to_be_bytes().to_vec();
+            data.extend_from_slice(self.to_string().as_bytes());
+            opt.options.push(EdnsOption { code: EDE_OPTION, data });
+        }
+
+        if sinkhole.is_empty() {
+            return res.set_rcode(3);
+        }
+
+        let record = |rdata| AnswerRecord {
+            ttl: BLOCK_TTL,
+            ..AnswerRecord::new(question.qname.clone(), rdata)
+        };
+        res.answers = sinkhole
+            .iter()
+            .filter_map(|ip| match (ip, question.qtype) {
+                (IpAddr::V4(ip), Type::A)    => Some(RData::A(*ip)),
+                (IpAddr::V6(ip), Type::AAAA) => Some(RData::AAAA(*ip)),
+                _ => None,
+            })
+            .map(record)
+            .collect();
+        res.additionals.push(record(RData::txt(&self.to_string())));
+        Ok(())
+    }
+}
+
+/// Policy engine deciding which names clients may resolve.
+///
+/// Names are compared in their ASCII form, so that a blocklist entry
+/// matches a domain whether it is written in Unicode or as A-labels
+/// (`xn--...`), on either side.
+///
+/// The blocklists loaded from files and URLs are kept apart from the
+/// configured domains, one set per source, so that each can be replaced
+/// when it is reloaded. The configured domains may be changed at runtime.
+#[derive(Debug, Default)]
+pub struct Policy {
+    /// Blocked domains, in ASCII form.
+    blocked: RwLock<HashSet<String>>,
+    /// Domains of the blocklists, in ASCII form, by source.
+    lists: RwLock<Vec<HashSet<String>>>,
+    /// Names whose lookalikes are flagged, in ASCII form.
+    protected: HashSet<String>,
+}
+
+impl Policy {
+    /// Creates a policy from the blocked and the protected domains.
+    pub fn new(blocklist: &[String], protected: &[String]) -> Self {
+        Policy {
+            blocked:   RwLock::new(blocklist.iter().filter_map(|name| idna::to_ascii(name)).collect()),
+            lists:     RwLock::new(Vec::new()),
+            protected: protected.iter().filter_map(|name| idna::to_ascii(name)).collect(),
+        }
+    }
+
+    /// Loads the blocklists of `sources`. A list that can't be loaded is
+    /// logged and skipped, or keeps the domains it had if it was loaded
+    /// before.
+    pub async fn load(&self, sources: &[Source]) {
+        for (n, source) in sources.iter().enumerate() {
+            let names = match source.load().await {
+                Ok(names) => names,
+                Err(e)    => {
+                    tracing::warn!(source = %source, error = %e, "can't load the blocklist");
+                    continue;
+                }
+            };
+            tracing::info!(source = %source, entries = %names.len(), "blocklist loaded");
+
+            let names = names.iter().filter_map(|name| idna::to_ascii(name)).collect();
+            let mut lists = self.lists.write().unwrap();
+            if lists.len() <= n {
+                lists.resize(n + 1, HashSet::new());
+            }
+            lists[n] = names;
+        }
+    }
+
+    /// Blocks `name` and its subdomains, returning whether it is a new
+    /// entry, or `None` if it isn't a valid name.
+    #[cfg(feature = "admin")]
+    pub fn block(&self, name: &str) -> Option<bool> {
+        let name = idna::to_ascii(name)?;
+        Some(self.blocked.write().unwrap().insert(name))
+    }
+
+    /// Removes `name` from the blocked domains, returning whether it was
+    /// there. The domains of the blocklists stay blocked.
+    #[cfg(feature = "admin")]
+    pub fn unblock(&self, name: &str) -> bool {
+        idna::to_ascii(name).is_some_and(|name| self.blocked.write().unwrap().remove(&name))
+    }
+
+    /// Reloads the blocklists of `sources` every `every`, forever.
+    pub async fn refresh(self: Arc<Self>, sources: Vec<Source>, every: Duration) {
+        loop {
+            time::sleep(every).await;
+            self.load(&sources).await;
+        }
+    }
+
+    /// Checks whether `qname`, in presentation format, may be resolved.
+    /// Names that aren't valid Unicode can't be compared with the
+    /// blocklists, and are never let through.
+    pub fn check(&self, qname: &str) -> Verdict {
+        let Some(name) = idna::to_ascii(qname) else {
+            return Verdict::Unreadable;
+        };
+
+        let domains = self.blocked.read().unwrap();
+        let lists   = self.lists.read().unwrap();
+        let blocked = |name: &str| domains.contains(name) || lists.iter().any(|list| list.contains(name));
+        if let Some(entry) = parents(&name).find(|parent| blocked(parent)) {
+            return Verdict::Blocked(entry.to_string());
+        }
+        drop((domains, lists));
+
+        match self.lookalike(&name) {
+            Some(target) => Verdict::Homograph(target),
+            None         => Verdict::Allow,
+        }
+    }
+
+    /// Returns the protected name `name` is a lookalike of, if any: once
+    /// the confusable letters are replaced by their Latin counterparts,
+    /// the name or one of its parents becomes a protected name.
+    fn lookalike(&self, name: &str) -> Option<String> {
+        if self.protected.is_empty() || !name.split('.').any(|label| label.starts_with("xn--")) {
+            return None;
+        }
+
+        let skeleton: String = idna::to_unicode(name).chars().map(skeleton).collect();
+        parents(&skeleton)
+            .find(|parent| self.protected.contains(*parent))
+            .filter(|parent| !parents(name).any(|own| own == *parent))
+            .map(str::to_string)
+    }
+}
+
+/// Returns the Latin lookalike of a character, or the character itself.
+fn skeleton(c: char) -> char {
+    CONFUSABLES
+        .iter()
+        .find(|(confusable, _)| *confusable == c)
+        .map_or(c, |(_, latin)| *latin)
+}
+
+/// Iterates over a name and its parent domains, up to the top-level one.
+fn parents(name: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(name), |name| name.split_once('.').map(|(_, parent)| parent))
+}
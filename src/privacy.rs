@@ -0,0 +1,94 @@
+use crate::{
+    rng::DnsRng,
+    types::{Dns, DnsError, DnsReadBuffer},
+};
+#[cfg(any(feature = "doh", feature = "doq", feature = "dot"))]
+use crate::{
+    infra::MAX_UDP_SIZE,
+    types::{EdnsOption, OptRecord},
+};
+use std::{borrow::Cow, time::Duration};
+use tokio::time;
+
+/// EDNS options telling who a query is for: the subnet of the client
+/// (RFC 7871) and its cookie (RFC 7873).
+const IDENTIFYING_OPTIONS: [u16; 2] = [8, 10];
+
+/// Option code of the EDNS padding (RFC 7830).
+#[cfg(any(feature = "doh", feature = "doq", feature = "dot"))]
+const PADDING_OPTION: u16 = 12;
+
+/// Size the encrypted queries are padded to a multiple of (RFC 8467,
+/// section 4.1).
+#[cfg(any(feature = "doh", feature = "doq", feature = "dot"))]
+const PADDING_BLOCK: usize = 128;
+
+/// Privacy profile of the queries sent upstream.
+///
+/// Enabled, nothing that identifies a client leaves with its queries: the
+/// identifying EDNS options are stripped from the relayed ones, the queries
+/// sent over the encrypted transports are padded to a common size, and
+/// each query can be held back for a random delay, so that its timing
+/// tells less about the client query that caused it. Disabled, queries
+/// leave untouched.
+#[derive(Debug, Clone, Default)]
+pub struct Privacy {
+    enabled: bool,
+    delay:   Duration,
+}
+
+impl Privacy {
+    /// Creates the profile, holding the queries back for up to `delay`
+    /// when enabled.
+    pub fn new(enabled: bool, delay: Duration) -> Self {
+        Privacy { enabled, delay }
+    }
+
+    /// Strips the identifying options from a raw outgoing query.
+    ///
+    /// Queries without EDNS are left as they are. Those that can't be
+    /// decoded are refused, since they can't be checked.
+    pub fn scrub<'a>(&self, query: &'a [u8]) -> Result<Cow<'a, [u8]>, DnsError> {
+        if !self.enabled || query.get(10..12).is_none_or(|count| count == [0, 0]) {
+            return Ok(Cow::Borrowed(query));
+        }
+
+        let mut dns = Dns::decode(&mut DnsReadBuffer::new(query))?;
+        let Some(opt) = dns.opt.as_mut() else {
+            return Ok(Cow::Borrowed(query));
+        };
+        if !opt.options.iter().any(|option| IDENTIFYING_OPTIONS.contains(&option.code)) {
+            return Ok(Cow::Borrowed(query));
+        }
+        opt.options.retain(|option| !IDENTIFYING_OPTIONS.contains(&option.code));
+        Ok(Cow::Owned(dns.encode()?.into_inner()))
+    }
+
+    /// Pads a query to be sent over an encrypted transport to a multiple
+    /// of the block size, so that its length doesn't give the name away.
+    #[cfg(any(feature = "doh", feature = "doq", feature = "dot"))]
+    pub fn pad(&self, req: &mut Dns) -> Result<(), DnsError> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        req.opt
+            .get_or_insert_with(|| OptRecord::new(MAX_UDP_SIZE))
+            .options
+            .retain(|option| option.code != PADDING_OPTION);
+        let length = req.encode()?.data.len() + 4;
+        let data   = vec![0; (PADDING_BLOCK - length % PADDING_BLOCK) % PADDING_BLOCK];
+
+        if let Some(opt) = req.opt.as_mut() {
+            opt.options.push(EdnsOption { code: PADDING_OPTION, data });
+        }
+        Ok(())
+    }
+
+    /// Holds an outgoing query back for a random delay, if configured.
+    pub async fn wait(&self, rng: &DnsRng) {
+        if self.enabled && !self.delay.is_zero() {
+            time::sleep(rng.jitter(self.delay)).await;
+        }
+    }
+}
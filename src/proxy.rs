@@ -0,0 +1,76 @@
+use crate::{contact, sockets::SocketPool, types::DnsError};
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// How long to wait for the reply of the upstream server.
+const PROXY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Upstream servers the client queries are relayed to.
+///
+/// The upstreams are tried in order until one answers, starting from the
+/// last one that did, so that a dead server only delays the queries until
+/// the next one takes over.
+#[derive(Debug)]
+pub struct Proxy {
+    upstreams: Vec<SocketAddr>,
+    preferred: AtomicUsize,
+}
+
+impl Proxy {
+    /// Creates a proxy relaying to `upstreams`, in order of preference.
+    pub fn new(upstreams: Vec<SocketAddr>) -> Self {
+        Proxy { upstreams, preferred: AtomicUsize::new(0) }
+    }
+
+    /// Forwards a raw client query to the upstreams, and returns the raw
+    /// reply of the first one that answers.
+    pub async fn forward(&self, query: &[u8], pool: &SocketPool) -> Result<Vec<u8>, DnsError> {
+        let preferred = self.preferred.load(Ordering::Relaxed);
+        let mut error = DnsError::IOError("no upstream to forward to".into());
+        for n in 0..self.upstreams.len() {
+            let index = (preferred + n) % self.upstreams.len();
+            match forward(query, self.upstreams[index], pool).await {
+                Ok(reply) => {
+                    self.preferred.store(index, Ordering::Relaxed);
+                    return Ok(reply);
+                }
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+}
+
+/// Forwards a raw client query to `upstream` and returns its raw reply.
+///
+/// The packets are relayed verbatim, so that record types the codec does
+/// not support yet survive the trip. Only the transaction ID is rewritten:
+/// the query leaves with a fresh random one, and the reply is accepted only
+/// if it carries it back, before being given the client's ID again.
+async fn forward(query: &[u8], upstream: SocketAddr, pool: &SocketPool) -> Result<Vec<u8>, DnsError> {
+    if query.len() < 12 {
+        return Err(DnsError::InvalidField);
+    }
+
+    let id = pool.rng().query_id().to_be_bytes();
+    let mut packet = query.to_vec();
+    packet[..2].copy_from_slice(&id);
+
+    let mut buffer = [0u8; 65535];
+    let reply = contact::contact(&packet, upstream, &mut buffer, PROXY_TIMEOUT, pool).await?;
+    if reply.len() < 12 || reply[..2] != id {
+        return Err(DnsError::IOError(format!("mismatched reply from {}", upstream)));
+    }
+
+    // A truncated reply is fetched whole over TCP, the client getting it
+    // truncated again only if it doesn't fit its own transport
+    let mut reply = match reply[2] & 0x02 != 0 {
+        true  => contact::contact_tcp(&packet, upstream, PROXY_TIMEOUT, pool).await?,
+        false => reply.to_vec(),
+    };
+    reply[..2].copy_from_slice(&query[..2]);
+    Ok(reply)
+}
@@ -0,0 +1,147 @@
+use crate::{
+    metrics::{CacheEvent, Metrics},
+    types::{AnswerRecord, DnsError, Type},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Maximum number of reverse lookups kept in the cache.
+const MAX_ENTRIES: usize = 1024;
+
+/// Longest a positive reverse lookup is cached.
+const MAX_TTL: Duration = Duration::from_secs(3600);
+
+/// Outcome of a reverse lookup, as cached.
+type Outcome = Result<Vec<AnswerRecord>, DnsError>;
+
+/// Dedicated path for the PTR queries of reverse lookups.
+///
+/// Log collectors resolve the address of every sender, and most of those
+/// lookups fail or come back empty. Such outcomes are not kept by the
+/// answer cache, so each query would start a new resolution: here they
+/// are cached for a while instead, in a small cache of their own so that
+/// a storm of lookups can't push the other entries out. The resolutions
+/// started from this path are also limited to `rate` per second, the
+/// queries over it failing right away. Its hits and misses are counted
+/// with those of the answer cache, failed and empty lookups as negative.
+#[derive(Debug)]
+pub struct ReversePath {
+    metrics:      Arc<Metrics>,
+    negative_ttl: Duration,
+    interval:     Duration,
+    tolerance:    Duration,
+    entries:      Mutex<HashMap<String, (Outcome, Instant)>>,
+    /// Theoretical arrival time of the next resolution.
+    tat:          Mutex<Instant>,
+}
+
+impl ReversePath {
+    /// Creates the path, caching failed and empty lookups for
+    /// `negative_ttl`, and reporting its activity to `metrics`. A zero
+    /// `rate` disables the rate limit.
+    pub fn new(rate: u32, negative_ttl: Duration, metrics: Arc<Metrics>) -> Self {
+        let interval = if rate == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(1) / rate
+        };
+
+        ReversePath {
+            metrics,
+            negative_ttl,
+            interval,
+            tolerance: interval * rate.saturating_sub(1),
+            entries:   Mutex::new(HashMap::new()),
+            tat:       Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Returns the cached outcome of the lookup of `qname`, if any.
+    pub fn get(&self, qname: &str) -> Option<Outcome> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = qname.trim_end_matches('.').to_ascii_lowercase();
+
+        let Some((outcome, expires)) = entries.get(&key) else {
+            self.metrics.cache_event(Type::PTR, true, CacheEvent::Miss);
+            return None;
+        };
+        let now = Instant::now();
+        if *expires <= now {
+            self.metrics.cache_event(Type::PTR, is_positive(outcome), CacheEvent::Expired);
+            entries.remove(&key);
+            return None;
+        }
+        self.metrics.cache_event(Type::PTR, is_positive(outcome), CacheEvent::Hit);
+
+        let remaining = (*expires - now).as_secs() as u32;
+        Some(outcome.clone().map(|answers| {
+            answers
+                .into_iter()
+                .map(|mut answer| {
+                    answer.ttl = answer.ttl.min(remaining);
+                    answer
+                })
+                .collect()
+        }))
+    }
+
+    /// Returns whether a new resolution may start now.
+    pub fn admit(&self) -> bool {
+        if self.interval.is_zero() {
+            return true;
+        }
+
+        let mut tat = self.tat.lock().unwrap();
+        let now = Instant::now();
+        if *tat < now {
+            *tat = now;
+        }
+        if tat.saturating_duration_since(now) > self.tolerance {
+            return false;
+        }
+        *tat += self.interval;
+        true
+    }
+
+    /// Caches the outcome of the lookup of `qname`.
+    pub fn insert(&self, qname: &str, outcome: &Outcome) {
+        let ttl = match outcome {
+            Ok(answers) if !answers.is_empty() => {
+                let ttl = answers.iter().map(|answer| answer.ttl).min().unwrap_or(0);
+                Duration::from_secs(ttl as u64).min(MAX_TTL)
+            }
+            _ => self.negative_ttl,
+        };
+        if ttl.is_zero() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, (_, expires)| *expires > now);
+        }
+        if entries.len() >= MAX_ENTRIES
+            && let Some(key) = entries
+                .iter()
+                .min_by_key(|(_, (_, expires))| *expires)
+                .map(|(key, _)| key.clone())
+            && let Some((evicted, _)) = entries.remove(&key)
+        {
+            self.metrics.cache_event(Type::PTR, is_positive(&evicted), CacheEvent::Evicted);
+        }
+
+        entries.insert(
+            qname.trim_end_matches('.').to_ascii_lowercase(),
+            (outcome.clone(), now + ttl),
+        );
+    }
+}
+
+/// Returns whether a cached outcome carries answers.
+fn is_positive(outcome: &Outcome) -> bool {
+    outcome.as_ref().is_ok_and(|answers| !answers.is_empty())
+}
@@ -0,0 +1,229 @@
+use crate::{
+    compare::target,
+    dns::{class_name, rcode_name},
+    rng::DnsRng,
+    types::{AnswerRecord, Dns, DnsError, DnsReadBuffer, Type},
+};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time,
+};
+
+/// How long to wait for the response.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// UDP payload size advertised in the query.
+const UDP_SIZE: u16 = 1232;
+
+/// Options of the `query` subcommand.
+#[derive(Debug)]
+struct Options {
+    /// Name asked for.
+    name:   String,
+    /// Type asked for.
+    qtype:  Type,
+    /// Server the query is sent to.
+    server: SocketAddr,
+    /// Whether the query goes over TCP rather than UDP.
+    tcp:    bool,
+}
+
+impl Options {
+    /// Parses the arguments following `query`, in any order: the name,
+    /// then the type, `@server` and `+tcp`.
+    fn parse(args: impl Iterator<Item = String>, server: SocketAddr) -> Result<Self, DnsError> {
+        let usage = || DnsError::IOError("usage: query <name> [type] [@server] [+tcp]".into());
+        let mut name   = None;
+        let mut qtype  = None;
+        let mut server = server;
+        let mut tcp    = false;
+        for arg in args {
+            match arg.as_str() {
+                "+tcp"   => tcp = true,
+                "+notcp" => tcp = false,
+                _ if arg.starts_with('@') => server = target(&arg[1..]).ok_or_else(usage)?,
+                _ if arg.starts_with('+') => return Err(usage()),
+                _ if name.is_none()  => name  = Some(arg.trim_end_matches('.').to_string()),
+                _ if qtype.is_none() => qtype = Some(arg.parse().map_err(|_| usage())?),
+                _ => return Err(usage()),
+            }
+        }
+        Ok(Options {
+            name:  name.ok_or_else(usage)?,
+            qtype: qtype.unwrap_or(Type::A),
+            server,
+            tcp,
+        })
+    }
+}
+
+/// Runs the `query` subcommand: asks `server` (the local instance unless
+/// told otherwise) a single question, recursion desired, and prints the
+/// response the way `dig` does.
+///
+/// The query goes over UDP, and again over TCP if the response is
+/// truncated, or straight over TCP with `+tcp`.
+pub async fn run(args: impl Iterator<Item = String>, server: SocketAddr) -> Result<(), DnsError> {
+    let args: Vec<String> = args.collect();
+    let options = Options::parse(args.iter().cloned(), server)?;
+    println!("; <<>> dns-resolver <<>> {}", args.join(" "));
+
+    let mut query = Dns::new_question(&options.name, options.qtype, DnsRng::from_entropy().query_id());
+    query.header.flags.rd = true;
+    query.set_edns(UDP_SIZE);
+    let query = query.encode()?.into_inner();
+
+    let start = Instant::now();
+    let mut tcp = options.tcp;
+    let mut message = match tcp {
+        true  => ask_tcp(&query, options.server).await?,
+        false => ask_udp(&query, options.server).await?,
+    };
+    let mut response = Dns::decode(&mut DnsReadBuffer::new(&message))?;
+    if !tcp && response.header.flags.tc {
+        println!(";; Truncated, retrying in TCP mode.");
+        tcp = true;
+        message  = ask_tcp(&query, options.server).await?;
+        response = Dns::decode(&mut DnsReadBuffer::new(&message))?;
+    }
+    let elapsed = start.elapsed();
+
+    print_message(&response);
+    println!();
+    println!(";; Query time: {} msec", elapsed.as_millis());
+    println!(";; SERVER: {}#{} ({})", options.server.ip(), options.server.port(), if tcp { "TCP" } else { "UDP" });
+    println!(";; MSG SIZE  rcvd: {}", message.len());
+    Ok(())
+}
+
+/// Sends `query` to `server` over UDP, returning its response.
+async fn ask_udp(query: &[u8], server: SocketAddr) -> Result<Vec<u8>, DnsError> {
+    let local = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let sock = UdpSocket::bind(local).await.map_err(|_| DnsError::SocketError)?;
+    sock.connect(server).await.map_err(|_| DnsError::SocketError)?;
+    sock.send(query).await.map_err(|_| DnsError::SocketError)?;
+
+    // Whatever doesn't answer the query, such as a late response to an
+    // earlier one, is skipped
+    let mut buffer = [0u8; 65535];
+    time::timeout(QUERY_TIMEOUT, async {
+        loop {
+            let size = sock.recv(&mut buffer).await.map_err(|_| DnsError::SocketError)?;
+            if answers(query, &buffer[..size]) {
+                return Ok(buffer[..size].to_vec());
+            }
+        }
+    })
+    .await
+    .map_err(|_| DnsError::Timeout)?
+}
+
+/// Sends `query` to `server` over TCP, returning its response.
+async fn ask_tcp(query: &[u8], server: SocketAddr) -> Result<Vec<u8>, DnsError> {
+    time::timeout(QUERY_TIMEOUT, async {
+        let mut stream = TcpStream::connect(server)
+            .await
+            .map_err(|e| DnsError::IOError(format!("can't reach {}: {}", server, e)))?;
+        let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(query);
+        stream.write_all(&framed).await.map_err(|_| DnsError::SocketError)?;
+
+        let length = stream.read_u16().await.map_err(|_| DnsError::SocketError)?;
+        let mut message = vec![0u8; length as usize];
+        stream.read_exact(&mut message).await.map_err(|_| DnsError::SocketError)?;
+        match answers(query, &message) {
+            true  => Ok(message),
+            false => Err(DnsError::IOError("the response doesn't answer the query".into())),
+        }
+    })
+    .await
+    .map_err(|_| DnsError::Timeout)?
+}
+
+/// Returns whether `message` is a response carrying the ID of `query`.
+fn answers(query: &[u8], message: &[u8]) -> bool {
+    message.len() >= 12 && message[2] & 0x80 != 0 && message[..2] == query[..2]
+}
+
+/// Prints a message in the presentation format of `dig`: the header, the
+/// OPT pseudo-record, then the sections that aren't empty.
+fn print_message(dns: &Dns) {
+    let header = &dns.header;
+    let flags  = &header.flags;
+    let set: Vec<&str> = [
+        (flags.qr, "qr"),
+        (flags.aa, "aa"),
+        (flags.tc, "tc"),
+        (flags.rd, "rd"),
+        (flags.ra, "ra"),
+    ]
+    .into_iter()
+    .filter_map(|(on, name)| on.then_some(name))
+    .collect();
+
+    println!(";; Got answer:");
+    println!(";; ->>HEADER<<- opcode: {}, status: {}, id: {}", opcode_name(flags.opcode), rcode_name(dns.rcode()), header.id);
+    println!(
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        set.join(" "),
+        header.qd_count,
+        header.an_count,
+        header.ns_count,
+        header.ar_count,
+    );
+
+    if let Some(opt) = &dns.opt {
+        println!();
+        println!(";; OPT PSEUDOSECTION:");
+        println!(
+            "; EDNS: version: {}, flags:{}; udp: {}",
+            opt.version,
+            if opt.dnssec_ok { " do" } else { "" },
+            opt.udp_size,
+        );
+    }
+
+    if !dns.questions.is_empty() {
+        println!();
+        println!(";; QUESTION SECTION:");
+        for question in &dns.questions {
+            println!(";{}.\t\t{}\t{}", question.qname.trim_end_matches('.'), class_name(question.qclass), question.qtype);
+        }
+    }
+
+    for (title, records) in [("ANSWER", &dns.answers), ("AUTHORITY", &dns.authorities), ("ADDITIONAL", &dns.additionals)] {
+        print_section(title, records);
+    }
+}
+
+/// Prints a section of records, one per line, unless it's empty.
+fn print_section(title: &str, records: &[AnswerRecord]) {
+    if records.is_empty() {
+        return;
+    }
+    println!();
+    println!(";; {} SECTION:", title);
+    for record in records {
+        println!("{}", record);
+    }
+}
+
+/// Returns the mnemonic of an operation code, or its `OPCODEnn` form.
+fn opcode_name(opcode: u8) -> String {
+    match opcode {
+        0 => "QUERY".to_string(),
+        1 => "IQUERY".to_string(),
+        2 => "STATUS".to_string(),
+        4 => "NOTIFY".to_string(),
+        5 => "UPDATE".to_string(),
+        n => format!("OPCODE{}", n),
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    logging,
+    types::{DnsError, Type},
+};
+use std::{
+    fs::OpenOptions,
+    io::ErrorKind,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+    time::Instant,
+};
+
+/// Lines waiting to be written. Beyond that, the queries aren't logged
+/// rather than slowed down.
+const QUEUE_LENGTH: usize = 8192;
+
+/// When the log files are rotated, and how many of the old ones are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// Size past which the file is rotated, in bytes, never if 0.
+    pub size:     u64,
+    /// Age past which the file is rotated, never if zero.
+    pub interval: Duration,
+    /// Old files kept, as `<path>.1` (the newest) to `<path>.<keep>`.
+    pub keep:     usize,
+}
+
+/// Log of every query answered, one line per query with the time, the
+/// client, the question, the response code and the latency.
+///
+/// The lines are queued and written by a task of their own, through a
+/// buffer flushed whenever the queue is empty, so that the queries never
+/// wait on the disk.
+#[derive(Debug)]
+pub struct QueryLog {
+    lines: mpsc::Sender<String>,
+}
+
+impl QueryLog {
+    /// Opens the query log, appending to `path` and rotating it as set.
+    pub fn open(path: &Path, rotation: Rotation) -> Result<Self, DnsError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| DnsError::IOError(format!("can't open query log: {}", e)))?;
+
+        let (lines, queue) = mpsc::channel(QUEUE_LENGTH);
+        tokio::spawn(write(path.to_path_buf(), File::from_std(file), rotation, queue));
+        Ok(QueryLog { lines })
+    }
+
+    /// Records a query.
+    pub fn record(&self, client: SocketAddr, qname: &str, qtype: Type, rcode: &str, latency: Duration) {
+        let _ = self.lines.try_send(format!(
+            "{} client={} qname={} qtype={} rcode={} latency_us={}\n",
+            logging::rfc3339_now(),
+            client,
+            qname,
+            qtype,
+            rcode,
+            latency.as_micros(),
+        ));
+    }
+}
+
+/// Writes the queued lines to `file`, rotating it when it gets too large
+/// or too old, until the queue is closed.
+async fn write(path: PathBuf, file: File, rotation: Rotation, mut queue: mpsc::Receiver<String>) {
+    let mut size   = file.metadata().await.map_or(0, |meta| meta.len());
+    let mut opened = Instant::now();
+    let mut file   = Some(BufWriter::new(file));
+
+    while let Some(mut line) = queue.recv().await {
+        loop {
+            let full  = rotation.size > 0 && size > 0 && size + line.len() as u64 > rotation.size;
+            let stale = !rotation.interval.is_zero() && opened.elapsed() >= rotation.interval;
+            if full || stale {
+                if let Some(mut old) = file.take() {
+                    let _ = old.flush().await;
+                }
+                file = match rotate(&path, rotation.keep).await {
+                    Ok(new) => Some(BufWriter::new(new)),
+                    Err(e)  => {
+                        tracing::error!(path = %path.display(), error = %e, "can't rotate the query log");
+                        fs::OpenOptions::new().create(true).append(true).open(&path).await.ok().map(BufWriter::new)
+                    }
+                };
+                size   = 0;
+                opened = Instant::now();
+            }
+
+            // Lines are dropped when the file can't be opened again
+            if let Some(out) = file.as_mut() {
+                if let Err(e) = out.write_all(line.as_bytes()).await {
+                    tracing::error!(path = %path.display(), error = %e, "can't write the query log");
+                }
+                size += line.len() as u64;
+            }
+            match queue.try_recv() {
+                Ok(next) => line = next,
+                Err(_)   => break,
+            }
+        }
+        if let Some(out) = file.as_mut() {
+            let _ = out.flush().await;
+        }
+    }
+}
+
+/// Moves the log file at `path` to `<path>.1`, shifting the older ones and
+/// removing the one past `keep`, and opens a new one.
+async fn rotate(path: &Path, keep: usize) -> std::io::Result<File> {
+    let old = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+
+    for n in (1..keep).rev() {
+        match fs::rename(old(n), old(n + 1)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    let moved = match keep {
+        0 => fs::remove_file(path).await,
+        _ => fs::rename(path, old(1)).await,
+    };
+    match moved {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => File::create(path).await,
+    }
+}
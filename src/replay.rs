@@ -0,0 +1,309 @@
+use crate::types::{Dns, DnsError, DnsReadBuffer, Type};
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    process,
+    time::Duration,
+};
+use tokio::{net::UdpSocket, task::JoinSet, time};
+
+/// How long to wait for the answer to a replayed query.
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Link types of the pcap captures that can be read.
+const LINKTYPE_ETHERNET:  u32 = 1;
+const LINKTYPE_RAW:       u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+/// A query read from a capture, with the response it got back then.
+#[derive(Debug)]
+struct Captured {
+    /// When the query was sent, since the start of the capture.
+    at:       Duration,
+    /// The query, as sent by the client.
+    query:    Vec<u8>,
+    /// The response captured along with it, if any.
+    response: Option<Vec<u8>>,
+}
+
+/// Options of the `replay` subcommand.
+#[derive(Debug)]
+struct Options {
+    /// Query log or pcap capture to replay.
+    path:     PathBuf,
+    /// Instance the queries are sent to.
+    server:   SocketAddr,
+    /// Instance whose answers are the reference, instead of the captured
+    /// responses.
+    baseline: Option<SocketAddr>,
+    /// How many times faster than recorded the queries are sent, 0 for as
+    /// fast as possible.
+    speed:    f64,
+}
+
+impl Options {
+    /// Parses the arguments following `replay`.
+    fn parse(mut args: impl Iterator<Item = String>, server: SocketAddr) -> Result<Self, DnsError> {
+        let usage = || DnsError::IOError("usage: replay <log or pcap> [--server addr] [--baseline addr] [--speed factor]".into());
+        let mut path     = None;
+        let mut server   = server;
+        let mut baseline = None;
+        let mut speed    = 1.0;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(usage);
+            match arg.as_str() {
+                "--server"   => server   = value()?.parse().map_err(|_| usage())?,
+                "--baseline" => baseline = Some(value()?.parse().map_err(|_| usage())?),
+                "--speed"    => speed    = value()?.parse().map_err(|_| usage())?,
+                _ if path.is_none() && !arg.starts_with("--") => path = Some(PathBuf::from(arg)),
+                _ => return Err(usage()),
+            }
+        }
+        if speed < 0.0 {
+            return Err(usage());
+        }
+        Ok(Options { path: path.ok_or_else(usage)?, server, baseline, speed })
+    }
+}
+
+/// Runs the `replay` subcommand: sends the queries of a capture to
+/// `server` (the local instance unless told otherwise) at the recorded
+/// pace, and reports the answers that differ from the captured responses
+/// or from those of a baseline instance.
+///
+/// The process exits with status 1 if any answer differs.
+pub async fn run(args: impl Iterator<Item = String>, server: SocketAddr) -> Result<(), DnsError> {
+    let options  = Options::parse(args, server)?;
+    let data     = fs::read(&options.path)
+        .map_err(|e| DnsError::IOError(format!("can't read {}: {}", options.path.display(), e)))?;
+    let captured = if is_pcap(&data) { read_pcap(&data)? } else { read_log(&data) };
+
+    // Send the queries on schedule, each waiting for its answers on its own
+    let start     = time::Instant::now();
+    let mut tasks = JoinSet::new();
+    for (n, entry) in captured.iter().enumerate() {
+        if options.speed > 0.0 {
+            time::sleep_until(start + entry.at.div_f64(options.speed)).await;
+        }
+        let query    = entry.query.clone();
+        let server   = options.server;
+        let baseline = options.baseline;
+        tasks.spawn(async move {
+            let (answer, expected) = match baseline {
+                Some(baseline) => tokio::join!(ask(&query, server), ask(&query, baseline)),
+                None           => (ask(&query, server).await, None),
+            };
+            (n, answer, expected)
+        });
+    }
+
+    let mut results = Vec::with_capacity(captured.len());
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok(result) = joined {
+            results.push(result);
+        }
+    }
+    results.sort_by_key(|(n, ..)| *n);
+
+    // Compare the answers with the reference
+    let (mut same, mut different, mut unchecked) = (0, 0, 0);
+    for (n, answer, expected) in results {
+        let expected = expected.or_else(|| captured[n].response.clone());
+        let Some(expected) = expected else {
+            unchecked += 1;
+            continue;
+        };
+        let expected = summary(&expected);
+        let answer   = answer.as_deref().map_or("no answer".to_string(), summary);
+        if answer == expected {
+            same += 1;
+        } else {
+            different += 1;
+            println!("{}: expected {}, got {}", question(&captured[n].query), expected, answer);
+        }
+    }
+
+    println!(
+        "replayed {} queries: {} same, {} different, {} not compared",
+        captured.len(), same, different, unchecked,
+    );
+    if different > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Sends `query` to `server` and returns its answer, if it comes in time.
+pub async fn ask(query: &[u8], server: SocketAddr) -> Option<Vec<u8>> {
+    let local = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let sock = UdpSocket::bind(local).await.ok()?;
+    sock.connect(server).await.ok()?;
+    sock.send(query).await.ok()?;
+
+    let mut buffer = [0u8; 65535];
+    let size = time::timeout(REPLAY_TIMEOUT, sock.recv(&mut buffer)).await.ok()?.ok()?;
+    Some(buffer[..size].to_vec())
+}
+
+/// Describes the question of a message, such as `www.example.com A`.
+fn question(msg: &[u8]) -> String {
+    match Dns::decode(&mut DnsReadBuffer::new(msg)) {
+        Ok(dns) => dns
+            .questions
+            .first()
+            .map_or("(no question)".to_string(), |q| format!("{} {}", q.qname, q.qtype)),
+        Err(_) => "(malformed query)".to_string(),
+    }
+}
+
+/// Describes the outcome of a response in a form that doesn't depend on
+/// the TTLs or the order of the records, such as `NOERROR [A 192.0.2.1]`.
+pub fn summary(msg: &[u8]) -> String {
+    let Ok(dns) = Dns::decode(&mut DnsReadBuffer::new(msg)) else {
+        return "malformed response".to_string();
+    };
+    let rcode = match dns.header.flags.rcode {
+        0 => "NOERROR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        5 => "REFUSED".to_string(),
+        n => format!("RCODE{}", n),
+    };
+    let mut records: Vec<String> = dns
+        .answers
+        .iter()
+        .map(|answer| format!("{} {} {:?}", answer.aname.to_ascii_lowercase(), answer.atype, answer.rdata))
+        .collect();
+    records.sort();
+    format!("{} [{}]", rcode, records.join(", "))
+}
+
+/// Reads the queries of the slow-query log, the lines of which look like
+/// `1700000000 client=... qname=www.example.com qtype=A ...`. The log
+/// doesn't record the answers, so they can only be compared with those of
+/// a baseline instance.
+fn read_log(data: &[u8]) -> Vec<Captured> {
+    let text  = String::from_utf8_lossy(data);
+    let mut first   = None;
+    let mut queries = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(timestamp) = fields.next().and_then(|ts| ts.parse::<u64>().ok()) else {
+            continue;
+        };
+        let fields: HashMap<&str, &str> = fields.filter_map(|field| field.split_once('=')).collect();
+        let (Some(qname), Some(qtype)) = (fields.get("qname"), fields.get("qtype").and_then(|t| t.parse::<Type>().ok())) else {
+            continue;
+        };
+
+        let first = *first.get_or_insert(timestamp);
+        let mut query = Dns::new_question(qname, qtype, queries.len() as u16);
+        query.header.flags.rd = true;
+        let Ok(query) = query.encode() else {
+            continue;
+        };
+        queries.push(Captured {
+            at:       Duration::from_secs(timestamp.saturating_sub(first)),
+            query:    query.data,
+            response: None,
+        });
+    }
+    queries
+}
+
+/// Returns whether `data` is a pcap capture, in either byte order and
+/// with either timestamp resolution.
+fn is_pcap(data: &[u8]) -> bool {
+    data.len() >= 24 && matches!(&data[..4], [0xd4, 0xc3, 0xb2, 0xa1] | [0xa1, 0xb2, 0xc3, 0xd4] | [0x4d, 0x3c, 0xb2, 0xa1] | [0xa1, 0xb2, 0x3c, 0x4d])
+}
+
+/// Reads the DNS queries sent over UDP in a pcap capture, along with the
+/// responses they got, matched by client and ID.
+fn read_pcap(data: &[u8]) -> Result<Vec<Captured>, DnsError> {
+    let invalid  = || DnsError::IOError("invalid pcap capture".into());
+    let little   = data[0] == 0xd4 || data[0] == 0x4d;
+    let nanos    = data[0] == 0x4d || data[3] == 0x4d;
+    let read_u32 = |at: usize| -> Option<u32> {
+        let bytes: [u8; 4] = data.get(at..at + 4)?.try_into().ok()?;
+        Some(if little { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+
+    let linktype = read_u32(20).ok_or_else(invalid)?;
+    let header   = match linktype {
+        LINKTYPE_ETHERNET  => 14,
+        LINKTYPE_RAW       => 0,
+        LINKTYPE_LINUX_SLL => 16,
+        _ => return Err(DnsError::IOError(format!("unsupported pcap link type {}", linktype))),
+    };
+
+    let mut first    = None;
+    let mut captured = Vec::new();
+    let mut pending: HashMap<(SocketAddr, u16), usize> = HashMap::new();
+    let mut offset   = 24;
+    while offset + 16 <= data.len() {
+        let seconds  = read_u32(offset).ok_or_else(invalid)?;
+        let fraction = read_u32(offset + 4).ok_or_else(invalid)?;
+        let length   = read_u32(offset + 8).ok_or_else(invalid)? as usize;
+        let frame    = data.get(offset + 16..offset + 16 + length).ok_or_else(invalid)?;
+        offset      += 16 + length;
+
+        let Some((src, dst, payload)) = frame.get(header..).and_then(udp) else {
+            continue;
+        };
+        if payload.len() < 12 {
+            continue;
+        }
+        let id       = u16::from_be_bytes([payload[0], payload[1]]);
+        let response = payload[2] & 0x80 != 0;
+
+        let at = Duration::from_secs(seconds as u64)
+            + if nanos { Duration::from_nanos(fraction as u64) } else { Duration::from_micros(fraction as u64) };
+        let first = *first.get_or_insert(at);
+
+        if !response && dst.port() == 53 {
+            pending.insert((src, id), captured.len());
+            captured.push(Captured { at: at.saturating_sub(first), query: payload.to_vec(), response: None });
+        } else if response && src.port() == 53
+            && let Some(n) = pending.remove(&(dst, id))
+        {
+            captured[n].response = Some(payload.to_vec());
+        }
+    }
+    Ok(captured)
+}
+
+/// Extracts the source, the destination and the payload of a UDP datagram
+/// carried by an IPv4 or IPv6 packet.
+fn udp(packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (src, dst, datagram): (IpAddr, IpAddr, &[u8]) = match packet.first()? >> 4 {
+        4 => {
+            let ihl = (packet[0] & 0x0f) as usize * 4;
+            if *packet.get(9)? != 17 {
+                return None;
+            }
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (src.into(), dst.into(), packet.get(ihl..)?)
+        }
+        6 => {
+            if *packet.get(6)? != 17 {
+                return None;
+            }
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (src.into(), dst.into(), packet.get(40..)?)
+        }
+        _ => return None,
+    };
+
+    let src_port = u16::from_be_bytes([*datagram.first()?, *datagram.get(1)?]);
+    let dst_port = u16::from_be_bytes([*datagram.get(2)?, *datagram.get(3)?]);
+    let length   = u16::from_be_bytes([*datagram.get(4)?, *datagram.get(5)?]) as usize;
+    let payload  = datagram.get(8..length.max(8))?;
+    Some((SocketAddr::new(src, src_port), SocketAddr::new(dst, dst_port), payload))
+}
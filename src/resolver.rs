@@ -1,135 +1,570 @@
 use crate::{
     contact,
+    infra::InfraCache,
+    pacer::Pacer,
+    rng::DnsRng,
+    routing::Route,
+    sockets::SocketPool,
+    timeouts::Timeouts,
     types::{AnswerRecord, Dns, DnsError, DnsReadBuffer, RData, Type},
 };
 use async_recursion::async_recursion;
-use std::net::Ipv4Addr;
+use std::{
+    fmt,
+    future,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    task::Poll,
+};
+#[cfg(any(feature = "doh", feature = "doq", feature = "dot"))]
+use crate::timeouts::RetryPolicy;
+
+/// Response code of the servers not supporting the EDNS version of a query
+/// (RFC 6891, section 9).
+const BADVERS: u16 = 16;
+
+/// How the queries for the root and the top-level domains are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApexMode {
+    /// Answer from the data of the root server.
+    Root,
+    /// Refuse them.
+    Refuse,
+}
+
+impl FromStr for ApexMode {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "root"   => Ok(ApexMode::Root),
+            "refuse" => Ok(ApexMode::Refuse),
+            _ => Err(DnsError::IOError(format!("invalid apex mode: {}", s))),
+        }
+    }
+}
+
+/// How the queries that don't ask for recursion (RD=0) are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonRecursiveMode {
+    /// Answer from the cache and the local data only.
+    Cache,
+    /// Resolve them anyway.
+    Recurse,
+    /// Refuse them, unless they are answered locally.
+    Refuse,
+}
+
+impl FromStr for NonRecursiveMode {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cache"   => Ok(NonRecursiveMode::Cache),
+            "recurse" => Ok(NonRecursiveMode::Recurse),
+            "refuse"  => Ok(NonRecursiveMode::Refuse),
+            _ => Err(DnsError::IOError(format!("invalid non-recursive mode: {}", s))),
+        }
+    }
+}
+
+/// Address families the name servers are contacted over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamFamily {
+    /// IPv4 only, from the A records of the name servers.
+    Ipv4,
+    /// IPv6 only, from their AAAA records, for hosts without IPv4.
+    Ipv6,
+    /// Both, on dual-stack hosts.
+    Dual,
+}
+
+impl UpstreamFamily {
+    /// Returns whether the name servers are contacted at `ip`.
+    pub fn allows(self, ip: IpAddr) -> bool {
+        match self {
+            UpstreamFamily::Ipv4 => ip.is_ipv4(),
+            UpstreamFamily::Ipv6 => ip.is_ipv6(),
+            UpstreamFamily::Dual => true,
+        }
+    }
 
+    /// Types of the address records of the name servers.
+    fn types(self) -> &'static [Type] {
+        match self {
+            UpstreamFamily::Ipv4 => &[Type::A],
+            UpstreamFamily::Ipv6 => &[Type::AAAA],
+            UpstreamFamily::Dual => &[Type::AAAA, Type::A],
+        }
+    }
+
+    /// Returns the address of a name server given by an address record,
+    /// if it is contacted over its family.
+    fn address(self, rdata: &RData) -> Option<IpAddr> {
+        let ip = rdata.as_a().map(IpAddr::V4).or_else(|| rdata.as_aaaa().map(IpAddr::V6))?;
+        self.allows(ip).then_some(ip)
+    }
+}
+
+impl FromStr for UpstreamFamily {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ipv4" => Ok(UpstreamFamily::Ipv4),
+            "ipv6" => Ok(UpstreamFamily::Ipv6),
+            "dual" => Ok(UpstreamFamily::Dual),
+            _ => Err(DnsError::IOError(format!("invalid upstream family: {}", s))),
+        }
+    }
+}
+
+/// Servers contacted while resolving a query, in order.
+#[derive(Debug, Default)]
+pub struct Trace {
+    steps: Mutex<Vec<(SocketAddr, String)>>,
+}
+
+impl Trace {
+    /// Creates an empty trace.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `server` was asked about `domain`.
+    fn push(&self, server: SocketAddr, domain: &str) {
+        self.steps.lock().unwrap().push((server, domain.to_owned()));
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let steps = self.steps.lock().unwrap();
+        for (i, (server, domain)) in steps.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}@{}", domain, server)?;
+        }
+        Ok(())
+    }
+}
+
+/// State carried along a single resolution.
+pub struct Context {
+    /// Servers contacted so far.
+    pub trace: Trace,
+    /// Root server the resolution starts over from.
+    root: SocketAddr,
+    /// Knowledge about the upstream servers, shared across resolutions.
+    infra: Arc<InfraCache>,
+    /// Whether to randomize the case of the outgoing query names.
+    use_0x20: bool,
+    /// Port the name servers found through referrals are queried on.
+    delegation_port: u16,
+    /// Name servers of a zone queried at once.
+    fanout: usize,
+    /// Address families the name servers are contacted over.
+    family: UpstreamFamily,
+    /// Rate limiter of the queries sent to each server.
+    pacer: Arc<Pacer>,
+    /// Retry policies of the queries of specific zones or upstreams.
+    timeouts: Arc<Timeouts>,
+    /// Sockets the queries are sent from, with the source of the query
+    /// IDs, source ports and server selection.
+    sockets: Arc<SocketPool>,
+}
+
+impl Context {
+    /// Creates the context for a new resolution.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        root:            SocketAddr,
+        infra:           Arc<InfraCache>,
+        use_0x20:        bool,
+        delegation_port: u16,
+        fanout:          usize,
+        family:          UpstreamFamily,
+        pacer:           Arc<Pacer>,
+        timeouts:        Arc<Timeouts>,
+        sockets:         Arc<SocketPool>,
+    ) -> Self {
+        Context {
+            trace: Trace::new(),
+            root,
+            infra,
+            use_0x20,
+            delegation_port,
+            fanout: fanout.max(1),
+            family,
+            pacer,
+            timeouts,
+            sockets,
+        }
+    }
+
+    /// Returns the root server the resolution starts from.
+    pub fn root(&self) -> SocketAddr {
+        self.root
+    }
+
+    /// Returns the random generator of the resolution.
+    fn rng(&self) -> &DnsRng {
+        self.sockets.rng()
+    }
+}
+
+/// Sends a query for the `qtype` records of `domain` to `address` and
+/// returns its response.
+///
+/// Servers that keep failing are put on hold for an exponentially growing
+/// time, shared across the resolutions through the infrastructure cache:
+/// for every zone after timeouts or garbage, and only for `zone`, the one
+/// they were asked as servers of, after error or lame responses. Once the
+/// hold expires, the next query acts as a probe that rehabilitates the
+/// server if it succeeds.
+///
+/// With `recursive`, the server is a recursive resolver asked to do the
+/// whole resolution: its answers are not expected to be authoritative.
+async fn query(
+    domain:    &str,
+    qtype:     Type,
+    address:   SocketAddr,
+    zone:      &str,
+    recursive: bool,
+    ctx:       &Context,
+) -> Result<Dns, DnsError> {
+    if ctx.infra.is_held_down(address.ip(), zone) {
+        return Err(DnsError::IOError(format!("server {} is held down", address)));
+    }
+
+    match exchange(domain, qtype, address, recursive, ctx).await {
+        Ok(res) if is_lame(&res, recursive) => {
+            ctx.infra.record_lame(address.ip(), zone);
+            Err(DnsError::IOError(format!("lame response from {}", address)))
+        }
+        Ok(res) => {
+            ctx.infra.record_success(address.ip(), zone);
+            Ok(res)
+        }
+        Err(e @ (DnsError::Timeout | DnsError::InvalidField | DnsError::InvalidRData)) => {
+            ctx.infra.record_failure(address.ip());
+            Err(e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns whether a response shows that the server can't help: it
+/// reported an error, or, unless it is a recursive resolver, it neither
+/// answered nor referred us elsewhere without being authoritative for the
+/// name.
+fn is_lame(res: &Dns, recursive: bool) -> bool {
+    // FORMERR, SERVFAIL, NOTIMP and REFUSED
+    if matches!(res.header.flags.rcode, 1 | 2 | 4 | 5) {
+        return true;
+    }
+    if recursive {
+        return false;
+    }
+
+    let referral = res
+        .authorities
+        .iter()
+        .any(|auth| auth.atype == Type::NS);
+
+    !res.header.flags.aa && res.answers.is_empty() && !referral
+}
+
+/// Sends a query for the `qtype` records of `domain` to `address` and
+/// decodes the response.
+///
+/// The query advertises the UDP payload size known to work with the
+/// server. When it times out with a large size, the query is retried with
+/// a smaller one, and the server is remembered as needing it. When it
+/// still times out with the smallest size, or the server rejects EDNS or
+/// answers with a malformed response, the query is retried without EDNS,
+/// and the server is remembered as needing that if it then answers.
+/// Otherwise, it is retried as many times as the retry policy of the name
+/// or of the server allows, waiting twice as long each time.
+///
+/// With 0x20 enabled, the case of the name is randomized and the response
+/// is only accepted if its question echoes it exactly.
+async fn exchange(
+    domain:    &str,
+    qtype:     Type,
+    address:   SocketAddr,
+    recursive: bool,
+    ctx:       &Context,
+) -> Result<Dns, DnsError> {
+    let mut buffer   = [0u8; 4096];
+    let mut udp_size = ctx.infra.udp_size(address.ip());
+    let mut edns     = ctx.infra.edns(address.ip());
+    let probing      = edns;
+    let policy       = ctx.timeouts.policy(domain, &Route::Udp(address), ctx.timeouts.default_policy());
+    let mut retry    = 0;
+
+    loop {
+        let qname = if ctx.use_0x20 {
+            ctx.rng().randomize_case(domain)
+        } else {
+            domain.to_string()
+        };
+
+        let mut req = Dns::new_question(&qname, qtype, ctx.rng().query_id());
+        req.header.flags.rd = recursive;
+        if edns {
+            req.set_edns(udp_size);
+        }
+
+        ctx.pacer.wait(address.ip(), ctx.rng()).await?;
+        ctx.trace.push(address, domain);
+        let data = req.encode()?.into_inner();
+        match contact::contact(&data, address, &mut buffer, policy.timeout(retry), &ctx.sockets).await {
+            Ok(reply) => {
+                let res = match Dns::decode(&mut DnsReadBuffer::new(reply)) {
+                    Ok(res) if edns && rejects_edns(&res) => {
+                        edns = false;
+                        continue;
+                    }
+                    Ok(res) => res,
+                    Err(_) if edns => {
+                        edns = false;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+
+                // A truncated response is incomplete: the whole of it is
+                // asked again over TCP, never used or cached as it is
+                let res = match res.header.flags.tc {
+                    true  => {
+                        let reply = contact::contact_tcp(&data, address, policy.timeout(retry), &ctx.sockets).await?;
+                        Dns::decode(&mut DnsReadBuffer::new(&reply))?
+                    }
+                    false => res,
+                };
+                if ctx.use_0x20 && res.questions.first().map(|q| q.qname.as_str()) != Some(&qname) {
+                    return Err(DnsError::IOError("response question does not match".into()));
+                }
+                if probing && !edns {
+                    ctx.infra.disable_edns(address.ip());
+                }
+                return Ok(res);
+            }
+            Err(DnsError::Timeout) => match ctx.infra.downgrade(address.ip(), udp_size) {
+                Some(reduced) => udp_size = reduced,
+                None if edns => edns = false,
+                None if retry < policy.retries => retry += 1,
+                None => return Err(DnsError::Timeout),
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Returns whether the response to a query carrying EDNS shows that the
+/// server doesn't support it: it rejected the query without an OPT record
+/// of its own (RFC 6891, section 7), or rejected version 0 of EDNS.
+fn rejects_edns(res: &Dns) -> bool {
+    (matches!(res.header.flags.rcode, 1 | 4) && res.opt.is_none()) || res.rcode() == BADVERS
+}
+
+/// Returns the negative answer a response carries, if any: the name
+/// doesn't exist (NXDOMAIN), or the response has neither answers nor a
+/// referral (NODATA). It may be cached as long as the TTL of the SOA
+/// record of the authority section, and no longer than its minimum field
+/// (RFC 2308, section 5), or not at all without one.
+fn negative(res: &Dns) -> Option<DnsError> {
+    let nxdomain = res.header.flags.rcode == 3;
+    let referral = !res.header.flags.aa && res.authorities.iter().any(|auth| auth.atype == Type::NS);
+    if !nxdomain && (res.header.flags.rcode != 0 || !res.answers.is_empty() || referral) {
+        return None;
+    }
+
+    let ttl = res
+        .authorities
+        .iter()
+        .find_map(|auth| auth.rdata.as_soa().map(|soa| auth.ttl.min(soa.minimum)))
+        .unwrap_or(0);
+    Some(DnsError::Negative { nxdomain, ttl })
+}
+
+/// Splits the answers of a response into the final records and the
+/// canonical names the queried domain is an alias of.
+///
+/// Only the chain of aliases starting from `domain` is followed, and only
+/// as long as it stays within `zone`, the one the server has authority
+/// over: records owned by any other name, which a forged or careless
+/// response could slip in, are ignored.
 fn inspect(
-    answers: &Vec<AnswerRecord>
+    domain:  &str,
+    zone:    &str,
+    answers: &[AnswerRecord]
 ) -> (Vec<RData>, 
-      Vec<RData>, 
       Vec<RData>) {
 
-    let mut ipv4_addresses = Vec::new();
-    let mut ipv6_addresses = Vec::new();
     let mut cnonical_names = Vec::new();
 
-    // Inspect the answers and collect the results
-    for answer in answers {
-        match &answer.rdata {
-            RData::A(addr)     => ipv4_addresses.push(RData::A(*addr)),
-            RData::AAAA(addr)  => ipv6_addresses.push(RData::AAAA(*addr)),
-            RData::CNAME(name)   => cnonical_names.push(RData::CNAME(name.to_owned())),
-            _ => {}
+    // Follow the aliases, in whatever order they come
+    let mut owner = domain;
+    for _ in 0..answers.len() {
+        if !is_within(owner, zone) {
+            break;
+        }
+        let alias = answers
+            .iter()
+            .find(|answer| answer.atype == Type::CNAME && same_name(&answer.aname, owner));
+        match alias.and_then(|answer| answer.rdata.as_cname()) {
+            Some(name) => {
+                cnonical_names.push(RData::CNAME(name.to_owned()));
+                owner = name;
+            }
+            None => break,
         }
     }
 
-    (ipv4_addresses, ipv6_addresses, cnonical_names)
+    // Collect the records of the name the chain ends at
+    let records = answers
+        .iter()
+        .filter(|answer| answer.atype != Type::CNAME && same_name(&answer.aname, owner) && is_within(owner, zone))
+        .map(|answer| answer.rdata.clone())
+        .collect();
+
+    (records, cnonical_names)
 }
 
-#[async_recursion]
+/// Returns whether two domain names are the same, ignoring case and the
+/// trailing dot.
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+/// Returns whether `name` is `zone` or one of its subdomains.
+fn is_within(name: &str, zone: &str) -> bool {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+    zone.is_empty() || name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+/// Resolves the records of type `qtype` of `domain`, starting from the
+/// server at `address`.
+///
+/// The returned records come after the canonical names that were followed
+/// to reach them, if any.
 pub async fn resolve(
     domain:  &str,
-    address: &str,
+    qtype:   Type,
+    address: SocketAddr,
+    depth:   usize,
+    ctx:     &Context,
+) -> Result<Vec<RData>, DnsError> {
+    descend(domain, qtype, address, "", depth, ctx).await
+}
+
+/// Resolves the records of type `qtype` of `domain`, starting from the
+/// server at `address`, which was delegated `zone`.
+///
+/// The server is only trusted within its zone (its bailiwick): it may
+/// refer us to a subzone on the way to `domain`, and give the addresses
+/// of the name servers of that subzone when they are inside its own zone.
+/// Other delegations and addresses are ignored.
+#[async_recursion]
+async fn descend(
+    domain:  &str,
+    qtype:   Type,
+    address: SocketAddr,
+    zone:    &str,
     depth:   usize,
-) -> Result<(Vec<RData>, 
-             Vec<RData>, 
-             Vec<RData>), DnsError> {
+    ctx:     &Context,
+) -> Result<Vec<RData>, DnsError> {
 
     if depth == 0 {
         return Err(DnsError::IOError("max recursion depth reached".into()));
     }
 
-    // Generate a brand new buffer, ask the DNS which is the IPv4 
-    // address associated to domain passed as argument to the 
-    // function. In the end, decode the response into a DNS data 
-    // type and inspect the result
-    let mut buffer = [0u8; 4096];
-    let req = Dns::new_a_question(domain, 0x1234);
-
-    // Request the DNS the response
-    contact::contact(&req.encode()?.data, &format!("{}:53", address), &mut buffer).await?;
-    let res = Dns::decode(&mut DnsReadBuffer::new(&buffer))?;
+    // Ask the DNS which are the records associated to domain passed as
+    // argument to the function. In the end, decode the response into
+    // a DNS data type and inspect the result
+    let res = query(domain, qtype, address, zone, false, ctx).await?;
+    if let Some(negative) = negative(&res) {
+        return Err(negative);
+    }
 
     // Inspect the answers within the response
-    let (ipv4_addresses, 
-         ipv6_addresses, 
-         cnonical_names) = inspect(&res.answers);
-
-    // println!("{:?}", ipv4_addresses);
-    // println!("{:?}", ipv6_addresses);
-    // println!("{:?}", cnonical_names);
+    let (records, 
+         cnonical_names) = inspect(domain, zone, &res.answers);
 
-    // The server name has replied us with some IPv4/IPv6 records,
-    // meaning that we have reached the end of the hierarchy and
-    // we found the IP address of the requested domain
-    if !ipv4_addresses.is_empty() || !ipv6_addresses.is_empty() {
-        return Ok((ipv4_addresses, 
-                   ipv6_addresses, 
-                   cnonical_names));
+    // The server name has replied us with some records, meaning that
+    // we have reached the end of the hierarchy and we found what the
+    // requested domain resolves to
+    if !records.is_empty() {
+        return Ok(cnonical_names.into_iter().chain(records).collect());
     }
 
     // The server name has replied us with the CNAME (Canonical Name)
     // of the domain we are looking for. For instance, looking for
     // www.polito.it which is actually webp01.polito.it. Take the
-    // first one to be resolved
-    if let Some(cname) = cnonical_names.get(0) {
-        return resolve(&cname.as_cname().unwrap(), address, depth - 1).await;
+    // last one of the chain to be resolved. The alias may live in a zone
+    // served by other servers, as with classless reverse delegations
+    // (RFC 2317) where 5.2.0.192.in-addr.arpa points to
+    // 5.0/26.2.0.192.in-addr.arpa, so start over from the root
+    if let Some(cname) = cnonical_names.last() {
+        let records = resolve(cname.as_cname().unwrap(), qtype, ctx.root, depth - 1, ctx).await?;
+        return Ok(cnonical_names.into_iter().chain(records).collect());
     }
     
     // If here, we are not at the end of the hierarchy. We have to ask
-    // next name server the IP address of the requested domain. Get the
-    // list of authorities
-    let authorities: Vec<String> = res
+    // next name server the IP address of the requested domain. Find the
+    // subzone we are referred to, below the zone of the server and on the
+    // way to the domain, and get the list of its authorities
+    let Some(cut) = res
+        .authorities
+        .iter()
+        .find(|auth| {
+            auth.atype == Type::NS
+                && is_within(domain, &auth.aname)
+                && is_within(&auth.aname, zone)
+                && !same_name(&auth.aname, zone)
+        })
+        .map(|auth| auth.aname.clone())
+    else {
+        return Err(DnsError::IOError("no valid answer found".into()));
+    };
+
+    let mut authorities: Vec<String> = res
         .authorities
         .iter()
         .filter_map(|auth| {
-            if Type::from_u16(auth.atype) == Some(Type::NS) {
-                if let RData::NS(ns) = &auth.rdata {
-                    Some(ns.to_owned())
-                } else { None }
+            if auth.atype == Type::NS && same_name(&auth.aname, &cut) {
+                auth.rdata.as_ns().map(|ns| ns.to_owned())
             } else { None }
     }).collect();
-    //println!();
 
     // Using the additional record, find the addresses of such authorities
-    // servers... They are supposed to be included by the name servers...
-    let addresses: Vec<Ipv4Addr> = res
+    // servers... They are supposed to be included by the name servers,
+    // and only believed for the names within their own zone. Those of the
+    // families the servers aren't contacted over are left out
+    let mut addresses: Vec<IpAddr> = res
         .additionals
         .iter()
         .filter_map(|add| {
-            if Type::from_u16(add.atype) == Some(Type::A) {
-                if let RData::A(ip) = &add.rdata {
-                    Some(*ip)
-                } else { None }
+            let glue = authorities.iter().any(|ns| same_name(ns, &add.aname));
+            if glue && is_within(&add.aname, zone) {
+                ctx.family.address(&add.rdata)
             } else { None }
     }).collect();
-    //println!();
-
-    // Take the first authority address and ask the authority server the IP
-    // address which is associated with the domain we are looking for
-    for address in addresses {
-        if let Ok((ipv4_addresses, 
-                   ipv6_addresses, 
-                   cnonical_names)) = resolve(domain, &address.to_string(), depth - 1).await {
-
-            // The server name has replied us with some IPv4/IPv6 records,
-            // meaning that we have reached the end of the hierarchy and
-            // we found the IP address of the requested domain
-            if !ipv4_addresses.is_empty() || !ipv6_addresses.is_empty() {
-                return Ok((ipv4_addresses, 
-                           ipv6_addresses, 
-                           cnonical_names));
-            }
 
-            // The server name has replied us with the CNAME (Canonical Name)
-            // of the domain we are looking for. For instance, looking for
-            // www.polito.it which is actually webp01.polito.it. Take the
-            // first one to be resolved
-            if let Some(cname) = cnonical_names.get(0) {
-                return resolve(&cname.as_cname().unwrap(), &address.to_string(), depth - 1).await;
-            }      
+    // Spread the load among the servers of the zone
+    ctx.rng().shuffle(&mut authorities);
+    ctx.rng().shuffle(&mut addresses);
+
+    // Take the first authority addresses and ask the authority servers the
+    // records which are associated with the domain we are looking for
+    for batch in addresses.chunks(ctx.fanout) {
+        if let Ok(records) = descend_any(domain, qtype, batch, &cut, depth - 1, ctx).await {
+            return Ok(records);
         }
     }
 
@@ -138,25 +573,209 @@ pub async fn resolve(
     // As a consequence, we need to know the IP addresses of the authority
     // servers before continue
     for authority in authorities {
-        let root = "198.41.0.4";
-        if let Ok((ipv4_addresses, 
-                   ipv6_addresses, 
-                   cnonical_names)) = resolve(&authority, root, depth - 1).await {
-                    
-            for ipv4 in ipv4_addresses {
-                if let RData::A(ipv4) = ipv4 {
-                    if let Ok((ipv4_addresses, 
-                               ipv6_addresses, 
-                               cnonical_names)) = resolve(domain, &ipv4.to_string(), depth - 1).await {
-
-                        return Ok((ipv4_addresses, 
-                                   ipv6_addresses, 
-                                   cnonical_names));
-                    }
-                }
+        let mut addresses = Vec::new();
+        for &rtype in ctx.family.types() {
+            if let Ok(records) = resolve(&authority, rtype, ctx.root, depth - 1, ctx).await {
+                addresses.extend(records.iter().filter_map(|record| ctx.family.address(record)));
+            }
+        }
+        for batch in addresses.chunks(ctx.fanout) {
+            if let Ok(records) = descend_any(domain, qtype, batch, &cut, depth - 1, ctx).await {
+                return Ok(records);
             }
         }
     }
 
     Err(DnsError::IOError("no valid answer found".into()))
 }
+
+/// Asks the name servers of `zone` at `addresses` at once, returning the
+/// first records found, or the last error if none are.
+///
+/// The queries still running once records are found are dropped, and
+/// counted as wasted: the responses they might still get are ignored.
+async fn descend_any(
+    domain:    &str,
+    qtype:     Type,
+    addresses: &[IpAddr],
+    zone:      &str,
+    depth:     usize,
+    ctx:       &Context,
+) -> Result<Vec<RData>, DnsError> {
+    let mut attempts: Vec<_> = addresses
+        .iter()
+        .map(|ip| descend(domain, qtype, SocketAddr::from((*ip, ctx.delegation_port)), zone, depth, ctx))
+        .collect();
+    let mut error = DnsError::IOError("no valid answer found".into());
+
+    future::poll_fn(|cx| {
+        let mut i = 0;
+        while i < attempts.len() {
+            match attempts[i].as_mut().poll(cx) {
+                Poll::Ready(Ok(records)) => {
+                    ctx.infra.record_wasted(attempts.len() - 1);
+                    return Poll::Ready(Ok(records));
+                }
+                Poll::Ready(Err(e)) => {
+                    drop(attempts.swap_remove(i));
+                    error = e;
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+        if attempts.is_empty() {
+            Poll::Ready(Err(error.clone()))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Returns whether `domain` is the root or a top-level domain.
+pub fn is_apex(domain: &str) -> bool {
+    !domain.trim_end_matches('.').contains('.')
+}
+
+/// Resolves the records of type `qtype` of the root or of a top-level
+/// domain.
+///
+/// The root server holds the data of its own zone and the delegations of
+/// the top-level domains, so it is asked directly: its answer, or for the
+/// NS records of a top-level domain its referral, is returned as is. The
+/// other records of a top-level domain are resolved as usual.
+pub async fn resolve_apex(
+    domain: &str,
+    qtype:  Type,
+    depth:  usize,
+    ctx:    &Context,
+) -> Result<Vec<RData>, DnsError> {
+    let res = query(domain, qtype, ctx.root, "", false, ctx).await?;
+
+    let (records,
+         cnonical_names) = inspect(domain, "", &res.answers);
+    if !records.is_empty() || !cnonical_names.is_empty() {
+        return Ok(cnonical_names.into_iter().chain(records).collect());
+    }
+
+    if qtype == Type::NS {
+        let delegation: Vec<RData> = res
+            .authorities
+            .iter()
+            .filter(|auth| auth.atype == Type::NS && auth.aname.eq_ignore_ascii_case(domain))
+            .map(|auth| auth.rdata.clone())
+            .collect();
+        if !delegation.is_empty() {
+            return Ok(delegation);
+        }
+    }
+
+    if domain.trim_end_matches('.').is_empty() {
+        return Ok(Vec::new());
+    }
+
+    resolve(domain, qtype, ctx.root, depth, ctx).await
+}
+
+/// Resolves the records of type `qtype` of `domain` by asking the
+/// recursive resolver at `address`, instead of iterating from the root.
+pub async fn forward(
+    domain:  &str,
+    qtype:   Type,
+    address: SocketAddr,
+    ctx:     &Context,
+) -> Result<Vec<RData>, DnsError> {
+    let res = query(domain, qtype, address, "", true, ctx).await?;
+    if let Some(negative) = negative(&res) {
+        return Err(negative);
+    }
+
+    // A recursive resolver vouches for the whole chain of aliases
+    let (records,
+         cnonical_names) = inspect(domain, "", &res.answers);
+    Ok(cnonical_names.into_iter().chain(records).collect())
+}
+
+/// Builds a recursive query for the `qtype` records of `domain`, with a
+/// zero ID as recommended for the encrypted transports, and padded if the
+/// privacy profile asks for it.
+#[cfg(any(feature = "doh", feature = "doq", feature = "dot"))]
+fn stub_question(domain: &str, qtype: Type, ctx: &Context) -> Result<Dns, DnsError> {
+    let mut req = Dns::new_question(domain, qtype, 0);
+    req.header.flags.rd = true;
+    ctx.sockets.privacy().pad(&mut req)?;
+    Ok(req)
+}
+
+/// Extracts the records of the response of a recursive resolver.
+#[cfg(any(feature = "doh", feature = "doq", feature = "dot"))]
+fn stub_records(domain: &str, res: &Dns, server: &str) -> Result<Vec<RData>, DnsError> {
+    if is_lame(res, true) {
+        return Err(DnsError::IOError(format!("error response from {}", server)));
+    }
+    if let Some(negative) = negative(res) {
+        return Err(negative);
+    }
+
+    let (records,
+         cnonical_names) = inspect(domain, "", &res.answers);
+    Ok(cnonical_names.into_iter().chain(records).collect())
+}
+
+/// Resolves the records of type `qtype` of `domain` by asking the
+/// recursive resolver behind the DoH `url`.
+///
+/// The query carries a zero ID, so that responses can be cached by HTTP
+/// intermediaries (RFC 8484, section 4.1).
+#[cfg(feature = "doh")]
+pub async fn forward_https(
+    domain: &str,
+    qtype:  Type,
+    url:    &str,
+    client: &crate::doh::DohClient,
+    ctx:    &Context,
+) -> Result<Vec<RData>, DnsError> {
+    let req    = stub_question(domain, qtype, ctx)?;
+    let policy = ctx.timeouts.policy(domain, &Route::Https(url.to_string()), RetryPolicy::new(crate::doh::DOH_TIMEOUT));
+    ctx.sockets.privacy().wait(ctx.rng()).await;
+    let res    = policy.run(|timeout| client.exchange(url, &req, timeout)).await?;
+    stub_records(domain, &res, url)
+}
+
+/// Resolves the records of type `qtype` of `domain` by asking the
+/// recursive resolver at `address` over QUIC, whose certificate must be
+/// valid for `name`.
+#[cfg(feature = "doq")]
+pub async fn forward_quic(
+    domain:  &str,
+    qtype:   Type,
+    address: SocketAddr,
+    name:    &str,
+    client:  &crate::doq::DoqClient,
+    ctx:     &Context,
+) -> Result<Vec<RData>, DnsError> {
+    let req    = stub_question(domain, qtype, ctx)?;
+    let policy = ctx.timeouts.policy(domain, &Route::Quic(address, name.to_string()), RetryPolicy::new(crate::doq::DOQ_TIMEOUT));
+    ctx.sockets.privacy().wait(ctx.rng()).await;
+    let res    = policy.run(|timeout| client.exchange(address, name, &req, timeout)).await?;
+    stub_records(domain, &res, name)
+}
+
+/// Resolves the records of type `qtype` of `domain` by asking the
+/// recursive resolver at `address` over TLS, whose certificate must be
+/// valid for `name`.
+#[cfg(feature = "dot")]
+pub async fn forward_tls(
+    domain:  &str,
+    qtype:   Type,
+    address: SocketAddr,
+    name:    &str,
+    client:  &crate::dot::DotClient,
+    ctx:     &Context,
+) -> Result<Vec<RData>, DnsError> {
+    let req    = stub_question(domain, qtype, ctx)?;
+    let policy = ctx.timeouts.policy(domain, &Route::Tls(address, name.to_string()), RetryPolicy::new(crate::dot::DOT_TIMEOUT));
+    ctx.sockets.privacy().wait(ctx.rng()).await;
+    let res    = policy.run(|timeout| client.exchange(address, name, &req, timeout)).await?;
+    stub_records(domain, &res, name)
+}
@@ -0,0 +1,78 @@
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
+use std::{sync::Mutex, time::Duration};
+
+/// Lowest port used as source port of the outgoing queries.
+const MIN_SOURCE_PORT: u16 = 1024;
+
+/// Source of randomness of the resolver.
+///
+/// Every random choice (query IDs, 0x20 casing, source ports and upstream
+/// selection) goes through this type. In production it is seeded from the
+/// operating system and backed by a CSPRNG; a fixed seed makes the whole
+/// resolution deterministic, for tests and fuzzing.
+#[derive(Debug)]
+pub struct DnsRng {
+    inner: Mutex<StdRng>,
+}
+
+impl DnsRng {
+    /// Creates a generator seeded from the operating system.
+    pub fn from_entropy() -> Self {
+        DnsRng {
+            inner: Mutex::new(StdRng::from_os_rng()),
+        }
+    }
+
+    /// Creates a deterministic generator from a fixed seed.
+    pub fn from_seed(seed: u64) -> Self {
+        DnsRng {
+            inner: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    /// Returns a new transaction ID for an outgoing query.
+    pub fn query_id(&self) -> u16 {
+        self.inner.lock().unwrap().random()
+    }
+
+    /// Returns a source port for an outgoing query, outside the range of
+    /// the well-known ports.
+    pub fn source_port(&self) -> u16 {
+        self.inner.lock().unwrap().random_range(MIN_SOURCE_PORT..=u16::MAX)
+    }
+
+    /// Returns a random index into a list of `len` items, which must not
+    /// be empty.
+    pub fn index(&self, len: usize) -> usize {
+        self.inner.lock().unwrap().random_range(0..len)
+    }
+
+    /// Randomizes the case of the letters of a domain name, so that the
+    /// response must echo the exact casing to be accepted (DNS 0x20).
+    pub fn randomize_case(&self, name: &str) -> String {
+        let mut rng = self.inner.lock().unwrap();
+        name.chars()
+            .map(|c| {
+                if c.is_ascii_alphabetic() && rng.random::<bool>() {
+                    c.to_ascii_uppercase()
+                } else {
+                    c.to_ascii_lowercase()
+                }
+            })
+            .collect()
+    }
+
+    /// Returns a random delay of at most `max`.
+    pub fn jitter(&self, max: Duration) -> Duration {
+        if max.is_zero() {
+            return Duration::ZERO;
+        }
+        self.inner.lock().unwrap().random_range(Duration::ZERO..=max)
+    }
+
+    /// Shuffles a list of candidates, such as the servers of a zone, so that
+    /// the load is spread among them.
+    pub fn shuffle<T>(&self, items: &mut [T]) {
+        items.shuffle(&mut *self.inner.lock().unwrap());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::types::DnsError;
+use std::{net::SocketAddr, str::FromStr};
+
+/// How the names of a domain are resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// Iteratively, starting from the root.
+    Iterate,
+    /// Never: the queries are refused.
+    Never,
+    /// By a recursive resolver, over UDP.
+    Udp(SocketAddr),
+    /// By a recursive resolver, over HTTPS (DoH), given its URL. Plain
+    /// `http://` URLs are accepted too, for servers behind a local TLS
+    /// terminator.
+    Https(String),
+    /// By a recursive resolver, over QUIC (DoQ), given its address and
+    /// the name its certificate is valid for.
+    Quic(SocketAddr, String),
+    /// By a recursive resolver, over TLS (DoT), given its address and the
+    /// name its certificate is valid for.
+    Tls(SocketAddr, String),
+}
+
+impl FromStr for Route {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::IOError(format!("invalid route: {}", s));
+        match s {
+            "iterate" => Ok(Route::Iterate),
+            "never"   => Ok(Route::Never),
+            _ if s.starts_with("https://") || s.starts_with("http://") => {
+                if !cfg!(feature = "doh") {
+                    return Err(DnsError::IOError(format!("DoH routes need the doh feature: {}", s)));
+                }
+                Ok(Route::Https(s.to_string()))
+            }
+            _ => {
+                if let Some(addr) = s.strip_prefix("udp:") {
+                    addr.parse().map(Route::Udp).map_err(|_| invalid())
+                } else if let Some(server) = s.strip_prefix("quic:") {
+                    if !cfg!(feature = "doq") {
+                        return Err(DnsError::IOError(format!("DoQ routes need the doq feature: {}", s)));
+                    }
+                    let (addr, name) = named_server(server).ok_or_else(invalid)?;
+                    Ok(Route::Quic(addr, name))
+                } else if let Some(server) = s.strip_prefix("tls:") {
+                    if !cfg!(feature = "dot") {
+                        return Err(DnsError::IOError(format!("DoT routes need the dot feature: {}", s)));
+                    }
+                    let (addr, name) = named_server(server).ok_or_else(invalid)?;
+                    Ok(Route::Tls(addr, name))
+                } else {
+                    Err(invalid())
+                }
+            }
+        }
+    }
+}
+
+/// Parses the server of an encrypted route, written as `name@addr` or just
+/// as its address, which the certificate is then valid for.
+fn named_server(server: &str) -> Option<(SocketAddr, String)> {
+    let (name, addr) = server.rsplit_once('@').unwrap_or(("", server));
+    let addr: SocketAddr = addr.parse().ok()?;
+    let name = match name {
+        "" => addr.ip().to_string(),
+        _  => name.to_string(),
+    };
+    Some((addr, name))
+}
+
+/// A rule routing a domain and its subdomains, written as
+/// `domain=route`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomainRule {
+    /// Domain the rule applies to, with its subdomains.
+    pub domain: String,
+    /// How its names are resolved.
+    pub route: Route,
+}
+
+impl FromStr for DomainRule {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (domain, route) = s
+            .split_once('=')
+            .ok_or_else(|| DnsError::IOError(format!("invalid domain rule: {}", s)))?;
+        Ok(DomainRule {
+            domain: domain.trim().trim_start_matches("*.").trim_end_matches('.').to_ascii_lowercase(),
+            route:  route.trim().parse()?,
+        })
+    }
+}
+
+/// Per-domain routing of the resolutions.
+#[derive(Debug, Default)]
+pub struct Routes {
+    rules: Vec<DomainRule>,
+}
+
+impl Routes {
+    /// Creates the routing table from its rules.
+    pub fn new(rules: &[DomainRule]) -> Self {
+        Routes { rules: rules.to_vec() }
+    }
+
+    /// Returns how `qname` is resolved: the route of the most specific
+    /// rule matching it, or iteration if none does.
+    pub fn route(&self, qname: &str) -> Route {
+        let name = qname.trim_end_matches('.').to_ascii_lowercase();
+        self.rules
+            .iter()
+            .filter(|rule| {
+                rule.domain.is_empty()
+                    || name == rule.domain
+                    || name.ends_with(&format!(".{}", rule.domain))
+            })
+            .max_by_key(|rule| rule.domain.len())
+            .map_or(Route::Iterate, |rule| rule.route.clone())
+    }
+}
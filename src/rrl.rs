@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Most responses a client subnet may still be sent at once, in seconds of
+/// its rate.
+const WINDOW: u32 = 5;
+
+/// Entries kept before those of the idle clients are forgotten.
+const MAX_ENTRIES: usize = 16384;
+
+/// What to do with a response over the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Send the response.
+    Send,
+    /// Send a truncated response instead, so that a real client retries
+    /// over TCP.
+    Slip,
+    /// Send nothing.
+    Drop,
+}
+
+/// The rate limit of a client subnet asking for a name and a type.
+#[derive(Debug)]
+struct Bucket {
+    /// Theoretical arrival time of the next response.
+    tat:    Instant,
+    /// Responses over the limit so far.
+    excess: u32,
+}
+
+/// Response rate limiting (RRL), which keeps the UDP answers from being
+/// reflected at a spoofed address in floods.
+///
+/// The responses sent to a client subnet (a /24 in IPv4, a /56 in IPv6)
+/// for the same name and type are limited to `rate` per second, with
+/// bursts of a few seconds' worth. Every `slip`th response over the limit
+/// is sent truncated, and the others dropped, so that a real client behind
+/// the subnet can still get its answer over TCP while a victim gets
+/// nothing bigger than the queries.
+#[derive(Debug)]
+pub struct ResponseLimiter {
+    interval:  Duration,
+    tolerance: Duration,
+    slip:      u32,
+    buckets:   Mutex<HashMap<(IpAddr, String, u16), Bucket>>,
+}
+
+impl ResponseLimiter {
+    /// Creates a new limiter. A zero `rate` disables limiting, and a zero
+    /// `slip` drops all the responses over the limit.
+    pub fn new(rate: u32, slip: u32) -> Self {
+        let interval = if rate == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(1) / rate
+        };
+
+        ResponseLimiter {
+            interval,
+            tolerance: interval * rate.saturating_mul(WINDOW).saturating_sub(1),
+            slip,
+            buckets:   Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Accounts for a response to `client`, returning what to do with it.
+    pub fn check(&self, client: IpAddr, response: &[u8]) -> Action {
+        if self.interval.is_zero() {
+            return Action::Send;
+        }
+        let Some((qname, qtype, _)) = question(response) else {
+            return Action::Send;
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = Instant::now();
+
+        // Forget the clients that have been idle long enough
+        if buckets.len() > MAX_ENTRIES {
+            buckets.retain(|_, bucket| bucket.tat > now);
+        }
+
+        let bucket = buckets
+            .entry((subnet(client), qname, qtype))
+            .or_insert(Bucket { tat: now, excess: 0 });
+        if bucket.tat < now {
+            bucket.tat    = now;
+            bucket.excess = 0;
+        }
+
+        let slot = bucket.tat.checked_sub(self.tolerance).unwrap_or(now);
+        if slot <= now {
+            bucket.tat += self.interval;
+            return Action::Send;
+        }
+
+        bucket.excess += 1;
+        match self.slip {
+            0                                          => Action::Drop,
+            slip if bucket.excess.is_multiple_of(slip) => Action::Slip,
+            _                                          => Action::Drop,
+        }
+    }
+}
+
+/// Returns the subnet a client is limited as part of.
+fn subnet(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V4(addr) => IpAddr::V4(Ipv4Addr::from(u32::from(addr) & 0xffff_ff00)),
+        IpAddr::V6(addr) => IpAddr::V6(Ipv6Addr::from(u128::from(addr) & !((1u128 << 72) - 1))),
+    }
+}
+
+/// Reads the question of a response, returning its lowercase name, its
+/// type, and where it ends.
+fn question(response: &[u8]) -> Option<(String, u16, usize)> {
+    let mut labels = Vec::new();
+    let mut pos    = 12;
+    loop {
+        let length = *response.get(pos)? as usize;
+        pos += 1;
+        if length == 0 {
+            break;
+        }
+        if length > 63 {
+            return None;
+        }
+        labels.push(String::from_utf8_lossy(response.get(pos..pos + length)?).to_ascii_lowercase());
+        pos += length;
+    }
+    let qtype = response.get(pos..pos + 2)?;
+    Some((labels.join("."), u16::from_be_bytes([qtype[0], qtype[1]]), pos + 4))
+}
+
+/// Truncates a response to its header and its question, with the TC bit
+/// set.
+pub fn truncate(response: &[u8]) -> Vec<u8> {
+    let end = match question(response) {
+        Some((_, _, end)) if end <= response.len() => end,
+        _ => return response.get(..12).map(<[u8]>::to_vec).unwrap_or_default(),
+    };
+    let mut out = response[..end].to_vec();
+    out[2] |= 0x02;
+    out[4..6].copy_from_slice(&1u16.to_be_bytes());
+    out[6..12].fill(0);
+    out
+}
@@ -0,0 +1,1088 @@
+use crate::{
+    cache::Cache,
+    clock::{Clock, SystemClock},
+    compare,
+    config::Config,
+    diagnostics,
+    dns::rcode_name,
+    dnstap::{Dnstap, Kind, Message, Protocol},
+    dynamic::DynamicZone,
+    hosted::{HostedZones, OPCODE_NOTIFY},
+    infra::{InfraCache, MIN_UDP_SIZE},
+    local::{self, LocalData},
+    logging,
+    lookup::Resolver,
+    metrics::Metrics,
+    pacer::Pacer,
+    peer::Gossip,
+    policy::{Block, HomographAction, PluginVerdict, Policy, Source, Verdict},
+    privacy::Privacy,
+    proxy::Proxy,
+    query,
+    querylog::QueryLog,
+    replay,
+    resolver::{is_apex, ApexMode, NonRecursiveMode},
+    rng::DnsRng,
+    routing::Route,
+    rrl::{self, Action, ResponseLimiter},
+    sinkhole::Sinkhole,
+    slowlog::SlowLog,
+    sockets::{self, SocketPool},
+    special,
+    supervisor::{self, supervise, PanicLog},
+    tcp,
+    types::{AnswerRecord, Dns, DnsError, DnsReadBuffer, DnsWriteBuffer, QueryRecord, Type},
+    update::{UpdateKey, NOTAUTH, OPCODE_UPDATE},
+    zone::Zone,
+};
+#[cfg(feature = "acme")]
+use crate::acme::Challenges;
+#[cfg(feature = "admin")]
+use crate::admin::{Admin, Reload};
+#[cfg(feature = "zone-transfers")]
+use crate::axfr;
+#[cfg(feature = "consul")]
+use crate::consul::Consul;
+#[cfg(feature = "doq")]
+use crate::doq;
+#[cfg(feature = "dot")]
+use crate::dot;
+#[cfg(feature = "health")]
+use crate::health::Health;
+#[cfg(feature = "wasm-plugins")]
+use crate::plugins::Plugins;
+#[cfg(feature = "sig0")]
+use crate::sig0::Sig0;
+#[cfg(any(feature = "dot", feature = "doq"))]
+use crate::tls;
+#[cfg(unix)]
+use crate::unix;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, RwLock},
+    time::{Instant, SystemTime},
+};
+#[cfg(feature = "admin")]
+use tokio::sync::mpsc;
+use tokio::{net::UdpSocket, sync::watch, task::JoinSet};
+use tracing::{field, Instrument, Span};
+
+/// Printed by `--help`.
+const USAGE: &str = "\
+Usage: dns-resolver [--<option> <value>]...
+       dns-resolver replay <capture> [--server <addr>] [--baseline <addr>] [--speed <factor>]
+       dns-resolver compare <names> --baseline <addr> [--server <addr>] [--type <type>]
+       dns-resolver decode <file>...
+       dns-resolver zone <origin> <file>
+       dns-resolver transfer <origin> <primary>
+       dns-resolver query <name> [type] [@server] [+tcp]
+
+Every DNSR_* environment variable can be set by the flag named after it,
+such as --max-depth 30 for DNSR_MAX_DEPTH=30. Flags without a value are
+true. --mode recursive|proxy checks how the queries are answered.
+";
+
+/// State shared by all the request tasks.
+struct State {
+    config:    Config,
+    resolver:  Resolver,
+    /// Records served locally, replaced when the configuration is
+    /// reloaded.
+    local:     RwLock<Arc<LocalData>>,
+    dynamic:   Arc<DynamicZone>,
+    zones:     Arc<HostedZones>,
+    #[cfg(feature = "acme")]
+    acme:      Arc<Challenges>,
+    policy:    Arc<Policy>,
+    #[cfg(feature = "wasm-plugins")]
+    plugins:   Plugins,
+    #[cfg(feature = "sig0")]
+    sig0:      Sig0,
+    sinkhole:  Option<Arc<Sinkhole>>,
+    proxy:     Proxy,
+    limiter:   ResponseLimiter,
+    sockets:   Arc<SocketPool>,
+    dnstap:    Option<Arc<Dnstap>>,
+    metrics:   Arc<Metrics>,
+    slow_log:  SlowLog,
+    query_log: Option<QueryLog>,
+}
+
+impl State {
+    /// Returns the records served locally.
+    fn local(&self) -> Arc<LocalData> {
+        Arc::clone(&self.local.read().unwrap())
+    }
+
+    /// Answers from the pending ACME challenges, when they are built in.
+    fn challenge(&self, qname: &str, qtype: Type) -> Option<Vec<AnswerRecord>> {
+        #[cfg(feature = "acme")]
+        return self.acme.answer(qname, qtype);
+        #[cfg(not(feature = "acme"))]
+        {
+            let _ = (qname, qtype);
+            None
+        }
+    }
+
+    /// Checks a query with the policy plugins, when they are built in.
+    fn plugin(&self, qname: &str, qtype: Type, client: IpAddr) -> Option<(String, PluginVerdict)> {
+        #[cfg(feature = "wasm-plugins")]
+        return self.plugins.check(qname, qtype, client);
+        #[cfg(not(feature = "wasm-plugins"))]
+        {
+            let _ = (qname, qtype, client);
+            None
+        }
+    }
+
+    /// Returns the key of the SIG(0) signature of an update received as
+    /// `data`, when they are built in, or `None` if it isn't signed.
+    fn signer(&self, data: &[u8], update: &Dns) -> Result<Option<UpdateKey>, DnsError> {
+        #[cfg(feature = "sig0")]
+        return self.sig0.verify(data, update);
+        #[cfg(not(feature = "sig0"))]
+        {
+            let _ = (data, update);
+            Ok(None)
+        }
+    }
+}
+
+/// Address the clients of the unix domain socket, which have none, are
+/// known by.
+const UNIX_CLIENT: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// Transport a query reached the server over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    /// UDP datagrams, whose replies may be truncated.
+    Udp,
+    /// Length-prefixed messages over TCP.
+    Tcp,
+    /// Length-prefixed messages over the unix domain socket.
+    Unix,
+    /// Length-prefixed messages over TLS.
+    #[cfg(feature = "dot")]
+    Tls,
+    /// A stream of its own for every query, over QUIC.
+    #[cfg(feature = "doq")]
+    Quic,
+}
+
+impl Transport {
+    /// Name of the transport, as reported to the clients asking.
+    fn name(self) -> &'static str {
+        match self {
+            Transport::Udp  => "udp",
+            Transport::Tcp  => "tcp",
+            Transport::Unix => "unix",
+            #[cfg(feature = "dot")]
+            Transport::Tls  => "tls",
+            #[cfg(feature = "doq")]
+            Transport::Quic => "quic",
+        }
+    }
+
+    /// Protocol of the transport in the dnstap messages, where the unix
+    /// domain socket counts as TCP, whose framing it shares.
+    fn protocol(self) -> Protocol {
+        match self {
+            Transport::Udp  => Protocol::Udp,
+            Transport::Tcp  => Protocol::Tcp,
+            Transport::Unix => Protocol::Tcp,
+            #[cfg(feature = "dot")]
+            Transport::Tls  => Protocol::Dot,
+            #[cfg(feature = "doq")]
+            Transport::Quic => Protocol::Doq,
+        }
+    }
+}
+
+/// Runs the resolver, as the binary does, with the arguments following the
+/// name of the program: serves the queries until asked to stop, or runs the
+/// subcommand they start with.
+pub async fn run(args: Vec<String>) -> Result<(), DnsError> {
+
+    // The arguments are either the flags of the server, or a subcommand
+    // followed by its own arguments
+    let (command, flags) = match args.split_first() {
+        Some((command, rest)) if !command.starts_with('-') => (Some(command.as_str()), rest),
+        _                                                  => (None, args.as_slice()),
+    };
+
+    if command.is_none() && flags.iter().any(|flag| flag == "--help" || flag == "-h") {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+
+    let config = Config::load(if command.is_some() { &[] } else { flags })?;
+    logging::init(&config.log_target, config.log_format, &config.log_filter)?;
+    supervisor::install_panic_hook();
+
+    // Subcommands run instead of the server
+    if let Some(command) = command {
+        let args = flags.iter().cloned();
+        return match command {
+            "replay"   => replay::run(args, config.listen[0]).await,
+            "compare"  => compare::run(args, config.listen[0]).await,
+            "decode"   => decode(args),
+            "zone"     => check_zone(args),
+            #[cfg(feature = "zone-transfers")]
+            "transfer" => transfer(args).await,
+            "query"    => query::run(args, config.listen[0]).await,
+            _          => Err(DnsError::IOError(format!("unknown command: {}", command))),
+        };
+    }
+
+    // Report the startup progress to the supervisor, if asked to
+    #[cfg(feature = "health")]
+    let health = Arc::new(Health::new());
+    #[cfg(feature = "health")]
+    if let Some(addr) = config.health_listen {
+        tokio::spawn(Arc::clone(&health).serve(addr));
+    }
+
+    // Generate a new UDP socket for listening incoming packets
+    // from clients, on every address, along with a TCP listener for the
+    // clients whose responses don't fit in a datagram
+    let mut socks         = Vec::with_capacity(config.listen.len());
+    let mut tcp_listeners = Vec::with_capacity(config.listen.len());
+    for &addr in &config.listen {
+        let sock = sockets::listen_udp(addr)
+            .and_then(UdpSocket::from_std)
+            .map_err(|_| DnsError::SocketError)?;
+        tcp_listeners.push(tcp::listen(addr)?);
+        tracing::info!(addr = %addr, "listening for queries");
+        socks.push(Arc::new(sock));
+    }
+
+    // Local processes may also query over a unix domain socket
+    #[cfg(unix)]
+    let unix_listener = match &config.unix_listen {
+        Some(path) => {
+            let listener = unix::listen(path)?;
+            tracing::info!(path = %path.display(), "listening for queries");
+            Some(listener)
+        }
+        None => None,
+    };
+
+    // Remote clients may also query over TLS, such as phones using the
+    // server as their private resolver
+    #[cfg(feature = "dot")]
+    let mut tls_listeners = Vec::new();
+    #[cfg(feature = "dot")]
+    if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key)
+        && !config.tls_listen.is_empty()
+    {
+        let tls = tls::server_config(cert, key, dot::DOT_ALPN)?;
+        for &addr in &config.tls_listen {
+            tls_listeners.push((dot::listen(addr)?, tls.clone()));
+            tracing::info!(addr = %addr, "listening for queries over TLS");
+        }
+    }
+
+    // And over QUIC, with the same certificate
+    #[cfg(feature = "doq")]
+    let mut quic_endpoints = Vec::new();
+    #[cfg(feature = "doq")]
+    if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key)
+        && !config.quic_listen.is_empty()
+    {
+        let tls = tls::server_config(cert, key, doq::DOQ_ALPN)?;
+        for &addr in &config.quic_listen {
+            quic_endpoints.push(doq::listen(addr, tls.clone())?);
+            tracing::info!(addr = %addr, "listening for queries over QUIC");
+        }
+    }
+
+    // Secondaries transfer the zones over TCP
+    #[cfg(feature = "zone-transfers")]
+    let transfer_listener = match config.transfer_listen {
+        Some(addr) => {
+            let listener = axfr::listen(addr).await?;
+            tracing::info!(addr = %addr, "listening for zone transfers");
+            Some(listener)
+        }
+        None => None,
+    };
+    #[cfg(feature = "health")]
+    health.set_listening();
+
+    let metrics  = Arc::new(Metrics::new());
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock::new(config.clock_offset));
+    let cache    = Arc::new(Cache::new(
+        Arc::clone(&metrics),
+        Arc::clone(&clock),
+        config.signature_skew,
+        config.serve_stale,
+    ));
+    if let Some(path) = config.cache_file.as_deref().filter(|path| path.exists()) {
+        match cache.load(path) {
+            Ok(count) => tracing::info!(entries = %count, "cache loaded"),
+            Err(e)    => tracing::warn!(error = %e, "can't load the cache"),
+        }
+    }
+    #[cfg(feature = "health")]
+    health.set_cache_loaded();
+    let zones    = Arc::new(HostedZones::load(&config.zones, &config.secondary_zones, config.notify.clone())?);
+    let changes  = zones.changes();
+    tokio::spawn(Arc::clone(&zones).maintain());
+    let local    = local_data(&config)?.with_zone_addresses(&zones.addresses());
+    let policy   = Arc::new(Policy::new(&config.blocklist, &config.protected_names));
+    let sources  = blocklist_sources(&config);
+    policy.load(&sources).await;
+    if !sources.is_empty() && !config.blocklist_refresh.is_zero() {
+        tokio::spawn(Arc::clone(&policy).refresh(sources.clone(), config.blocklist_refresh));
+    }
+    #[cfg(feature = "admin")]
+    let (reloads, reload_requests) = mpsc::channel(1);
+    #[cfg(feature = "admin")]
+    if let (Some(addr), Some(token)) = (config.admin_listen, &config.admin_token) {
+        let admin = Admin::new(
+            token.clone(),
+            Arc::clone(&cache),
+            Arc::clone(&policy),
+            sources,
+            Arc::clone(&metrics),
+            reloads,
+        );
+        tokio::spawn(Arc::new(admin).serve(addr));
+    }
+    #[cfg(feature = "wasm-plugins")]
+    let plugins  = Plugins::load(&config.plugins)?;
+    #[cfg(feature = "sig0")]
+    let sig0     = Sig0::new(config.update_keys.clone(), clock, config.signature_skew);
+    let dynamic  = Arc::new(DynamicZone::new());
+    let sinkhole = config.sinkhole_listen.map(|addr| {
+        let sinkhole = Arc::new(Sinkhole::new());
+        tokio::spawn(Arc::clone(&sinkhole).serve(addr));
+        sinkhole
+    });
+    #[cfg(feature = "consul")]
+    if let Some(url) = &config.consul {
+        let source = Consul::new(url, &config.consul_domain, config.consul_token.clone())?;
+        tokio::spawn(Arc::clone(&dynamic).follow(source));
+    }
+    #[cfg(feature = "acme")]
+    let acme     = Arc::new(Challenges::new(
+        config.acme_token.as_deref().unwrap_or_default(),
+        &config.acme_zones,
+    ));
+    #[cfg(feature = "acme")]
+    if let Some(addr) = config.acme_listen {
+        tokio::spawn(Arc::clone(&acme).serve(addr));
+    }
+    let infra    = Arc::new(InfraCache::new(config.max_udp_size, Arc::clone(&metrics)));
+    let rng      = Arc::new(match config.rng_seed {
+        Some(seed) => DnsRng::from_seed(seed),
+        None       => DnsRng::from_entropy(),
+    });
+    let pacer    = Arc::new(Pacer::new(
+        config.outgoing_rate,
+        config.outgoing_burst,
+        config.outgoing_jitter,
+    ));
+    let dnstap   = config.dnstap.as_deref().map(Dnstap::open).transpose()?.map(Arc::new);
+    let sockets  = Arc::new(SocketPool::new(
+        config.outgoing_sockets,
+        config.socket_lifetime,
+        Arc::clone(&rng),
+        Privacy::new(config.privacy, config.privacy_delay),
+        dnstap.clone(),
+    ));
+
+    // Keep the peers' caches in sync with ours, and ours with theirs
+    let peer_key = config.peer_key.as_ref().map(|key| key.as_bytes().to_vec());
+    let gossip   = if config.peers.is_empty() {
+        None
+    } else {
+        Some(Arc::new(Gossip::new(config.peers.clone(), peer_key.clone()).await?))
+    };
+    if let Some(addr) = config.peer_listen {
+        tokio::spawn(Gossip::listen(addr, config.peers.clone(), peer_key, Arc::clone(&cache)));
+    }
+
+    let resolver = Resolver::new(&config, Arc::clone(&cache), infra, pacer, Arc::clone(&sockets), gossip)?;
+    let slow_log  = SlowLog::open(config.slow_query_threshold, config.slow_log.as_deref())?;
+    let query_log = config
+        .query_log
+        .as_deref()
+        .map(|path| QueryLog::open(path, config.query_log_rotation))
+        .transpose()?;
+
+    // Readiness waits for the root to answer
+    #[cfg(feature = "health")]
+    if config.health_listen.is_some() {
+        tokio::spawn(Arc::clone(&health).prime(resolver.clone()));
+    }
+
+    // Dump the counters to stderr on demand
+    #[cfg(all(unix, feature = "metrics"))]
+    tokio::spawn(dump_metrics(Arc::clone(&metrics)));
+
+    let proxy     = Proxy::new(config.proxy.clone());
+    let limiter   = ResponseLimiter::new(config.rrl_rate, config.rrl_slip);
+    let state     = Arc::new(State {
+        config,
+        resolver,
+        local:     RwLock::new(Arc::new(local)),
+        dynamic,
+        zones,
+        #[cfg(feature = "acme")]
+        acme,
+        policy,
+        #[cfg(feature = "wasm-plugins")]
+        plugins,
+        #[cfg(feature = "sig0")]
+        sig0,
+        sinkhole,
+        proxy,
+        limiter,
+        sockets,
+        dnstap,
+        metrics,
+        slow_log,
+        query_log,
+    });
+    let panic_log = Arc::new(PanicLog::new());
+    tokio::spawn(follow_zones(Arc::clone(&state), changes));
+    #[cfg(feature = "admin")]
+    tokio::spawn(reload(Arc::clone(&state), flags.to_vec(), reload_requests));
+
+    for listener in tcp_listeners {
+        let shared = Arc::clone(&state);
+        tokio::spawn(tcp::serve(listener, move |addr, query| {
+            let state = Arc::clone(&shared);
+            async move { answer(&state, addr, Transport::Tcp, &query).await }
+        }));
+    }
+
+    #[cfg(unix)]
+    if let Some(listener) = unix_listener {
+        let shared = Arc::clone(&state);
+        tokio::spawn(unix::serve(listener, state.config.unix_allow.clone(), move |query| {
+            let state = Arc::clone(&shared);
+            async move { answer(&state, UNIX_CLIENT, Transport::Unix, &query).await }
+        }));
+    }
+
+    #[cfg(feature = "dot")]
+    for (listener, tls) in tls_listeners {
+        let shared = Arc::clone(&state);
+        tokio::spawn(dot::serve(listener, tls, move |addr, query| {
+            let state = Arc::clone(&shared);
+            async move { answer(&state, addr, Transport::Tls, &query).await }
+        }));
+    }
+
+    #[cfg(feature = "doq")]
+    for endpoint in quic_endpoints {
+        let shared = Arc::clone(&state);
+        tokio::spawn(doq::serve(endpoint, move |addr, query| {
+            let state = Arc::clone(&shared);
+            async move { answer(&state, addr, Transport::Quic, &query).await }
+        }));
+    }
+
+    #[cfg(feature = "zone-transfers")]
+    if let Some(listener) = transfer_listener {
+        tokio::spawn(axfr::serve(listener, Arc::clone(&state.zones), state.config.allow_transfer.clone()));
+    }
+
+    // Every socket is served by a task of its own, all of them sharing the
+    // state, until asked to stop or one of them fails
+    let mut listeners = JoinSet::new();
+    for sock in socks {
+        listeners.spawn(serve(sock, Arc::clone(&state), Arc::clone(&panic_log)));
+    }
+    tokio::select! {
+        _                = shutdown()            => {}
+        Some(Ok(Err(e))) = listeners.join_next() => return Err(e),
+    }
+
+    if let Some(path) = &state.config.unix_listen {
+        let _ = std::fs::remove_file(path);
+    }
+    if let Some(path) = &state.config.cache_file {
+        match cache.save(path) {
+            Ok(count) => tracing::info!(entries = %count, "cache saved"),
+            Err(e)    => tracing::error!(error = %e, "can't save the cache"),
+        }
+    }
+    Ok(())
+}
+
+/// Decodes the messages in the given files, and encodes them back, printing
+/// what they hold or why they are invalid.
+///
+/// Invalid messages are expected: only unreadable files are errors. This
+/// runs the fuzzing findings kept as regression tests.
+fn decode(paths: impl Iterator<Item = String>) -> Result<(), DnsError> {
+    for path in paths {
+        let data = std::fs::read(&path).map_err(|e| DnsError::IOError(format!("can't read {}: {}", path, e)))?;
+        let outcome = Dns::decode(&mut DnsReadBuffer::new(&data)).and_then(|dns| {
+            let enc = dns.encode()?;
+            Ok(format!(
+                "{} questions, {} answers, {} authorities, {} additionals, {} bytes encoded",
+                dns.questions.len(),
+                dns.answers.len(),
+                dns.authorities.len(),
+                dns.additionals.len(),
+                enc.data.len(),
+            ))
+        });
+        match outcome {
+            Ok(summary) => println!("{}: {}", path, summary),
+            Err(e)      => println!("{}: {}", path, e),
+        }
+    }
+    Ok(())
+}
+
+/// Reads a zone from its master file, printing its record sets, or where
+/// the file is invalid.
+fn check_zone(mut args: impl Iterator<Item = String>) -> Result<(), DnsError> {
+    let (Some(origin), Some(path)) = (args.next(), args.next()) else {
+        return Err(DnsError::IOError("usage: dns-resolver zone <origin> <file>".into()));
+    };
+
+    let zone = Zone::load(std::path::Path::new(&path), &origin)?;
+    print_zone(&zone);
+    Ok(())
+}
+
+/// Transfers a zone from its primary server, printing its record sets.
+#[cfg(feature = "zone-transfers")]
+async fn transfer(mut args: impl Iterator<Item = String>) -> Result<(), DnsError> {
+    let (Some(origin), Some(primary)) = (args.next(), args.next()) else {
+        return Err(DnsError::IOError("usage: dns-resolver transfer <origin> <primary>".into()));
+    };
+    let primary = primary
+        .parse()
+        .map_err(|_| DnsError::IOError(format!("invalid primary: {}", primary)))?;
+
+    let zone = Zone::transfer(primary, &origin).await?;
+    print_zone(&zone);
+    Ok(())
+}
+
+/// Prints the record sets of a zone, and a summary.
+fn print_zone(zone: &Zone) {
+    for rrset in &zone.rrsets {
+        println!("{} {} {} {} records", rrset.name, rrset.ttl(), rrset.rtype, rrset.records.len());
+    }
+    println!(
+        "{}: serial {}, {} records in {} sets",
+        zone.origin,
+        zone.soa().map(|soa| soa.serial).unwrap_or_default(),
+        zone.len(),
+        zone.rrsets.len(),
+    );
+}
+
+/// Lists where the blocklists are loaded from: the files, then the URLs.
+fn blocklist_sources(config: &Config) -> Vec<Source> {
+    #[allow(unused_mut)]
+    let mut sources: Vec<Source> = config.blocklist_files.iter().cloned().map(Source::File).collect();
+    #[cfg(feature = "blocklist-urls")]
+    sources.extend(config.blocklist_urls.iter().cloned().map(Source::Url));
+    sources
+}
+
+/// Builds the records served locally: the static records and those of
+/// the hosts file, within the local zones.
+fn local_data(config: &Config) -> Result<LocalData, DnsError> {
+    let mut records = config.local_records.clone();
+    if let Some(path) = &config.hosts_file {
+        records.extend(local::load_hosts(path)?);
+    }
+    Ok(LocalData::new(&records, &config.local_zones, config.synthesize_ptr))
+}
+
+/// Loads the configuration again from `flags`, the environment and the
+/// configuration file on every request of the administration API, and
+/// applies what can change while the server runs: the local records and
+/// zones, the domain rules and the timeouts. The other options keep the
+/// values they had on startup. A configuration that can't be loaded
+/// changes nothing.
+#[cfg(feature = "admin")]
+async fn reload(state: Arc<State>, flags: Vec<String>, mut requests: mpsc::Receiver<Reload>) {
+    while let Some(reply) = requests.recv().await {
+        let outcome = Config::load(&flags).and_then(|config| {
+            let local = local_data(&config)?;
+            state.resolver.reconfigure(&config)?;
+            *state.local.write().unwrap() = Arc::new(local.with_zone_addresses(&state.zones.addresses()));
+            Ok(())
+        });
+        if let Err(e) = &outcome {
+            tracing::warn!(error = %e, "can't reload the configuration");
+        }
+        let _ = reply.send(outcome);
+    }
+}
+
+/// Synthesizes the PTR records of the hosted zones again every time one of
+/// them changes, for as long as the server runs.
+async fn follow_zones(state: Arc<State>, mut changes: watch::Receiver<u64>) {
+    while changes.changed().await.is_ok() {
+        let addresses = state.zones.addresses();
+        let mut local = state.local.write().unwrap();
+        *local = Arc::new(local.with_zone_addresses(&addresses));
+    }
+}
+
+/// Receives the queries of the clients on a UDP socket, answering each of
+/// them in a task of its own. Only returns if the socket fails.
+async fn serve(sock: Arc<UdpSocket>, state: Arc<State>, panic_log: Arc<PanicLog>) -> Result<(), DnsError> {
+    let mut buf = [0u8; 4096];
+
+    loop {
+
+        // Read incoming packet from the socket
+        let (length, addr) = sock.recv_from(&mut buf).await.map_err(|_| DnsError::SocketError)?;
+
+        let data = buf[..length].to_vec();
+
+        // Use an asyncio task, offloading the logic for resolving the IP
+        // address of the requested domain. The task is supervised, so that
+        // a panic while handling the query still gets the client an answer
+        let task = handle(Arc::clone(&sock), Arc::clone(&state), addr, data.clone());
+        tokio::spawn(supervise(
+            task,
+            Arc::clone(&sock),
+            addr,
+            data,
+            Arc::clone(&state.metrics),
+            Arc::clone(&panic_log),
+        ));
+    }
+}
+
+/// Decodes and answers a single client query.
+async fn handle(
+    sock:   Arc<UdpSocket>,
+    state:  Arc<State>,
+    addr:   SocketAddr,
+    data:   Vec<u8>,
+) {
+    let Some(reply) = answer(&state, addr, Transport::Udp, &data).await else {
+        return;
+    };
+
+    // Responses over the rate limit are truncated or dropped, so that the
+    // server can't be used to flood a spoofed address
+    let reply = match state.limiter.check(addr.ip().to_canonical(), &reply) {
+        Action::Send => reply,
+        Action::Slip => {
+            state.metrics.record_limited(true);
+            rrl::truncate(&reply)
+        }
+        Action::Drop => {
+            state.metrics.record_limited(false);
+            return;
+        }
+    };
+    if sock.send_to(&reply, addr).await.is_err() {
+        tracing::error!(
+            client = %addr,
+            error  = %DnsError::SocketError,
+            "DNS request processing error",
+        );
+    }
+}
+
+/// Answers a raw client query, whatever its transport. Returns `None`
+/// when the query gets no reply.
+async fn answer(
+    state:     &State,
+    addr:      SocketAddr,
+    transport: Transport,
+    data:      &[u8],
+) -> Option<Vec<u8>> {
+    // The IPv4 clients of the dual-stack listeners are known by their own
+    // addresses, rather than IPv4-mapped ones
+    let addr    = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+    let relayed = !state.config.proxy.is_empty();
+
+    // Everything logged while answering carries the client and the
+    // question, the latter recorded once decoded
+    let span = tracing::info_span!(
+        target: "dns_resolver::query",
+        "query",
+        client    = %addr,
+        transport = transport.name(),
+        qname     = field::Empty,
+        qtype     = field::Empty,
+    );
+    let start    = Instant::now();
+    let received = SystemTime::now();
+    if let Some(dnstap) = &state.dnstap {
+        dnstap.record(Message {
+            kind:     Kind::ClientQuery,
+            protocol: transport.protocol(),
+            peer:     addr,
+            query:    (data, received),
+            response: None,
+        });
+    }
+
+    let outcome = async {
+        match relayed {
+            true  => relay(state, transport, data).await,
+            false => match Dns::decode(&mut DnsReadBuffer::new(data)) {
+                Ok(dns) => process(state, addr, transport, data, &dns).await,
+                Err(e)  => Err(e),
+            },
+        }
+    }
+    .instrument(span.clone())
+    .await;
+
+    let _span = span.enter();
+    let reply = match outcome {
+        Ok(reply) => Some(reply),
+        Err(e)    => {
+            tracing::error!(
+                client = %addr,
+                error  = %e,
+                "DNS request processing error",
+            );
+            // Queries none of the upstream servers answer get a SERVFAIL
+            relayed.then(|| Dns::new_servfail(data)).flatten()
+        }
+    };
+    tracing::debug!(
+        target: "dns_resolver::query",
+        duration_us = start.elapsed().as_micros() as u64,
+        outcome     = %outcome_name(reply.as_deref()),
+        "query processed",
+    );
+    if let (Some(dnstap), Some(reply)) = (&state.dnstap, &reply) {
+        dnstap.record(Message {
+            kind:     Kind::ClientResponse,
+            protocol: transport.protocol(),
+            peer:     addr,
+            query:    (data, received),
+            response: Some((reply, SystemTime::now())),
+        });
+    }
+    reply
+}
+
+/// Names the outcome of a query after the response code of its reply.
+fn outcome_name(reply: Option<&[u8]>) -> String {
+    match reply {
+        Some(reply) => rcode_name(reply.get(3).map_or(0, |flags| flags & 0x0f) as u16),
+        None        => "no reply".to_string(),
+    }
+}
+
+/// Relays a raw client query to the upstream servers, returning their
+/// reply, truncated if it doesn't fit the transport of the client.
+async fn relay(state: &State, transport: Transport, data: &[u8]) -> Result<Vec<u8>, DnsError> {
+    let start = Instant::now();
+    let reply = state.proxy.forward(data, &state.sockets).await?;
+    state.metrics.observe_latency(start.elapsed());
+
+    let reply = match state.config.minimal_responses {
+        true  => minimized(reply),
+        false => reply,
+    };
+    let advertised = Dns::decode(&mut DnsReadBuffer::new(data)).ok().and_then(|req| req.udp_payload_size());
+    Ok(match reply.len() > reply_limit(state, transport, advertised) {
+        true  => rrl::truncate(&reply),
+        false => reply,
+    })
+}
+
+/// Returns the size a reply sent over `transport` may take: over UDP, the
+/// payload size the client advertised, if any, within what the
+/// administrator allows. Streams carry messages of any size.
+fn reply_limit(state: &State, transport: Transport, advertised: Option<u16>) -> usize {
+    let limit = match transport {
+        Transport::Udp => advertised
+            .unwrap_or(MIN_UDP_SIZE)
+            .clamp(MIN_UDP_SIZE, state.resolver.infra().max_udp_size()),
+        _ => u16::MAX,
+    };
+    limit as usize
+}
+
+/// Leaves out of a relayed reply the records the client didn't ask for.
+/// Replies that can't be decoded are relayed as they are.
+fn minimized(reply: Vec<u8>) -> Vec<u8> {
+    let Ok(mut dns) = Dns::decode(&mut DnsReadBuffer::new(&reply)) else {
+        return reply;
+    };
+    dns.minimize();
+    dns.encode().map(DnsWriteBuffer::into_inner).unwrap_or(reply)
+}
+
+/// Waits for the process to be asked to stop, with SIGINT or SIGTERM.
+async fn shutdown() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        if let Ok(mut term) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = term.recv()             => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Prints the metrics to stderr every time the process receives SIGUSR1.
+#[cfg(all(unix, feature = "metrics"))]
+async fn dump_metrics(metrics: Arc<Metrics>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let Ok(mut usr1) = signal(SignalKind::user_defined1()) else {
+        tracing::warn!("can't install the SIGUSR1 handler, metrics dump disabled");
+        return;
+    };
+
+    while usr1.recv().await.is_some() {
+        eprint!("{}", metrics.render());
+    }
+}
+
+/// Answers a decoded client query, returning the encoded reply.
+async fn process(
+    state:     &State,
+    addr:      SocketAddr,
+    transport: Transport,
+    data:      &[u8],
+    req:       &Dns,
+) -> Result<Vec<u8>, DnsError> {
+
+    let start = Instant::now();
+    let ctx   = state.resolver.context();
+
+    // Get the first question from the DNS packet from the client
+    let qrc = req
+        .questions
+        .first()
+        .cloned()
+        .ok_or_else(|| DnsError::IOError("no questions found".into()))?;
+    Span::current()
+        .record("qname", field::display(&qrc.qname))
+        .record("qtype", field::display(qrc.qtype));
+
+    // Build the response from the client's query, so that it always
+    // carries the client's transaction ID and question
+    let mut res = Dns::new_reply(req);
+
+    // The primaries of the secondary zones notify their changes
+    if req.header.flags.opcode == OPCODE_NOTIFY {
+        return notified(state, addr, &qrc, res);
+    }
+
+    // The zones of master files may be updated by the allowed clients
+    if req.header.flags.opcode == OPCODE_UPDATE {
+        return updated(state, addr, data, req, res);
+    }
+
+    // Answers are cached separately depending on whether the client
+    // asked for DNSSEC records
+    let dnssec_ok = req.dnssec_ok();
+
+    // Names rejected by the policy are answered with NXDOMAIN or the
+    // sinkhole, without being resolved
+    let blocked = match state.policy.check(&qrc.qname) {
+        Verdict::Allow          => None,
+        Verdict::Blocked(entry) => Some(Block::blocklist(entry)),
+        Verdict::Unreadable     => Some(Block::unreadable(qrc.qname.clone())),
+        Verdict::Homograph(target) => {
+            tracing::warn!(
+                client = %addr,
+                qname  = %qrc.qname,
+                target = %target,
+                "query for a lookalike of a protected name",
+            );
+            (state.config.homograph_action == HomographAction::Block).then(|| Block::homograph(target))
+        }
+    };
+
+    // The policy plugins check the names the policy lets through: they may
+    // block them too, rewrite them to other addresses or route them
+    // elsewhere
+    let plugin = match blocked {
+        Some(_) => None,
+        None    => state.plugin(&qrc.qname, qrc.qtype, addr.ip()),
+    };
+    let blocked = match &plugin {
+        Some((name, PluginVerdict::Block)) => Some(Block::plugin(name.clone())),
+        _                                  => blocked,
+    };
+    let verdict   = plugin.map(|(_, verdict)| verdict);
+    let forwarded = verdict.as_ref().and_then(PluginVerdict::route);
+
+    // Rewritten names, diagnostic names, ACME challenges, static and
+    // dynamic records and localhost are answered locally, everything else
+    // comes from the cache or from a full resolution
+    let local = verdict
+        .as_ref()
+        .and_then(|verdict| verdict.answers(&qrc.qname, qrc.qtype))
+        .or_else(|| diagnostics::answer(&qrc.qname, qrc.qtype, addr, transport.name()))
+        .or_else(|| state.challenge(&qrc.qname, qrc.qtype))
+        .or_else(|| state.local().answer(&qrc.qname, qrc.qtype))
+        .or_else(|| state.dynamic.answer(&qrc.qname, qrc.qtype))
+        .or_else(|| special::answer(&qrc.qname, qrc.qtype));
+
+    // Special-use names that can't exist are never sent upstream, nor are
+    // the names of the local zones
+    let nonexistent = local.is_none() && special::is_nxdomain(&qrc.qname, state.config.mdns);
+    let zoned       = local.is_none().then(|| state.local().rcode(&qrc.qname)).flatten();
+
+    // The root and the top-level domains may be off limits for clients,
+    // as may the domains routed nowhere
+    let route   = forwarded.cloned().unwrap_or_else(|| state.resolver.route(&qrc.qname));
+    let refused = (is_apex(&qrc.qname) && state.config.apex_queries == ApexMode::Refuse)
+        || route == Route::Never;
+
+    // Clients not asking for recursion get what is already known, and are
+    // refused what isn't, unless configured otherwise
+    let non_recursive = if req.header.flags.rd {
+        None
+    } else {
+        Some(state.config.non_recursive)
+    };
+
+    if let Some(block) = blocked {
+        if req.opt.is_some() {
+            res.set_edns(state.resolver.infra().max_udp_size());
+        }
+        block.answer(&mut res, &qrc, &state.config.sinkhole)?;
+        if let Some(sinkhole) = &state.sinkhole {
+            sinkhole.record(&qrc.qname, &block);
+        }
+    } else if nonexistent {
+        res.set_rcode(3)?;
+    } else if let Some(rcode) = zoned {
+        res.set_rcode(rcode)?;
+    } else if refused || (local.is_none() && non_recursive == Some(NonRecursiveMode::Refuse)) {
+        res.set_rcode(5)?;
+    } else {
+        res.answers = match (local, non_recursive) {
+            (Some(answers), _) => answers,
+            (None, Some(NonRecursiveMode::Cache)) => {
+                match state.resolver.lookup_cached(&qrc.qname, qrc.qtype, dnssec_ok) {
+                    Some(answers) => answers,
+                    None          => {
+                        res.set_rcode(5)?;
+                        Vec::new()
+                    }
+                }
+            }
+            (None, _) => {
+                let outcome = match forwarded {
+                    Some(_) => state.resolver.lookup_via(&qrc.qname, qrc.qtype, route, &ctx).await,
+                    None    => state.resolver.lookup(&qrc.qname, qrc.qtype, dnssec_ok, &ctx).await,
+                };
+
+                // The names that don't exist, and those without records of
+                // the type, get an empty answer
+                match outcome {
+                    Err(DnsError::Negative { nxdomain, .. }) => {
+                        if nxdomain {
+                            res.set_rcode(3)?;
+                        }
+                        Vec::new()
+                    }
+                    outcome => outcome?,
+                }
+            }
+        };
+    }
+
+    if state.config.minimal_responses {
+        res.minimize();
+    }
+
+    // Encode DNS response into binary format. If it does not fit in the
+    // payload size the client can receive, or the administrator allows,
+    // drop the answers and set the TC flag so that the client retries
+    // over TCP
+    let mut enc = res.encode()?;
+    if enc.data.len() > reply_limit(state, transport, req.udp_payload_size()) {
+        res.header.flags.tc = true;
+        res.answers.clear();
+        enc = res.encode()?;
+    }
+
+    // Keep track of the latency, logging the query if it was too slow
+    let elapsed = start.elapsed();
+    state.metrics.observe_latency(elapsed);
+    state.slow_log.record(addr, &qrc.qname, qrc.qtype, elapsed, &ctx.trace);
+    if let Some(query_log) = &state.query_log {
+        query_log.record(addr, &qrc.qname, qrc.qtype, &rcode_name(res.rcode()), elapsed);
+    }
+
+    Ok(enc.into_inner())
+
+}
+
+/// Answers the NOTIFY of a zone change, refreshing the zone if it is a
+/// secondary zone of the sender. Any other NOTIFY is refused.
+#[cfg(feature = "zone-transfers")]
+fn notified(state: &State, addr: SocketAddr, qrc: &QueryRecord, mut res: Dns) -> Result<Vec<u8>, DnsError> {
+    res.header.flags.ra = false;
+    if qrc.qtype == Type::SOA && state.zones.notified(&qrc.qname, addr.ip()) {
+        tracing::info!(primary = %addr, zone = %qrc.qname, "zone change notified");
+        res.header.flags.aa = true;
+    } else {
+        tracing::warn!(client = %addr, zone = %qrc.qname, "NOTIFY refused");
+        res.set_rcode(5)?;
+    }
+    Ok(res.encode()?.into_inner())
+}
+
+/// Answers a NOTIFY without the zone transfers built in: it isn't
+/// implemented.
+#[cfg(not(feature = "zone-transfers"))]
+fn notified(_state: &State, _addr: SocketAddr, _qrc: &QueryRecord, mut res: Dns) -> Result<Vec<u8>, DnsError> {
+    res.header.flags.ra = false;
+    res.set_rcode(4)?;
+    Ok(res.encode()?.into_inner())
+}
+
+/// Answers a dynamic update (RFC 2136), received as `data`, applied if it
+/// is signed with one of the update keys allowed to change its zone, or
+/// else if the client is allowed to update the zones. The response
+/// carries no records.
+fn updated(state: &State, addr: SocketAddr, data: &[u8], req: &Dns, mut res: Dns) -> Result<Vec<u8>, DnsError> {
+    res.header.flags.ra = false;
+    let zone    = req.questions.first().map_or("", |question| question.qname.as_str());
+    let allowed = state.config.allow_update.iter().any(|network| network.contains(addr.ip()));
+    let outcome = match state.signer(data, req) {
+        Ok(Some(key)) if !key.may_update(zone) => {
+            tracing::warn!(client = %addr, zone = %zone, key = %key.name, "zone update outside the scope of its key");
+            Err(5)
+        }
+        Ok(Some(key)) => {
+            tracing::info!(client = %addr, zone = %zone, key = %key.name, "zone update signed");
+            state.zones.update(req)
+        }
+        Ok(None) if allowed => state.zones.update(req),
+        Ok(None)            => Err(5),
+        Err(e)              => {
+            tracing::warn!(client = %addr, error = %e, "zone update signature rejected");
+            Err(NOTAUTH)
+        }
+    };
+
+    match outcome {
+        Ok(())     => tracing::info!(client = %addr, zone = %zone, "zone update applied"),
+        Err(rcode) => {
+            tracing::warn!(client = %addr, zone = %zone, rcode = %rcode, "zone update rejected");
+            res.set_rcode(rcode.into())?;
+        }
+    }
+    Ok(res.encode()?.into_inner())
+}
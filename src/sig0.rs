@@ -0,0 +1,150 @@
+use crate::{
+    clock::Clock,
+    types::{Dns, DnsError},
+    update::UpdateKey,
+};
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use std::{sync::Arc, time::Duration};
+
+/// Type of the SIG records (RFC 2535).
+const SIG: u16 = 24;
+
+/// Class of the SIG(0) records.
+const CLASS_ANY: u16 = 255;
+
+/// Length of the fixed fields of the data of a SIG record, before the
+/// signer's name.
+const FIXED_LENGTH: usize = 18;
+
+/// Verifies the SIG(0) signatures of the dynamic updates (RFC 2931), made
+/// with the private keys of the clients and checked with their public
+/// keys.
+///
+/// A SIG(0) signature is the last record of the message, a SIG record of
+/// the root in class ANY covering no type. It signs its own data, without
+/// the signature, followed by the message before it, whose header doesn't
+/// count it.
+pub struct Sig0 {
+    keys:  Vec<UpdateKey>,
+    clock: Arc<dyn Clock>,
+    /// How far the clocks of the clients may be off.
+    skew:  u32,
+}
+
+impl Sig0 {
+    /// Creates a verifier of the signatures made with `keys`, valid `skew`
+    /// outside of their validity period as well.
+    pub fn new(keys: Vec<UpdateKey>, clock: Arc<dyn Clock>, skew: Duration) -> Self {
+        Sig0 { keys, clock, skew: skew.as_secs().try_into().unwrap_or(u32::MAX) }
+    }
+
+    /// Verifies the SIG(0) signature of the message `msg`, received as
+    /// `data`, returning the key that made it, or `None` if the message
+    /// isn't signed.
+    ///
+    /// Fails if the signature is invalid, expired, or made with an unknown
+    /// key.
+    pub fn verify(&self, data: &[u8], msg: &Dns) -> Result<Option<UpdateKey>, DnsError> {
+        let invalid = |reason: &str| DnsError::IOError(format!("invalid SIG(0) signature: {}", reason));
+        let Some(record) = msg.additionals.last().filter(|record| u16::from(record.atype) == SIG) else {
+            return Ok(None);
+        };
+        let span = record.wire_span().ok_or_else(|| invalid("not in the message"))?;
+        let root = record.aname.is_empty() || record.aname == ".";
+        if span.end != data.len() || record.aclass != CLASS_ANY || !root {
+            return Err(invalid("not the last record of the message"));
+        }
+
+        // The data of the record follows its fixed fields
+        let rdata = &data[span.end - record.length as usize..span.end];
+        let fixed = rdata.get(..FIXED_LENGTH).ok_or_else(|| invalid("truncated"))?;
+        let covered    = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let algorithm  = fixed[2];
+        let expiration = u32::from_be_bytes([fixed[8], fixed[9], fixed[10], fixed[11]]);
+        let inception  = u32::from_be_bytes([fixed[12], fixed[13], fixed[14], fixed[15]]);
+        let (signer, length) = read_name(&rdata[FIXED_LENGTH..]).ok_or_else(|| invalid("bad signer's name"))?;
+        let signature = &rdata[FIXED_LENGTH + length..];
+        if covered != 0 {
+            return Err(invalid("covering a type"));
+        }
+
+        // The times are compared in serial number arithmetic (RFC 1982)
+        let now     = self.clock.unix() as u32;
+        let started = now.wrapping_sub(inception) as i32 as i64 + self.skew as i64;
+        let left    = expiration.wrapping_sub(now) as i32 as i64 + self.skew as i64;
+        if started < 0 || left < 0 {
+            return Err(invalid("outside of its validity period"));
+        }
+
+        // The signed data is the record data up to the signature, then the
+        // message up to the record, with one additional record less
+        let mut signed = rdata[..FIXED_LENGTH + length].to_vec();
+        let header     = signed.len();
+        signed.extend_from_slice(&data[..span.start]);
+        let additionals = u16::from_be_bytes([signed[header + 10], signed[header + 11]]);
+        signed[header + 10..header + 12].copy_from_slice(&additionals.saturating_sub(1).to_be_bytes());
+
+        let key = self
+            .keys
+            .iter()
+            .filter(|key| key.algorithm == algorithm && key.name.eq_ignore_ascii_case(&signer))
+            .find(|key| verify(key, &signed, signature))
+            .ok_or_else(|| invalid(&format!("not made by a key of {}", signer)))?;
+        Ok(Some(key.clone()))
+    }
+}
+
+/// Reads an uncompressed name, as those of the data of the SIG records,
+/// returning it with its length on the wire.
+fn read_name(data: &[u8]) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut pos    = 0;
+    loop {
+        let length = *data.get(pos)? as usize;
+        pos += 1;
+        if length == 0 {
+            break;
+        }
+        if length > 63 {
+            return None;
+        }
+        labels.push(String::from_utf8_lossy(data.get(pos..pos + length)?).into_owned());
+        pos += length;
+    }
+    Some((labels.join("."), pos))
+}
+
+/// Checks the `signature` of `data` with a key.
+fn verify(key: &UpdateKey, data: &[u8], signature: &[u8]) -> bool {
+    match key.algorithm {
+        8 | 10 => {
+            // The exponent comes first, after its length (RFC 3110)
+            let (length, rest) = match key.key.split_first() {
+                Some((0, rest)) if rest.len() > 2 => (u16::from_be_bytes([rest[0], rest[1]]) as usize, &rest[2..]),
+                Some((&length, rest))             => (length as usize, rest),
+                None                              => return false,
+            };
+            if rest.len() <= length {
+                return false;
+            }
+            let (e, n) = rest.split_at(length);
+            let parameters = match key.algorithm {
+                8 => &signature::RSA_PKCS1_2048_8192_SHA256,
+                _ => &signature::RSA_PKCS1_2048_8192_SHA512,
+            };
+            RsaPublicKeyComponents { n, e }.verify(parameters, data, signature).is_ok()
+        }
+        13 | 14 => {
+            // The key is the point alone, without its uncompressed form tag
+            let mut point = vec![4];
+            point.extend_from_slice(&key.key);
+            let algorithm = match key.algorithm {
+                13 => &signature::ECDSA_P256_SHA256_FIXED,
+                _  => &signature::ECDSA_P384_SHA384_FIXED,
+            };
+            UnparsedPublicKey::new(algorithm, point).verify(data, signature).is_ok()
+        }
+        15 => UnparsedPublicKey::new(&signature::ED25519, &key.key).verify(data, signature).is_ok(),
+        _  => false,
+    }
+}
@@ -0,0 +1,158 @@
+use crate::policy::Block;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+/// How long a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request accepted.
+const MAX_REQUEST: usize = 4096;
+
+/// Blocked names remembered for the page, the oldest being forgotten
+/// first.
+const MAX_BLOCKS: usize = 4096;
+
+/// Names recently blocked, by the page served to the browsers sent to the
+/// sinkhole.
+#[derive(Debug, Default)]
+struct Blocks {
+    reasons: HashMap<String, Block>,
+    order:   VecDeque<String>,
+}
+
+/// Web server explaining to the browsers sent to the sinkhole why the
+/// name they asked for was blocked.
+///
+/// The name comes from the `Host` header of the request, and the reason
+/// from the blocks answered recently.
+#[derive(Debug, Default)]
+pub struct Sinkhole {
+    blocks: Mutex<Blocks>,
+}
+
+impl Sinkhole {
+    /// Creates the server, with no block recorded.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `name` was blocked.
+    pub fn record(&self, name: &str, block: &Block) {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let mut blocks = self.blocks.lock().unwrap();
+        if blocks.reasons.insert(name.clone(), block.clone()).is_none() {
+            blocks.order.push_back(name);
+        }
+        while blocks.order.len() > MAX_BLOCKS {
+            if let Some(oldest) = blocks.order.pop_front() {
+                blocks.reasons.remove(&oldest);
+            }
+        }
+    }
+
+    /// Serves the block page over HTTP on `addr`, whatever the path.
+    pub async fn serve(self: Arc<Self>, addr: SocketAddr) {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!(addr = %addr, error = %e, "can't listen for the sinkhole page");
+                return;
+            }
+        };
+
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let sinkhole = Arc::clone(&self);
+            tokio::spawn(async move {
+                let _ = time::timeout(REQUEST_TIMEOUT, sinkhole.respond(stream)).await;
+            });
+        }
+    }
+
+    /// Answers a single HTTP request.
+    async fn respond(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut request = Vec::new();
+        let mut buf     = [0u8; 512];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+
+        let request = String::from_utf8_lossy(&request);
+        let host = request
+            .split("\r\n")
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .find(|(header, _)| header.trim().eq_ignore_ascii_case("host"))
+            .map(|(_, value)| host_name(value.trim()));
+
+        let body = match host {
+            Some(host) => {
+                let reason = self
+                    .blocks
+                    .lock()
+                    .unwrap()
+                    .reasons
+                    .get(&host)
+                    .map(|block| format!("<p>{}</p>\n", escape(&block.to_string())))
+                    .unwrap_or_default();
+                page(&format!("{} was blocked", escape(&host)), &reason)
+            }
+            None => page("This domain was blocked", ""),
+        };
+
+        let response = format!(
+            "HTTP/1.1 403 Forbidden\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body,
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+/// Returns the name of a `Host` header value, without its port.
+fn host_name(value: &str) -> String {
+    let name = match value.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next(),
+        None       => value.split(':').next(),
+    };
+    let name = name.unwrap_or_default();
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Builds the HTML page with the given title and paragraphs.
+fn page(title: &str, paragraphs: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n{1}</body>\n</html>\n",
+        title, paragraphs,
+    )
+}
+
+/// Escapes the characters that HTML gives a meaning to.
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&'  => "&amp;".to_string(),
+            '<'  => "&lt;".to_string(),
+            '>'  => "&gt;".to_string(),
+            '"'  => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            c    => c.to_string(),
+        })
+        .collect()
+}
@@ -0,0 +1,94 @@
+use crate::{resolver::Trace, types::{DnsError, Type}};
+use std::{
+    fs::OpenOptions,
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::File,
+    io::{self, AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
+
+/// Lines waiting to be written. Beyond that, the slow queries aren't
+/// logged rather than slowed down further.
+const QUEUE_LENGTH: usize = 1024;
+
+/// Log of the queries whose resolution took longer than a threshold.
+///
+/// Each line carries the client, the question, the time spent and the
+/// delegation trace that was followed, much like database slow logs. The
+/// lines are queued and written by a task of their own, as in the query
+/// log.
+pub struct SlowLog {
+    threshold: Duration,
+    lines:     mpsc::Sender<String>,
+}
+
+impl SlowLog {
+    /// Opens the slow-query log, appending to `path` or writing to stderr.
+    pub fn open(threshold: Duration, path: Option<&Path>) -> Result<Self, DnsError> {
+        let sink: Box<dyn AsyncWrite + Send + Unpin> = match path {
+            Some(path) => Box::new(File::from_std(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| DnsError::IOError(format!("can't open slow log: {}", e)))?,
+            )),
+            None => Box::new(io::stderr()),
+        };
+
+        let (lines, queue) = mpsc::channel(QUEUE_LENGTH);
+        tokio::spawn(write(sink, queue));
+        Ok(SlowLog { threshold, lines })
+    }
+
+    /// Records a query, if it exceeded the threshold.
+    pub fn record(
+        &self,
+        client:   SocketAddr,
+        qname:    &str,
+        qtype:    Type,
+        duration: Duration,
+        trace:    &Trace,
+    ) {
+        if duration < self.threshold {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let _ = self.lines.try_send(format!(
+            "{} client={} qname={} qtype={} duration_ms={} trace={}\n",
+            timestamp,
+            client,
+            qname,
+            qtype,
+            duration.as_millis(),
+            trace,
+        ));
+    }
+}
+
+/// Writes the queued lines to `sink` until the queue is closed, flushing
+/// whenever the queue is empty.
+async fn write(sink: Box<dyn AsyncWrite + Send + Unpin>, mut queue: mpsc::Receiver<String>) {
+    let mut sink = BufWriter::new(sink);
+    while let Some(mut line) = queue.recv().await {
+        loop {
+            if let Err(e) = sink.write_all(line.as_bytes()).await {
+                tracing::error!(error = %e, "can't write the slow log");
+            }
+            match queue.try_recv() {
+                Ok(next) => line = next,
+                Err(_)   => break,
+            }
+        }
+        let _ = sink.flush().await;
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{dnstap::Dnstap, privacy::Privacy, rng::DnsRng, types::DnsError};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::{net::UdpSocket, sync::mpsc, time};
+
+/// How often an idle socket checks whether it was retired.
+const RETIRE_CHECK: Duration = Duration::from_secs(1);
+
+/// Packets buffered for a query before the next ones are dropped.
+const QUEUE_LENGTH: usize = 8;
+
+/// Attempts at binding a random port before leaving the choice to the
+/// operating system.
+const BIND_ATTEMPTS: usize = 3;
+
+/// Connections a TCP listener queues before accepting them.
+const BACKLOG: i32 = 1024;
+
+/// Sockets the upstream queries are sent from.
+///
+/// Binding a socket for every query is costly under load, while a few
+/// long-lived sockets would let an attacker learn their ports. The pool
+/// keeps a fixed number of sockets on random ports, shared by the queries,
+/// and replaces each of them by one on another port once it gets old. The
+/// packets received on a socket are dispatched to the queries by server
+/// and ID.
+#[derive(Debug)]
+pub struct SocketPool {
+    v4:       Vec<Mutex<Option<Slot>>>,
+    v6:       Vec<Mutex<Option<Slot>>>,
+    lifetime: Duration,
+    rng:      Arc<DnsRng>,
+    privacy:  Privacy,
+    dnstap:   Option<Arc<Dnstap>>,
+}
+
+/// Queries waiting on a socket, by server and ID.
+type Waiting = HashMap<(SocketAddr, u16), mpsc::Sender<Vec<u8>>>;
+
+/// A place in the pool, with the socket currently bound for it.
+#[derive(Debug)]
+struct Slot {
+    socket: Arc<PooledSocket>,
+    bound:  Instant,
+}
+
+/// A socket of the pool, with the queries waiting for packets on it.
+#[derive(Debug)]
+struct PooledSocket {
+    sock:    UdpSocket,
+    waiting: Mutex<Waiting>,
+    retired: AtomicBool,
+}
+
+/// A query waiting on a socket of the pool, receiving the packets that
+/// come from its server with its ID.
+#[derive(Debug)]
+pub struct Pending {
+    socket:  Arc<PooledSocket>,
+    key:     (SocketAddr, u16),
+    packets: mpsc::Receiver<Vec<u8>>,
+}
+
+impl SocketPool {
+    /// Creates a pool of `size` sockets per address family, each used for
+    /// `lifetime` before being replaced. With a size of 0, every query gets
+    /// a socket of its own. The queries leave as `privacy` allows, and are
+    /// written to `dnstap` along with their responses, if set.
+    pub fn new(
+        size:     usize,
+        lifetime: Duration,
+        rng:      Arc<DnsRng>,
+        privacy:  Privacy,
+        dnstap:   Option<Arc<Dnstap>>,
+    ) -> Self {
+        SocketPool {
+            v4: (0..size).map(|_| Mutex::new(None)).collect(),
+            v6: (0..size).map(|_| Mutex::new(None)).collect(),
+            lifetime,
+            rng,
+            privacy,
+            dnstap,
+        }
+    }
+
+    /// Returns the random generator the ports are drawn from.
+    pub fn rng(&self) -> &DnsRng {
+        &self.rng
+    }
+
+    /// Returns the privacy profile of the outgoing queries.
+    pub fn privacy(&self) -> &Privacy {
+        &self.privacy
+    }
+
+    /// Returns the dnstap output of the outgoing queries, if any.
+    pub fn dnstap(&self) -> Option<&Dnstap> {
+        self.dnstap.as_deref()
+    }
+
+    /// Registers a query with `id` to `server` on a random socket of the
+    /// pool. Returns `None` if the pool is disabled, or if the socket
+    /// can't take the query: another one with the same ID is waiting for
+    /// the same server, or no socket could be bound.
+    pub fn register(&self, server: SocketAddr, id: u16) -> Option<Pending> {
+        let slots = match server {
+            SocketAddr::V4(_) => &self.v4,
+            SocketAddr::V6(_) => &self.v6,
+        };
+        if slots.is_empty() {
+            return None;
+        }
+
+        let socket = {
+            let mut slot = slots[self.rng.index(slots.len())].lock().unwrap();
+            if slot.as_ref().is_none_or(|slot| slot.bound.elapsed() >= self.lifetime) {
+                if let Some(old) = slot.take() {
+                    old.socket.retired.store(true, Ordering::Relaxed);
+                }
+                *slot = self.bind(server).map(|socket| Slot { socket, bound: Instant::now() });
+            }
+            Arc::clone(&slot.as_ref()?.socket)
+        };
+
+        let (sender, packets) = mpsc::channel(QUEUE_LENGTH);
+        match socket.waiting.lock().unwrap().entry((server, id)) {
+            Entry::Occupied(_)   => return None,
+            Entry::Vacant(entry) => entry.insert(sender),
+        };
+        Some(Pending { socket, key: (server, id), packets })
+    }
+
+    /// Binds a new socket of the family of `server` on a random port, and
+    /// starts dispatching its packets.
+    fn bind(&self, server: SocketAddr) -> Option<Arc<PooledSocket>> {
+        let ip = match server {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let sock = (0..BIND_ATTEMPTS)
+            .map(|_| self.rng.source_port())
+            .chain([0])
+            .find_map(|port| std::net::UdpSocket::bind(SocketAddr::new(ip, port)).ok())?;
+        let sock = sock
+            .set_nonblocking(true)
+            .and_then(|_| UdpSocket::from_std(sock))
+            .inspect_err(|e| tracing::warn!(error = %e, "can't set up a pooled socket"))
+            .ok()?;
+
+        let socket = Arc::new(PooledSocket {
+            sock,
+            waiting: Mutex::new(HashMap::new()),
+            retired: AtomicBool::new(false),
+        });
+        tokio::spawn(Arc::clone(&socket).dispatch());
+        Some(socket)
+    }
+}
+
+impl PooledSocket {
+    /// Hands the packets received to the queries they are for, until the
+    /// socket is retired and no query waits on it anymore.
+    async fn dispatch(self: Arc<Self>) {
+        let mut buffer = vec![0u8; 65535];
+        while !(self.retired.load(Ordering::Relaxed) && self.waiting.lock().unwrap().is_empty()) {
+            let Ok(Ok((size, from))) = time::timeout(RETIRE_CHECK, self.sock.recv_from(&mut buffer)).await else {
+                continue;
+            };
+            if size < 2 {
+                continue;
+            }
+            let id = u16::from_be_bytes([buffer[0], buffer[1]]);
+            if let Some(sender) = self.waiting.lock().unwrap().get(&(from, id)) {
+                let _ = sender.try_send(buffer[..size].to_vec());
+            }
+        }
+    }
+}
+
+impl Pending {
+    /// Sends the query to its server.
+    pub async fn send(&self, data: &[u8]) -> Result<(), DnsError> {
+        self.socket
+            .sock
+            .send_to(data, self.key.0)
+            .await
+            .map(|_| ())
+            .map_err(|_| DnsError::IOError("can't send DNS packet".into()))
+    }
+
+    /// Waits for the next packet from the server carrying the ID of the
+    /// query.
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        self.packets.recv().await
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.socket.waiting.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Creates a socket the client queries are received on, bound to `addr`.
+///
+/// Bound to the unspecified IPv6 address (`[::]`), the socket is dual
+/// stack whatever the system default, so that the IPv4 clients are
+/// received too, with IPv4-mapped addresses.
+fn listener(addr: SocketAddr, kind: Type, protocol: Protocol) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), kind, Some(protocol))?;
+    if let SocketAddr::V6(v6) = addr {
+        socket.set_only_v6(!v6.ip().is_unspecified())?;
+    }
+    if protocol == Protocol::TCP && cfg!(unix) {
+        socket.set_reuse_address(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket)
+}
+
+/// Binds the UDP socket the client queries are received on.
+pub fn listen_udp(addr: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    listener(addr, Type::DGRAM, Protocol::UDP).map(Into::into)
+}
+
+/// Binds a TCP listener the clients connect to.
+pub fn listen_tcp(addr: SocketAddr) -> io::Result<std::net::TcpListener> {
+    let socket = listener(addr, Type::STREAM, Protocol::TCP)?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
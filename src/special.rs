@@ -0,0 +1,90 @@
+use crate::types::{AnswerRecord, RData, Type};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// TTL of the loopback records of `localhost`.
+const LOCALHOST_TTL: u32 = 86400;
+
+/// Special-use domains that never exist in the global DNS (RFC 6761,
+/// RFC 7686), and are answered with NXDOMAIN without being forwarded.
+const NONEXISTENT: &[&str] = &["invalid", "test", "onion"];
+
+/// Domain reserved for Multicast DNS (RFC 6762).
+const MDNS_DOMAIN: &str = "local";
+
+/// Returns whether `qname` is `domain` or one of its subdomains.
+fn is_within(qname: &str, domain: &str) -> bool {
+    let qname = qname.trim_end_matches('.').to_ascii_lowercase();
+    qname == domain || qname.ends_with(&format!(".{}", domain))
+}
+
+/// Returns whether `qname` is a reverse lookup name, under `in-addr.arpa`
+/// or `ip6.arpa`.
+pub fn is_reverse(qname: &str) -> bool {
+    is_within(qname, "in-addr.arpa") || is_within(qname, "ip6.arpa")
+}
+
+/// Answers the names under `localhost` with the loopback addresses
+/// (RFC 6761, section 6.3), if `qname` is one of them.
+pub fn answer(qname: &str, qtype: Type) -> Option<Vec<AnswerRecord>> {
+    if !is_within(qname, "localhost") {
+        return None;
+    }
+
+    let rdata = match qtype {
+        Type::A    => vec![RData::A(Ipv4Addr::LOCALHOST)],
+        Type::AAAA => vec![RData::AAAA(Ipv6Addr::LOCALHOST)],
+        _ => Vec::new(),
+    };
+
+    Some(
+        rdata
+            .into_iter()
+            .map(|rdata| {
+                let mut answer = AnswerRecord::new(qname.to_string(), rdata);
+                answer.ttl = LOCALHOST_TTL;
+                answer
+            })
+            .collect(),
+    )
+}
+
+/// Returns whether `qname` belongs to a special-use domain that must be
+/// answered with NXDOMAIN locally. Names under `.local` are left alone
+/// when Multicast DNS is enabled.
+pub fn is_nxdomain(qname: &str, mdns: bool) -> bool {
+    NONEXISTENT.iter().any(|domain| is_within(qname, domain))
+        || (!mdns && is_within(qname, MDNS_DOMAIN))
+        || is_private_reverse(qname)
+}
+
+/// Returns whether `qname` is a reverse lookup name within private address
+/// space: RFC 1918 and link-local IPv4 addresses, link-local and unique
+/// local IPv6 addresses. Such lookups only make sense on the local network,
+/// and would otherwise end up at the AS112 servers (RFC 7534), leaking the
+/// private addresses in use.
+fn is_private_reverse(qname: &str) -> bool {
+    let name = qname.trim_end_matches('.').to_ascii_lowercase();
+
+    if let Some(labels) = name.strip_suffix(".in-addr.arpa") {
+        let octets: Option<Vec<u8>> = labels.split('.').rev().map(|label| label.parse().ok()).collect();
+        matches!(
+            octets.as_deref(),
+            Some([10, ..] | [172, 16..=31, ..] | [192, 168, ..] | [169, 254, ..])
+        )
+    } else if let Some(labels) = name.strip_suffix(".ip6.arpa") {
+        let nibbles: Option<Vec<u8>> = labels
+            .split('.')
+            .rev()
+            .map(|label| match label.len() {
+                1 => u8::from_str_radix(label, 16).ok(),
+                _ => None,
+            })
+            .collect();
+        matches!(
+            nibbles.as_deref(),
+            Some([0xf, 0xc..=0xd, ..] | [0xf, 0xe, 0x8..=0xb, ..])
+        )
+    } else {
+        false
+    }
+}
@@ -0,0 +1,93 @@
+use crate::types::{AnswerRecord, Dns, DnsError, DnsReadBuffer};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Stream of the answer records carried by a sequence of length-prefixed
+/// messages, as sent over TCP (RFC 1035, section 4.2.2).
+///
+/// Responses with many records, such as zone transfers spanning many
+/// messages or large TXT sets, are decoded one record at a time: only the
+/// message being read is kept in memory, never the whole set of records.
+pub struct RecordStream<R> {
+    reader:    R,
+    /// Message the records are being read from.
+    message:   Vec<u8>,
+    /// Position of the next record within the message.
+    index:     usize,
+    /// Answer records left in the message.
+    remaining: u16,
+    /// Whether the stream ended, or failed.
+    done:      bool,
+}
+
+impl<R: AsyncRead + Unpin> RecordStream<R> {
+    /// Creates a stream reading the messages from `reader`.
+    pub fn new(reader: R) -> Self {
+        RecordStream {
+            reader,
+            message:   Vec::new(),
+            index:     0,
+            remaining: 0,
+            done:      false,
+        }
+    }
+
+    /// Returns the next answer record, or `None` once the reader is
+    /// exhausted. The stream ends after the first error.
+    pub async fn next(&mut self) -> Option<Result<AnswerRecord, DnsError>> {
+        while !self.done && self.remaining == 0 {
+            match self.read_message().await {
+                Ok(true)  => {}
+                Ok(false) => self.done = true,
+                Err(e)    => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        if self.done {
+            return None;
+        }
+
+        let mut buf = DnsReadBuffer::new(&self.message);
+        let record = buf
+            .set_index(self.index)
+            .map_err(|_| DnsError::InvalidField)
+            .and_then(|buf| Dns::decode_record(buf));
+
+        match record {
+            Ok(_)  => {
+                self.index = buf.get_index();
+                self.remaining -= 1;
+            }
+            Err(_) => self.done = true,
+        }
+        Some(record)
+    }
+
+    /// Reads the next message, skipping its header and questions. Returns
+    /// `false` if the reader ended cleanly before a new message.
+    async fn read_message(&mut self) -> Result<bool, DnsError> {
+        let length = match self.reader.read_u16().await {
+            Ok(length) => length,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(_) => return Err(DnsError::SocketError),
+        };
+
+        self.message.resize(length as usize, 0);
+        self.reader
+            .read_exact(&mut self.message)
+            .await
+            .map_err(|_| DnsError::SocketError)?;
+
+        let mut buf = DnsReadBuffer::new(&self.message);
+        let header = Dns::decode_header(&mut buf)?;
+        if header.flags.rcode != 0 {
+            return Err(DnsError::IOError(format!("response code {}", header.flags.rcode)));
+        }
+        Dns::decode_questions(&mut buf, header.qd_count)?;
+
+        self.index     = buf.get_index();
+        self.remaining = header.an_count;
+        Ok(true)
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{metrics::Metrics, types::Dns};
+use std::{
+    any::Any,
+    future::Future,
+    net::SocketAddr,
+    panic,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::net::UdpSocket;
+
+/// Maximum number of panics logged in each window.
+const MAX_PANIC_LOGS: u32 = 10;
+
+/// Length of the panic logging window.
+const PANIC_LOG_WINDOW: Duration = Duration::from_secs(60);
+
+tokio::task_local! {
+    /// Set while running a supervised request task.
+    static SUPERVISED: ();
+}
+
+/// Installs a panic hook that stays silent for supervised tasks, whose
+/// panics are reported by the supervisor itself. Any other panic is
+/// handled by the default hook.
+pub fn install_panic_hook() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if SUPERVISED.try_with(|_| ()).is_err() {
+            default(info);
+        }
+    }));
+}
+
+/// Rate limiter for the panic log messages.
+#[derive(Debug)]
+pub struct PanicLog {
+    window: Mutex<(Instant, u32)>,
+}
+
+impl PanicLog {
+    /// Creates a new limiter with an empty window.
+    pub fn new() -> Self {
+        PanicLog {
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Logs a panic, unless too many were logged in the current window.
+    fn log(&self, client: SocketAddr, message: &str) {
+        let mut window = self.window.lock().unwrap();
+        let (start, count) = &mut *window;
+
+        if start.elapsed() >= PANIC_LOG_WINDOW {
+            if *count > MAX_PANIC_LOGS {
+                let suppressed = *count - MAX_PANIC_LOGS;
+                tracing::warn!(count = %suppressed, "panic messages suppressed");
+            }
+            *start = Instant::now();
+            *count = 0;
+        }
+
+        *count += 1;
+        if *count <= MAX_PANIC_LOGS {
+            tracing::error!(
+                client = %client,
+                panic  = %message,
+                "request task panicked",
+            );
+        }
+    }
+}
+
+/// Runs a request task, catching its panics.
+///
+/// When the task panics, the crash is counted, logged (rate-limited) and
+/// the client gets a SERVFAIL, provided that the query carried at least a
+/// full header.
+pub async fn supervise<F>(
+    task:      F,
+    sock:      Arc<UdpSocket>,
+    addr:      SocketAddr,
+    query:     Vec<u8>,
+    metrics:   Arc<Metrics>,
+    panic_log: Arc<PanicLog>,
+) where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let Err(err) = tokio::spawn(SUPERVISED.scope((), task)).await else {
+        return;
+    };
+
+    if !err.is_panic() {
+        return;
+    }
+
+    metrics.record_panic();
+    panic_log.log(addr, &panic_message(err.into_panic()));
+
+    if let Some(reply) = Dns::new_servfail(&query) {
+        let _ = sock.send_to(&reply, addr).await;
+    }
+}
+
+/// Extracts the message from a panic payload.
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
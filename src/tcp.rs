@@ -0,0 +1,105 @@
+use crate::{
+    sockets,
+    types::{Dns, DnsError},
+};
+use std::{future::Future, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+    time,
+};
+
+/// How long a connection may stay idle between two queries (RFC 7766,
+/// section 6.2.3), and how long a client has to send the rest of a query
+/// or take its response.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections served at once. Beyond that, the new ones wait in the
+/// backlog of the listener until another is closed.
+const MAX_CONNECTIONS: usize = 512;
+
+/// Delay before accepting connections again after failing to, such as
+/// when the process runs out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Binds the TCP listener the queries are received on, next to the UDP
+/// socket of the same address.
+pub fn listen(addr: SocketAddr) -> Result<TcpListener, DnsError> {
+    sockets::listen_tcp(addr)
+        .and_then(TcpListener::from_std)
+        .map_err(|e| DnsError::IOError(format!("can't bind {}: {}", addr, e)))
+}
+
+/// Serves the clients connecting over TCP (RFC 7766), answering their
+/// queries with `answer`. Clients come here when a UDP response was
+/// truncated, or to get large responses in the first place.
+///
+/// Messages are framed with their length (RFC 1035, section 4.2.2). The
+/// queries of a connection are answered in turn, and connections idle for
+/// too long, or too slow to send a query, are closed. At most
+/// `MAX_CONNECTIONS` are served at once.
+pub async fn serve<F, Fut>(listener: TcpListener, answer: F)
+where
+    F:   Fn(SocketAddr, Vec<u8>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Option<Vec<u8>>> + Send + 'static,
+{
+    let slots = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        let Ok(slot) = Arc::clone(&slots).acquire_owned().await else {
+            return;
+        };
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e)       => {
+                tracing::warn!(error = %e, "can't accept a TCP connection");
+                time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let answer = answer.clone();
+        tokio::spawn(async move {
+            connection(stream, addr, answer).await;
+            drop(slot);
+        });
+    }
+}
+
+/// Answers the queries of a client, until it disconnects or stays idle.
+async fn connection<F, Fut>(mut stream: TcpStream, addr: SocketAddr, answer: F)
+where
+    F:   Fn(SocketAddr, Vec<u8>) -> Fut,
+    Fut: Future<Output = Option<Vec<u8>>>,
+{
+    loop {
+        let Ok(Ok(length)) = time::timeout(IDLE_TIMEOUT, stream.read_u16()).await else {
+            return;
+        };
+
+        let mut query = vec![0; length as usize];
+        let Ok(Ok(_)) = time::timeout(IDLE_TIMEOUT, stream.read_exact(&mut query)).await else {
+            return;
+        };
+
+        // A response too large to be framed can't be sent, and fails
+        // rather than leaving the client waiting
+        let header: Vec<u8> = query.iter().take(12).copied().collect();
+        let Some(reply) = answer(addr, query).await else {
+            continue;
+        };
+        let reply = match u16::try_from(reply.len()) {
+            Ok(_)  => reply,
+            Err(_) => match Dns::new_servfail(&header) {
+                Some(servfail) => servfail,
+                None           => continue,
+            },
+        };
+
+        let mut framed = Vec::with_capacity(reply.len() + 2);
+        framed.extend_from_slice(&(reply.len() as u16).to_be_bytes());
+        framed.extend_from_slice(&reply);
+        let Ok(Ok(())) = time::timeout(IDLE_TIMEOUT, stream.write_all(&framed)).await else {
+            return;
+        };
+    }
+}
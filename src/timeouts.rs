@@ -0,0 +1,155 @@
+use crate::{routing::Route, types::DnsError};
+use std::{future::Future, str::FromStr, time::Duration};
+
+/// Longest time waited for a single response, however many times the
+/// timeout was doubled.
+const MAX_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for the response of an upstream server, and how many
+/// more times to ask it when it doesn't come.
+///
+/// Every retry waits twice as long as the attempt before it, so that a
+/// congested server or path isn't flooded with queries it can't answer in
+/// time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How long to wait for each response.
+    pub timeout: Duration,
+    /// Queries sent again after a timeout.
+    pub retries: u32,
+}
+
+impl RetryPolicy {
+    /// Creates a policy waiting `timeout` for a single query.
+    pub fn new(timeout: Duration) -> Self {
+        RetryPolicy { timeout, retries: 0 }
+    }
+
+    /// Returns how long to wait for the response to the `retry`-th retry,
+    /// 0 being the first query.
+    pub fn timeout(&self, retry: u32) -> Duration {
+        self.timeout
+            .saturating_mul(1 << retry.min(16))
+            .min(MAX_ATTEMPT_TIMEOUT.max(self.timeout))
+    }
+
+    /// Runs `attempt` with the timeout of the policy, again as long as it
+    /// times out and retries are left.
+    #[cfg_attr(not(any(feature = "doh", feature = "doq", feature = "dot")), allow(dead_code))]
+    pub async fn run<T, F, Fut>(&self, mut attempt: F) -> Result<T, DnsError>
+    where
+        F:   FnMut(Duration) -> Fut,
+        Fut: Future<Output = Result<T, DnsError>>,
+    {
+        let mut retry = 0;
+        loop {
+            match attempt(self.timeout(retry)).await {
+                Err(DnsError::Timeout) if retry < self.retries => retry += 1,
+                res => return res,
+            }
+        }
+    }
+}
+
+impl FromStr for RetryPolicy {
+    type Err = DnsError;
+
+    /// Parses a timeout in milliseconds, optionally followed by the number
+    /// of retries, such as `1500` or `4000/2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::IOError(format!("invalid retry policy: {}", s));
+        let (millis, retries) = s.split_once('/').unwrap_or((s, "0"));
+        let millis: u64 = millis.trim().parse().map_err(|_| invalid())?;
+        if millis == 0 {
+            return Err(invalid());
+        }
+        Ok(RetryPolicy {
+            timeout: Duration::from_millis(millis),
+            retries: retries.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// What a timeout rule applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// The queries for the names of a domain and its subdomains.
+    Zone(String),
+    /// The queries sent to an upstream server, written as a route.
+    Upstream(Route),
+}
+
+/// A rule overriding the retry policy of a zone or of an upstream server,
+/// written as `target=policy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeoutRule {
+    /// What the rule applies to.
+    pub target: Target,
+    /// The retry policy.
+    pub policy: RetryPolicy,
+}
+
+impl FromStr for TimeoutRule {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // URLs may contain '=', but policies don't
+        let (target, policy) = s
+            .rsplit_once('=')
+            .ok_or_else(|| DnsError::IOError(format!("invalid timeout rule: {}", s)))?;
+        let target = target.trim();
+        let target = if ["udp:", "quic:", "https://", "http://"].iter().any(|p| target.starts_with(p)) {
+            Target::Upstream(target.parse()?)
+        } else {
+            Target::Zone(target.trim_start_matches("*.").trim_end_matches('.').to_ascii_lowercase())
+        };
+        Ok(TimeoutRule { target, policy: policy.parse()? })
+    }
+}
+
+/// Retry policies of the upstream queries.
+#[derive(Debug)]
+pub struct Timeouts {
+    rules:   Vec<TimeoutRule>,
+    default: RetryPolicy,
+}
+
+impl Timeouts {
+    /// Creates the table from its rules, and the policy of the plain UDP
+    /// queries without any.
+    pub fn new(rules: &[TimeoutRule], default: RetryPolicy) -> Self {
+        Timeouts { rules: rules.to_vec(), default }
+    }
+
+    /// Returns the policy of the plain UDP queries without a rule.
+    pub fn default_policy(&self) -> RetryPolicy {
+        self.default
+    }
+
+    /// Returns the policy of a query for `qname` sent to `upstream`: the
+    /// one of the upstream if it has a rule, or else the one of the most
+    /// specific zone rule matching the name, or else `default`.
+    pub fn policy(&self, qname: &str, upstream: &Route, default: RetryPolicy) -> RetryPolicy {
+        let name = qname.trim_end_matches('.').to_ascii_lowercase();
+        let upstream_rule = self
+            .rules
+            .iter()
+            .find(|rule| rule.target == Target::Upstream(upstream.clone()));
+        let zone_rule = || {
+            self.rules
+                .iter()
+                .filter_map(|rule| match &rule.target {
+                    Target::Zone(zone) if zone.is_empty() || name == *zone || name.ends_with(&format!(".{}", zone)) => {
+                        Some((zone.len(), rule))
+                    }
+                    _ => None,
+                })
+                .max_by_key(|(len, _)| *len)
+                .map(|(_, rule)| rule)
+        };
+
+        upstream_rule
+            .or_else(zone_rule)
+            .map_or(default, |rule| rule.policy)
+    }
+}
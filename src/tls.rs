@@ -0,0 +1,63 @@
+use crate::types::DnsError;
+#[cfg(feature = "dot")]
+use rustls::{ClientConfig, RootCertStore};
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig,
+};
+use std::{fmt::Display, path::Path, sync::Arc};
+
+/// Loads the certificate chain and the private key, both PEM encoded, a
+/// TLS server presents to its clients, negotiating the protocol `alpn`.
+///
+/// The listeners of every encrypted transport share the same certificate,
+/// each with its own ALPN token.
+pub fn server_config(cert: &Path, key: &Path, alpn: &[u8]) -> Result<ServerConfig, DnsError> {
+    let cant_load = |path: &Path, e: &dyn Display| DnsError::IOError(format!("can't load {}: {}", path.display(), e));
+
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| cant_load(cert, &e))?;
+    if chain.is_empty() {
+        return Err(cant_load(cert, &"no certificate"));
+    }
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| cant_load(key, &e))?;
+
+    let mut tls = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| DnsError::IOError(format!("can't configure TLS: {}", e)))?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(|e| DnsError::IOError(format!("can't configure TLS: {}", e)))?;
+    tls.alpn_protocols = vec![alpn.to_vec()];
+    Ok(tls)
+}
+
+/// Builds the configuration of a TLS client negotiating the protocol
+/// `alpn`, trusting the certificate authorities of the PEM file `roots`,
+/// or else the usual web PKI roots.
+#[cfg(feature = "dot")]
+pub fn client_config(roots: Option<&Path>, alpn: &[u8]) -> Result<ClientConfig, DnsError> {
+    let store = match roots {
+        Some(path) => {
+            let mut store = RootCertStore::empty();
+            let certs = CertificateDer::pem_file_iter(path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| DnsError::IOError(format!("can't load {}: {}", path.display(), e)))?;
+            let (added, _) = store.add_parsable_certificates(certs);
+            if added == 0 {
+                return Err(DnsError::IOError(format!("can't load {}: no certificate", path.display())));
+            }
+            store
+        }
+        None => RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    };
+
+    let mut tls = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| DnsError::IOError(format!("can't configure TLS: {}", e)))?
+        .with_root_certificates(store)
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![alpn.to_vec()];
+    Ok(tls)
+}
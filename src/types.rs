@@ -1,10 +1,15 @@
 use core::fmt;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    ops::Range,
+    str::FromStr,
+};
 
 /// DNS message header.
 ///
 /// Contains fields identifying the message and counts of question,
-/// answer, authority, and additional records.
+/// answer, authority, and additional records. The counts are the ones
+/// read from the wire: encoding derives them from the sections.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Header {
     /// Message identifier.
@@ -52,7 +57,7 @@ pub struct QueryRecord {
     /// Domain name being queried.
     pub qname: String,
     /// Query type (e.g., A, AAAA, NS).
-    pub qtype: u16,
+    pub qtype: Type,
     /// Query class (usually IN for internet).
     pub qclass: u16,
 }
@@ -66,67 +71,298 @@ pub enum RData {
     AAAA(Ipv6Addr),
     NS(String),
     CNAME(String),
-    // TXT(String),
-    // MX {
-    //     preference: u16,
-    //     exchange:   String,
-    // },
-    // SOA {
-    //     mname:   String,
-    //     rname:   String,
-    //     serial:  u32,
-    //     refresh: u32,
-    //     retry:   u32,
-    //     expire:  u32,
-    //     minimum: u32,
-    // },
-    // PTR(String),
-    EMPTY([u8; 0]), // Generic fallback
+    /// The character-strings of the record, as sent: each one holds at
+    /// most 255 bytes, which need not be UTF-8.
+    TXT(Vec<Vec<u8>>),
+    MX {
+        preference: u16,
+        exchange:   String,
+    },
+    SOA(Soa),
+    PTR(String),
+    SRV {
+        priority: u16,
+        weight:   u16,
+        port:     u16,
+        target:   String,
+    },
+    SVCB(Svcb),
+    HTTPS(Svcb),
+    /// Data of a type without a variant of its own, kept as is so that
+    /// the record can be relayed unchanged (RFC 3597).
+    Unknown {
+        rtype: u16,
+        data:  Vec<u8>,
+    },
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u16)]
+/// Data of an SVCB or HTTPS record, binding a service to its endpoint
+/// and connection parameters (RFC 9460).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Svcb {
+    /// Priority of the endpoint, 0 for an alias to `target`.
+    pub priority: u16,
+    /// Name of the endpoint, `.` for the owner name itself.
+    pub target: String,
+    /// Parameters of the endpoint, in the order they were given.
+    pub params: Vec<SvcParam>,
+}
+
+/// A parameter of an SVCB or HTTPS record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SvcParam {
+    /// Keys of the parameters the client must support (key 0).
+    Mandatory(Vec<u16>),
+    /// Application protocols supported, such as `h2` (key 1).
+    Alpn(Vec<String>),
+    /// The default protocol is not supported (key 2).
+    NoDefaultAlpn,
+    /// Port of the endpoint (key 3).
+    Port(u16),
+    /// IPv4 addresses of the endpoint (key 4).
+    Ipv4Hint(Vec<Ipv4Addr>),
+    /// Encrypted ClientHello configuration, as is (key 5).
+    Ech(Vec<u8>),
+    /// IPv6 addresses of the endpoint (key 6).
+    Ipv6Hint(Vec<Ipv6Addr>),
+    /// Parameter of another key, with its raw value.
+    Unknown(u16, Vec<u8>),
+}
+
+impl SvcParam {
+    /// Returns the key of the parameter.
+    pub fn key(&self) -> u16 {
+        match self {
+            SvcParam::Mandatory(_)   => 0,
+            SvcParam::Alpn(_)        => 1,
+            SvcParam::NoDefaultAlpn  => 2,
+            SvcParam::Port(_)        => 3,
+            SvcParam::Ipv4Hint(_)    => 4,
+            SvcParam::Ech(_)         => 5,
+            SvcParam::Ipv6Hint(_)    => 6,
+            SvcParam::Unknown(key, _) => *key,
+        }
+    }
+
+    /// Returns the length in bytes of the value of the parameter.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u16 {
+        match self {
+            SvcParam::Mandatory(keys)  => keys.len() as u16 * 2,
+            SvcParam::Alpn(ids)        => ids.iter().map(|id| id.len() as u16 + 1).sum(),
+            SvcParam::NoDefaultAlpn    => 0,
+            SvcParam::Port(_)          => 2,
+            SvcParam::Ipv4Hint(ips)    => ips.len() as u16 * 4,
+            SvcParam::Ech(config)      => config.len() as u16,
+            SvcParam::Ipv6Hint(ips)    => ips.len() as u16 * 16,
+            SvcParam::Unknown(_, data) => data.len() as u16,
+        }
+    }
+}
+
+/// Data of an SOA record, describing the zone it starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Soa {
+    /// Primary name server of the zone.
+    pub mname: String,
+    /// Mailbox of the person responsible for the zone.
+    pub rname: String,
+    /// Version number of the zone.
+    pub serial: u32,
+    /// Seconds before the secondaries refresh the zone.
+    pub refresh: u32,
+    /// Seconds before a failed refresh is retried.
+    pub retry: u32,
+    /// Seconds after which a zone that can't be refreshed expires.
+    pub expire: u32,
+    /// TTL of the negative answers from the zone (RFC 2308).
+    pub minimum: u32,
+}
+
+/// Record and query types.
+///
+/// Types without a variant of their own are kept as `Unknown`, so that
+/// every value read from the wire can be written back as it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Type {
-    A     = 1,
-    NS    = 2,
-    CNAME = 5,
-    MX    = 15,
-    TXT   = 16,
-    AAAA  = 28,
-    PTR   = 12,
-    SOA   = 6,
+    A,
+    NS,
+    CNAME,
+    SOA,
+    PTR,
+    MX,
+    TXT,
+    AAAA,
+    SRV,
+    OPT,
+    DS,
+    RRSIG,
+    NSEC,
+    DNSKEY,
+    NSEC3,
+    SVCB,
+    HTTPS,
+    IXFR,
+    AXFR,
+    ANY,
+    CAA,
+    Unknown(u16),
+}
+
+impl From<u16> for Type {
+    fn from(value: u16) -> Self {
+        match value {
+            1   => Type::A,
+            2   => Type::NS,
+            5   => Type::CNAME,
+            6   => Type::SOA,
+            12  => Type::PTR,
+            15  => Type::MX,
+            16  => Type::TXT,
+            28  => Type::AAAA,
+            33  => Type::SRV,
+            41  => Type::OPT,
+            43  => Type::DS,
+            46  => Type::RRSIG,
+            47  => Type::NSEC,
+            48  => Type::DNSKEY,
+            50  => Type::NSEC3,
+            64  => Type::SVCB,
+            65  => Type::HTTPS,
+            251 => Type::IXFR,
+            252 => Type::AXFR,
+            255 => Type::ANY,
+            257 => Type::CAA,
+            _   => Type::Unknown(value),
+        }
+    }
 }
 
-impl Type {
-    pub fn from_u16(value: u16) -> Option<Type> {
+impl From<Type> for u16 {
+    fn from(value: Type) -> Self {
         match value {
-            1  => Some(Type::A),
-            2  => Some(Type::NS),
-            5  => Some(Type::CNAME),
-            6  => Some(Type::SOA),
-            12 => Some(Type::PTR),
-            15 => Some(Type::MX),
-            16 => Some(Type::TXT),
-            28 => Some(Type::AAAA),
-            _  => None,
+            Type::A          => 1,
+            Type::NS         => 2,
+            Type::CNAME      => 5,
+            Type::SOA        => 6,
+            Type::PTR        => 12,
+            Type::MX         => 15,
+            Type::TXT        => 16,
+            Type::AAAA       => 28,
+            Type::SRV        => 33,
+            Type::OPT        => 41,
+            Type::DS         => 43,
+            Type::RRSIG      => 46,
+            Type::NSEC       => 47,
+            Type::DNSKEY     => 48,
+            Type::NSEC3      => 50,
+            Type::SVCB       => 64,
+            Type::HTTPS      => 65,
+            Type::IXFR       => 251,
+            Type::AXFR       => 252,
+            Type::ANY        => 255,
+            Type::CAA        => 257,
+            Type::Unknown(n) => n,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    /// Writes the mnemonic of the type, or `TYPEnn` for unknown ones
+    /// (RFC 3597, section 5).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Unknown(n) => write!(f, "TYPE{}", n),
+            t                => write!(f, "{:?}", t),
+        }
+    }
+}
+
+impl FromStr for Type {
+    type Err = DnsError;
+
+    /// Parses the mnemonic of a type, such as `AAAA`, in any case, or its
+    /// `TYPEnn` generic form.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_ascii_uppercase();
+        if let Some(n) = upper.strip_prefix("TYPE").and_then(|n| n.parse::<u16>().ok()) {
+            return Ok(Type::from(n));
+        }
+        match upper.as_str() {
+            "A"      => Ok(Type::A),
+            "NS"     => Ok(Type::NS),
+            "CNAME"  => Ok(Type::CNAME),
+            "SOA"    => Ok(Type::SOA),
+            "PTR"    => Ok(Type::PTR),
+            "MX"     => Ok(Type::MX),
+            "TXT"    => Ok(Type::TXT),
+            "AAAA"   => Ok(Type::AAAA),
+            "SRV"    => Ok(Type::SRV),
+            "OPT"    => Ok(Type::OPT),
+            "DS"     => Ok(Type::DS),
+            "RRSIG"  => Ok(Type::RRSIG),
+            "NSEC"   => Ok(Type::NSEC),
+            "DNSKEY" => Ok(Type::DNSKEY),
+            "NSEC3"  => Ok(Type::NSEC3),
+            "SVCB"   => Ok(Type::SVCB),
+            "HTTPS"  => Ok(Type::HTTPS),
+            "IXFR"   => Ok(Type::IXFR),
+            "AXFR"   => Ok(Type::AXFR),
+            "ANY"    => Ok(Type::ANY),
+            "CAA"    => Ok(Type::CAA),
+            _        => Err(DnsError::IOError(format!("unknown record type: {}", s))),
         }
     }
 }
 
 impl RData {
+    /// Creates a `TXT` record holding `text`, split into character-strings
+    /// of at most 255 bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dns_resolver::RData;
+    /// let rdata = RData::txt("v=spf1 -all");
+    /// assert_eq!(rdata, RData::TXT(vec![b"v=spf1 -all".to_vec()]));
+    /// ```
+    pub fn txt(text: &str) -> Self {
+        let bytes = text.as_bytes();
+        match bytes.is_empty() {
+            true  => RData::TXT(vec![Vec::new()]),
+            false => RData::TXT(bytes.chunks(255).map(<[u8]>::to_vec).collect()),
+        }
+    }
+
     /// Returns the length in bytes of the RData payload.
     ///
     /// For `A` and `AAAA` records, this is fixed.
-    /// For domain name records like `CNAME` and `NS`, length includes 
+    /// For domain name records like `CNAME`, `NS` and `PTR`, length includes 
     /// label length plus 2 bytes.
-    /// For other variants, returns 0.
+    /// For `TXT` records, it includes a length byte per character-string.
+    /// For `MX` records, the preference takes 2 more bytes than the name.
+    /// For `SOA` records, the five counters take 20 bytes after the names.
+    /// For `SRV` records, the priority, weight and port take 6 more bytes
+    /// than the target.
+    /// For `SVCB` and `HTTPS` records, the priority and the target are
+    /// followed by each parameter, with 4 bytes for its key and length.
+    /// For unknown types, this is the length of the raw data.
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> u16 {
         match self {
             RData::A(_)              => 4,
             RData::AAAA(_)           => 16,
             RData::CNAME(s) => s.len() as u16 + 2,
             RData::NS(s)    => s.len() as u16 + 2,
-            _                        => 0,
+            RData::PTR(s)   => s.len() as u16 + 2,
+            RData::TXT(s)   => s.iter().map(|s| s.len() as u16 + 1).sum::<u16>().max(1),
+            RData::MX { exchange, .. } => exchange.len() as u16 + 4,
+            RData::SOA(soa) => soa.mname.len() as u16 + soa.rname.len() as u16 + 4 + 20,
+            RData::SRV { target, .. } => target.len() as u16 + 8,
+            RData::SVCB(svcb) | RData::HTTPS(svcb) => {
+                svcb.target.len() as u16 + 4 + svcb.params.iter().map(|param| param.len() + 4).sum::<u16>()
+            }
+            RData::Unknown { data, .. } => data.len() as u16,
         }
     }
 
@@ -135,6 +371,8 @@ impl RData {
     /// # Examples
     ///
     /// ```
+    /// # use dns_resolver::RData;
+    /// # let rdata = RData::A(std::net::Ipv4Addr::new(192, 0, 2, 1));
     /// if let Some(ipv4) = rdata.as_a() {
     ///     println!("IPv4 address: {}", ipv4);
     /// }
@@ -152,6 +390,8 @@ impl RData {
     /// # Examples
     ///
     /// ```
+    /// # use dns_resolver::RData;
+    /// # let rdata = RData::AAAA("2001:db8::1".parse().unwrap());
     /// if let Some(ipv6) = rdata.as_aaaa() {
     ///     println!("IPv6 address: {}", ipv6);
     /// }
@@ -169,6 +409,8 @@ impl RData {
     /// # Examples
     ///
     /// ```
+    /// # use dns_resolver::RData;
+    /// # let rdata = RData::NS("ns1.example.com".into());
     /// if let Some(ns_name) = rdata.as_ns() {
     ///     println!("Name server domain: {}", ns_name);
     /// }
@@ -181,13 +423,15 @@ impl RData {
         }
     }
 
-     /// Returns the contained domain name if the record is an `CNAME` (name server) record.
+    /// Returns the contained domain name if the record is a `CNAME` (canonical name) record.
     ///
     /// # Examples
     ///
     /// ```
+    /// # use dns_resolver::RData;
+    /// # let rdata = RData::CNAME("www.example.com".into());
     /// if let Some(cname) = rdata.as_cname() {
-    ///     println!("Cnanonical server name: {}", cname);
+    ///     println!("Canonical name: {}", cname);
     /// }
     /// ```
     pub fn as_cname(&self) -> Option<&str> {
@@ -196,7 +440,225 @@ impl RData {
         } else {
             None
         }
-    }   
+    }
+
+    /// Returns the character-strings if the record is a `TXT` record.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dns_resolver::RData;
+    /// # let rdata = RData::txt("v=spf1 -all");
+    /// if let Some(strings) = rdata.as_txt() {
+    ///     println!("Text: {}", String::from_utf8_lossy(&strings.concat()));
+    /// }
+    /// ```
+    pub fn as_txt(&self) -> Option<&[Vec<u8>]> {
+        if let RData::TXT(text) = self {
+            Some(text)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the preference and the mail exchange if the record is an `MX` record.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dns_resolver::RData;
+    /// # let rdata = RData::MX { preference: 10, exchange: "mail.example.com".into() };
+    /// if let Some((preference, exchange)) = rdata.as_mx() {
+    ///     println!("Mail exchange: {} ({})", exchange, preference);
+    /// }
+    /// ```
+    pub fn as_mx(&self) -> Option<(u16, &str)> {
+        if let RData::MX { preference, exchange } = self {
+            Some((*preference, exchange))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the zone data if the record is an `SOA` record.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dns_resolver::{RData, Soa};
+    /// # let rdata = RData::SOA(Soa {
+    /// #     mname:   "ns1.example.com".into(),
+    /// #     rname:   "hostmaster.example.com".into(),
+    /// #     serial:  2024010101,
+    /// #     refresh: 7200,
+    /// #     retry:   3600,
+    /// #     expire:  1209600,
+    /// #     minimum: 300,
+    /// # });
+    /// if let Some(soa) = rdata.as_soa() {
+    ///     println!("Negative TTL: {}", soa.minimum);
+    /// }
+    /// ```
+    pub fn as_soa(&self) -> Option<&Soa> {
+        if let RData::SOA(soa) = self {
+            Some(soa)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the contained domain name if the record is a `PTR` (pointer) record.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dns_resolver::RData;
+    /// # let rdata = RData::PTR("host.example.com".into());
+    /// if let Some(name) = rdata.as_ptr() {
+    ///     println!("Pointed name: {}", name);
+    /// }
+    /// ```
+    pub fn as_ptr(&self) -> Option<&str> {
+        if let RData::PTR(name) = self {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the priority, weight, port and target if the record is an
+    /// `SRV` record.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dns_resolver::RData;
+    /// # let rdata = RData::SRV { priority: 10, weight: 5, port: 5060, target: "sip.example.com".into() };
+    /// if let Some((priority, weight, port, target)) = rdata.as_srv() {
+    ///     println!("Service at {}:{} ({}/{})", target, port, priority, weight);
+    /// }
+    /// ```
+    pub fn as_srv(&self) -> Option<(u16, u16, u16, &str)> {
+        if let RData::SRV { priority, weight, port, target } = self {
+            Some((*priority, *weight, *port, target))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the service binding if the record is an `SVCB` or an `HTTPS`
+    /// record.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dns_resolver::{RData, Svcb};
+    /// # let rdata = RData::SVCB(Svcb { priority: 1, target: "svc.example.com".into(), params: Vec::new() });
+    /// if let Some(svcb) = rdata.as_svcb() {
+    ///     println!("Endpoint: {} ({})", svcb.target, svcb.priority);
+    /// }
+    /// ```
+    pub fn as_svcb(&self) -> Option<&Svcb> {
+        if let RData::SVCB(svcb) | RData::HTTPS(svcb) = self {
+            Some(svcb)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the type of the record.
+    pub fn rtype(&self) -> Type {
+        match self {
+            RData::A(_)     => Type::A,
+            RData::AAAA(_)  => Type::AAAA,
+            RData::NS(_)    => Type::NS,
+            RData::CNAME(_) => Type::CNAME,
+            RData::TXT(_)   => Type::TXT,
+            RData::MX {..}  => Type::MX,
+            RData::SOA(_)   => Type::SOA,
+            RData::PTR(_)   => Type::PTR,
+            RData::SRV {..} => Type::SRV,
+            RData::SVCB(_)  => Type::SVCB,
+            RData::HTTPS(_) => Type::HTTPS,
+            RData::Unknown { rtype, .. } => Type::from(*rtype),
+        }
+    }
+
+    /// Consumes the record, returning its domain name if it is an `NS` record.
+    pub fn into_ns(self) -> Option<String> {
+        if let RData::NS(name) = self {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    /// Consumes the record, returning its domain name if it is a `CNAME` record.
+    pub fn into_cname(self) -> Option<String> {
+        if let RData::CNAME(name) = self {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    /// Consumes the record, returning its character-strings if it is a
+    /// `TXT` record.
+    pub fn into_txt(self) -> Option<Vec<Vec<u8>>> {
+        if let RData::TXT(text) = self {
+            Some(text)
+        } else {
+            None
+        }
+    }
+
+    /// Consumes the record, returning its preference and mail exchange if
+    /// it is an `MX` record.
+    pub fn into_mx(self) -> Option<(u16, String)> {
+        if let RData::MX { preference, exchange } = self {
+            Some((preference, exchange))
+        } else {
+            None
+        }
+    }
+
+    /// Consumes the record, returning its zone data if it is an `SOA` record.
+    pub fn into_soa(self) -> Option<Soa> {
+        if let RData::SOA(soa) = self {
+            Some(soa)
+        } else {
+            None
+        }
+    }
+
+    /// Consumes the record, returning its domain name if it is a `PTR` record.
+    pub fn into_ptr(self) -> Option<String> {
+        if let RData::PTR(name) = self {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    /// Consumes the record, returning its service binding if it is an
+    /// `SVCB` or an `HTTPS` record.
+    pub fn into_svcb(self) -> Option<Svcb> {
+        if let RData::SVCB(svcb) | RData::HTTPS(svcb) = self {
+            Some(svcb)
+        } else {
+            None
+        }
+    }
+
+    /// Consumes the record, returning its priority, weight, port and target
+    /// if it is an `SRV` record.
+    pub fn into_srv(self) -> Option<(u16, u16, u16, String)> {
+        if let RData::SRV { priority, weight, port, target } = self {
+            Some((priority, weight, port, target))
+        } else {
+            None
+        }
+    }
 }
 
 
@@ -208,7 +670,7 @@ pub struct AnswerRecord {
     /// Domain name this record pertains to.
     pub aname: String,
     /// Type of the record.
-    pub atype: u16,
+    pub atype: Type,
     /// Class of the record.
     pub aclass: u16,
     /// Time to live (in seconds).
@@ -217,6 +679,35 @@ pub struct AnswerRecord {
     pub length: u16,
     /// Resource data payload.
     pub rdata: RData,
+    /// Byte range of the record within the message it was decoded from.
+    pub span: Option<Range<usize>>,
+}
+
+/// An option carried by the OPT record (RFC 6891, section 6.1.2).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnsOption {
+    /// Option code.
+    pub code: u16,
+    /// Option data.
+    pub data: Vec<u8>,
+}
+
+/// The EDNS(0) OPT pseudo-record (RFC 6891).
+///
+/// Decoded from the additional section into its own field of the message,
+/// and written back at the end of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptRecord {
+    /// Largest UDP payload the sender can receive.
+    pub udp_size: u16,
+    /// Upper 8 bits of the 12-bit extended response code.
+    pub extended_rcode: u8,
+    /// EDNS version.
+    pub version: u8,
+    /// DNSSEC OK (DO) bit.
+    pub dnssec_ok: bool,
+    /// Options, in the order they appear.
+    pub options: Vec<EdnsOption>,
 }
 
 /// A parsed DNS message.
@@ -234,10 +725,12 @@ pub struct Dns {
     pub authorities: Vec<AnswerRecord>,
     /// Vector of additional records.
     pub additionals: Vec<AnswerRecord>,
+    /// EDNS(0) OPT record, if the message carries one.
+    pub opt: Option<OptRecord>,
 }
 
 /// DNS parsing or encoding errors.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum DnsError {
     /// Invalid field value encountered.
     InvalidField,
@@ -245,8 +738,28 @@ pub enum DnsError {
     InvalidRData,
     /// Socket-related error.
     SocketError,
+    /// No response was received in time.
+    Timeout,
     /// Generic I/O error with message.
     IOError(String),
+    /// The name doesn't exist (NXDOMAIN), or has no records of the type
+    /// asked for (NODATA), which may be cached for `ttl` seconds (RFC
+    /// 2308).
+    Negative { nxdomain: bool, ttl: u32 },
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsError::InvalidField => write!(f, "invalid field"),
+            DnsError::InvalidRData => write!(f, "invalid resource data"),
+            DnsError::SocketError  => write!(f, "socket error"),
+            DnsError::Timeout      => write!(f, "timed out"),
+            DnsError::IOError(msg) => write!(f, "I/O error: {}", msg),
+            DnsError::Negative { nxdomain: true, .. }  => write!(f, "no such name"),
+            DnsError::Negative { nxdomain: false, .. } => write!(f, "no records of the type"),
+        }
+    }
 }
 
 /// A read-only buffer wrapper for parsing DNS messages.
@@ -259,6 +772,8 @@ pub struct DnsReadBuffer<'a> {
     pub data: &'a [u8],
     /// Current read offset index into `data`.
     pub index: usize,
+    /// Bytes the decoded names and record data may still take.
+    pub budget: usize,
 }
 
 /// Errors that can occur during reading from a DNS buffer.
@@ -270,12 +785,14 @@ pub enum DnsBufferError {
     InvalidString,
     /// DNS label exceeded maximum length.
     LabelTooLong,
+    /// The decoded data exceeded the budget of the buffer.
+    BudgetExceeded,
 }
 
 /// A write-only buffer for constructing DNS messages.
 ///
 /// Holds a growable vector of bytes to which data can be appended.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct DnsWriteBuffer {
     /// Internal data buffer.
     pub data: Vec<u8>,
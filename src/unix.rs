@@ -0,0 +1,146 @@
+use crate::types::DnsError;
+use std::str::FromStr;
+
+#[cfg(unix)]
+use std::{
+    fs,
+    future::Future,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::Path,
+    time::Duration,
+};
+#[cfg(unix)]
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    time,
+};
+
+/// How long a connection may stay idle between two queries.
+#[cfg(unix)]
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A rule admitting the clients of the unix domain socket by the
+/// credentials of their process, written as `uid:<n>` or `gid:<n>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerRule {
+    /// Processes running as the user.
+    Uid(u32),
+    /// Processes running with the group as their primary group.
+    Gid(u32),
+}
+
+impl FromStr for PeerRule {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::IOError(format!("invalid peer rule: {}", s));
+        let (kind, id) = s.split_once(':').ok_or_else(invalid)?;
+        let id = id.trim().parse().map_err(|_| invalid())?;
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "uid" => Ok(PeerRule::Uid(id)),
+            "gid" => Ok(PeerRule::Gid(id)),
+            _     => Err(invalid()),
+        }
+    }
+}
+
+/// Tells whether a process running as `uid` and `gid` may query, which
+/// any may when there are no rules.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn allowed(rules: &[PeerRule], uid: u32, gid: u32) -> bool {
+    rules.is_empty()
+        || rules.iter().any(|rule| match *rule {
+            PeerRule::Uid(id) => id == uid,
+            PeerRule::Gid(id) => id == gid,
+        })
+}
+
+/// Binds the unix domain socket at `path`, replacing a stale socket left
+/// there, but no other kind of file.
+///
+/// The socket is open to every local user: the clients are admitted by
+/// their credentials instead, as the kernel reports them.
+#[cfg(unix)]
+pub fn listen(path: &Path) -> Result<UnixListener, DnsError> {
+    let cant_bind = |e: std::io::Error| DnsError::IOError(format!("can't bind {}: {}", path.display(), e));
+
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(DnsError::IOError(format!("{} exists and isn't a socket", path.display())));
+        }
+        fs::remove_file(path).map_err(cant_bind)?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(cant_bind)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666)).map_err(cant_bind)?;
+    Ok(listener)
+}
+
+/// Serves the clients connecting to the unix domain socket, answering
+/// their queries with `answer`.
+///
+/// Messages are framed as over TCP, each preceded by its length (RFC 1035,
+/// section 4.2.2). Clients whose credentials no rule admits are
+/// disconnected right away. The queries of a connection are answered in
+/// turn, and connections idle for too long are closed.
+#[cfg(unix)]
+pub async fn serve<F, Fut>(listener: UnixListener, rules: Vec<PeerRule>, answer: F)
+where
+    F:   Fn(Vec<u8>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Option<Vec<u8>>> + Send + 'static,
+{
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+
+        let Ok(cred) = stream.peer_cred() else {
+            continue;
+        };
+        if !allowed(&rules, cred.uid(), cred.gid()) {
+            tracing::warn!(
+                uid = %cred.uid(),
+                gid = %cred.gid(),
+                pid = %cred.pid().unwrap_or_default(),
+                "unix socket client refused",
+            );
+            continue;
+        }
+
+        tokio::spawn(connection(stream, answer.clone()));
+    }
+}
+
+/// Answers the queries of a client, until it disconnects or stays idle.
+#[cfg(unix)]
+async fn connection<F, Fut>(mut stream: UnixStream, answer: F)
+where
+    F:   Fn(Vec<u8>) -> Fut,
+    Fut: Future<Output = Option<Vec<u8>>>,
+{
+    loop {
+        let Ok(Ok(length)) = time::timeout(IDLE_TIMEOUT, stream.read_u16()).await else {
+            return;
+        };
+
+        let mut query = vec![0; length as usize];
+        if stream.read_exact(&mut query).await.is_err() {
+            return;
+        }
+
+        let Some(reply) = answer(query).await else {
+            continue;
+        };
+        let Ok(length) = u16::try_from(reply.len()) else {
+            continue;
+        };
+
+        let mut framed = Vec::with_capacity(reply.len() + 2);
+        framed.extend_from_slice(&length.to_be_bytes());
+        framed.extend_from_slice(&reply);
+        if stream.write_all(&framed).await.is_err() {
+            return;
+        }
+    }
+}
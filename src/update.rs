@@ -0,0 +1,275 @@
+use crate::{
+    dns::base64_decode,
+    types::{AnswerRecord, Dns, DnsError, RData, Type},
+    zone::{is_newer, RRset, Zone},
+};
+use std::str::FromStr;
+
+/// Operation code of the UPDATE messages (RFC 2136).
+pub const OPCODE_UPDATE: u8 = 5;
+
+/// Class of the prerequisites requiring the absence of records, and of
+/// the updates deleting a single record (RFC 2136, section 2.4).
+const CLASS_NONE: u16 = 254;
+
+/// Class of the prerequisites and updates about any record of a set.
+const CLASS_ANY: u16 = 255;
+
+/// Response codes of the updates that fail (RFC 2136, section 2.2).
+pub const FORMERR:  u8 = 1;
+pub const SERVFAIL: u8 = 2;
+pub const NXDOMAIN: u8 = 3;
+pub const YXDOMAIN: u8 = 6;
+pub const YXRRSET:  u8 = 7;
+pub const NXRRSET:  u8 = 8;
+pub const NOTAUTH:  u8 = 9;
+pub const NOTZONE:  u8 = 10;
+
+/// A public key the dynamic updates may be signed with (SIG(0), RFC 2931),
+/// written as `<name>:<algorithm>:<base64 key>:<zones>`, the key being in
+/// the format of the DNSKEY records of its algorithm, and the zones it may
+/// update separated by slashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateKey {
+    /// Name of the key, the signer's name of the signatures.
+    pub name: String,
+    /// Number of the DNSSEC algorithm of the key.
+    pub algorithm: u8,
+    /// The public key.
+    pub key: Vec<u8>,
+    /// The zones the updates signed with the key may change.
+    pub zones: Vec<String>,
+}
+
+impl UpdateKey {
+    /// Returns whether the updates signed with the key may change `zone`.
+    pub fn may_update(&self, zone: &str) -> bool {
+        let zone = zone.trim_end_matches('.');
+        self.zones.iter().any(|scope| scope.eq_ignore_ascii_case(zone))
+    }
+}
+
+impl FromStr for UpdateKey {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::IOError(format!("invalid update key: {}", s));
+        let fields: Vec<&str> = s.splitn(4, ':').map(str::trim).collect();
+        let [name, algorithm, key, zones] = fields[..] else {
+            return Err(invalid());
+        };
+        let zones: Vec<String> = zones.split('/').map(|zone| zone.trim().trim_end_matches('.').to_string()).collect();
+
+        // Only the algorithms that can be verified are accepted, with keys
+        // of their size: RSA/SHA-256, RSA/SHA-512, ECDSA P-256/SHA-256,
+        // ECDSA P-384/SHA-384 and Ed25519
+        let algorithm = algorithm.parse().map_err(|_| invalid())?;
+        let key       = base64_decode(&key.replace(' ', "")).ok_or_else(invalid)?;
+        let valid     = match algorithm {
+            8 | 10 => key.len() > 3,
+            13     => key.len() == 64,
+            14     => key.len() == 96,
+            15     => key.len() == 32,
+            _      => false,
+        };
+        if name.is_empty() || !valid || zones.iter().any(String::is_empty) {
+            return Err(invalid());
+        }
+        Ok(UpdateKey { name: name.trim_end_matches('.').to_string(), algorithm, key, zones })
+    }
+}
+
+/// Applies an UPDATE message to `zone` (RFC 2136, section 3): the
+/// prerequisites of its answer section must all hold, then the updates of
+/// its authority section are applied in order. The serial of the zone is
+/// increased if the zone changed and the updates didn't increase it.
+///
+/// Returns the updated zone, or the response code telling why the update
+/// was rejected as a whole.
+pub fn apply(zone: &Zone, update: &Dns) -> Result<Zone, u8> {
+    let class = zone
+        .rrset(&zone.origin, Type::SOA)
+        .map(|soa| soa.class)
+        .ok_or(NOTAUTH)?;
+    check_prerequisites(zone, &update.answers, class)?;
+    prescan(zone, &update.authorities, class)?;
+
+    let mut updated = zone.clone();
+    for record in &update.authorities {
+        match record.aclass {
+            CLASS_ANY  => delete_rrset(&mut updated, record),
+            CLASS_NONE => delete_record(&mut updated, record),
+            _          => add(&mut updated, record),
+        }
+    }
+
+    let serial = |zone: &Zone| zone.soa().map(|soa| soa.serial);
+    if updated != *zone && serial(&updated) == serial(zone) {
+        let soa = updated
+            .rrsets
+            .iter_mut()
+            .find(|rrset| rrset.rtype == Type::SOA && rrset.name.eq_ignore_ascii_case(&zone.origin))
+            .and_then(|rrset| rrset.records.first_mut());
+        if let Some(RData::SOA(soa)) = soa.map(|record| &mut record.rdata) {
+            soa.serial = soa.serial.wrapping_add(1);
+        }
+    }
+    Ok(updated)
+}
+
+/// Checks the prerequisites of an update (RFC 2136, section 3.2): the
+/// names and the record sets that must or mustn't exist, and the record
+/// sets that must hold exactly the records given.
+fn check_prerequisites(zone: &Zone, prerequisites: &[AnswerRecord], class: u16) -> Result<(), u8> {
+    let mut expected: Vec<RRset> = Vec::new();
+    for record in prerequisites {
+        if record.ttl != 0 {
+            return Err(FORMERR);
+        }
+        if !zone.contains(&record.aname) {
+            return Err(NOTZONE);
+        }
+
+        let name   = &record.aname;
+        let empty  = record.rdata.len() == 0;
+        let any    = record.atype == Type::ANY;
+        let in_use = zone.rrsets.iter().any(|rrset| rrset.name.eq_ignore_ascii_case(name));
+        let exists = zone.rrset(name, record.atype).is_some();
+        match record.aclass {
+            CLASS_ANY | CLASS_NONE if !empty => return Err(FORMERR),
+            CLASS_ANY if any && !in_use      => return Err(NXDOMAIN),
+            CLASS_ANY if !any && !exists     => return Err(NXRRSET),
+            CLASS_NONE if any && in_use      => return Err(YXDOMAIN),
+            CLASS_NONE if !any && exists     => return Err(YXRRSET),
+            CLASS_ANY | CLASS_NONE           => {}
+            aclass if aclass == class => {
+                let position = expected
+                    .iter()
+                    .position(|rrset| rrset.rtype == record.atype && rrset.name.eq_ignore_ascii_case(name));
+                match position {
+                    Some(n) => expected[n].records.push(record.clone()),
+                    None    => expected.push(RRset {
+                        name:    name.clone(),
+                        rtype:   record.atype,
+                        class,
+                        records: vec![record.clone()],
+                    }),
+                }
+            }
+            _ => return Err(FORMERR),
+        }
+    }
+
+    // The record sets given must be those of the zone, whatever the order
+    for rrset in expected {
+        let current  = zone.rrset(&rrset.name, rrset.rtype).ok_or(NXRRSET)?;
+        let contains = |records: &[AnswerRecord], rdata: &RData| records.iter().any(|record| record.rdata == *rdata);
+        let same     = rrset.records.iter().all(|record| contains(&current.records, &record.rdata))
+            && current.records.iter().all(|record| contains(&rrset.records, &record.rdata));
+        if !same {
+            return Err(NXRRSET);
+        }
+    }
+    Ok(())
+}
+
+/// Checks the updates before any is applied (RFC 2136, section 3.4.1), so
+/// that an invalid one rejects them all.
+fn prescan(zone: &Zone, updates: &[AnswerRecord], class: u16) -> Result<(), u8> {
+    for record in updates {
+        if !zone.contains(&record.aname) {
+            return Err(NOTZONE);
+        }
+
+        // Meta types, such as OPT or AXFR, aren't records of a zone
+        let meta  = matches!(u16::from(record.atype), 41 | 128..=255);
+        let valid = match record.aclass {
+            CLASS_ANY  => record.ttl == 0 && record.rdata.len() == 0 && (!meta || record.atype == Type::ANY),
+            CLASS_NONE => record.ttl == 0 && !meta,
+            aclass     => aclass == class && !meta,
+        };
+        if !valid {
+            return Err(FORMERR);
+        }
+    }
+    Ok(())
+}
+
+/// Adds a record to its set, replacing the same record with another TTL.
+///
+/// A name holds either a CNAME record or records of other types, so the
+/// records breaking that rule are ignored; the SOA record is only
+/// replaced by one with a newer serial.
+fn add(zone: &mut Zone, record: &AnswerRecord) {
+    let name   = &record.aname;
+    let record = AnswerRecord { length: record.rdata.len(), span: None, ..record.clone() };
+
+    let owned = |rrset: &RRset| rrset.name.eq_ignore_ascii_case(name);
+    let cname = zone.rrsets.iter().any(|rrset| owned(rrset) && rrset.rtype == Type::CNAME);
+    let other = zone.rrsets.iter().any(|rrset| owned(rrset) && rrset.rtype != Type::CNAME);
+    if (record.atype == Type::CNAME && other) || (record.atype != Type::CNAME && cname) {
+        return;
+    }
+
+    let position = zone.rrsets.iter().position(|rrset| owned(rrset) && rrset.rtype == record.atype);
+    let Some(n) = position else {
+        if record.atype != Type::SOA {
+            zone.rrsets.push(RRset {
+                name:    name.clone(),
+                rtype:   record.atype,
+                class:   record.aclass,
+                records: vec![record],
+            });
+        }
+        return;
+    };
+
+    let rrset = &mut zone.rrsets[n];
+    match (&record.rdata, rrset.records.first().map(|current| &current.rdata)) {
+        (RData::SOA(new), Some(RData::SOA(current))) => {
+            if is_newer(new.serial, current.serial) {
+                rrset.records = vec![record];
+            }
+        }
+        _ if record.atype == Type::CNAME => rrset.records = vec![record],
+        _ => match rrset.records.iter_mut().find(|current| current.rdata == record.rdata) {
+            Some(current) => *current = record,
+            None          => rrset.records.push(record),
+        },
+    }
+}
+
+/// Deletes the record set of a name and a type, or all the record sets of
+/// the name for the type ANY. The SOA and NS record sets of the apex are
+/// kept.
+fn delete_rrset(zone: &mut Zone, record: &AnswerRecord) {
+    let apex = record.aname.eq_ignore_ascii_case(&zone.origin);
+    zone.rrsets.retain(|rrset| {
+        let deleted = rrset.name.eq_ignore_ascii_case(&record.aname)
+            && (record.atype == Type::ANY || rrset.rtype == record.atype);
+        !deleted || (apex && matches!(rrset.rtype, Type::SOA | Type::NS))
+    });
+}
+
+/// Deletes a single record from its set, along with the set if it ends up
+/// empty. The SOA record, and the last NS record of the apex, are kept.
+fn delete_record(zone: &mut Zone, record: &AnswerRecord) {
+    let apex     = record.aname.eq_ignore_ascii_case(&zone.origin);
+    let position = zone
+        .rrsets
+        .iter()
+        .position(|rrset| rrset.rtype == record.atype && rrset.name.eq_ignore_ascii_case(&record.aname));
+    let Some(n) = position else {
+        return;
+    };
+
+    let rrset = &mut zone.rrsets[n];
+    let last  = rrset.records.len() == 1;
+    if rrset.rtype == Type::SOA || (apex && rrset.rtype == Type::NS && last) {
+        return;
+    }
+    rrset.records.retain(|current| current.rdata != record.rdata);
+    if rrset.records.is_empty() {
+        zone.rrsets.remove(n);
+    }
+}
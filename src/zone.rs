@@ -0,0 +1,554 @@
+use crate::types::{AnswerRecord, DnsError, RData, Soa, Type};
+#[cfg(feature = "zone-transfers")]
+use crate::{
+    rng::DnsRng,
+    stream::RecordStream,
+    types::{Dns, DnsReadBuffer},
+};
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+#[cfg(feature = "zone-transfers")]
+use std::time::Duration;
+#[cfg(feature = "zone-transfers")]
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpStream, UdpSocket},
+    time,
+};
+
+/// How long a zone transfer may take, connection included.
+#[cfg(feature = "zone-transfers")]
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait for the primary to tell the serial of a zone.
+#[cfg(feature = "zone-transfers")]
+const SERIAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A zone to load from its master file, written as `<origin>=<path>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneFile {
+    /// Name of the apex of the zone.
+    pub origin: String,
+    /// Path of the master file.
+    pub path: PathBuf,
+}
+
+impl FromStr for ZoneFile {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::IOError(format!("invalid zone: {}", s));
+        let (origin, path) = s.split_once('=').ok_or_else(invalid)?;
+        let (origin, path) = (origin.trim(), path.trim());
+        if origin.is_empty() || path.is_empty() {
+            return Err(invalid());
+        }
+        Ok(ZoneFile { origin: origin.to_string(), path: path.into() })
+    }
+}
+
+/// A zone kept in sync with its primary server, written as
+/// `<origin>=<primary address>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecondaryZone {
+    /// Name of the apex of the zone.
+    pub origin: String,
+    /// Primary server the zone is transferred from.
+    pub primary: SocketAddr,
+}
+
+impl FromStr for SecondaryZone {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::IOError(format!("invalid secondary zone: {}", s));
+        let (origin, primary) = s.split_once('=').ok_or_else(invalid)?;
+        let origin = origin.trim();
+        if origin.is_empty() {
+            return Err(invalid());
+        }
+        Ok(SecondaryZone {
+            origin:  origin.to_string(),
+            primary: primary.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// The records of a name sharing a type and a class (RFC 2181, section 5).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RRset {
+    /// Owner name of the records.
+    pub name: String,
+    /// Type of the records.
+    pub rtype: Type,
+    /// Class of the records.
+    pub class: u16,
+    /// The records, in the order of the file.
+    pub records: Vec<AnswerRecord>,
+}
+
+impl RRset {
+    /// Returns the TTL of the set, the lowest of its records'.
+    pub fn ttl(&self) -> u32 {
+        self.records.iter().map(|record| record.ttl).min().unwrap_or_default()
+    }
+}
+
+/// A zone read from a master file (RFC 1035, section 5).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    /// Name of the apex of the zone, without its trailing dot.
+    pub origin: String,
+    /// The record sets, in the order their first record appears.
+    pub rrsets: Vec<RRset>,
+}
+
+impl Zone {
+    /// Reads the zone whose apex is `origin` from a master file.
+    pub fn load(path: &Path, origin: &str) -> Result<Self, DnsError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| DnsError::IOError(format!("can't read the zone {}: {}", path.display(), e)))?;
+        Zone::parse(&text, origin).map_err(|e| match e {
+            DnsError::IOError(msg) => DnsError::IOError(format!("{}: {}", path.display(), msg)),
+            e                      => e,
+        })
+    }
+
+    /// Parses the text of a master file, names being relative to `origin`
+    /// until a `$ORIGIN` directive changes it.
+    ///
+    /// Records are in presentation syntax, with `;` starting a comment and
+    /// parentheses continuing a record over several lines. The owner may be
+    /// `@` for the origin, or left blank for the owner of the previous
+    /// record; a missing TTL is the one of `$TTL`, or else of the previous
+    /// record. `$INCLUDE` isn't supported. The zone must have an SOA record
+    /// at its apex.
+    pub fn parse(text: &str, origin: &str) -> Result<Self, DnsError> {
+        let apex = absolute(origin, ".");
+        let mut parser = Parser { origin: apex.clone(), ttl: None, owner: None, last_ttl: None };
+
+        let mut records = Vec::new();
+        for (number, line) in logical_lines(text)? {
+            let located = |e: DnsError| match e {
+                DnsError::IOError(msg) => DnsError::IOError(format!("line {}: {}", number, msg)),
+                e                      => e,
+            };
+            if let Some(record) = parser.entry(&line).map_err(located)? {
+                records.push(record);
+            }
+        }
+        Zone::from_records(origin, records)
+    }
+
+    /// Transfers the zone whose apex is `origin` from the `primary` server
+    /// over TCP (AXFR, RFC 5936).
+    ///
+    /// The records arrive in a stream of messages, starting and ending with
+    /// the SOA record of the zone, and are assembled as they come.
+    #[cfg(feature = "zone-transfers")]
+    pub async fn transfer(primary: SocketAddr, origin: &str) -> Result<Self, DnsError> {
+        let apex = without_dot(&absolute(origin, "."));
+        time::timeout(TRANSFER_TIMEOUT, async {
+            let mut stream = TcpStream::connect(primary)
+                .await
+                .map_err(|e| DnsError::IOError(format!("can't reach {}: {}", primary, e)))?;
+
+            let query = Dns::new_question(&apex, Type::AXFR, DnsRng::from_entropy().query_id()).encode()?;
+            let mut framed = (query.data.len() as u16).to_be_bytes().to_vec();
+            framed.extend_from_slice(&query.data);
+            stream.write_all(&framed).await.map_err(|_| DnsError::SocketError)?;
+
+            let mut records = Vec::new();
+            let mut stream  = RecordStream::new(stream);
+            while let Some(record) = stream.next().await {
+                let record = record?;
+                let soa    = record.atype == Type::SOA;
+                if records.is_empty() && !soa {
+                    return Err(DnsError::IOError(format!("the transfer of {} doesn't start with its SOA", apex)));
+                }
+                if soa && !records.is_empty() {
+                    return Zone::from_records(&apex, records);
+                }
+                records.push(record);
+            }
+            Err(DnsError::IOError(format!("the transfer of {} ended early", apex)))
+        })
+        .await
+        .map_err(|_| DnsError::Timeout)?
+    }
+
+    /// Asks the `primary` server for the serial of the zone whose apex is
+    /// `origin`, from its SOA record, over UDP.
+    #[cfg(feature = "zone-transfers")]
+    pub async fn serial_at(primary: SocketAddr, origin: &str) -> Result<u32, DnsError> {
+        let apex  = without_dot(&absolute(origin, "."));
+        let id    = DnsRng::from_entropy().query_id();
+        let query = Dns::new_question(&apex, Type::SOA, id).encode()?;
+
+        let local = match primary {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let sock = UdpSocket::bind(local).await.map_err(|_| DnsError::SocketError)?;
+        sock.connect(primary).await.map_err(|_| DnsError::SocketError)?;
+        sock.send(&query.data).await.map_err(|_| DnsError::SocketError)?;
+
+        // Only the response to this query counts
+        let mut buf = [0u8; 4096];
+        time::timeout(SERIAL_TIMEOUT, async {
+            loop {
+                let size = sock.recv(&mut buf).await.map_err(|_| DnsError::SocketError)?;
+                let Ok(res) = Dns::decode(&mut DnsReadBuffer::new(&buf[..size])) else {
+                    continue;
+                };
+                if res.header.id != id || !res.header.flags.qr {
+                    continue;
+                }
+                return res
+                    .answers
+                    .iter()
+                    .find_map(|answer| match &answer.rdata {
+                        RData::SOA(soa) if answer.aname.eq_ignore_ascii_case(&apex) => Some(soa.serial),
+                        _                                                           => None,
+                    })
+                    .ok_or_else(|| DnsError::IOError(format!("{} has no SOA record for {}", primary, apex)));
+            }
+        })
+        .await
+        .map_err(|_| DnsError::Timeout)?
+    }
+
+    /// Assembles the records of the zone whose apex is `origin` into
+    /// record sets. The zone must have an SOA record at its apex.
+    pub fn from_records(origin: &str, records: Vec<AnswerRecord>) -> Result<Self, DnsError> {
+        let mut rrsets: Vec<RRset> = Vec::new();
+        for record in records {
+            let position = rrsets.iter().position(|rrset| {
+                rrset.rtype == record.atype
+                    && rrset.class == record.aclass
+                    && rrset.name.eq_ignore_ascii_case(&record.aname)
+            });
+            match position {
+                Some(n) => rrsets[n].records.push(record),
+                None    => rrsets.push(RRset {
+                    name:    record.aname.clone(),
+                    rtype:   record.atype,
+                    class:   record.aclass,
+                    records: vec![record],
+                }),
+            }
+        }
+
+        let apex = absolute(origin, ".");
+        let zone = Zone { origin: without_dot(&apex), rrsets };
+        if zone.soa().is_none() {
+            return Err(DnsError::IOError(format!("no SOA record at {}", apex)));
+        }
+        Ok(zone)
+    }
+
+    /// Returns the SOA record of the apex.
+    pub fn soa(&self) -> Option<&Soa> {
+        self.rrset(&self.origin, Type::SOA)?
+            .records
+            .iter()
+            .find_map(|record| match &record.rdata {
+                RData::SOA(soa) => Some(soa),
+                _               => None,
+            })
+    }
+
+    /// Returns the record set of a name and a type, in any class.
+    pub fn rrset(&self, name: &str, rtype: Type) -> Option<&RRset> {
+        self.rrsets
+            .iter()
+            .find(|rrset| rrset.rtype == rtype && rrset.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the number of records of the zone.
+    pub fn len(&self) -> usize {
+        self.rrsets.iter().map(|rrset| rrset.records.len()).sum()
+    }
+
+    /// Tells whether `name` is the apex of the zone or below it.
+    pub fn contains(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let apex = self.origin.to_ascii_lowercase();
+        apex == "." || name == apex || name.ends_with(&format!(".{}", apex))
+    }
+
+    /// Writes the zone to its master file at `path`, starting with its SOA
+    /// record, one record per line with absolute names. The file is
+    /// replaced at once, so that it is never seen half written.
+    pub fn save(&self, path: &Path) -> Result<(), DnsError> {
+        let failed = |e: std::io::Error| DnsError::IOError(format!("can't write the zone {}: {}", path.display(), e));
+        let (soa, others): (Vec<&RRset>, Vec<&RRset>) = self
+            .rrsets
+            .iter()
+            .partition(|rrset| rrset.rtype == Type::SOA && rrset.name.eq_ignore_ascii_case(&self.origin));
+
+        let mut text = String::new();
+        for record in soa.into_iter().chain(others).flat_map(|rrset| &rrset.records) {
+            text.push_str(&record.to_string());
+            text.push('\n');
+        }
+
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, text).map_err(failed)?;
+        fs::rename(&temporary, path).map_err(failed)
+    }
+}
+
+/// Tells whether the serial `a` is newer than `b`, in the serial number
+/// arithmetic of RFC 1982.
+pub fn is_newer(a: u32, b: u32) -> bool {
+    a != b && (a.wrapping_sub(b) as i32) > 0
+}
+
+/// The state carried from one entry of a master file to the next.
+struct Parser {
+    /// Origin of the relative names, with its trailing dot.
+    origin: String,
+    /// TTL set by `$TTL`.
+    ttl: Option<u32>,
+    /// Owner of the previous record.
+    owner: Option<String>,
+    /// TTL of the previous record.
+    last_ttl: Option<u32>,
+}
+
+impl Parser {
+    /// Handles an entry, returning the record it holds, or `None` for a
+    /// directive or a blank line.
+    fn entry(&mut self, line: &str) -> Result<Option<AnswerRecord>, DnsError> {
+        let invalid = || DnsError::IOError(format!("invalid entry: {}", line.trim()));
+        let tokens  = tokenize(line).ok_or_else(invalid)?;
+        let Some(first) = tokens.first() else {
+            return Ok(None);
+        };
+
+        match first.to_ascii_uppercase().as_str() {
+            "$ORIGIN" => match tokens.as_slice() {
+                [_, name] => {
+                    self.origin = absolute(name, &self.origin);
+                    return Ok(None);
+                }
+                _ => return Err(invalid()),
+            },
+            "$TTL" => match tokens.as_slice() {
+                [_, ttl] => {
+                    self.ttl = Some(parse_ttl(ttl).ok_or_else(invalid)?);
+                    return Ok(None);
+                }
+                _ => return Err(invalid()),
+            },
+            directive if directive.starts_with('$') => {
+                return Err(DnsError::IOError(format!("unsupported directive: {}", first)));
+            }
+            _ => {}
+        }
+
+        // A blank owner is the owner of the previous record
+        let mut fields = tokens.iter();
+        let owner = match line.starts_with(char::is_whitespace) {
+            true  => self.owner.clone().ok_or_else(invalid)?,
+            false => absolute(fields.next().ok_or_else(invalid)?, &self.origin),
+        };
+
+        let mut ttl   = None;
+        let mut class = None;
+        let rtype = loop {
+            let field = fields.next().ok_or_else(invalid)?;
+            if ttl.is_none() && let Some(value) = parse_ttl(field) {
+                ttl = Some(value);
+                continue;
+            }
+            if let Ok(rtype) = field.parse::<Type>() {
+                break rtype;
+            }
+            match class {
+                None => class = Some(field.clone()),
+                _    => return Err(invalid()),
+            }
+        };
+
+        // The names in the data are relative to the origin as well
+        let mut data: Vec<String> = fields.cloned().collect();
+        let names: &[usize] = match rtype {
+            Type::NS | Type::CNAME | Type::PTR => &[0],
+            Type::MX | Type::SVCB | Type::HTTPS => &[1],
+            Type::SOA => &[0, 1],
+            Type::SRV => &[3],
+            _         => &[],
+        };
+        for &n in names {
+            if let Some(name) = data.get_mut(n) {
+                *name = absolute(name, &self.origin);
+            }
+        }
+
+        let ttl = ttl.or(self.ttl).or(self.last_ttl);
+        let presentation = [Some(owner.clone()), ttl.map(|ttl| ttl.to_string()), class, Some(rtype.to_string())]
+            .into_iter()
+            .flatten()
+            .chain(data)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let record: AnswerRecord = presentation.parse()?;
+
+        self.owner    = Some(owner);
+        self.last_ttl = Some(record.ttl);
+        Ok(Some(record))
+    }
+}
+
+/// Joins the lines of a master file into its entries, numbered after the
+/// line they start on, with comments removed and the lines between
+/// parentheses joined. Fails on unbalanced parentheses.
+fn logical_lines(text: &str) -> Result<Vec<(usize, String)>, DnsError> {
+    let mut entries = Vec::new();
+    let mut entry   = String::new();
+    let mut start   = 0;
+    let mut depth   = 0usize;
+
+    for (n, line) in text.lines().enumerate() {
+        if depth == 0 {
+            start = n + 1;
+        }
+
+        let mut quoted  = false;
+        let mut escaped = false;
+        for c in line.chars() {
+            match c {
+                _ if escaped             => escaped = false,
+                '\\'                     => escaped = true,
+                '"'                      => quoted = !quoted,
+                ';' if !quoted           => break,
+                '(' | ')' if !quoted     => {
+                    depth = match c {
+                        '(' => depth + 1,
+                        _   => depth.checked_sub(1).ok_or_else(|| {
+                            DnsError::IOError(format!("line {}: unbalanced parenthesis", n + 1))
+                        })?,
+                    };
+                    entry.push(' ');
+                    continue;
+                }
+                _ => {}
+            }
+            entry.push(c);
+        }
+
+        if depth == 0 {
+            entries.push((start, std::mem::take(&mut entry)));
+        } else {
+            entry.push(' ');
+        }
+    }
+
+    if depth > 0 {
+        return Err(DnsError::IOError(format!("line {}: unclosed parenthesis", start)));
+    }
+    Ok(entries)
+}
+
+/// Splits an entry into its fields, keeping the quotes and the escapes of
+/// the quoted ones. Returns `None` on an unterminated quote.
+fn tokenize(line: &str) -> Option<Vec<String>> {
+    let mut tokens  = Vec::new();
+    let mut token   = String::new();
+    let mut quoted  = false;
+    let mut escaped = false;
+
+    for c in line.chars() {
+        match c {
+            _ if escaped                   => escaped = false,
+            '\\'                           => escaped = true,
+            '"'                            => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        token.push(c);
+    }
+
+    if quoted {
+        return None;
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    Some(tokens)
+}
+
+/// Parses a TTL, given in seconds or with units, such as `1h30m`.
+fn parse_ttl(s: &str) -> Option<u32> {
+    if !s.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    if let Ok(ttl) = s.parse() {
+        return Some(ttl);
+    }
+
+    let mut total: u32 = 0;
+    let mut value: u32 = 0;
+    let mut digits     = false;
+    for c in s.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            value  = value.checked_mul(10)?.checked_add(digit)?;
+            digits = true;
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _   => return None,
+        };
+        if !digits {
+            return None;
+        }
+        total  = total.checked_add(value.checked_mul(unit)?)?;
+        value  = 0;
+        digits = false;
+    }
+    (!digits).then_some(total)
+}
+
+/// Makes a name absolute, with its trailing dot: `@` is the origin, and a
+/// name without a trailing dot is relative to it.
+fn absolute(name: &str, origin: &str) -> String {
+    match name {
+        "@"                     => origin.to_string(),
+        _ if is_absolute(name)  => name.to_string(),
+        _ if origin == "."      => format!("{}.", name),
+        _                       => format!("{}.{}", name, origin),
+    }
+}
+
+/// Tells whether a name ends with a dot that isn't escaped.
+fn is_absolute(name: &str) -> bool {
+    let Some(rest) = name.strip_suffix('.') else {
+        return false;
+    };
+    (rest.len() - rest.trim_end_matches('\\').len()) % 2 == 0
+}
+
+/// Strips the trailing dot of an absolute name, but not from the root.
+fn without_dot(name: &str) -> String {
+    match name {
+        "." => name.to_string(),
+        _   => name.trim_end_matches('.').to_string(),
+    }
+}
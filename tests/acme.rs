@@ -0,0 +1,75 @@
+#![cfg(feature = "acme")]
+
+mod common;
+
+use common::{an_count, answer_records, exchange, id, query, spawn_server_with, spawn_upstream};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+const TOKEN: &str = "s3cret";
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Returns a localhost TCP address that is currently free.
+fn free_tcp_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+/// Posts a challenge to `path`, returning the status code once the server
+/// accepts connections.
+fn post(addr: SocketAddr, path: &str, token: &str, fqdn: &str, value: &str) -> u16 {
+    let body = format!(r#"{{"fqdn": "{}", "value": "{}"}}"#, fqdn, value);
+    for _ in 0..50 {
+        let Ok(mut stream) = TcpStream::connect(addr) else {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        };
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            path,
+            token,
+            body.len(),
+            body,
+        ).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        return response[9..12].parse().unwrap();
+    }
+    panic!("no ACME endpoint at {}", addr);
+}
+
+#[test]
+fn challenges_are_published_and_withdrawn() {
+    let upstream = spawn_upstream(|q| answer_records(q, id(q), &[(16, b"\x08upstream".to_vec())]));
+    let api      = free_tcp_addr();
+    let server   = spawn_server_with(upstream, &[
+        ("DNSR_ACME_LISTEN", &api.to_string()),
+        ("DNSR_ACME_TOKEN",  TOKEN),
+        ("DNSR_ACME_ZONES",  "example.com"),
+    ]);
+    let name = "_acme-challenge.www.example.com";
+
+    assert_eq!(post(api, "/present", "wrong", name, "digest1"), 401);
+    assert_eq!(post(api, "/present", TOKEN, "_acme-challenge.example.org.", "digest1"), 403);
+    assert_eq!(post(api, "/present", TOKEN, "www.example.com.", "digest1"), 403);
+
+    assert_eq!(post(api, "/present", TOKEN, &format!("{}.", name), "digest1"), 200);
+    assert_eq!(post(api, "/present", TOKEN, name, "digest2"), 200);
+    let reply = exchange(&server, &query(1, name, 16));
+    assert_eq!(an_count(&reply), 2);
+    assert!(contains(&reply, b"\x07digest1"));
+    assert!(contains(&reply, b"\x07digest2"));
+
+    assert_eq!(post(api, "/cleanup", TOKEN, name, "digest1"), 200);
+    assert_eq!(post(api, "/cleanup", TOKEN, name, "digest2"), 200);
+    let reply = exchange(&server, &query(2, name, 16));
+    assert!(!contains(&reply, b"digest"));
+    assert!(contains(&reply, b"upstream"));
+}
@@ -0,0 +1,144 @@
+#![cfg(feature = "admin")]
+
+mod common;
+
+use common::{an_count, answer_a, exchange, free_addr, id, qname, query, rcode, spawn_server_with, spawn_upstream, wait_ready};
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const TOKEN: &str = "s3cret";
+
+/// Sends an HTTP request to the admin API, returning the status code and
+/// the body of the response.
+fn request(addr: SocketAddr, method: &str, path: &str, token: &str) -> (u16, String) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut stream = loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => break stream,
+            Err(_)     => assert!(Instant::now() < deadline, "the admin API never appeared"),
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\nContent-Length: 0\r\n\r\n",
+        method, path, token,
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body   = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
+
+#[test]
+fn the_running_resolver_is_administered_over_http() {
+    let queries  = Arc::new(AtomicUsize::new(0));
+    let seen     = Arc::clone(&queries);
+    let upstream = spawn_upstream(move |q| {
+        if qname(q) == "www.example.com" {
+            seen.fetch_add(1, Ordering::SeqCst);
+        }
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+
+    let list = env::temp_dir().join(format!("dnsr-admin-{}.txt", process::id()));
+    fs::write(&list, "").unwrap();
+    let admin  = free_addr();
+    let server = spawn_server_with(upstream, &[
+        ("DNSR_ADMIN_LISTEN",    &admin.to_string()),
+        ("DNSR_ADMIN_TOKEN",     TOKEN),
+        ("DNSR_BLOCKLIST_FILES", list.to_str().unwrap()),
+    ]);
+    wait_ready(&server);
+
+    assert_eq!(request(admin, "POST", "/cache/flush", "wrong").0, 401);
+    assert_eq!(request(admin, "GET", "/nowhere", TOKEN).0, 404);
+
+    // Flushing the cache sends the next query upstream again
+    exchange(&server, &query(1, "www.example.com", 1));
+    exchange(&server, &query(2, "www.example.com", 1));
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+    let (status, body) = request(admin, "POST", "/cache/flush", TOKEN);
+    assert_eq!(status, 200);
+    assert!(body.starts_with("flushed "));
+    exchange(&server, &query(3, "www.example.com", 1));
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+    // Domains are blocked and unblocked at runtime
+    assert_eq!(request(admin, "PUT", "/blocklist/ads.example.com", TOKEN).0, 201);
+    assert_eq!(request(admin, "PUT", "/blocklist/ads.example.com", TOKEN).0, 200);
+    assert_eq!(rcode(&exchange(&server, &query(4, "pixel.ads.example.com", 1))), 3);
+    assert_eq!(request(admin, "DELETE", "/blocklist/ads.example.com", TOKEN).0, 200);
+    assert_eq!(an_count(&exchange(&server, &query(5, "pixel.ads.example.com", 1))), 1);
+    assert_eq!(request(admin, "DELETE", "/blocklist/ads.example.com", TOKEN).0, 404);
+
+    // The names are percent-encoded
+    assert_eq!(request(admin, "PUT", "/blocklist/b%C3%BCcher.example", TOKEN).0, 201);
+    assert_eq!(rcode(&exchange(&server, &query(8, "www.xn--bcher-kva.example", 1))), 3);
+    assert_eq!(request(admin, "PUT", "/blocklist/b%C3%BC%2", TOKEN).0, 400);
+
+    // The blocklists are read again on demand
+    fs::write(&list, "tracker.example.com\n").unwrap();
+    assert_eq!(an_count(&exchange(&server, &query(6, "tracker.example.com", 1))), 1);
+    assert_eq!(request(admin, "POST", "/blocklists/reload", TOKEN).0, 200);
+    assert_eq!(rcode(&exchange(&server, &query(7, "tracker.example.com", 1))), 3);
+    let _ = fs::remove_file(&list);
+
+    // The counters are only served when they are built in
+    let (status, body) = request(admin, "GET", "/stats", TOKEN);
+    match cfg!(feature = "metrics") {
+        true  => assert!(status == 200 && !body.is_empty()),
+        false => assert_eq!(status, 404),
+    }
+    assert_eq!(request(admin, "GET", "/blocklists/reload", TOKEN).0, 405);
+    assert_eq!(request(admin, "GET", "/reload", TOKEN).0, 405);
+}
+
+#[test]
+fn the_configuration_file_is_reloaded_on_demand() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let config   = env::temp_dir().join(format!("dnsr-admin-{}.conf", process::id()));
+    fs::write(&config, "# Lab\nDNSR_LOCAL_RECORDS=nas.lan=192.168.1.20\n").unwrap();
+    let admin  = free_addr();
+    let server = spawn_server_with(upstream, &[
+        ("DNSR_ADMIN_LISTEN", &admin.to_string()),
+        ("DNSR_ADMIN_TOKEN",  TOKEN),
+        ("DNSR_CONFIG",       config.to_str().unwrap()),
+    ]);
+    wait_ready(&server);
+
+    let address = |id| {
+        let reply = exchange(&server, &query(id, "nas.lan", 1));
+        assert_eq!(an_count(&reply), 1);
+        reply[reply.len() - 4..].to_vec()
+    };
+    assert_eq!(address(1), [192, 168, 1, 20]);
+
+    // The local records follow the file once reloaded
+    fs::write(&config, "DNSR_LOCAL_RECORDS=nas.lan=192.168.1.21\n").unwrap();
+    assert_eq!(address(2), [192, 168, 1, 20]);
+    let (status, body) = request(admin, "POST", "/reload", TOKEN);
+    assert_eq!((status, body.as_str()), (200, "reloaded\n"));
+    assert_eq!(address(3), [192, 168, 1, 21]);
+
+    // A file that doesn't load changes nothing
+    fs::write(&config, "DNSR_LOCAL_RECORDS=nas.lan\n").unwrap();
+    let (status, body) = request(admin, "POST", "/reload", TOKEN);
+    assert_eq!(status, 500);
+    assert!(body.contains("DNSR_LOCAL_RECORDS"), "{}", body);
+    assert_eq!(address(4), [192, 168, 1, 21]);
+    let _ = fs::remove_file(&config);
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use common::{an_count, encode_name, exchange, free_addr, id, query, rcode, referral, spawn_server, spawn_server_with, spawn_upstream};
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+#[test]
+fn tld_delegation_is_answered_from_the_root() {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let seen = Arc::clone(&hits);
+    let root = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        referral(q, id(q), &["a.gtld-servers.net"])
+    });
+    let server = spawn_server(root);
+
+    let reply = exchange(&server, &query(1, "com", 2));
+
+    assert_eq!(id(&reply), 1);
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&encode_name("a.gtld-servers.net")));
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn apex_queries_can_be_refused() {
+    // Refused queries never reach a server
+    let server = spawn_server_with(free_addr(), &[("DNSR_APEX_QUERIES", "refuse")]);
+
+    let reply = exchange(&server, &query(2, "com", 2));
+    assert_eq!(rcode(&reply), 5);
+    assert_eq!(an_count(&reply), 0);
+
+    let reply = exchange(&server, &query(3, "", 2));
+    assert_eq!(rcode(&reply), 5);
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use common::{
+    an_count, answer_a, answer_records, encode_name, exchange, id, negative_reply, qname, query, rcode, spawn_server,
+    spawn_server_with, spawn_upstream, with_do,
+};
+use std::{
+    env, fs, process,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[test]
+fn repeated_queries_are_served_from_cache() {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let seen = Arc::clone(&hits);
+    let upstream = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+    let server = spawn_server(upstream);
+
+    let first  = exchange(&server, &query(1, "www.example.com", 1));
+    let second = exchange(&server, &query(2, "WWW.example.com", 1));
+
+    assert_eq!(id(&first), 1);
+    assert_eq!(id(&second), 2);
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn negative_answers_are_cached_for_the_soa_minimum() {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let seen = Arc::clone(&hits);
+    let upstream = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        match qname(q).as_str() {
+            "nodata.example.com" => negative_reply(q, 0, 60),
+            _                    => negative_reply(q, 0, 0),
+        }
+    });
+    let server = spawn_server(upstream);
+
+    // NODATA, kept for a minute
+    for n in 1..=2 {
+        let reply = exchange(&server, &query(n, "nodata.example.com", 1));
+        assert_eq!((rcode(&reply), an_count(&reply)), (0, 0));
+    }
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+    // A zero minimum keeps the answer out of the cache
+    for n in 3..=4 {
+        let reply = exchange(&server, &query(n, "zero.example.com", 1));
+        assert_eq!((rcode(&reply), an_count(&reply)), (0, 0));
+    }
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn dnssec_answers_supersede_plain_ones() {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let seen = Arc::clone(&hits);
+    let upstream = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+    let server = spawn_server(upstream);
+
+    // A plain answer cannot satisfy a DO query...
+    exchange(&server, &query(1, "www.example.com", 1));
+    exchange(&server, &with_do(query(2, "www.example.com", 1)));
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+    // ...while the DO answer serves both kinds of queries afterwards
+    exchange(&server, &query(3, "www.example.com", 1));
+    exchange(&server, &with_do(query(4, "www.example.com", 1)));
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+}
+
+/// Builds the data of an RRSIG record over A records, valid from
+/// `inception` to `expiration`, in seconds since the Unix epoch.
+fn rrsig(inception: u64, expiration: u64) -> Vec<u8> {
+    let mut rdata = vec![0, 1, 13, 3, 0, 0, 0x0E, 0x10];
+    rdata.extend_from_slice(&(expiration as u32).to_be_bytes());
+    rdata.extend_from_slice(&(inception as u32).to_be_bytes());
+    rdata.extend_from_slice(&[0x12, 0x34]);
+    rdata.extend(encode_name("example.com"));
+    rdata.extend_from_slice(&[0; 64]);
+    rdata
+}
+
+/// Returns how many of two DO queries for a signed answer, valid from
+/// `inception` to `expiration`, reach the upstream.
+fn signed_upstream_queries(inception: u64, expiration: u64, vars: &[(&str, &str)]) -> usize {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let seen = Arc::clone(&hits);
+    let upstream = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        answer_records(q, id(q), &[(1, vec![192, 0, 2, 1]), (46, rrsig(inception, expiration))])
+    });
+    let server = spawn_server_with(upstream, vars);
+
+    exchange(&server, &with_do(query(1, "www.example.com", 1)));
+    exchange(&server, &with_do(query(2, "www.example.com", 1)));
+    hits.load(Ordering::SeqCst)
+}
+
+#[test]
+fn answers_are_cached_while_their_signatures_are_valid() {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let day = 86400;
+
+    assert_eq!(signed_upstream_queries(now - day, now + day, &[]), 1);
+
+    // Expired signatures are only tolerated within the skew
+    assert_eq!(signed_upstream_queries(now - 2 * day, now - day, &[]), 2);
+    assert_eq!(signed_upstream_queries(now - 2 * day, now - 60, &[]), 1);
+    assert_eq!(signed_upstream_queries(now - 2 * day, now - 60, &[("DNSR_SIGNATURE_SKEW", "0")]), 2);
+
+    // A clock known to be late can be set right
+    assert_eq!(signed_upstream_queries(now + day, now + 2 * day, &[]), 2);
+    assert_eq!(signed_upstream_queries(now + day, now + 2 * day, &[("DNSR_CLOCK_OFFSET", "172800")]), 1);
+}
+
+#[test]
+fn expired_answers_are_served_when_resolution_fails() {
+    // The cache starts with an answer expiring in two seconds
+    let path    = env::temp_dir().join(format!("dnsr-stale-{}", process::id()));
+    let expires = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 2;
+    let entry   = answer_a(&query(0, "www.example.com", 1), 0, [192, 0, 2, 1]);
+    let mut data = expires.to_be_bytes().to_vec();
+    data.extend_from_slice(&(entry.len() as u32).to_be_bytes());
+    data.extend_from_slice(&entry);
+    fs::write(&path, data).unwrap();
+
+    // The upstream doesn't answer until it is back up
+    let up       = Arc::new(AtomicBool::new(false));
+    let back     = Arc::clone(&up);
+    let upstream = spawn_upstream(move |q| match back.load(Ordering::SeqCst) {
+        true  => answer_a(q, id(q), [192, 0, 2, 2]),
+        false => Vec::new(),
+    });
+    let server = spawn_server_with(upstream, &[
+        ("DNSR_CACHE_FILE",    path.to_str().unwrap()),
+        ("DNSR_SERVE_STALE",   "60"),
+        ("DNSR_QUERY_TIMEOUT", "200"),
+    ]);
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+    let _ = fs::remove_file(&path);
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+    thread::sleep(Duration::from_millis(2500));
+
+    // The expired answer is served for 30 seconds, and refreshed meanwhile
+    let reply = exchange(&server, &query(2, "www.example.com", 1));
+    let n     = reply.len();
+    assert_eq!(rcode(&reply), 0);
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+    assert_eq!(reply[n - 10..n - 6], 30u32.to_be_bytes());
+
+    up.store(true, Ordering::SeqCst);
+    let deadline = Instant::now() + Duration::from_secs(25);
+    while !exchange(&server, &query(3, "www.example.com", 1)).ends_with(&[192, 0, 2, 2]) {
+        assert!(Instant::now() < deadline, "the stale answer was never refreshed");
+        thread::sleep(Duration::from_millis(100));
+    }
+}
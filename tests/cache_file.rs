@@ -0,0 +1,27 @@
+mod common;
+
+use common::{an_count, answer_a, exchange, free_addr, id, query, spawn_server_with, spawn_upstream};
+use std::env;
+
+#[test]
+fn cache_survives_a_restart() {
+    let path = env::temp_dir().join(format!("dnsr-cache-{}", std::process::id()));
+    let file = path.to_str().unwrap();
+
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let mut server = spawn_server_with(upstream, &[("DNSR_CACHE_FILE", file)]);
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+    server.terminate();
+    assert!(path.exists());
+
+    // The restarted server can't reach any server, it can only answer from
+    // the cache it loaded
+    let server = spawn_server_with(free_addr(), &[("DNSR_CACHE_FILE", file)]);
+    let reply = exchange(&server, &query(2, "www.example.com", 1));
+    assert_eq!(id(&reply), 2);
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(&reply[reply.len() - 4..], &[192, 0, 2, 1]);
+
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{answer_a, exchange, id, qname, query, spawn_server_with_flags, spawn_upstream};
+use std::process::Command;
+
+#[test]
+fn flags_configure_the_server() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let root     = format!("--root={}", upstream);
+    let server   = spawn_server_with_flags(&[&root, "--mode", "recursive", "--max-depth", "30"]);
+
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+
+    assert_eq!(qname(&reply), "www.example.com");
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+}
+
+#[test]
+fn flags_win_over_the_environment() {
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .env("DNSR_MAX_DEPTH", "30")
+        .args(["--max-depth", "deep"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid value in --max-depth"));
+}
+
+#[test]
+fn invalid_flags_are_rejected() {
+    for flags in [&["--no-such-option", "1"][..], &["--mode", "proxy"], &["--mode", "forwarding"], &["stray"]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+            .env_remove("DNSR_PROXY")
+            .args(flags)
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{:?} was accepted", flags);
+    }
+}
+
+#[test]
+#[cfg(not(feature = "admin"))]
+fn options_of_the_subsystems_left_out_are_rejected() {
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .args(["--admin-listen", "127.0.0.1:8080", "--admin-token", "secret"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("DNSR_ADMIN_LISTEN is not supported by this build"));
+}
@@ -0,0 +1,383 @@
+//! Helpers shared by the integration tests.
+//!
+//! The tests drive the real binary over UDP: the server is spawned on an
+//! ephemeral port and pointed at a mock upstream running on localhost, and
+//! packets are built and inspected in raw wire format.
+
+#![allow(dead_code)]
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+/// A running resolver process, killed when dropped.
+pub struct Server {
+    child:    Child,
+    pub addr: SocketAddr,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Server {
+    /// Stops the server with SIGTERM, as a service manager would, and
+    /// waits for it to exit.
+    pub fn terminate(&mut self) {
+        self.signal("TERM");
+        let _ = self.child.wait();
+    }
+
+    /// Sends the signal named `name` to the server.
+    pub fn signal(&self, name: &str) {
+        let _ = Command::new("kill")
+            .arg(format!("-{}", name))
+            .arg(self.child.id().to_string())
+            .status();
+    }
+}
+
+/// Returns a localhost address that is currently free, over both UDP and
+/// TCP, as the server listens on both.
+pub fn free_addr() -> SocketAddr {
+    loop {
+        let addr = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        if TcpListener::bind(addr).is_ok() {
+            return addr;
+        }
+    }
+}
+
+/// Spawns the resolver listening on an ephemeral port, using `root` as
+/// the root server.
+pub fn spawn_server(root: SocketAddr) -> Server {
+    spawn_server_with(root, &[])
+}
+
+/// Spawns the resolver like `spawn_server`, with extra environment
+/// variables.
+pub fn spawn_server_with(root: SocketAddr, vars: &[(&str, &str)]) -> Server {
+    let addr = free_addr();
+    let child = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .env("DNSR_LISTEN", addr.to_string())
+        .env("DNSR_ROOT", root.to_string())
+        .envs(vars.iter().copied())
+        .spawn()
+        .unwrap();
+    Server { child, addr }
+}
+
+/// Spawns the resolver like `spawn_server_with`, returning the lines it
+/// writes to stderr as they come.
+pub fn spawn_server_logged(root: SocketAddr, vars: &[(&str, &str)]) -> (Server, Receiver<String>) {
+    let addr = free_addr();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .env("DNSR_LISTEN", addr.to_string())
+        .env("DNSR_ROOT", root.to_string())
+        .envs(vars.iter().copied())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let (lines, received) = mpsc::channel();
+    let stderr = BufReader::new(child.stderr.take().unwrap());
+    thread::spawn(move || {
+        for line in stderr.lines().map_while(Result::ok) {
+            let _ = lines.send(line);
+        }
+    });
+    (Server { child, addr }, received)
+}
+
+/// Spawns the resolver listening on an ephemeral port, configured by the
+/// given command line flags rather than the environment.
+pub fn spawn_server_with_flags(flags: &[&str]) -> Server {
+    let addr = free_addr();
+    let child = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .arg("--listen")
+        .arg(addr.to_string())
+        .args(flags)
+        .spawn()
+        .unwrap();
+    Server { child, addr }
+}
+
+/// Spawns a mock upstream answering every packet with `handler`. Packets
+/// for which the handler returns nothing are left unanswered.
+pub fn spawn_upstream<F>(handler: F) -> SocketAddr
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = sock.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok((len, peer)) = sock.recv_from(&mut buf) {
+            let reply = handler(&buf[..len]);
+            if !reply.is_empty() {
+                let _ = sock.send_to(&reply, peer);
+            }
+        }
+    });
+    addr
+}
+
+/// Serves the queries sent over TCP to `addr`, the address of a mock
+/// upstream, answering every one of them with `handler`.
+pub fn spawn_tcp_upstream<F>(addr: SocketAddr, handler: F)
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    let listener = TcpListener::bind(addr).unwrap();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            while let Some(query) = read_framed(&mut stream) {
+                write_framed(&mut stream, &handler(&query));
+            }
+        }
+    });
+}
+
+/// Spawns a mock upstream handing every packet to `script`, along with
+/// its sender and the upstream socket, for exchanges that take more than
+/// a reply: several packets, delays, or packets sent from elsewhere.
+pub fn spawn_scripted_upstream<F>(script: F) -> SocketAddr
+where
+    F: Fn(&UdpSocket, &[u8], SocketAddr) + Send + 'static,
+{
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = sock.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok((len, peer)) = sock.recv_from(&mut buf) {
+            script(&sock, &buf[..len], peer);
+        }
+    });
+    addr
+}
+
+/// Encodes a domain name as a sequence of labels.
+pub fn encode_name(name: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+    out
+}
+
+/// Builds a recursive stub query for `name` and `qtype`.
+pub fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&0x0100u16.to_be_bytes());
+    out.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    out.extend_from_slice(&encode_name(name));
+    out.extend_from_slice(&qtype.to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out
+}
+
+/// Appends an OPT record with the DNSSEC OK bit set to a query.
+pub fn with_do(mut query: Vec<u8>) -> Vec<u8> {
+    query[11] += 1;
+    query.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 0]);
+    query
+}
+
+/// Returns the question section of a message carrying a single question.
+pub fn question(msg: &[u8]) -> &[u8] {
+    let mut end = 12;
+    while msg[end] != 0 {
+        end += msg[end] as usize + 1;
+    }
+    &msg[12..end + 5]
+}
+
+/// Builds an authoritative answer to `query` carrying a single A record,
+/// using `id` as the transaction ID.
+pub fn answer_a(query: &[u8], id: u16, ip: [u8; 4]) -> Vec<u8> {
+    answer_many(query, id, &[ip])
+}
+
+/// Builds an authoritative answer to `query` carrying an A record for each
+/// of the given addresses.
+pub fn answer_many(query: &[u8], id: u16, ips: &[[u8; 4]]) -> Vec<u8> {
+    let records: Vec<(u16, Vec<u8>)> = ips.iter().map(|ip| (1, ip.to_vec())).collect();
+    answer_records(query, id, &records)
+}
+
+/// Builds an authoritative answer to `query` carrying the given records,
+/// as (type, rdata) pairs owned by the queried name.
+pub fn answer_records(query: &[u8], id: u16, records: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&0x8400u16.to_be_bytes());
+    out.extend_from_slice(&[0, 1]);
+    out.extend_from_slice(&(records.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0, 0, 0]);
+    out.extend_from_slice(question(query));
+    for (rtype, rdata) in records {
+        out.extend_from_slice(&[0xC0, 0x0C]);
+        out.extend_from_slice(&rtype.to_be_bytes());
+        out.extend_from_slice(&[0, 1, 0, 0, 0x0E, 0x10]);
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(rdata);
+    }
+    out
+}
+
+/// Builds a referral answering `query`, delegating the queried name to
+/// the given name servers, without glue.
+pub fn referral(query: &[u8], id: u16, servers: &[&str]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&0x8000u16.to_be_bytes());
+    out.extend_from_slice(&[0, 1, 0, 0]);
+    out.extend_from_slice(&(servers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(question(query));
+    for server in servers {
+        let rdata = encode_name(server);
+        out.extend_from_slice(&[0xC0, 0x0C]);
+        out.extend_from_slice(&[0, 2, 0, 1, 0, 2, 0xA3, 0]);
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(&rdata);
+    }
+    out
+}
+
+/// Reads the queried name of a message, in lowercase dotted form.
+pub fn qname(msg: &[u8]) -> String {
+    let mut labels = Vec::new();
+    let mut pos = 12;
+    while msg[pos] != 0 {
+        let len = msg[pos] as usize;
+        labels.push(String::from_utf8_lossy(&msg[pos + 1..pos + 1 + len]).to_ascii_lowercase());
+        pos += len + 1;
+    }
+    labels.join(".")
+}
+
+/// Reads the query type of a message.
+pub fn qtype(msg: &[u8]) -> u16 {
+    let end = 12 + question(msg).len();
+    u16::from_be_bytes([msg[end - 4], msg[end - 3]])
+}
+
+/// Builds an error response to `query` with the given rcode.
+pub fn error_reply(query: &[u8], rcode: u8) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id(query).to_be_bytes());
+    out.extend_from_slice(&(0x8000u16 | rcode as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    out.extend_from_slice(question(query));
+    out
+}
+
+/// Builds an authoritative negative response to `query`, NXDOMAIN or
+/// NODATA as `rcode` says, with the SOA record of the zone in its
+/// authority section, whose minimum field is `minimum`.
+pub fn negative_reply(query: &[u8], rcode: u8, minimum: u32) -> Vec<u8> {
+    let mut out = error_reply(query, rcode);
+    out[2] |= 0x04;
+    out[9]  = 1;
+    let mut rdata = encode_name("ns.example.com");
+    rdata.extend_from_slice(&encode_name("hostmaster.example.com"));
+    for field in [1, 3600, 600, 86400, minimum] {
+        rdata.extend_from_slice(&u32::to_be_bytes(field));
+    }
+    out.extend_from_slice(&encode_name("example.com"));
+    out.extend_from_slice(&[0, 6, 0, 1, 0, 0, 0x0E, 0x10]);
+    out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    out.extend_from_slice(&rdata);
+    out
+}
+
+/// Returns the UDP payload size advertised by a query, if it has an OPT
+/// record right after its question.
+pub fn advertised_size(query: &[u8]) -> Option<u16> {
+    let end = 12 + question(query).len();
+    if query.len() < end + 5 || query[end + 1..end + 3] != [0, 41] {
+        return None;
+    }
+    Some(u16::from_be_bytes([query[end + 3], query[end + 4]]))
+}
+
+/// Returns whether the TC flag of a message is set.
+pub fn truncated(msg: &[u8]) -> bool {
+    msg[2] & 0x02 != 0
+}
+
+/// Reads the response code of a message.
+pub fn rcode(msg: &[u8]) -> u8 {
+    msg[3] & 0x0f
+}
+
+/// Reads the transaction ID of a message.
+pub fn id(msg: &[u8]) -> u16 {
+    u16::from_be_bytes([msg[0], msg[1]])
+}
+
+/// Reads the answer count of a message.
+pub fn an_count(msg: &[u8]) -> u16 {
+    u16::from_be_bytes([msg[6], msg[7]])
+}
+
+/// Waits until the server answers queries.
+pub fn wait_ready(server: &Server) {
+    exchange(server, &query(0, "whoami.resolver.local", 16));
+}
+
+/// Sends `packet` to the server without waiting for a reply.
+pub fn send(server: &Server, packet: &[u8]) {
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sock.send_to(packet, server.addr).unwrap();
+}
+
+/// Sends `packet` to the server and waits for its reply, retrying while
+/// the server is starting up.
+pub fn exchange(server: &Server, packet: &[u8]) -> Vec<u8> {
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sock.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    let mut buf = [0u8; 4096];
+    for _ in 0..20 {
+        sock.send_to(packet, server.addr).unwrap();
+        if let Ok((len, _)) = sock.recv_from(&mut buf) {
+            return buf[..len].to_vec();
+        }
+    }
+    panic!("no reply from the server at {}", server.addr);
+}
+
+/// Sends `packet` to the server over TCP and waits for its reply.
+pub fn exchange_tcp(server: &Server, packet: &[u8]) -> Vec<u8> {
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write_framed(&mut stream, packet);
+    read_framed(&mut stream).expect("no reply from the server over TCP")
+}
+
+/// Writes a message preceded by its length, as over TCP.
+pub fn write_framed(stream: &mut TcpStream, message: &[u8]) {
+    stream.write_all(&(message.len() as u16).to_be_bytes()).unwrap();
+    stream.write_all(message).unwrap();
+}
+
+/// Reads a message preceded by its length, or `None` once the stream ends.
+pub fn read_framed(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length).ok()?;
+    let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut message).ok()?;
+    Some(message)
+}
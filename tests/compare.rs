@@ -0,0 +1,56 @@
+mod common;
+
+use common::{answer_a, error_reply, id, qname, spawn_server, spawn_upstream, wait_ready, Server};
+use std::{fs, net::SocketAddr, path::PathBuf, process::Command};
+
+/// Writes a list of names to compare.
+fn write_names(name: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("dnsr-compare-{}-{}.txt", name, std::process::id()));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+/// Compares the answers of `server` with those of `baseline` for the
+/// names of `path`, returning the exit status and output.
+fn compare(path: &PathBuf, server: &Server, baseline: SocketAddr) -> (bool, String) {
+    wait_ready(server);
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .arg("compare")
+        .arg(path)
+        .args(["--server", &server.addr.to_string(), "--baseline", &baseline.to_string()])
+        .output()
+        .unwrap();
+    (output.status.success(), String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[test]
+fn matching_answers_pass() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server(upstream);
+    let path     = write_names("same", "# popular names\nwww.example.com\n\nmail.example.com A\n");
+
+    let (ok, out) = compare(&path, &server, upstream);
+    assert!(ok, "{}", out);
+    assert!(out.contains("compared 2 names: 2 same, 0 different response codes, 0 different answers"), "{}", out);
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn differences_are_reported() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server(upstream);
+    let baseline = spawn_upstream(|q| match qname(q).as_str() {
+        "gone.example.com"  => error_reply(q, 3),
+        "moved.example.com" => answer_a(q, id(q), [192, 0, 2, 2]),
+        _                   => answer_a(q, id(q), [192, 0, 2, 1]),
+    });
+    let path = write_names("different", "www.example.com\ngone.example.com\nmoved.example.com\n");
+
+    let (ok, out) = compare(&path, &server, baseline);
+    assert!(!ok);
+    assert!(out.contains("gone.example.com A: baseline NXDOMAIN [], got NOERROR"), "{}", out);
+    assert!(out.contains("moved.example.com A: baseline NOERROR"), "{}", out);
+    assert!(!out.contains("www.example.com A:"), "{}", out);
+    assert!(out.contains("compared 3 names: 1 same, 1 different response codes, 1 different answers"), "{}", out);
+    let _ = fs::remove_file(path);
+}
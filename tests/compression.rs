@@ -0,0 +1,40 @@
+mod common;
+
+use common::{an_count, answer_a, exchange, id, query, send, spawn_server, spawn_upstream};
+use dns_resolver::{Dns, DnsReadBuffer};
+
+#[test]
+fn compression_loops_are_rejected() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server(upstream);
+    exchange(&server, &query(0, "localhost", 1));
+
+    // The question name is a pointer to itself
+    let mut packet = vec![0, 1, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    packet.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1]);
+    send(&server, &packet);
+
+    // The server is still up
+    let reply = exchange(&server, &query(2, "www.example.com", 1));
+    assert_eq!(id(&reply), 2);
+    assert_eq!(an_count(&reply), 1);
+}
+
+#[test]
+fn large_compressed_messages_are_decoded() {
+    // A response filling 64 KB with A records whose owner is a pointer to
+    // the question name
+    let mut packet = query(3, "www.example.com", 1);
+    packet[2] |= 0x80;
+    let count = (65535 - packet.len()) / 16;
+    packet[6..8].copy_from_slice(&(count as u16).to_be_bytes());
+    for n in 0..count {
+        packet.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+        packet.extend_from_slice(&(n as u32).to_be_bytes());
+    }
+    assert!(packet.len() > 65000);
+
+    let dns = Dns::decode(&mut DnsReadBuffer::new(&packet)).unwrap();
+    assert_eq!(dns.answers.len(), count);
+    assert_eq!(dns.answers[count - 1].aname, "www.example.com");
+}
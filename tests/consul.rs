@@ -0,0 +1,92 @@
+#![cfg(feature = "consul")]
+
+mod common;
+
+use common::{an_count, encode_name, exchange, free_addr, query, rcode, spawn_server_with};
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+const SERVICES: &str = r#"{"web": ["v1"]}"#;
+const WEB: &str = r#"[
+    {"Node": "node1", "Address": "10.0.0.1", "ServiceAddress": "", "ServicePort": 8080},
+    {"Node": "node2", "Address": "10.0.0.2", "ServiceAddress": "10.0.1.2", "ServicePort": 8081}
+]"#;
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Answers a request to the mock agent. Blocking queries past the first
+/// index hang, as if the catalog never changed.
+fn serve(mut stream: TcpStream) {
+    let mut buf = [0u8; 4096];
+    let len = stream.read(&mut buf).unwrap_or(0);
+    let request = String::from_utf8_lossy(&buf[..len]).to_string();
+    let path = request.split_whitespace().nth(1).unwrap_or_default().to_string();
+
+    let body = if path.starts_with("/v1/catalog/services?index=0") {
+        SERVICES
+    } else if path.starts_with("/v1/catalog/services") {
+        thread::sleep(Duration::from_secs(60));
+        SERVICES
+    } else if path == "/v1/catalog/service/web" {
+        WEB
+    } else {
+        "[]"
+    };
+    let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nX-Consul-Index: 7\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body,
+    );
+}
+
+#[test]
+fn catalog_services_are_served() {
+    let agent = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url   = format!("http://{}", agent.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in agent.incoming().flatten() {
+            thread::spawn(move || serve(stream));
+        }
+    });
+
+    // Nothing is resolved upstream
+    let server = spawn_server_with(free_addr(), &[("DNSR_CONSUL", url.as_str())]);
+
+    // The zone is filled in once the catalog is read
+    let mut reply = exchange(&server, &query(1, "web.service.consul", 1));
+    for n in 2..20 {
+        if an_count(&reply) > 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+        reply = exchange(&server, &query(n, "web.service.consul", 1));
+    }
+    assert_eq!(an_count(&reply), 2);
+    assert!(contains(&reply, &[10, 0, 0, 1]));
+    assert!(contains(&reply, &[10, 0, 1, 2]));
+
+    let reply = exchange(&server, &query(30, "_web._tcp.service.consul", 33));
+    assert_eq!(an_count(&reply), 2);
+    let mut srv = vec![0, 1, 0, 1, 0x1f, 0x90];
+    srv.extend(encode_name("node1.node.consul"));
+    assert!(contains(&reply, &srv));
+    let mut srv = vec![0, 1, 0, 1, 0x1f, 0x91];
+    srv.extend(encode_name("0a000102.addr.consul"));
+    assert!(contains(&reply, &srv));
+
+    let reply = exchange(&server, &query(31, "0a000102.addr.consul", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(&reply[reply.len() - 4..], &[10, 0, 1, 2]);
+
+    // A name that exists has no records of the other types
+    let reply = exchange(&server, &query(32, "node1.node.consul", 28));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 0);
+}
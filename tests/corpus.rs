@@ -0,0 +1,54 @@
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long decoding a single input may take.
+const DECODE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the inputs of the regression corpus.
+fn corpus() -> Vec<PathBuf> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    let mut inputs: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    inputs.sort();
+    inputs
+}
+
+#[test]
+fn corpus_inputs_decode_without_crashing() {
+    let inputs = corpus();
+    assert!(!inputs.is_empty());
+
+    for input in inputs {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+            .arg("decode")
+            .arg(&input)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let deadline = Instant::now() + DECODE_TIMEOUT;
+        let status = loop {
+            if let Some(status) = child.try_wait().unwrap() {
+                break Some(status);
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+
+        let status = status.unwrap_or_else(|| panic!("{} hung the decoder", input.display()));
+        assert!(status.success(), "{} crashed the decoder: {}", input.display(), status);
+    }
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use common::{an_count, exchange, free_addr, id, query, spawn_server};
+
+fn contains(haystack: &[u8], needle: &str) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle.as_bytes())
+}
+
+#[test]
+fn whoami_returns_the_client_address() {
+    // No upstream is needed, the name is answered locally
+    let server = spawn_server(free_addr());
+
+    let reply = exchange(&server, &query(7, "whoami.resolver.local", 16));
+
+    assert_eq!(id(&reply), 7);
+    assert_eq!(an_count(&reply), 3);
+    assert!(contains(&reply, "addr=127.0.0.1"));
+    assert!(contains(&reply, "port="));
+    assert!(contains(&reply, "transport=udp"));
+
+    let reply = exchange(&server, &query(8, "WHOAMI.resolver.local", 1));
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(&reply[reply.len() - 4..], &[127, 0, 0, 1]);
+}
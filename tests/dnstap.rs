@@ -0,0 +1,140 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{answer_a, exchange, id, query, spawn_server_with, spawn_upstream, wait_ready};
+use std::{
+    collections::HashMap,
+    env,
+    io::{ErrorKind, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+/// Reads a frame, returning its payload and whether it is a control frame.
+fn read_frame(stream: &mut UnixStream) -> (Vec<u8>, bool) {
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length).unwrap();
+    let control = length == [0; 4];
+    if control {
+        stream.read_exact(&mut length).unwrap();
+    }
+    let mut payload = vec![0u8; u32::from_be_bytes(length) as usize];
+    stream.read_exact(&mut payload).unwrap();
+    (payload, control)
+}
+
+/// Reads a protobuf varint.
+fn varint(buf: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = buf[0];
+        *buf = &buf[1..];
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            break;
+        }
+    }
+    value
+}
+
+/// Decodes the varint and length-delimited fields of a protobuf message,
+/// skipping the fixed-size ones.
+fn fields(mut buf: &[u8]) -> HashMap<u64, Vec<u8>> {
+    let mut out = HashMap::new();
+    while !buf.is_empty() {
+        let key = varint(&mut buf);
+        let value = match key & 7 {
+            0 => varint(&mut buf).to_be_bytes().to_vec(),
+            2 => {
+                let length = varint(&mut buf) as usize;
+                let (value, rest) = buf.split_at(length);
+                buf = rest;
+                value.to_vec()
+            }
+            5 => {
+                buf = &buf[4..];
+                continue;
+            }
+            kind => panic!("unexpected wire type {}", kind),
+        };
+        out.insert(key >> 3, value);
+    }
+    out
+}
+
+/// Reads a varint field decoded by `fields`.
+fn number(fields: &HashMap<u64, Vec<u8>>, field: u64) -> u64 {
+    u64::from_be_bytes(fields[&field].clone().try_into().unwrap())
+}
+
+#[test]
+fn messages_are_written_to_the_collector() {
+    let path     = env::temp_dir().join(format!("dnsr-dnstap-{}.sock", process::id()));
+    let _        = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    listener.set_nonblocking(true).unwrap();
+
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server_with(upstream, &[("DNSR_DNSTAP", path.to_str().unwrap())]);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut stream = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => panic!("the resolver never connected: {}", e),
+        }
+    };
+    stream.set_nonblocking(false).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    // The stream starts once the collector accepts the content type
+    let (ready, control) = read_frame(&mut stream);
+    assert!(control);
+    assert_eq!(&ready[..4], &[0, 0, 0, 4]);
+    assert!(ready.ends_with(b"protobuf:dnstap.Dnstap"));
+    let mut accept = vec![0, 0, 0, 0];
+    accept.extend_from_slice(&(ready.len() as u32).to_be_bytes());
+    accept.extend_from_slice(&[0, 0, 0, 1]);
+    accept.extend_from_slice(&ready[4..]);
+    stream.write_all(&accept).unwrap();
+    let (start, control) = read_frame(&mut stream);
+    assert!(control);
+    assert_eq!(&start[..4], &[0, 0, 0, 2]);
+
+    wait_ready(&server);
+    exchange(&server, &query(0x7a9, "www.example.com", 1));
+
+    // The client query and the reply carry the client's address, the
+    // queries sent upstream and their responses the server's
+    let mut seen = HashMap::new();
+    while seen.len() < 4 {
+        let (frame, control) = read_frame(&mut stream);
+        assert!(!control);
+        let tap = fields(&frame);
+        assert_eq!(number(&tap, 15), 1);
+        let message = fields(&tap[&14]);
+        let query   = &message[&10];
+        if query[..2] != 0x7a9u16.to_be_bytes() && number(&message, 1) >= 5 {
+            continue;
+        }
+        match number(&message, 1) {
+            kind @ (5 | 6) => {
+                assert_eq!(message[&4], [127, 0, 0, 1]);
+                seen.insert(kind, message.contains_key(&14));
+            }
+            kind @ (3 | 4) => {
+                assert_eq!(message[&5], [127, 0, 0, 1]);
+                assert_eq!(number(&message, 7), upstream.port() as u64);
+                seen.insert(kind, message.contains_key(&14));
+            }
+            kind => panic!("unexpected message type {}", kind),
+        }
+    }
+    assert_eq!(seen, HashMap::from([(3, false), (4, true), (5, false), (6, true)]));
+    let _ = std::fs::remove_file(&path);
+}
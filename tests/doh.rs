@@ -0,0 +1,66 @@
+#![cfg(feature = "doh")]
+
+mod common;
+
+use common::{answer_a, exchange, free_addr, id, query, rcode, spawn_server_with};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Spawns a plain HTTP server answering the DoH POST requests with an A
+/// record, and recording the content type of the last request.
+fn spawn_doh(seen: Arc<Mutex<String>>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_ascii_lowercase();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                if let Some(value) = line.strip_prefix("content-type:") {
+                    *seen.lock().unwrap() = value.trim().to_string();
+                }
+            }
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let reply = answer_a(&body, id(&body), [203, 0, 113, 9]);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                reply.len()
+            )
+            .unwrap();
+            stream.write_all(&reply).unwrap();
+        }
+    });
+    addr
+}
+
+#[test]
+fn domains_can_be_resolved_over_doh() {
+    let seen = Arc::new(Mutex::new(String::new()));
+    let doh  = spawn_doh(Arc::clone(&seen));
+    let rule = format!(".=http://{}/dns-query", doh);
+
+    // Everything goes to the DoH server, the root is never contacted
+    let server = spawn_server_with(free_addr(), &[("DNSR_DOMAIN_RULES", &rule)]);
+
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(&reply[reply.len() - 4..], &[203, 0, 113, 9]);
+    assert_eq!(*seen.lock().unwrap(), "application/dns-message");
+}
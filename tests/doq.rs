@@ -0,0 +1,81 @@
+#![cfg(feature = "doq")]
+
+mod common;
+
+use common::{an_count, answer_a, free_addr, id, query, spawn_server_with, spawn_upstream, wait_ready};
+use quinn::{crypto::rustls::QuicClientConfig, ClientConfig, Connection, Endpoint};
+use rustls::RootCertStore;
+use std::{env, fs, net::SocketAddr, process, sync::Arc, time::Duration};
+use tokio::{runtime::Runtime, time};
+
+/// Connects to the QUIC listener at `addr`, trusting the certificate
+/// `cert` only.
+async fn connect(addr: SocketAddr, cert: &rcgen::Certificate) -> Connection {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.der().clone()).unwrap();
+    let mut tls = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"doq".to_vec()];
+
+    let mut endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    endpoint.set_default_client_config(ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls).unwrap())));
+    endpoint.connect(addr, "dns.example.com").unwrap().await.unwrap()
+}
+
+/// Sends a query on a new stream of the connection, returning the reply.
+async fn exchange(conn: &Connection, packet: &[u8]) -> Vec<u8> {
+    let (mut send, mut recv) = conn.open_bi().await.unwrap();
+    let mut framed = (packet.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(packet);
+    send.write_all(&framed).await.unwrap();
+    send.finish().unwrap();
+
+    let reply = time::timeout(Duration::from_secs(5), recv.read_to_end(65537)).await.unwrap().unwrap();
+    assert_eq!(u16::from_be_bytes([reply[0], reply[1]]) as usize, reply.len() - 2);
+    reply[2..].to_vec()
+}
+
+#[test]
+fn queries_are_answered_over_quic() {
+    let certified = rcgen::generate_simple_self_signed(vec!["dns.example.com".to_string()]).unwrap();
+    let cert_path = env::temp_dir().join(format!("dnsr-doq-{}.crt", process::id()));
+    let key_path  = env::temp_dir().join(format!("dnsr-doq-{}.key", process::id()));
+    fs::write(&cert_path, certified.cert.pem()).unwrap();
+    fs::write(&key_path, certified.signing_key.serialize_pem()).unwrap();
+
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let listen   = free_addr();
+    let server   = spawn_server_with(upstream, &[
+        ("DNSR_QUIC_LISTEN", &listen.to_string()),
+        ("DNSR_TLS_CERT",    cert_path.to_str().unwrap()),
+        ("DNSR_TLS_KEY",     key_path.to_str().unwrap()),
+    ]);
+    wait_ready(&server);
+    let _ = fs::remove_file(&cert_path);
+    let _ = fs::remove_file(&key_path);
+
+    Runtime::new().unwrap().block_on(async {
+        let conn = connect(listen, &certified.cert).await;
+
+        // Every query has a stream of its own, with a zero ID
+        for name in ["www.example.com", "mail.example.com"] {
+            let reply = exchange(&conn, &query(0, name, 1)).await;
+            assert_eq!(id(&reply), 0);
+            assert_eq!(an_count(&reply), 1);
+            assert_eq!(&reply[reply.len() - 4..], &[192, 0, 2, 1]);
+        }
+
+        // A query with another ID breaks the protocol
+        let (mut send, mut recv) = conn.open_bi().await.unwrap();
+        let packet = query(7, "www.example.com", 1);
+        let mut framed = (packet.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&packet);
+        send.write_all(&framed).await.unwrap();
+        send.finish().unwrap();
+        assert!(recv.read_to_end(65537).await.is_err());
+        assert!(conn.close_reason().is_some());
+    });
+}
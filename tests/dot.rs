@@ -0,0 +1,167 @@
+#![cfg(feature = "dot")]
+
+mod common;
+
+use common::{
+    an_count, answer_a, exchange as exchange_udp, free_addr, id, query, spawn_server_with, spawn_upstream, wait_ready,
+};
+use rustls::{
+    pki_types::{PrivateKeyDer, ServerName},
+    ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+};
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Connects to the TLS listener at `addr`, trusting the certificate
+/// `cert` only, and waits for it to be bound.
+fn connect(addr: SocketAddr, cert: &rcgen::Certificate) -> StreamOwned<ClientConnection, TcpStream> {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.der().clone()).unwrap();
+    let tls = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let sock = loop {
+        match TcpStream::connect(addr) {
+            Ok(sock) => break sock,
+            Err(_)   => assert!(Instant::now() < deadline, "the listener never appeared"),
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let name = ServerName::try_from("dns.example.com").unwrap();
+    StreamOwned::new(ClientConnection::new(Arc::new(tls), name).unwrap(), sock)
+}
+
+/// Sends a query over the stream, returning the reply.
+fn exchange(stream: &mut StreamOwned<ClientConnection, TcpStream>, packet: &[u8]) -> Vec<u8> {
+    let mut framed = (packet.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(packet);
+    stream.write_all(&framed).unwrap();
+
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length).unwrap();
+    let mut reply = vec![0u8; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut reply).unwrap();
+    reply
+}
+
+#[test]
+fn queries_are_answered_over_tls() {
+    let certified = rcgen::generate_simple_self_signed(vec!["dns.example.com".to_string()]).unwrap();
+    let cert_path = env::temp_dir().join(format!("dnsr-dot-{}.crt", process::id()));
+    let key_path  = env::temp_dir().join(format!("dnsr-dot-{}.key", process::id()));
+    fs::write(&cert_path, certified.cert.pem()).unwrap();
+    fs::write(&key_path, certified.signing_key.serialize_pem()).unwrap();
+
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let listen   = free_addr();
+    let server   = spawn_server_with(upstream, &[
+        ("DNSR_TLS_LISTEN", &listen.to_string()),
+        ("DNSR_TLS_CERT",   cert_path.to_str().unwrap()),
+        ("DNSR_TLS_KEY",    key_path.to_str().unwrap()),
+    ]);
+    wait_ready(&server);
+    let _ = fs::remove_file(&cert_path);
+    let _ = fs::remove_file(&key_path);
+
+    // Several queries share the connection
+    let mut stream = connect(listen, &certified.cert);
+    for n in 0..3 {
+        let reply = exchange(&mut stream, &query(0x0d00 + n, "www.example.com", 1));
+        assert_eq!(id(&reply), 0x0d00 + n);
+        assert_eq!(an_count(&reply), 1);
+        assert_eq!(&reply[reply.len() - 4..], &[192, 0, 2, 1]);
+    }
+}
+
+/// Spawns a DoT server presenting the certificate of `certified` and
+/// answering every query with 192.0.2.7. Returns its address and the
+/// lengths of the queries received, by connection.
+fn spawn_tls_upstream(certified: &rcgen::CertifiedKey<rcgen::KeyPair>) -> (SocketAddr, Arc<Mutex<Vec<Vec<usize>>>>) {
+    let key = PrivateKeyDer::Pkcs8(certified.signing_key.serialize_der().into());
+    let tls = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![certified.cert.der().clone()], key)
+        .unwrap();
+    let tls = Arc::new(tls);
+
+    let listener    = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr        = listener.local_addr().unwrap();
+    let connections = Arc::new(Mutex::new(Vec::new()));
+    let seen        = Arc::clone(&connections);
+    thread::spawn(move || {
+        for sock in listener.incoming().flatten() {
+            let mut stream = StreamOwned::new(ServerConnection::new(Arc::clone(&tls)).unwrap(), sock);
+            let seen       = Arc::clone(&seen);
+            thread::spawn(move || {
+                let index = {
+                    let mut seen = seen.lock().unwrap();
+                    seen.push(Vec::new());
+                    seen.len() - 1
+                };
+                loop {
+                    let mut length = [0u8; 2];
+                    if stream.read_exact(&mut length).is_err() {
+                        return;
+                    }
+                    let mut query = vec![0u8; u16::from_be_bytes(length) as usize];
+                    if stream.read_exact(&mut query).is_err() {
+                        return;
+                    }
+                    seen.lock().unwrap()[index].push(query.len());
+
+                    let reply = answer_a(&query, id(&query), [192, 0, 2, 7]);
+                    let mut framed = (reply.len() as u16).to_be_bytes().to_vec();
+                    framed.extend_from_slice(&reply);
+                    if stream.write_all(&framed).and_then(|_| stream.flush()).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    (addr, connections)
+}
+
+#[test]
+fn queries_are_forwarded_over_tls() {
+    let certified = rcgen::generate_simple_self_signed(vec!["dns.example.com".to_string()]).unwrap();
+    let ca_path   = env::temp_dir().join(format!("dnsr-dot-ca-{}.crt", process::id()));
+    fs::write(&ca_path, certified.cert.pem()).unwrap();
+
+    let (upstream, connections) = spawn_tls_upstream(&certified);
+    let rule   = format!(".=tls:dns.example.com@{}", upstream);
+    let server = spawn_server_with(free_addr(), &[
+        ("DNSR_DOMAIN_RULES", &rule),
+        ("DNSR_UPSTREAM_CA",  ca_path.to_str().unwrap()),
+        ("DNSR_PRIVACY",      "true"),
+    ]);
+    wait_ready(&server);
+
+    for (n, name) in ["www.example.com", "a.much.longer.name.example.org"].into_iter().enumerate() {
+        let reply = exchange_udp(&server, &query(n as u16 + 1, name, 1));
+        assert_eq!(an_count(&reply), 1, "{}", name);
+        assert_eq!(&reply[reply.len() - 4..], &[192, 0, 2, 7]);
+    }
+    let _ = fs::remove_file(&ca_path);
+
+    // The connection is reused, and the queries are padded to the same
+    // length whatever their name
+    let connections = connections.lock().unwrap();
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0], [128, 128]);
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use common::{answer_a, exchange, id, query, spawn_server, spawn_upstream};
+
+/// A resource record of a response, with its owner name in dotted form.
+#[derive(Debug)]
+struct Record {
+    name:  String,
+    rtype: u16,
+    class: u16,
+    ttl:   u32,
+    rdata: Vec<u8>,
+}
+
+/// Reads the name at `pos`, following compression pointers, and returns
+/// it with the position right after it.
+fn read_name(msg: &[u8], mut pos: usize) -> (String, usize) {
+    let mut labels = Vec::new();
+    let mut end    = None;
+    loop {
+        let len = msg[pos] as usize;
+        if len & 0xC0 == 0xC0 {
+            end.get_or_insert(pos + 2);
+            pos = (len & 0x3F) << 8 | msg[pos + 1] as usize;
+            continue;
+        }
+        if len == 0 {
+            return (labels.join("."), end.unwrap_or(pos + 1));
+        }
+        labels.push(String::from_utf8_lossy(&msg[pos + 1..pos + 1 + len]).into_owned());
+        pos += len + 1;
+    }
+}
+
+/// Reads the answer section of a response carrying a single question.
+fn answers(msg: &[u8]) -> Vec<Record> {
+    let (_, mut pos) = read_name(msg, 12);
+    pos += 4;
+    let count = u16::from_be_bytes([msg[6], msg[7]]);
+    (0..count)
+        .map(|_| {
+            let (name, next) = read_name(msg, pos);
+            let field        = |at: usize| u16::from_be_bytes([msg[next + at], msg[next + at + 1]]);
+            let rdlength     = field(8) as usize;
+            let record = Record {
+                name,
+                rtype: field(0),
+                class: field(2),
+                ttl:   u32::from_be_bytes([msg[next + 4], msg[next + 5], msg[next + 6], msg[next + 7]]),
+                rdata: msg[next + 10..next + 10 + rdlength].to_vec(),
+            };
+            pos = next + 10 + rdlength;
+            record
+        })
+        .collect()
+}
+
+#[test]
+fn stub_queries_get_complete_answers() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server(upstream);
+
+    let request = query(0xBEEF, "www.example.com", 1);
+    let reply   = exchange(&server, &request);
+
+    assert_eq!(id(&reply), 0xBEEF);
+    assert_eq!(reply[2] & 0x80, 0x80, "QR must be set");
+    assert_eq!(reply[2] & 0x78, 0, "the opcode must be QUERY");
+    assert_eq!(reply[2] & 0x01, 0x01, "RD must be echoed");
+    assert_eq!(reply[3] & 0x80, 0x80, "RA must be set");
+    assert_eq!(reply[3] & 0x0F, 0, "the rcode must be NOERROR");
+    assert_eq!(&reply[4..6], &[0, 1]);
+    assert_eq!(&reply[12..request.len()], &request[12..]);
+
+    let answers = answers(&reply);
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0].name, "www.example.com");
+    assert_eq!((answers[0].rtype, answers[0].class), (1, 1));
+    assert!(answers[0].ttl > 0 && answers[0].ttl <= 3600, "ttl {}", answers[0].ttl);
+    assert_eq!(answers[0].rdata, vec![192, 0, 2, 1]);
+}
+
+#[test]
+fn nonexistent_names_get_complete_errors() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server(upstream);
+
+    let request = query(0x0A0B, "missing.invalid", 28);
+    let reply   = exchange(&server, &request);
+
+    assert_eq!(id(&reply), 0x0A0B);
+    assert_eq!(reply[2] & 0x81, 0x81, "QR and RD must be set");
+    assert_eq!(reply[3] & 0x0F, 3, "the rcode must be NXDOMAIN");
+    assert_eq!(&reply[4..6], &[0, 1]);
+    assert_eq!(&reply[12..request.len()], &request[12..]);
+    assert!(answers(&reply).is_empty());
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::{an_count, answer_a, encode_name, exchange, id, query, question, spawn_server_with, spawn_upstream, wait_ready};
+use std::{
+    net::UdpSocket,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Builds a referral answering `query`, delegating `zone` to a name server
+/// per glue address.
+fn delegation(query: &[u8], zone: &str, glue: &[[u8; 4]]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id(query).to_be_bytes());
+    out.extend_from_slice(&0x8000u16.to_be_bytes());
+    out.extend_from_slice(&[0, 1, 0, 0]);
+    out.extend_from_slice(&(glue.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(glue.len() as u16).to_be_bytes());
+    out.extend_from_slice(question(query));
+
+    for n in 0..glue.len() {
+        let rdata = encode_name(&format!("ns{}.{}", n, zone));
+        out.extend(encode_name(zone));
+        out.extend_from_slice(&[0, 2, 0, 1, 0, 2, 0xA3, 0]);
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend(rdata);
+    }
+    for (n, ip) in glue.iter().enumerate() {
+        out.extend(encode_name(&format!("ns{}.{}", n, zone)));
+        out.extend_from_slice(&[0, 1, 0, 1, 0, 2, 0xA3, 0, 0, 4]);
+        out.extend_from_slice(ip);
+    }
+    out
+}
+
+#[test]
+fn name_servers_can_be_raced() {
+    // Two servers of example.com on the same port: one answers, the other
+    // never does
+    let good = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let dead = UdpSocket::bind(("127.0.0.2", good.port())).unwrap();
+    let seen = Arc::new(AtomicUsize::new(0));
+    let hits = Arc::clone(&seen);
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while dead.recv_from(&mut buf).is_ok() {
+            hits.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+
+    let root   = spawn_upstream(|q| delegation(q, "example.com", &[[127, 0, 0, 1], [127, 0, 0, 2]]));
+    let port   = good.port().to_string();
+    let server = spawn_server_with(root, &[("DNSR_DELEGATION_PORT", &port), ("DNSR_FANOUT", "2")]);
+
+    // Whatever their order, the dead server doesn't hold the answer up
+    wait_ready(&server);
+    let start = Instant::now();
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(seen.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn late_answers_of_a_race_are_discarded() {
+    // Both servers answer, the second one too late and differently
+    let fast = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let slow = UdpSocket::bind(("127.0.0.2", fast.port())).unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok((len, peer)) = slow.recv_from(&mut buf) {
+            thread::sleep(Duration::from_millis(300));
+            let _ = slow.send_to(&answer_a(&buf[..len], id(&buf[..len]), [192, 0, 2, 2]), peer);
+        }
+    });
+
+    let root   = spawn_upstream(|q| delegation(q, "example.com", &[[127, 0, 0, 1], [127, 0, 0, 2]]));
+    let port   = fast.port().to_string();
+    let server = spawn_server_with(root, &[("DNSR_DELEGATION_PORT", &port), ("DNSR_FANOUT", "2")]);
+
+    for (n, name) in ["one.example.com", "two.example.com"].iter().enumerate() {
+        let reply = exchange(&server, &query(n as u16, name, 1));
+        assert_eq!(an_count(&reply), 1);
+        assert!(reply.ends_with(&[192, 0, 2, 1]));
+        thread::sleep(Duration::from_millis(500));
+    }
+}
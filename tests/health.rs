@@ -0,0 +1,57 @@
+#![cfg(feature = "health")]
+
+mod common;
+
+use common::{answer_a, free_addr, id, spawn_server_with, spawn_upstream};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+/// Returns a localhost TCP address that is currently free.
+fn free_tcp_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+/// Sends a GET request for `path`, returning the status code once the
+/// server accepts connections.
+fn get(addr: SocketAddr, path: &str) -> u16 {
+    for _ in 0..50 {
+        let Ok(mut stream) = TcpStream::connect(addr) else {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        };
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        return response[9..12].parse().unwrap();
+    }
+    panic!("no health endpoint at {}", addr);
+}
+
+#[test]
+fn ready_once_the_root_is_primed() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let health   = free_tcp_addr();
+    let _server  = spawn_server_with(upstream, &[("DNSR_HEALTH_LISTEN", &health.to_string())]);
+
+    assert_eq!(get(health, "/healthz"), 200);
+    let ready = (0..20).any(|_| {
+        thread::sleep(Duration::from_millis(100));
+        get(health, "/readyz") == 200
+    });
+    assert!(ready);
+    assert_eq!(get(health, "/metrics"), 404);
+}
+
+#[test]
+fn not_ready_while_the_root_is_unreachable() {
+    let health  = free_tcp_addr();
+    let _server = spawn_server_with(free_addr(), &[("DNSR_HEALTH_LISTEN", &health.to_string())]);
+
+    assert_eq!(get(health, "/healthz"), 200);
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(get(health, "/readyz"), 503);
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::{
+    answer_a, encode_name, error_reply, exchange, id, query, question, send, spawn_server, spawn_server_with,
+    spawn_upstream, wait_ready,
+};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+/// Builds a referral answering `query`, delegating `zone` to a single
+/// name server at 127.0.0.1.
+fn delegation(query: &[u8], zone: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id(query).to_be_bytes());
+    out.extend_from_slice(&0x8000u16.to_be_bytes());
+    out.extend_from_slice(&[0, 1, 0, 0, 0, 1, 0, 1]);
+    out.extend_from_slice(question(query));
+
+    let server = format!("ns.{}", zone);
+    let rdata  = encode_name(&server);
+    out.extend(encode_name(zone));
+    out.extend_from_slice(&[0, 2, 0, 1, 0, 2, 0xA3, 0]);
+    out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    out.extend(rdata);
+
+    out.extend(encode_name(&server));
+    out.extend_from_slice(&[0, 1, 0, 1, 0, 2, 0xA3, 0, 0, 4, 127, 0, 0, 1]);
+    out
+}
+
+#[test]
+fn refusing_servers_are_held_down() {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let seen = Arc::clone(&hits);
+    let upstream = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        error_reply(q, 5)
+    });
+    let server = spawn_server(upstream);
+    wait_ready(&server);
+
+    // A server isn't given up on at its first failure
+    for n in 1..=3 {
+        send(&server, &query(n, &format!("name{}.example.com", n), 1));
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(hits.load(Ordering::SeqCst), n as usize);
+    }
+
+    // The server refused three times in a row, so it is not asked again
+    // for a while
+    send(&server, &query(4, "name4.example.com", 1));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn lame_servers_are_only_held_down_for_their_zone() {
+    // The same server is delegated both zones, and only serves one
+    let hits = Arc::new(AtomicUsize::new(0));
+    let seen = Arc::clone(&hits);
+    let ns   = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        match question(q).windows(7).any(|w| w == b"example") {
+            true  => error_reply(q, 5),
+            false => answer_a(q, id(q), [192, 0, 2, 1]),
+        }
+    });
+    let root = spawn_upstream(|q| match question(q).windows(7).any(|w| w == b"example") {
+        true  => delegation(q, "example.com"),
+        false => delegation(q, "other.org"),
+    });
+    let port   = ns.port().to_string();
+    let server = spawn_server_with(root, &[("DNSR_DELEGATION_PORT", &port)]);
+    wait_ready(&server);
+
+    for n in 1..=4 {
+        send(&server, &query(n, &format!("name{}.example.com", n), 1));
+        thread::sleep(Duration::from_millis(300));
+    }
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+    let reply = exchange(&server, &query(5, "www.other.org", 1));
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+    assert_eq!(hits.load(Ordering::SeqCst), 4);
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use common::{an_count, answer_a, exchange, id, qname, query, spawn_scripted_upstream, spawn_server, wait_ready};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn concurrent_identical_queries_share_a_resolution() {
+    // The upstream takes its time, so that all the queries arrive while
+    // the first one is being resolved
+    let queries  = Arc::new(AtomicUsize::new(0));
+    let seen     = Arc::clone(&queries);
+    let upstream = spawn_scripted_upstream(move |sock, q, from| {
+        if qname(q) == "www.example.com" {
+            seen.fetch_add(1, Ordering::SeqCst);
+        }
+        thread::sleep(Duration::from_millis(300));
+        let _ = sock.send_to(&answer_a(q, id(q), [192, 0, 2, 1]), from);
+    });
+    let server = Arc::new(spawn_server(upstream));
+    wait_ready(&server);
+
+    thread::scope(|scope| {
+        for n in 0..20 {
+            let server = Arc::clone(&server);
+            scope.spawn(move || {
+                let reply = exchange(&server, &query(n, "www.example.com", 1));
+                assert_eq!(id(&reply), n);
+                assert_eq!(an_count(&reply), 1);
+            });
+        }
+    });
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+}
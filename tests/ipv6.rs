@@ -0,0 +1,103 @@
+mod common;
+
+use common::{answer_a, encode_name, exchange, free_addr, id, query, question, spawn_server_with, wait_ready};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::Duration,
+};
+
+/// Spawns a mock upstream bound to `addr`, answering every packet with
+/// `handler`, and returns the address it is bound to.
+fn spawn_upstream_on<F>(addr: &str, handler: F) -> SocketAddr
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    let sock = UdpSocket::bind(addr).unwrap();
+    let addr = sock.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok((len, peer)) = sock.recv_from(&mut buf) {
+            let _ = sock.send_to(&handler(&buf[..len]), peer);
+        }
+    });
+    addr
+}
+
+/// Builds a referral answering `query`, delegating `example.com` to a
+/// name server with both an IPv4 and an IPv6 glue address.
+fn delegation(query: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id(query).to_be_bytes());
+    out.extend_from_slice(&0x8000u16.to_be_bytes());
+    out.extend_from_slice(&[0, 1, 0, 0, 0, 1, 0, 2]);
+    out.extend_from_slice(question(query));
+
+    let rdata = encode_name("ns.example.com");
+    out.extend(encode_name("example.com"));
+    out.extend_from_slice(&[0, 2, 0, 1, 0, 2, 0xA3, 0]);
+    out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    out.extend(rdata);
+
+    out.extend(encode_name("ns.example.com"));
+    out.extend_from_slice(&[0, 1, 0, 1, 0, 2, 0xA3, 0, 0, 4, 127, 0, 0, 1]);
+    out.extend(encode_name("ns.example.com"));
+    out.extend_from_slice(&[0, 28, 0, 1, 0, 2, 0xA3, 0, 0, 16]);
+    out.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    out
+}
+
+/// Spawns the name servers of `example.com` on the same port of both
+/// loopback addresses, each answering with the address of its family, and
+/// returns the port.
+fn spawn_name_servers() -> u16 {
+    let v6 = spawn_upstream_on("[::1]:0", |q| answer_a(q, id(q), [192, 0, 2, 6]));
+    spawn_upstream_on(&format!("127.0.0.1:{}", v6.port()), |q| answer_a(q, id(q), [192, 0, 2, 4]));
+    v6.port()
+}
+
+#[test]
+fn dual_stack_listener_answers_both_families() {
+    let port       = free_addr().port();
+    let listen     = format!("[::]:{}", port);
+    let mut server = spawn_server_with(free_addr(), &[("DNSR_LISTEN", &listen)]);
+    server.addr    = SocketAddr::from(([127, 0, 0, 1], port));
+
+    // The IPv4 clients are known by their own address, not a mapped one
+    wait_ready(&server);
+    let reply = exchange(&server, &query(1, "whoami.resolver.local", 16));
+    assert!(reply.windows(14).any(|w| w == b"addr=127.0.0.1"));
+
+    let sock = UdpSocket::bind("[::1]:0").unwrap();
+    sock.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    sock.send_to(&query(2, "whoami.resolver.local", 16), ("::1", port)).unwrap();
+    let mut buf = [0u8; 4096];
+    let (len, _) = sock.recv_from(&mut buf).unwrap();
+    assert_eq!(id(&buf[..len]), 2);
+    assert!(buf[..len].windows(8).any(|w| w == b"addr=::1"));
+}
+
+#[test]
+fn name_servers_are_contacted_over_ipv6() {
+    let port   = spawn_name_servers();
+    let root   = spawn_upstream_on("[::1]:0", delegation);
+    let server = spawn_server_with(root, &[
+        ("DNSR_DELEGATION_PORT", &port.to_string()),
+        ("DNSR_UPSTREAM_FAMILY", "ipv6"),
+    ]);
+    wait_ready(&server);
+
+    let reply = exchange(&server, &query(3, "www.example.com", 1));
+    assert!(reply.ends_with(&[192, 0, 2, 6]));
+}
+
+#[test]
+fn name_servers_are_contacted_over_ipv4_by_default() {
+    let port   = spawn_name_servers();
+    let root   = spawn_upstream_on("127.0.0.1:0", delegation);
+    let server = spawn_server_with(root, &[("DNSR_DELEGATION_PORT", &port.to_string())]);
+    wait_ready(&server);
+
+    let reply = exchange(&server, &query(4, "www.example.com", 1));
+    assert!(reply.ends_with(&[192, 0, 2, 4]));
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use common::{answer_a, error_reply, exchange, id, query, spawn_server_with, spawn_upstream};
+use std::{env, fs};
+
+#[test]
+fn cluster_services_go_to_the_cluster_dns() {
+    let root = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    // The cluster DNS only answers recursive queries
+    let cluster = spawn_upstream(|q| match q[2] & 0x01 {
+        0 => error_reply(q, 5),
+        _ => answer_a(q, id(q), [10, 96, 0, 10]),
+    });
+
+    let path = env::temp_dir().join(format!("dnsr-resolv-{}.conf", std::process::id()));
+    fs::write(&path, format!(
+        "search default.svc.k8s.internal svc.k8s.internal k8s.internal\nnameserver {}\noptions ndots:5\n",
+        cluster,
+    )).unwrap();
+    let server = spawn_server_with(root, &[
+        ("DNSR_KUBERNETES",  "true"),
+        ("DNSR_RESOLV_CONF", path.to_str().unwrap()),
+    ]);
+
+    let reply = exchange(&server, &query(1, "api.default.svc.k8s.internal", 1));
+    assert_eq!(&reply[reply.len() - 4..], &[10, 96, 0, 10]);
+
+    let reply = exchange(&server, &query(2, "www.example.com", 1));
+    assert_eq!(&reply[reply.len() - 4..], &[192, 0, 2, 1]);
+
+    fs::remove_file(&path).unwrap();
+}
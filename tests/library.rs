@@ -0,0 +1,59 @@
+mod common;
+
+use common::{answer_a, id, query, spawn_upstream};
+use dns_resolver::{Config, Dns, DnsReadBuffer, RData, Resolver, Type};
+use std::{
+    net::Ipv4Addr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+#[tokio::test]
+async fn names_are_resolved_by_an_embedded_resolver() {
+    let queries  = Arc::new(AtomicUsize::new(0));
+    let seen     = Arc::clone(&queries);
+    let upstream = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+    let config   = Config { root: upstream, ..Config::default() };
+    let resolver = Resolver::from_config(&config).unwrap();
+
+    let answers = resolver.resolve("www.example.com", Type::A).await.unwrap();
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0].rdata, RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+
+    // The clones share the cache
+    let answers = resolver.clone().resolve("www.example.com", Type::A).await.unwrap();
+    assert_eq!(answers.len(), 1);
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn batches_and_watches_go_through_the_public_api() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let config   = Config { root: upstream, ..Config::default() };
+    let resolver = Resolver::from_config(&config).unwrap();
+
+    let questions = [("a.example.com".to_string(), Type::A), ("b.example.com".to_string(), Type::A)];
+    let outcomes  = resolver.lookup_many(questions, Duration::from_secs(5)).await;
+    assert_eq!(outcomes.len(), 2);
+    assert!(outcomes.iter().all(|outcome| outcome.as_ref().is_ok_and(|answers| answers.len() == 1)));
+
+    let mut watch = resolver.watch("c.example.com", Type::A);
+    let answers   = watch.next().await.unwrap();
+    assert_eq!(answers[0].rdata.as_a(), Some(Ipv4Addr::new(192, 0, 2, 1)));
+}
+
+#[test]
+fn messages_are_decoded_and_encoded_back() {
+    let packet = query(7, "www.example.com", 28);
+    let dns    = Dns::decode(&mut DnsReadBuffer::new(&packet)).unwrap();
+    assert_eq!(dns.header.id, 7);
+    assert_eq!(dns.questions[0].qname, "www.example.com");
+    assert_eq!(dns.questions[0].qtype, Type::AAAA);
+    assert_eq!(dns.encode().unwrap().into_inner(), packet);
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use common::{an_count, answer_a, exchange, free_addr, id, qname, query, spawn_server_with, spawn_upstream, wait_ready};
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+#[test]
+fn queries_are_answered_on_every_listener() {
+    let queries  = Arc::new(AtomicUsize::new(0));
+    let seen     = Arc::clone(&queries);
+    let upstream = spawn_upstream(move |q| {
+        if qname(q) == "www.example.com" {
+            seen.fetch_add(1, Ordering::SeqCst);
+        }
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+    let addrs      = [free_addr(), free_addr(), free_addr()];
+    let listen     = addrs.map(|addr| addr.to_string()).join(",");
+    let mut server = spawn_server_with(upstream, &[("DNSR_LISTEN", &listen)]);
+
+    // Each socket is served on its own, all of them sharing the cache
+    for (n, addr) in addrs.into_iter().enumerate() {
+        server.addr = addr;
+        wait_ready(&server);
+        let reply = exchange(&server, &query(n as u16, "www.example.com", 1));
+        assert_eq!(id(&reply), n as u16);
+        assert_eq!(an_count(&reply), 1);
+    }
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::{
+    an_count, encode_name, exchange, free_addr, id, negative_reply, query, rcode, spawn_server_with, spawn_upstream,
+};
+use std::{
+    env, fs, process, thread,
+    time::{Duration, Instant},
+};
+
+const RECORDS: &str = "nas.lan=192.168.1.20,nas.lan=fd00::20";
+
+#[test]
+fn static_records_are_answered_locally() {
+    // No upstream is needed, the names are answered locally
+    let server = spawn_server_with(free_addr(), &[("DNSR_LOCAL_RECORDS", RECORDS)]);
+
+    let reply = exchange(&server, &query(1, "NAS.lan", 1));
+    assert_eq!(id(&reply), 1);
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(&reply[reply.len() - 4..], &[192, 168, 1, 20]);
+
+    let reply = exchange(&server, &query(2, "nas.lan", 28));
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(&reply[reply.len() - 2..], &[0, 0x20]);
+
+    // The name exists, but has no records of this type
+    let reply = exchange(&server, &query(3, "nas.lan", 16));
+    assert_eq!(an_count(&reply), 0);
+}
+
+#[test]
+fn ptr_records_are_synthesized_from_static_records() {
+    let server = spawn_server_with(
+        free_addr(),
+        &[("DNSR_LOCAL_RECORDS", RECORDS), ("DNSR_SYNTHESIZE_PTR", "true")],
+    );
+
+    let reply = exchange(&server, &query(4, "20.1.168.192.in-addr.arpa", 12));
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&encode_name("nas.lan")));
+
+    let name = format!("0.2{}.d.f.ip6.arpa", ".0".repeat(28));
+    let reply = exchange(&server, &query(5, &name, 12));
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&encode_name("nas.lan")));
+}
+
+#[test]
+fn hosts_file_entries_are_answered_locally() {
+    let path = env::temp_dir().join(format!("dnsr-hosts-{}", process::id()));
+    fs::write(&path, concat!(
+        "# Lab machines\n",
+        "10.0.0.5   build.lab build   # the CI runner\n",
+        "fd00::5    build.lab\n",
+        "not-an-ip  ignored.lab\n",
+    )).unwrap();
+
+    let server = spawn_server_with(free_addr(), &[
+        ("DNSR_HOSTS_FILE", path.to_str().unwrap()),
+        ("DNSR_LOCAL_RECORDS", RECORDS),
+    ]);
+
+    let reply = exchange(&server, &query(1, "build.lab", 1));
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&[10, 0, 0, 5]));
+
+    let reply = exchange(&server, &query(2, "build", 1));
+    assert!(reply.ends_with(&[10, 0, 0, 5]));
+
+    let reply = exchange(&server, &query(3, "build.lab", 28));
+    assert!(reply.ends_with(&[0, 5]));
+
+    let reply = exchange(&server, &query(4, "nas.lan", 1));
+    assert!(reply.ends_with(&[192, 168, 1, 20]));
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn local_zones_are_never_resolved() {
+    // The upstream doesn't answer: every name must be answered locally
+    let server = spawn_server_with(free_addr(), &[
+        ("DNSR_LOCAL_ZONES",   "lan=static,corp=refuse,ads.example=redirect,tracker.example=nxdomain"),
+        ("DNSR_LOCAL_RECORDS", "nas.office.lan=192.168.1.20,ads.example=0.0.0.0,tracker.example=0.0.0.0"),
+    ]);
+
+    let reply = exchange(&server, &query(1, "nas.office.lan", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert!(reply.ends_with(&[192, 168, 1, 20]));
+
+    // Names above the records exist, the others don't
+    let reply = exchange(&server, &query(2, "office.lan", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 0);
+    let reply = exchange(&server, &query(3, "printer.lan", 1));
+    assert_eq!(rcode(&reply), 3);
+
+    let reply = exchange(&server, &query(4, "wiki.corp", 1));
+    assert_eq!(rcode(&reply), 5);
+
+    let reply = exchange(&server, &query(5, "banner.cdn.ads.example", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&[0, 0, 0, 0]));
+
+    let reply = exchange(&server, &query(6, "tracker.example", 1));
+    assert_eq!(rcode(&reply), 3);
+    assert_eq!(an_count(&reply), 0);
+}
+
+#[test]
+fn ptr_records_are_synthesized_from_hosted_zones() {
+    let zone = |serial: u32, host: u8| {
+        format!(
+            "@ 3600 IN SOA ns1 hostmaster {} 7200 3600 1209600 300\n@ IN NS ns1\nwww 300 IN A 192.0.2.{}\n",
+            serial, host,
+        )
+    };
+    let path = env::temp_dir().join(format!("dnsr-ptr-zone-{}", process::id()));
+    fs::write(&path, zone(1, 80)).unwrap();
+
+    // The names outside the local data don't exist upstream
+    let upstream = spawn_upstream(|q| negative_reply(q, 3, 0));
+    let zones    = format!("example.com={}", path.display());
+    let server   = spawn_server_with(upstream, &[("DNSR_ZONES", &zones), ("DNSR_SYNTHESIZE_PTR", "true")]);
+
+    let reply = exchange(&server, &query(1, "80.2.0.192.in-addr.arpa", 12));
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&encode_name("www.example.com")));
+
+    // The records follow the changes of the zone
+    fs::write(&path, zone(2, 81)).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(15);
+    while an_count(&exchange(&server, &query(2, "81.2.0.192.in-addr.arpa", 12))) == 0 {
+        assert!(Instant::now() < deadline, "the PTR record of the new address never appeared");
+        thread::sleep(Duration::from_millis(200));
+    }
+    let reply = exchange(&server, &query(3, "80.2.0.192.in-addr.arpa", 12));
+    assert_eq!(rcode(&reply), 3);
+    let _ = fs::remove_file(&path);
+}
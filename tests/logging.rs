@@ -0,0 +1,65 @@
+mod common;
+
+use common::{answer_a, exchange, free_addr, id, query, spawn_server_with, spawn_upstream, wait_ready};
+use std::{net::UdpSocket, time::Duration};
+
+#[test]
+fn errors_are_sent_to_syslog_over_udp() {
+    let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    collector.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    let target = format!("syslog:udp:{}", collector.local_addr().unwrap());
+
+    let server = spawn_server_with(free_addr(), &[("DNSR_LOG_TARGET", target.as_str())]);
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    // Keep sending a truncated packet until the server is up and logs the
+    // decoding failure
+    let mut buf = [0u8; 4096];
+    for _ in 0..20 {
+        client.send_to(&[0, 1, 2], server.addr).unwrap();
+        while let Ok(len) = collector.recv(&mut buf) {
+            let line = String::from_utf8_lossy(&buf[..len]).to_string();
+            if line.contains("processing error") {
+                assert!(line.starts_with("<27>1 "));
+                assert!(line.contains(" dns-resolver "));
+                assert!(line.contains(&format!("client=\"{}\"", client.local_addr().unwrap())));
+                return;
+            }
+        }
+    }
+    panic!("no error message received by the collector");
+}
+
+#[test]
+fn queries_are_logged_when_their_module_is_debugged() {
+    let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    collector.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    let target = format!("syslog:udp:{}", collector.local_addr().unwrap());
+
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server_with(upstream, &[
+        ("DNSR_LOG_TARGET", target.as_str()),
+        ("DNSR_LOG_FILTER", "warn,dns_resolver::query=debug"),
+    ]);
+    wait_ready(&server);
+    exchange(&server, &query(9, "www.example.com", 1));
+
+    // The informational messages are filtered out, the query is logged at
+    // the debug level with the fields of its span
+    let mut buf = [0u8; 4096];
+    let mut logged = false;
+    while let Ok(len) = collector.recv(&mut buf) {
+        let line = String::from_utf8_lossy(&buf[..len]).to_string();
+        assert!(!line.starts_with("<30>1 "), "unexpected message: {}", line);
+        if line.contains("qname=\"www.example.com\"") {
+            assert!(line.starts_with("<31>1 "));
+            assert!(line.contains("qtype=\"A\""));
+            assert!(line.contains("transport=\"udp\""));
+            assert!(line.contains("outcome=\"NOERROR\""));
+            assert!(line.contains("duration_us=\""));
+            assert!(line.ends_with(" query processed"));
+            logged = true;
+        }
+    }
+    assert!(logged, "the query was not logged");
+}
@@ -0,0 +1,17 @@
+mod common;
+
+use common::{an_count, answer_a, exchange, id, query, spawn_server, spawn_upstream};
+
+#[test]
+fn reply_echoes_client_id_not_upstream_id() {
+    // The upstream query carries an ID of its own, which the response
+    // must echo to be accepted
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(0x4242, "www.example.com", 1));
+
+    assert_eq!(id(&reply), 0x4242);
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(&reply[reply.len() - 4..], &[192, 0, 2, 1]);
+}
@@ -0,0 +1,69 @@
+#![cfg(feature = "metrics")]
+
+mod common;
+
+use common::{answer_a, exchange, id, negative_reply, qname, query, rcode, spawn_server_logged, spawn_upstream};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+#[test]
+fn cache_counters_are_dumped_on_sigusr1() {
+    let queries  = Arc::new(AtomicUsize::new(0));
+    let seen     = Arc::clone(&queries);
+    let upstream = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        match qname(q).as_str() {
+            "gone.example.com" => negative_reply(q, 3, 60),
+            _                  => answer_a(q, id(q), [192, 0, 2, 1]),
+        }
+    });
+    let (server, stderr) = spawn_server_logged(upstream, &[]);
+
+    // A miss, then a hit
+    exchange(&server, &query(1, "www.example.com", 1));
+    exchange(&server, &query(2, "www.example.com", 1));
+
+    // The name that doesn't exist is cached as well
+    assert_eq!(rcode(&exchange(&server, &query(3, "gone.example.com", 1))), 3);
+    assert_eq!(rcode(&exchange(&server, &query(4, "gone.example.com", 1))), 3);
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+    server.signal("USR1");
+    let dump: Vec<String> = std::iter::from_fn(|| stderr.recv_timeout(Duration::from_secs(5)).ok())
+        .skip_while(|line| line != "# TYPE dns_cache_hits_total counter")
+        .take_while(|line| line != "# TYPE dns_cache_stale_total counter")
+        .collect();
+    let dump = dump.join("\n") + "\n";
+
+    assert!(dump.contains("dns_cache_hits_total{qtype=\"A\",kind=\"positive\"} 1\n"), "{}", dump);
+    assert!(dump.contains("dns_cache_hits_total{qtype=\"A\",kind=\"negative\"} 1\n"), "{}", dump);
+    assert!(dump.contains("dns_cache_misses_total{qtype=\"A\",kind=\"positive\"} 2\n"), "{}", dump);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn panicking_requests_are_answered_counted_and_logged() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let (server, stderr) = spawn_server_logged(upstream, &[]);
+
+    // Every query gets a SERVFAIL, and the server keeps answering
+    for n in 1..=12 {
+        let reply = exchange(&server, &query(n, "panic.resolver.local", 1));
+        assert_eq!(id(&reply), n);
+        assert_eq!(rcode(&reply), 2);
+    }
+    assert_eq!(rcode(&exchange(&server, &query(13, "www.example.com", 1))), 0);
+
+    server.signal("USR1");
+    let lines: Vec<String> = std::iter::from_fn(|| stderr.recv_timeout(Duration::from_secs(2)).ok()).collect();
+
+    // Only the first ten panics of the minute are logged
+    let logged = lines.iter().filter(|line| line.contains("request task panicked")).count();
+    assert_eq!(logged, 10, "{:?}", lines);
+    assert!(lines.iter().any(|line| line == "dns_request_panics_total 12"), "{:?}", lines);
+}
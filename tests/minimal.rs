@@ -0,0 +1,52 @@
+mod common;
+
+use common::{an_count, answer_a, answer_records, exchange, id, query, spawn_server_with, spawn_upstream};
+
+/// Appends an NS record of the queried name to the authority section of
+/// a response, and the address of that server to its additional section.
+fn with_infrastructure(mut reply: Vec<u8>) -> Vec<u8> {
+    reply[9]  += 1;
+    reply[11] += 1;
+    reply.extend_from_slice(&[0xC0, 0x0C, 0, 2, 0, 1, 0, 0, 0x0E, 0x10, 0, 6, 3, b'n', b's', b'1', 0xC0, 0x0C]);
+    let ns = reply.len() - 6;
+    reply.extend_from_slice(&[0xC0 | (ns >> 8) as u8, ns as u8, 0, 1, 0, 1, 0, 0, 0x0E, 0x10, 0, 4, 192, 0, 2, 53]);
+    reply
+}
+
+/// Reads the authority and additional counts of a message.
+fn extra_counts(msg: &[u8]) -> (u16, u16) {
+    (u16::from_be_bytes([msg[8], msg[9]]), u16::from_be_bytes([msg[10], msg[11]]))
+}
+
+#[test]
+fn answers_only_carry_the_records_asked_for() {
+    let upstream = spawn_upstream(|q| {
+        answer_records(q, id(q), &[(1, vec![192, 0, 2, 1]), (16, b"\x05hello".to_vec())])
+    });
+
+    let server = spawn_server_with(upstream, &[]);
+    let reply  = exchange(&server, &query(1, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 2);
+
+    let server = spawn_server_with(upstream, &[("DNSR_MINIMAL_RESPONSES", "true")]);
+    let reply  = exchange(&server, &query(1, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+}
+
+#[test]
+fn relayed_answers_leave_the_infrastructure_out() {
+    let upstream = spawn_upstream(|q| with_infrastructure(answer_a(q, id(q), [192, 0, 2, 1])));
+
+    let server = spawn_server_with(upstream, &[("DNSR_PROXY", &upstream.to_string())]);
+    let reply  = exchange(&server, &query(1, "www.example.com", 1));
+    assert_eq!(extra_counts(&reply), (1, 1));
+
+    let server = spawn_server_with(upstream, &[
+        ("DNSR_PROXY",             &upstream.to_string()),
+        ("DNSR_MINIMAL_RESPONSES", "true"),
+    ]);
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(extra_counts(&reply), (0, 0));
+}
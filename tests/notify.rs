@@ -0,0 +1,166 @@
+#![cfg(feature = "zone-transfers")]
+
+mod common;
+
+use common::{answer_records, encode_name, exchange, id, qname, rcode, spawn_server_with, wait_ready, Server};
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, UdpSocket},
+    process::{self, Command},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Builds the rdata of the SOA record of `example.com` with `serial`.
+fn soa(serial: u32) -> Vec<u8> {
+    let mut rdata = encode_name("ns1.example.com");
+    rdata.extend_from_slice(&encode_name("hostmaster.example.com"));
+    for value in [serial, 7200, 3600, 1209600, 300] {
+        rdata.extend_from_slice(&value.to_be_bytes());
+    }
+    rdata
+}
+
+/// Builds a NOTIFY for the zone whose apex is `name`.
+fn notify(id: u16, name: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&0x2400u16.to_be_bytes());
+    out.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    out.extend_from_slice(&encode_name(name));
+    out.extend_from_slice(&6u16.to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out
+}
+
+/// Spawns the primary of `example.com`, whose SOA record has the current
+/// `serial`, telling it over UDP and transferring the zone over TCP.
+fn spawn_primary(serial: Arc<AtomicU32>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr     = listener.local_addr().unwrap();
+    let sock     = UdpSocket::bind(addr).unwrap();
+
+    let current = Arc::clone(&serial);
+    thread::spawn(move || {
+        let mut buf = [0u8; 512];
+        while let Ok((n, from)) = sock.recv_from(&mut buf) {
+            let serial = current.load(Ordering::SeqCst);
+            let reply  = answer_records(&buf[..n], id(&buf[..n]), &[(6, soa(serial))]);
+            let _ = sock.send_to(&reply, from);
+        }
+    });
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut length = [0u8; 2];
+            if stream.read_exact(&mut length).is_err() {
+                continue;
+            }
+            let mut query = vec![0u8; u16::from_be_bytes(length) as usize];
+            if stream.read_exact(&mut query).is_err() {
+                continue;
+            }
+            let serial = serial.load(Ordering::SeqCst);
+            let host   = vec![192, 0, 2, serial as u8];
+            let reply  = answer_records(&query, id(&query), &[(6, soa(serial)), (1, host), (6, soa(serial))]);
+            let _ = stream.write_all(&(reply.len() as u16).to_be_bytes());
+            let _ = stream.write_all(&reply);
+        }
+    });
+    addr
+}
+
+/// Returns the serial of `example.com` served on `listen`, if any.
+fn served_serial(listen: SocketAddr) -> Option<u32> {
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .args(["transfer", "example.com", &listen.to_string()])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let serial = stdout.split("example.com: serial ").nth(1)?;
+    serial.split(',').next()?.parse().ok()
+}
+
+/// Waits for `listen` to serve `example.com` with `serial`.
+fn wait_serial(listen: SocketAddr, serial: u32) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while served_serial(listen) != Some(serial) {
+        assert!(Instant::now() < deadline, "serial {} never served", serial);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Spawns a secondary of `example.com`, transferred from `primary`, and
+/// serving it on the returned address.
+fn spawn_secondary(primary: SocketAddr) -> (Server, SocketAddr) {
+    let listen    = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let secondary = format!("example.com={}", primary);
+    let server    = spawn_server_with("127.0.0.1:9".parse().unwrap(), &[
+        ("DNSR_SECONDARY_ZONES", &secondary),
+        ("DNSR_TRANSFER_LISTEN", &listen.to_string()),
+        ("DNSR_ALLOW_TRANSFER",  "127.0.0.1"),
+    ]);
+    wait_ready(&server);
+    (server, listen)
+}
+
+#[test]
+fn notified_zones_are_refreshed_from_their_primary() {
+    let serial           = Arc::new(AtomicU32::new(1));
+    let primary          = spawn_primary(Arc::clone(&serial));
+    let (server, listen) = spawn_secondary(primary);
+    wait_serial(listen, 1);
+
+    // The refresh interval is hours away: only the NOTIFY gets the change
+    serial.store(2, Ordering::SeqCst);
+    let reply = exchange(&server, &notify(0x4e01, "example.com"));
+    assert_eq!(id(&reply), 0x4e01);
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(reply[2] & 0x7c, 0x24, "expected an authoritative NOTIFY response");
+    wait_serial(listen, 2);
+}
+
+#[test]
+fn notifies_of_other_zones_are_refused() {
+    let (server, _listen) = spawn_secondary("127.0.0.1:9".parse().unwrap());
+
+    let reply = exchange(&server, &notify(0x4e02, "example.org"));
+    assert_eq!(id(&reply), 0x4e02);
+    assert_eq!(rcode(&reply), 5);
+}
+
+#[test]
+fn zone_changes_are_notified_to_secondaries() {
+    let secondary = UdpSocket::bind("127.0.0.1:0").unwrap();
+    secondary.set_read_timeout(Some(Duration::from_secs(15))).unwrap();
+
+    let path = env::temp_dir().join(format!("dnsr-notify-{}", process::id()));
+    fs::write(&path, "@ 3600 IN SOA ns1 hostmaster 1 7200 3600 1209600 300\n").unwrap();
+    let zones  = format!("example.com={}", path.display());
+    let notify = secondary.local_addr().unwrap().to_string();
+    let server = spawn_server_with("127.0.0.1:9".parse().unwrap(), &[
+        ("DNSR_ZONES",  &zones),
+        ("DNSR_NOTIFY", &notify),
+    ]);
+    wait_ready(&server);
+
+    fs::write(&path, "@ 3600 IN SOA ns1 hostmaster 2 7200 3600 1209600 300\n").unwrap();
+    let mut buf = [0u8; 512];
+    let (n, from) = secondary.recv_from(&mut buf).unwrap();
+    let _ = fs::remove_file(&path);
+
+    let msg = &buf[..n];
+    assert_eq!(msg[2] & 0x78, 0x20, "expected a NOTIFY");
+    assert_eq!(qname(msg), "example.com");
+
+    // Acknowledged, the NOTIFY isn't sent again
+    let mut ack = answer_records(msg, id(msg), &[]);
+    ack[2] |= 0x20;
+    secondary.send_to(&ack, from).unwrap();
+    secondary.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+    assert!(secondary.recv_from(&mut buf).is_err());
+}
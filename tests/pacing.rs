@@ -0,0 +1,42 @@
+mod common;
+
+use common::{answer_a, exchange, id, query, spawn_server_with, spawn_upstream};
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn queries_to_one_server_are_paced() {
+    let arrivals = Arc::new(Mutex::new(Vec::new()));
+    let log      = Arc::clone(&arrivals);
+    let upstream = spawn_upstream(move |q| {
+        log.lock().unwrap().push(Instant::now());
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+    let server = Arc::new(spawn_server_with(upstream, &[
+        ("DNSR_OUTGOING_RATE", "8"),
+        ("DNSR_OUTGOING_BURST", "1"),
+    ]));
+
+    // Make sure the server is up before firing the burst
+    exchange(&server, &query(0, "warmup.example.com", 1));
+    thread::sleep(Duration::from_millis(200));
+    arrivals.lock().unwrap().clear();
+
+    let clients: Vec<_> = (1..=4)
+        .map(|i| {
+            let server = Arc::clone(&server);
+            thread::spawn(move || exchange(&server, &query(i, &format!("n{}.example.com", i), 1)))
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+
+    // Four queries at eight per second need at least three intervals
+    let arrivals = arrivals.lock().unwrap();
+    assert_eq!(arrivals.len(), 4);
+    assert!(arrivals[3] - arrivals[0] >= Duration::from_millis(330));
+}
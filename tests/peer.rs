@@ -0,0 +1,31 @@
+mod common;
+
+use common::{an_count, answer_a, exchange, free_addr, id, query, spawn_server_with, spawn_upstream};
+use std::{thread, time::Duration};
+
+#[test]
+fn cache_entries_are_shared_with_peers() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let (primary_peer, standby_peer) = (free_addr().to_string(), free_addr().to_string());
+
+    let primary = spawn_server_with(upstream, &[
+        ("DNSR_PEERS",       &standby_peer),
+        ("DNSR_PEER_LISTEN", &primary_peer),
+        ("DNSR_PEER_KEY",    "secret"),
+    ]);
+    // The standby can't reach any server, it can only answer from its cache
+    let standby = spawn_server_with(free_addr(), &[
+        ("DNSR_PEERS",       &primary_peer),
+        ("DNSR_PEER_LISTEN", &standby_peer),
+        ("DNSR_PEER_KEY",    "secret"),
+    ]);
+
+    let reply = exchange(&primary, &query(1, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+    thread::sleep(Duration::from_millis(200));
+
+    let reply = exchange(&standby, &query(2, "www.example.com", 1));
+    assert_eq!(id(&reply), 2);
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(&reply[reply.len() - 4..], &[192, 0, 2, 1]);
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "wasm-plugins")]
+
+mod common;
+
+use common::{an_count, answer_a, exchange, id, query, rcode, spawn_server_with, spawn_upstream};
+use std::{env, fs, net::SocketAddr, path::PathBuf, process};
+
+/// Writes a plugin in the text format.
+fn write_plugin(name: &str, wat: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("dnsr-plugin-{}-{}.wat", name, process::id()));
+    fs::write(&path, wat).unwrap();
+    path
+}
+
+/// A plugin blocking the names starting with `ads.`, rewriting those
+/// starting with `rew.` and forwarding those starting with `fwd.` to
+/// `upstream`.
+fn policy_plugin(upstream: SocketAddr) -> String {
+    let route = format!("udp:{}", upstream);
+    format!(r#"
+(module
+  (import "dnsr" "query_name" (func $name (param i32 i32) (result i32)))
+  (import "dnsr" "set_target" (func $target (param i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "192.0.2.77, 2001:db8::77")
+  (data (i32.const 200) "{route}")
+  (func (export "check") (result i32)
+    (local $label i32)
+    (drop (call $name (i32.const 0) (i32.const 64)))
+    (local.set $label (i32.load (i32.const 0)))
+    (if (i32.eq (local.get $label) (i32.const 0x2e736461)) (then (return (i32.const 1))))
+    (if (i32.eq (local.get $label) (i32.const 0x2e776572))
+      (then (call $target (i32.const 100) (i32.const 24)) (return (i32.const 2))))
+    (if (i32.eq (local.get $label) (i32.const 0x2e647766))
+      (then (call $target (i32.const 200) (i32.const {len})) (return (i32.const 3))))
+    (i32.const 0)))
+"#, route = route, len = route.len())
+}
+
+#[test]
+fn plugins_block_rewrite_and_forward() {
+    let upstream  = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let forwarded = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 2]));
+    let plugin    = write_plugin("policy", &policy_plugin(forwarded));
+    let server    = spawn_server_with(upstream, &[("DNSR_PLUGINS", plugin.to_str().unwrap())]);
+
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+
+    let reply = exchange(&server, &query(2, "ads.example.com", 1));
+    assert_eq!(rcode(&reply), 3);
+
+    let reply = exchange(&server, &query(3, "rew.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&[192, 0, 2, 77]));
+
+    let reply = exchange(&server, &query(4, "fwd.example.com", 1));
+    assert!(reply.ends_with(&[192, 0, 2, 2]));
+
+    let _ = fs::remove_file(plugin);
+}
+
+#[test]
+fn runaway_plugins_allow_the_query() {
+    let plugin = write_plugin("loop", r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "check") (result i32)
+    (loop $forever (br $forever))
+    (i32.const 1)))
+"#);
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server_with(upstream, &[("DNSR_PLUGINS", plugin.to_str().unwrap())]);
+
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+
+    let _ = fs::remove_file(plugin);
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use common::{an_count, answer_a, exchange, free_addr, id, query, rcode, spawn_server_with, spawn_upstream, with_do};
+use std::{
+    env, fs, process,
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(feature = "blocklist-urls")]
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+};
+
+#[test]
+fn blocklist_matches_unicode_and_ascii_forms() {
+    // Blocked names are never resolved, so no upstream is needed
+    let server = spawn_server_with(free_addr(), &[("DNSR_BLOCKLIST", "bücher.example,xn--e1aybc.example")]);
+
+    let reply = exchange(&server, &query(1, "www.xn--bcher-kva.example", 1));
+    assert_eq!(id(&reply), 1);
+    assert_eq!(rcode(&reply), 3);
+    assert_eq!(an_count(&reply), 0);
+
+    let reply = exchange(&server, &query(2, "XN--E1AYBC.example", 1));
+    assert_eq!(rcode(&reply), 3);
+
+    // Names sent in raw UTF-8 are compared in their ASCII form too
+    let reply = exchange(&server, &query(3, "www.bücher.example", 1));
+    assert_eq!(id(&reply), 3);
+    assert_eq!(rcode(&reply), 3);
+}
+
+#[test]
+fn names_that_are_not_unicode_are_blocked() {
+    let server = spawn_server_with(free_addr(), &[("DNSR_BLOCKLIST", "bücher.example")]);
+
+    // A label of "bücher" in Latin-1, which isn't valid UTF-8
+    let mut request = query(4, "www.bxcher.example", 1);
+    request[18] = 0xFC;
+    let reply = exchange(&server, &request);
+    assert_eq!(id(&reply), 4);
+    assert_eq!(rcode(&reply), 3);
+}
+
+#[test]
+fn lookalikes_of_protected_names_are_blocked() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server_with(upstream, &[("DNSR_PROTECTED_NAMES", "paypal.com")]);
+
+    // Cyrillic "а" in place of the Latin "a"
+    let reply = exchange(&server, &query(3, "www.xn--pypal-4ve.com", 1));
+    assert_eq!(rcode(&reply), 3);
+
+    let reply = exchange(&server, &query(4, "www.paypal.com", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 1);
+}
+
+#[test]
+fn lookalikes_of_protected_names_can_be_logged_only() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server_with(upstream, &[
+        ("DNSR_PROTECTED_NAMES",  "paypal.com"),
+        ("DNSR_HOMOGRAPH_ACTION", "log"),
+    ]);
+
+    let reply = exchange(&server, &query(5, "xn--pypal-4ve.com", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 1);
+}
+
+/// Whether `haystack` contains `needle`.
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+#[test]
+fn blocked_names_can_resolve_to_a_sinkhole() {
+    let server = spawn_server_with(free_addr(), &[
+        ("DNSR_BLOCKLIST", "ads.example.net"),
+        ("DNSR_SINKHOLE", "192.0.2.53,2001:db8::53"),
+    ]);
+
+    let reply = exchange(&server, &query(1, "tracker.ads.example.net", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 1);
+    assert!(contains(&reply, &[192, 0, 2, 53]));
+    assert!(contains(&reply, b"blocked: blocklist (rule ads.example.net)"));
+
+    // Other types get no records, but still the explanation
+    let reply = exchange(&server, &query(2, "tracker.ads.example.net", 15));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 0);
+    assert!(contains(&reply, b"rule ads.example.net"));
+}
+
+#[test]
+fn blocked_names_carry_an_extended_error() {
+    let server = spawn_server_with(free_addr(), &[("DNSR_BLOCKLIST", "ads.example.net")]);
+
+    let reply = exchange(&server, &with_do(query(1, "ads.example.net", 1)));
+    let text  = b"blocked: blocklist (rule ads.example.net)";
+    let mut option = vec![0, 15, 0, 2 + text.len() as u8, 0, 15];
+    option.extend_from_slice(text);
+
+    assert_eq!(rcode(&reply), 3);
+    assert!(contains(&reply, &option));
+
+    // Clients without EDNS get no OPT record
+    let reply = exchange(&server, &query(2, "ads.example.net", 1));
+    assert_eq!(rcode(&reply), 3);
+    assert!(!contains(&reply, text));
+}
+
+#[test]
+#[cfg(feature = "blocklist-urls")]
+fn blocklists_are_fetched_on_startup() {
+    let list = "# Blocked\n127.0.0.1 localhost\n0.0.0.0 ads.example tracker.example\nmalware.example\n";
+    let http = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url  = format!("http://{}/hosts", http.local_addr().unwrap());
+    thread::spawn(move || {
+        for mut stream in http.incoming().flatten() {
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf);
+            let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", list.len(), list);
+        }
+    });
+
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server_with(upstream, &[("DNSR_BLOCKLIST_URLS", url.as_str())]);
+
+    for (n, name) in ["ads.example", "www.tracker.example", "malware.example"].iter().enumerate() {
+        let reply = exchange(&server, &query(n as u16, name, 1));
+        assert_eq!(rcode(&reply), 3);
+    }
+
+    let reply = exchange(&server, &query(9, "localhost", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 1);
+}
+
+#[test]
+fn blocklist_files_are_reloaded() {
+    let path = env::temp_dir().join(format!("dnsr-blocklist-{}", process::id()));
+    fs::write(&path, "0.0.0.0 ads.example\n").unwrap();
+
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let files    = path.display().to_string();
+    let server   = spawn_server_with(upstream, &[
+        ("DNSR_BLOCKLIST_FILES",   &files),
+        ("DNSR_BLOCKLIST_REFRESH", "1"),
+    ]);
+
+    let reply = exchange(&server, &query(1, "www.ads.example", 1));
+    assert_eq!(rcode(&reply), 3);
+    let reply = exchange(&server, &query(2, "tracker.example", 1));
+    assert_eq!(rcode(&reply), 0);
+
+    // The new list replaces the old one on the next reload
+    fs::write(&path, "# Trackers\ntracker.example\n").unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while rcode(&exchange(&server, &query(3, "tracker.example", 1))) != 3 {
+        assert!(Instant::now() < deadline, "the blocklist was never reloaded");
+        thread::sleep(Duration::from_millis(100));
+    }
+    let _ = fs::remove_file(&path);
+
+    let reply = exchange(&server, &query(4, "www.ads.example", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 1);
+}
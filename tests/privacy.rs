@@ -0,0 +1,47 @@
+mod common;
+
+use common::{answer_a, exchange, id, query, spawn_server_with, spawn_upstream};
+use std::process::Command;
+
+/// EDNS Client Subnet option for 192.0.2.0/24.
+const ECS: [u8; 11] = [0, 8, 0, 7, 0, 1, 24, 0, 192, 0, 2];
+
+/// Appends an OPT record carrying the client subnet to a query.
+fn with_ecs(mut query: Vec<u8>) -> Vec<u8> {
+    query[11] += 1;
+    query.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, ECS.len() as u8]);
+    query.extend_from_slice(&ECS);
+    query
+}
+
+#[test]
+fn relayed_queries_leave_without_the_client_subnet() {
+    // The upstream tells whether the subnet reached it by the address
+    // it answers with
+    let upstream = spawn_upstream(|q| match q.windows(ECS.len()).any(|w| w == ECS) {
+        true  => answer_a(q, id(q), [192, 0, 2, 66]),
+        false => answer_a(q, id(q), [192, 0, 2, 1]),
+    });
+    let proxy = upstream.to_string();
+
+    let server = spawn_server_with(upstream, &[("DNSR_PROXY", &proxy)]);
+    let reply  = exchange(&server, &with_ecs(query(1, "www.example.com", 1)));
+    assert!(reply.ends_with(&[192, 0, 2, 66]));
+
+    let server = spawn_server_with(upstream, &[("DNSR_PROXY", &proxy), ("DNSR_PRIVACY", "true")]);
+    let reply  = exchange(&server, &with_ecs(query(2, "www.example.com", 1)));
+    assert_eq!(id(&reply), 2);
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+}
+
+#[test]
+fn privacy_requires_shared_sockets() {
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .env("DNSR_PRIVACY", "true")
+        .env("DNSR_OUTGOING_SOCKETS", "0")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("DNSR_PRIVACY requires DNSR_OUTGOING_SOCKETS"));
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{answer_records, exchange, free_addr, id, query, spawn_server_with, spawn_upstream, with_do};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[test]
+fn proxy_relays_packets_verbatim() {
+    let seen  = Arc::new(Mutex::new(Vec::new()));
+    let saved = Arc::clone(&seen);
+    let upstream = spawn_upstream(move |q| {
+        *saved.lock().unwrap() = q.to_vec();
+        // A record type the codec knows nothing about
+        answer_records(q, id(q), &[(65280, vec![0xde, 0xad, 0xbe, 0xef])])
+    });
+    let server = spawn_server_with(upstream, &[("DNSR_PROXY", &upstream.to_string())]);
+
+    let packet = with_do(query(0x1234, "www.example.com", 65280));
+    let reply  = exchange(&server, &packet);
+
+    // Only the transaction ID was changed on the way
+    let sent = seen.lock().unwrap().clone();
+    assert_eq!(&sent[2..], &packet[2..]);
+
+    let expected = answer_records(&sent, 0x1234, &[(65280, vec![0xde, 0xad, 0xbe, 0xef])]);
+    assert_eq!(reply, expected);
+}
+
+#[test]
+fn proxy_fails_over_to_the_next_upstream() {
+    let dead     = free_addr();
+    let upstream = spawn_upstream(|q| answer_records(q, id(q), &[(1, vec![192, 0, 2, 1])]));
+    let server   = spawn_server_with(upstream, &[("DNSR_PROXY", &format!("{},{}", dead, upstream))]);
+
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+
+    // The dead upstream is held down, and no longer delays the queries
+    let start = Instant::now();
+    let reply = exchange(&server, &query(2, "www.example.com", 1));
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::{
+    an_count, answer_records, encode_name, exchange, id, query, send, spawn_server_with,
+    spawn_upstream,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn failed_reverse_lookups_are_cached() {
+    // The upstream answers without records nor referral, which fails the
+    // resolution without holding the server down
+    let queries = Arc::new(AtomicUsize::new(0));
+    let seen    = Arc::clone(&queries);
+    let upstream = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        answer_records(q, id(q), &[])
+    });
+    let server = spawn_server_with(upstream, &[]);
+
+    // Let the server start before counting the queries it sends
+    exchange(&server, &query(0, "localhost", 1));
+
+    send(&server, &query(1, "7.2.0.192.in-addr.arpa", 12));
+    thread::sleep(Duration::from_millis(500));
+    let sent = queries.load(Ordering::SeqCst);
+    assert!(sent > 0);
+
+    send(&server, &query(2, "7.2.0.192.in-addr.arpa", 12));
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(queries.load(Ordering::SeqCst), sent);
+}
+
+#[test]
+fn reverse_lookups_are_rate_limited() {
+    let queries = Arc::new(AtomicUsize::new(0));
+    let seen    = Arc::clone(&queries);
+    let upstream = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        answer_records(q, id(q), &[(12, encode_name("host.example.com"))])
+    });
+    let server = spawn_server_with(upstream, &[("DNSR_PTR_RATE", "1")]);
+
+    let reply = exchange(&server, &query(1, "7.2.0.192.in-addr.arpa", 12));
+    assert_eq!(an_count(&reply), 1);
+    let sent = queries.load(Ordering::SeqCst);
+
+    // A second lookup within the same second is not resolved...
+    send(&server, &query(2, "8.2.0.192.in-addr.arpa", 12));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(queries.load(Ordering::SeqCst), sent);
+
+    // ...while the first one is still answered from the cache
+    let reply = exchange(&server, &query(3, "7.2.0.192.in-addr.arpa", 12));
+    assert_eq!(id(&reply), 3);
+    assert_eq!(an_count(&reply), 1);
+}
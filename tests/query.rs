@@ -0,0 +1,65 @@
+mod common;
+
+use common::{answer_a, error_reply, id, spawn_tcp_upstream, spawn_upstream};
+use std::process::Command;
+
+/// Runs the `query` subcommand with `args`, returning its output.
+fn query(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .arg("query")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn responses_are_printed_like_dig() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = format!("@{}", upstream);
+
+    let out = query(&["www.example.com", "A", &server]);
+    assert!(out.contains("status: NOERROR"), "{}", out);
+    assert!(out.contains(";; flags: qr aa;"), "{}", out);
+    assert!(out.contains(";; QUESTION SECTION:\n;www.example.com.\t\tIN\tA\n"), "{}", out);
+    assert!(out.contains(";; ANSWER SECTION:\nwww.example.com. "), "{}", out);
+    assert!(out.contains(" IN A 192.0.2.1\n"), "{}", out);
+    assert!(out.contains(&format!(";; SERVER: 127.0.0.1#{} (UDP)", upstream.port())), "{}", out);
+}
+
+#[test]
+fn response_codes_of_updates_are_named() {
+    let upstream = spawn_upstream(|q| error_reply(q, 10));
+    let server   = format!("@{}", upstream);
+
+    let out = query(&["www.example.com", "A", &server]);
+    assert!(out.contains("status: NOTZONE"), "{}", out);
+}
+
+#[test]
+fn queries_go_over_tcp_when_asked() {
+    let upstream = spawn_upstream(|_| Vec::new());
+    spawn_tcp_upstream(upstream, |q| answer_a(q, id(q), [192, 0, 2, 2]));
+    let server = format!("@{}", upstream);
+
+    let out = query(&["www.example.com", &server, "+tcp"]);
+    assert!(out.contains(" IN A 192.0.2.2\n"), "{}", out);
+    assert!(out.contains(&format!(";; SERVER: 127.0.0.1#{} (TCP)", upstream.port())), "{}", out);
+}
+
+#[test]
+fn truncated_responses_are_asked_again_over_tcp() {
+    let upstream = spawn_upstream(|q| {
+        let mut reply = answer_a(q, id(q), [192, 0, 2, 1]);
+        reply[2] |= 0x02;
+        reply
+    });
+    spawn_tcp_upstream(upstream, |q| answer_a(q, id(q), [192, 0, 2, 2]));
+    let server = format!("@{}", upstream);
+
+    let out = query(&["www.example.com", &server]);
+    assert!(out.contains(";; Truncated, retrying in TCP mode."), "{}", out);
+    assert!(out.contains(" IN A 192.0.2.2\n"), "{}", out);
+    assert!(out.contains("(TCP)"), "{}", out);
+}
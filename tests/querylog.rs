@@ -0,0 +1,58 @@
+mod common;
+
+use common::{exchange, free_addr, query, spawn_server_with, wait_ready};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+/// Returns the path of a rotated log file.
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), n))
+}
+
+#[test]
+fn query_log_is_rotated_past_its_size() {
+    let path   = env::temp_dir().join(format!("dnsr-queries-{}.log", process::id()));
+    let _      = fs::remove_file(&path);
+    let server = spawn_server_with(free_addr(), &[
+        ("DNSR_QUERY_LOG",      path.to_str().unwrap()),
+        ("DNSR_QUERY_LOG_SIZE", "300"),
+        ("DNSR_QUERY_LOG_KEEP", "2"),
+    ]);
+    wait_ready(&server);
+
+    // Each line takes a third of the file or so
+    for n in 1..=8 {
+        exchange(&server, &query(n, "whoami.resolver.local", 16));
+    }
+
+    // The lines are written in the background: wait until both rotated
+    // files exist and the writer is done moving them around
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut last = None;
+    loop {
+        let sizes = [rotated(&path, 1), rotated(&path, 2)].map(|file| fs::metadata(file).map(|m| m.len()).ok());
+        if sizes.iter().all(Option::is_some) && last == Some(sizes) {
+            break;
+        }
+        assert!(Instant::now() < deadline, "the query log was never rotated");
+        last = Some(sizes);
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(!rotated(&path, 3).exists());
+
+    for file in [rotated(&path, 1), rotated(&path, 2)] {
+        let content = fs::read_to_string(&file).unwrap();
+        assert!(content.len() <= 300);
+        for line in content.lines() {
+            assert!(line.contains(" client=127.0.0.1:"));
+            assert!(line.contains(" qname=whoami.resolver.local qtype=TXT rcode=NOERROR latency_us="));
+        }
+    }
+    for file in [path.clone(), rotated(&path, 1), rotated(&path, 2)] {
+        let _ = fs::remove_file(file);
+    }
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use common::{
+    an_count, answer_many, answer_records, encode_name, exchange, id, query, spawn_server, spawn_server_with,
+    spawn_upstream,
+};
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[test]
+fn mx_records_are_relayed() {
+    let upstream = spawn_upstream(|q| {
+        let mut rdata = vec![0, 10];
+        rdata.extend(encode_name("mail.example.com"));
+        answer_records(q, id(q), &[(15, rdata)])
+    });
+    let server = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(1, "example.com", 15));
+
+    assert_eq!(an_count(&reply), 1);
+    let mut rdata = vec![0, 10];
+    rdata.extend(encode_name("mail.example.com"));
+    assert!(contains(&reply, &rdata));
+}
+
+#[test]
+fn soa_records_are_relayed() {
+    let mut soa = encode_name("ns1.example.com");
+    soa.extend(encode_name("hostmaster.example.com"));
+    for counter in [2024010101u32, 7200, 3600, 1209600, 300] {
+        soa.extend_from_slice(&counter.to_be_bytes());
+    }
+    let rdata = soa.clone();
+    let upstream = spawn_upstream(move |q| answer_records(q, id(q), &[(6, rdata.clone())]));
+    let server   = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(1, "example.com", 6));
+
+    assert_eq!(an_count(&reply), 1);
+    assert!(contains(&reply, &soa));
+}
+
+#[test]
+fn txt_strings_are_relayed_as_they_are() {
+    // Two character-strings, one holding a byte that isn't UTF-8
+    let txt = [&[5][..], b"hello", &[3, b'a', 0xFF, b'b']].concat();
+    let rdata = txt.clone();
+    let upstream = spawn_upstream(move |q| answer_records(q, id(q), &[(16, rdata.clone())]));
+    let server   = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(1, "example.com", 16));
+
+    assert_eq!(an_count(&reply), 1);
+    let mut expected = (txt.len() as u16).to_be_bytes().to_vec();
+    expected.extend(&txt);
+    assert!(contains(&reply, &expected));
+}
+
+#[test]
+fn srv_records_are_relayed() {
+    let mut srv = vec![0, 10, 0, 60, 0x01, 0x85];
+    srv.extend(encode_name("ldap1.example.com"));
+    let rdata = srv.clone();
+    let upstream = spawn_upstream(move |q| answer_records(q, id(q), &[(33, rdata.clone())]));
+    let server   = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(1, "_ldap._tcp.example.com", 33));
+
+    assert_eq!(an_count(&reply), 1);
+    assert!(contains(&reply, &srv));
+}
+
+#[test]
+fn https_records_are_relayed() {
+    let mut https = vec![0, 1, 0];
+    https.extend_from_slice(&[0, 1, 0, 6, 2, b'h', b'2', 2, b'h', b'3']);
+    https.extend_from_slice(&[0, 3, 0, 2, 0x01, 0xbb]);
+    https.extend_from_slice(&[0, 4, 0, 4, 192, 0, 2, 1]);
+    https.extend_from_slice(&[0, 5, 0, 3, 0xfe, 0x0d, 0x00]);
+    let rdata = https.clone();
+    let upstream = spawn_upstream(move |q| answer_records(q, id(q), &[(65, rdata.clone())]));
+    let server   = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(1, "www.example.com", 65));
+
+    assert_eq!(an_count(&reply), 1);
+    assert!(contains(&reply, &https));
+}
+
+#[test]
+fn unknown_records_are_relayed_unchanged() {
+    // CAA has no decoder, and 65280 is a private-use type
+    let mut caa = vec![0, 5];
+    caa.extend_from_slice(b"issueca.example.net");
+    let private = vec![0xde, 0xad, 0xbe, 0xef, 0x00, 0x01];
+
+    for (qtype, rdata) in [(257, caa), (65280, private)] {
+        let expected = rdata.clone();
+        let upstream = spawn_upstream(move |q| answer_records(q, id(q), &[(qtype, rdata.clone())]));
+        let server   = spawn_server(upstream);
+
+        let reply = exchange(&server, &query(1, "example.com", qtype));
+
+        assert_eq!(an_count(&reply), 1);
+        let mut rdata = (expected.len() as u16).to_be_bytes().to_vec();
+        rdata.extend_from_slice(&expected);
+        assert!(contains(&reply, &rdata));
+    }
+}
+
+#[test]
+fn bogus_and_duplicate_records_are_dropped() {
+    let upstream = spawn_upstream(|q| {
+        answer_many(q, id(q), &[[0, 0, 0, 0], [192, 0, 2, 1], [192, 0, 2, 1], [255, 255, 255, 255]])
+    });
+
+    let server = spawn_server(upstream);
+    let reply  = exchange(&server, &query(1, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+
+    let server = spawn_server_with(upstream, &[("DNSR_BOGUS_ADDRESSES", "")]);
+    let reply  = exchange(&server, &query(1, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 3);
+
+    // Forwarders may block names with such addresses on purpose
+    let rule   = format!(".=udp:{}", upstream);
+    let server = spawn_server_with(upstream, &[("DNSR_DOMAIN_RULES", &rule)]);
+    let reply  = exchange(&server, &query(1, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 3);
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use common::{
+    an_count, answer_a, exchange, id, query, rcode, spawn_server, spawn_server_with,
+    spawn_upstream,
+};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Builds a query with the RD bit cleared.
+fn non_recursive(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut packet = query(id, name, qtype);
+    packet[2] &= !0x01;
+    packet
+}
+
+#[test]
+fn non_recursive_queries_are_answered_from_the_cache() {
+    let queries = Arc::new(AtomicUsize::new(0));
+    let seen    = Arc::clone(&queries);
+    let upstream = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+    let server = spawn_server(upstream);
+
+    // Nothing is known yet, and nothing is resolved
+    let reply = exchange(&server, &non_recursive(1, "www.example.com", 1));
+    assert_eq!(rcode(&reply), 5);
+    assert_eq!(an_count(&reply), 0);
+    assert_eq!(queries.load(Ordering::SeqCst), 0);
+
+    let reply = exchange(&server, &query(2, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+
+    let reply = exchange(&server, &non_recursive(3, "www.example.com", 1));
+    assert_eq!(id(&reply), 3);
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(&reply[reply.len() - 4..], &[192, 0, 2, 1]);
+}
+
+#[test]
+fn non_recursive_queries_can_be_refused() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server_with(upstream, &[("DNSR_NON_RECURSIVE", "refuse")]);
+
+    let reply = exchange(&server, &non_recursive(1, "www.example.com", 1));
+    assert_eq!(rcode(&reply), 5);
+
+    // Local data is still served
+    let reply = exchange(&server, &non_recursive(2, "localhost", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 1);
+}
+
+#[test]
+fn non_recursive_queries_can_be_resolved() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server_with(upstream, &[("DNSR_NON_RECURSIVE", "recurse")]);
+
+    let reply = exchange(&server, &non_recursive(1, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+}
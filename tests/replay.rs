@@ -0,0 +1,101 @@
+mod common;
+
+use common::{answer_a, id, query, spawn_server, spawn_server_with, spawn_upstream, wait_ready, Server};
+use std::{fs, path::PathBuf, process::Command};
+
+/// Wraps a DNS message into a raw IPv4/UDP packet.
+fn packet(src: ([u8; 4], u16), dst: ([u8; 4], u16), payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x45, 0];
+    out.extend_from_slice(&(20 + 8 + payload.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+    out.extend_from_slice(&src.0);
+    out.extend_from_slice(&dst.0);
+    out.extend_from_slice(&src.1.to_be_bytes());
+    out.extend_from_slice(&dst.1.to_be_bytes());
+    out.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(payload);
+    out
+}
+
+/// Writes a pcap capture of raw IP packets, one per millisecond.
+fn write_pcap(name: &str, packets: &[Vec<u8>]) -> PathBuf {
+    let mut out = Vec::new();
+    out.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+    out.extend_from_slice(&[2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    out.extend_from_slice(&65535u32.to_le_bytes());
+    out.extend_from_slice(&101u32.to_le_bytes());
+    for (n, packet) in packets.iter().enumerate() {
+        out.extend_from_slice(&1_700_000_000u32.to_le_bytes());
+        out.extend_from_slice(&(n as u32 * 1000).to_le_bytes());
+        out.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        out.extend_from_slice(packet);
+    }
+
+    let path = std::env::temp_dir().join(format!("dnsr-replay-{}-{}.pcap", name, std::process::id()));
+    fs::write(&path, out).unwrap();
+    path
+}
+
+/// Replays `path` against `server`, returning the exit status and output.
+fn replay(path: &PathBuf, server: &Server, extra: &[&str]) -> (bool, String) {
+    wait_ready(server);
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .arg("replay")
+        .arg(path)
+        .args(["--server", &server.addr.to_string(), "--speed", "0"])
+        .args(extra)
+        .output()
+        .unwrap();
+    (output.status.success(), String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[test]
+fn captured_answers_are_compared() {
+    let client = ([10, 0, 0, 2], 40000);
+    let dns    = ([10, 0, 0, 1], 53);
+    let q      = query(7, "www.example.com", 1);
+    let path   = write_pcap("pcap", &[
+        packet(client, dns, &q),
+        packet(dns, client, &answer_a(&q, 7, [192, 0, 2, 1])),
+    ]);
+
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server(upstream);
+    let (ok, out) = replay(&path, &server, &[]);
+    assert!(ok, "{}", out);
+    assert!(out.contains("replayed 1 queries: 1 same, 0 different"), "{}", out);
+
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 99]));
+    let server   = spawn_server(upstream);
+    let (ok, out) = replay(&path, &server, &[]);
+    assert!(!ok);
+    assert!(out.contains("www.example.com A: expected"), "{}", out);
+    assert!(out.contains("192.0.2.99"), "{}", out);
+
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn logged_queries_are_compared_with_a_baseline() {
+    let path = std::env::temp_dir().join(format!("dnsr-replay-log-{}.log", std::process::id()));
+    fs::write(&path, concat!(
+        "1700000000 client=127.0.0.1:5000 qname=www.example.com qtype=A duration_ms=1200 trace=\n",
+        "1700000001 client=127.0.0.1:5000 qname=ads.example.net qtype=A duration_ms=1500 trace=\n",
+    )).unwrap();
+
+    // The candidate configuration blocks a name the baseline resolves
+    let upstream  = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let baseline  = spawn_server(upstream);
+    let candidate = spawn_server_with(upstream, &[("DNSR_BLOCKLIST", "ads.example.net")]);
+    wait_ready(&baseline);
+
+    let (ok, out) = replay(&path, &candidate, &["--baseline", &baseline.addr.to_string()]);
+
+    assert!(!ok);
+    assert!(out.contains("ads.example.net A: expected NOERROR"), "{}", out);
+    assert!(out.contains("got NXDOMAIN"), "{}", out);
+    assert!(out.contains("replayed 2 queries: 1 same, 1 different"), "{}", out);
+    let _ = fs::remove_file(path);
+}
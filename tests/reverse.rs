@@ -0,0 +1,48 @@
+mod common;
+
+use common::{
+    an_count, answer_records, encode_name, error_reply, exchange, id, qname, qtype, query,
+    spawn_server, spawn_upstream,
+};
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[test]
+fn classless_reverse_delegations_are_followed() {
+    // The /24 zone aliases each address into the /26 zone of the customer
+    // (RFC 2317), which holds the actual PTR records
+    let upstream = spawn_upstream(|q| match (qname(q).as_str(), qtype(q)) {
+        ("5.2.0.192.in-addr.arpa", 12) => {
+            answer_records(q, id(q), &[(5, encode_name("5.0/26.2.0.192.in-addr.arpa"))])
+        }
+        ("5.0/26.2.0.192.in-addr.arpa", 12) => {
+            answer_records(q, id(q), &[(12, encode_name("host.example.com"))])
+        }
+        _ => error_reply(q, 3),
+    });
+    let server = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(1, "5.2.0.192.in-addr.arpa", 12));
+
+    assert_eq!(an_count(&reply), 2);
+    assert!(contains(&reply, &encode_name("5.0/26.2.0.192.in-addr.arpa")));
+    assert!(contains(&reply, &encode_name("host.example.com")));
+}
+
+#[test]
+fn ip6_reverse_names_are_resolved() {
+    // 2001:db8::1, one nibble per label, least significant first
+    let name = "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa";
+    let upstream = spawn_upstream(move |q| match (qname(q).as_str(), qtype(q)) {
+        (n, 12) if n == name => answer_records(q, id(q), &[(12, encode_name("host.example.com"))]),
+        _ => error_reply(q, 3),
+    });
+    let server = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(1, name, 12));
+
+    assert_eq!(an_count(&reply), 1);
+    assert!(contains(&reply, &encode_name("host.example.com")));
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::{an_count, answer_a, exchange, id, query, question, spawn_server_with, spawn_upstream};
+use std::sync::{Arc, Mutex};
+
+/// Resolves a name with a seeded resolver, returning the ID and question
+/// of the query received upstream.
+fn upstream_query(seed: &str) -> (u16, Vec<u8>) {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log  = Arc::clone(&seen);
+    let upstream = spawn_upstream(move |q| {
+        log.lock().unwrap().push((id(q), question(q).to_vec()));
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+    let server = spawn_server_with(upstream, &[
+        ("DNSR_RNG_SEED", seed),
+        ("DNSR_USE_0X20", "true"),
+    ]);
+
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+
+    seen.lock().unwrap()[0].clone()
+}
+
+#[test]
+fn seeded_resolution_is_deterministic() {
+    let (first_id, first_question)   = upstream_query("42");
+    let (second_id, second_question) = upstream_query("42");
+
+    assert_eq!(first_id, second_id);
+    assert_eq!(first_question, second_question);
+}
+
+#[test]
+fn names_are_sent_with_randomized_case() {
+    let (_, question) = upstream_query("7");
+
+    let name = String::from_utf8_lossy(&question[..question.len() - 4]).to_string();
+    assert!(name.to_ascii_lowercase().contains("example"));
+    assert_ne!(name, name.to_ascii_lowercase());
+}
+
+#[test]
+fn query_ids_are_random() {
+    let ids      = Arc::new(Mutex::new(Vec::new()));
+    let log      = Arc::clone(&ids);
+    let upstream = spawn_upstream(move |q| {
+        log.lock().unwrap().push(id(q));
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+    let server = spawn_server_with(upstream, &[]);
+
+    for (n, name) in ["a.example.com", "b.example.com", "c.example.com", "d.example.com"].iter().enumerate() {
+        let reply = exchange(&server, &query(n as u16, name, 1));
+        assert_eq!(an_count(&reply), 1);
+    }
+
+    let mut ids = ids.lock().unwrap().clone();
+    ids.sort_unstable();
+    ids.dedup();
+    assert!(ids.len() > 1, "every query went out with the same ID");
+}
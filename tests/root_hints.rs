@@ -0,0 +1,77 @@
+mod common;
+
+use common::{answer_a, exchange, free_addr, id, query, spawn_server_with, spawn_upstream};
+use std::{
+    fs,
+    net::UdpSocket,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+/// Starts a root server mock on `ip` and `port`, answering every query,
+/// and counting them.
+fn spawn_root(ip: &str, port: u16, hits: Arc<AtomicUsize>) {
+    let sock = UdpSocket::bind((ip, port)).unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok((len, peer)) = sock.recv_from(&mut buf) {
+            hits.fetch_add(1, Ordering::SeqCst);
+            let _ = sock.send_to(&answer_a(&buf[..len], id(&buf[..len]), [192, 0, 2, 1]), peer);
+        }
+    });
+}
+
+#[test]
+fn resolutions_start_from_the_hinted_roots() {
+    let first  = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let port   = first.port();
+    let second = Arc::new(AtomicUsize::new(0));
+    let other  = Arc::new(AtomicUsize::new(0));
+    spawn_root("127.0.0.2", port, Arc::clone(&second));
+    spawn_root("127.0.0.3", port, Arc::clone(&other));
+
+    // An extract of named.root, with an address that isn't a root server's
+    let path = std::env::temp_dir().join(format!("dnsr-root-hints-{}", std::process::id()));
+    fs::write(&path, concat!(
+        ";       This file holds the information on root name servers\n",
+        ".                        3600000      NS    A.ROOT-SERVERS.NET.\n",
+        "A.ROOT-SERVERS.NET.      3600000      A     127.0.0.1\n",
+        "A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30\n",
+        ";\n",
+        ".                        3600000      NS    B.ROOT-SERVERS.NET.\n",
+        "B.ROOT-SERVERS.NET.      3600000  IN  A     127.0.0.2 ; comment\n",
+        "NOT-A-ROOT.NET.          3600000      A     127.0.0.3\n",
+    )).unwrap();
+
+    // The configured root is never asked
+    let server = spawn_server_with(free_addr(), &[
+        ("DNSR_ROOT_HINTS", path.to_str().unwrap()),
+        ("DNSR_DELEGATION_PORT", &port.to_string()),
+    ]);
+
+    for n in 0..16 {
+        let reply = exchange(&server, &query(n, &format!("host{}.example.com", n), 1));
+        assert!(reply.ends_with(&[192, 0, 2, 1]));
+    }
+    assert!(second.load(Ordering::SeqCst) > 0);
+    assert_eq!(other.load(Ordering::SeqCst), 0);
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn root_hints_without_addresses_are_rejected() {
+    let path = std::env::temp_dir().join(format!("dnsr-root-hints-empty-{}", std::process::id()));
+    fs::write(&path, ".  3600000  NS  A.ROOT-SERVERS.NET.\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .env("DNSR_LISTEN", free_addr().to_string())
+        .env("DNSR_ROOT_HINTS", &path)
+        .status()
+        .unwrap();
+
+    assert!(!status.success());
+    let _ = fs::remove_file(path);
+}
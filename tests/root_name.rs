@@ -0,0 +1,32 @@
+mod common;
+
+use common::{advertised_size, an_count, answer_records, encode_name, exchange, id, query, question, rcode, spawn_server, spawn_upstream, with_do};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn root_owned_records_round_trip() {
+    let seen  = Arc::new(Mutex::new(Vec::new()));
+    let saved = Arc::clone(&seen);
+    let root  = spawn_upstream(move |q| {
+        *saved.lock().unwrap() = q.to_vec();
+        answer_records(q, id(q), &[(2, encode_name("a.root-servers.net"))])
+    });
+    let server = spawn_server(root);
+
+    let reply = exchange(&server, &with_do(query(1, ".", 2)));
+
+    // The outgoing query names the root with a single zero byte, and its
+    // OPT record, owned by the root too, is well-formed
+    let sent = seen.lock().unwrap().clone();
+    assert_eq!(question(&sent), &[0, 0, 2, 0, 1]);
+    assert_eq!(advertised_size(&sent), Some(1232));
+    assert_eq!(sent.len(), 12 + 5 + 11);
+
+    // So are the question and the answer sent back to the client
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(question(&reply), &[0, 0, 2, 0, 1]);
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(reply[17], 0);
+    assert_eq!(&reply[18..20], &[0, 2]);
+    assert!(reply.ends_with(&encode_name("a.root-servers.net")));
+}
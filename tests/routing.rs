@@ -0,0 +1,25 @@
+mod common;
+
+use common::{answer_a, error_reply, exchange, id, query, rcode, spawn_server_with, spawn_upstream};
+
+#[test]
+fn domains_are_routed_per_rule() {
+    let root = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    // The forwarder only answers recursive queries
+    let forwarder = spawn_upstream(|q| match q[2] & 0x01 {
+        0 => error_reply(q, 5),
+        _ => answer_a(q, id(q), [198, 51, 100, 7]),
+    });
+    let rules = format!("*.bank.example=udp:{},ads.example=never", forwarder);
+    let server = spawn_server_with(root, &[("DNSR_DOMAIN_RULES", &rules)]);
+
+    let reply = exchange(&server, &query(1, "www.bank.example", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(&reply[reply.len() - 4..], &[198, 51, 100, 7]);
+
+    let reply = exchange(&server, &query(2, "www.example.com", 1));
+    assert_eq!(&reply[reply.len() - 4..], &[192, 0, 2, 1]);
+
+    let reply = exchange(&server, &query(3, "tracker.ads.example", 1));
+    assert_eq!(rcode(&reply), 5);
+}
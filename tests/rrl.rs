@@ -0,0 +1,49 @@
+mod common;
+
+use common::{an_count, exchange_tcp, id, query, spawn_server_with, truncated, wait_ready, Server};
+use std::{net::UdpSocket, time::Duration};
+
+/// Sends `packet` once, returning the reply if one comes.
+fn try_exchange(sock: &UdpSocket, server: &Server, packet: &[u8]) -> Option<Vec<u8>> {
+    let mut buf = [0u8; 4096];
+    sock.send_to(packet, server.addr).unwrap();
+    let (len, _) = sock.recv_from(&mut buf).ok()?;
+    Some(buf[..len].to_vec())
+}
+
+#[test]
+fn responses_over_the_rate_are_slipped_or_dropped() {
+    let server = spawn_server_with("127.0.0.1:9".parse().unwrap(), &[
+        ("DNSR_RRL_RATE", "1"),
+        ("DNSR_RRL_SLIP", "2"),
+    ]);
+    wait_ready(&server);
+
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sock.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+
+    // A burst of five seconds' worth is answered in full
+    for n in 0..5 {
+        let reply = try_exchange(&sock, &server, &query(n, "localhost", 1)).expect("a full answer");
+        assert_eq!(id(&reply), n);
+        assert_eq!(an_count(&reply), 1);
+        assert!(!truncated(&reply));
+    }
+
+    // Then one response in two is dropped, the other one truncated
+    assert!(try_exchange(&sock, &server, &query(5, "localhost", 1)).is_none());
+    let reply = try_exchange(&sock, &server, &query(6, "localhost", 1)).expect("a truncated answer");
+    assert_eq!(id(&reply), 6);
+    assert_eq!(an_count(&reply), 0);
+    assert!(truncated(&reply));
+
+    // The client told to retry gets the answer over TCP, which isn't limited
+    let reply = exchange_tcp(&server, &query(6, "localhost", 1));
+    assert_eq!(id(&reply), 6);
+    assert_eq!(an_count(&reply), 1);
+    assert!(!truncated(&reply));
+
+    // Other types are limited on their own
+    let reply = try_exchange(&sock, &server, &query(7, "localhost", 28)).expect("a full answer");
+    assert_eq!(an_count(&reply), 1);
+}
@@ -0,0 +1,138 @@
+#![cfg(feature = "sig0")]
+
+mod common;
+
+use common::{encode_name, exchange, id, rcode, spawn_server_with, wait_ready, Server};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::{
+    env, fs,
+    path::PathBuf,
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const ZONE: &str = "\
+$ORIGIN example.com.
+@    3600 IN SOA ns1 hostmaster 1 7200 3600 1209600 300
+@    3600 IN NS  ns1
+ns1   300 IN A   192.0.2.1
+";
+
+/// Seed of the Ed25519 key of the client.
+const SEED: [u8; 32] = [7; 32];
+
+/// Encodes `data` in base64, as the keys are configured.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for n in 0..4 {
+            match n <= chunk.len() {
+                true  => out.push(ALPHABET[(value >> (18 - 6 * n)) as usize & 63] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+/// Builds an UPDATE of `example.com` adding an A record to `www`, signed
+/// by the key `signer` made from `seed`.
+fn signed_update(id: u16, signer: &str, seed: &[u8; 32]) -> Vec<u8> {
+    let mut msg = Vec::new();
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&0x2800u16.to_be_bytes());
+    msg.extend_from_slice(&[0, 1, 0, 0, 0, 1, 0, 0]);
+    msg.extend_from_slice(&encode_name("example.com"));
+    msg.extend_from_slice(&[0, 6, 0, 1]);
+    msg.extend_from_slice(&encode_name("www.example.com"));
+    msg.extend_from_slice(&[0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 192, 0, 2, 80]);
+
+    // SIG(0): no covered type, Ed25519, valid from five minutes ago
+    let now       = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+    let mut rdata = vec![0, 0, 15, 0, 0, 0, 0, 0];
+    rdata.extend_from_slice(&(now + 300).to_be_bytes());
+    rdata.extend_from_slice(&(now - 300).to_be_bytes());
+    rdata.extend_from_slice(&[0, 0]);
+    rdata.extend_from_slice(&encode_name(signer));
+
+    let mut signed = rdata.clone();
+    signed.extend_from_slice(&msg);
+    let key = Ed25519KeyPair::from_seed_unchecked(seed).unwrap();
+    rdata.extend_from_slice(key.sign(&signed).as_ref());
+
+    msg[11] = 1;
+    msg.push(0);
+    msg.extend_from_slice(&[0, 24, 0, 255, 0, 0, 0, 0]);
+    msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    msg.extend_from_slice(&rdata);
+    msg
+}
+
+/// Spawns a primary of `example.com` updated with the key of `SEED`,
+/// allowed to update `scope`, by no client unless it signs its updates,
+/// returning the path of its master file.
+fn spawn_primary(name: &str, scope: &str) -> (Server, PathBuf) {
+    let path = env::temp_dir().join(format!("dnsr-sig0-{}-{}", name, process::id()));
+    fs::write(&path, ZONE).unwrap();
+
+    let public = Ed25519KeyPair::from_seed_unchecked(&SEED).unwrap().public_key().as_ref().to_vec();
+    let zones  = format!("example.com={}", path.display());
+    let keys   = format!("client.example.com:15:{}:{}", base64(&public), scope);
+    let server = spawn_server_with("127.0.0.1:9".parse().unwrap(), &[
+        ("DNSR_ZONES",        &zones),
+        ("DNSR_ALLOW_UPDATE", "192.0.2.0/24"),
+        ("DNSR_UPDATE_KEYS",  &keys),
+    ]);
+    wait_ready(&server);
+    (server, path)
+}
+
+#[test]
+fn signed_updates_are_applied() {
+    let (server, path) = spawn_primary("signed", "example.org/example.com");
+
+    let reply = exchange(&server, &signed_update(0x5b01, "client.example.com", &SEED));
+    let text  = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(id(&reply), 0x5b01);
+    assert_eq!(rcode(&reply), 0);
+    assert!(text.contains("www.example.com. 300 IN A 192.0.2.80\n"), "{}", text);
+}
+
+#[test]
+fn badly_signed_updates_are_rejected() {
+    let (server, path) = spawn_primary("rejected", "example.com");
+
+    // Signed with another key
+    let reply = exchange(&server, &signed_update(0x5b02, "client.example.com", &[8; 32]));
+    assert_eq!(rcode(&reply), 9);
+
+    // Signed by an unknown key
+    let reply = exchange(&server, &signed_update(0x5b03, "other.example.com", &SEED));
+    assert_eq!(rcode(&reply), 9);
+
+    // Changed after it was signed
+    let mut update = signed_update(0x5b04, "client.example.com", &SEED);
+    update[59] = 81;
+    let reply = exchange(&server, &update);
+    assert_eq!(rcode(&reply), 9);
+
+    let text = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(text, ZONE);
+}
+
+#[test]
+fn signed_updates_outside_the_scope_of_their_key_are_refused() {
+    let (server, path) = spawn_primary("scope", "example.org");
+
+    let reply = exchange(&server, &signed_update(0x5b05, "client.example.com", &SEED));
+    let text  = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(id(&reply), 0x5b05);
+    assert_eq!(rcode(&reply), 5);
+    assert_eq!(text, ZONE);
+}
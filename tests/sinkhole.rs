@@ -0,0 +1,52 @@
+mod common;
+
+use common::{exchange, free_addr, query, spawn_server_with};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+/// Returns a localhost TCP address that is currently free.
+fn free_tcp_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+/// Requests the page for `host` once the server accepts connections,
+/// returning the whole response.
+fn get(addr: SocketAddr, host: &str) -> String {
+    for _ in 0..50 {
+        let Ok(mut stream) = TcpStream::connect(addr) else {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        };
+        write!(stream, "GET /ads.js HTTP/1.1\r\nHost: {}\r\n\r\n", host).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        return response;
+    }
+    panic!("no sinkhole page at {}", addr);
+}
+
+#[test]
+fn the_page_tells_why_a_name_was_blocked() {
+    let page   = free_tcp_addr();
+    let server = spawn_server_with(free_addr(), &[
+        ("DNSR_BLOCKLIST", "ads.example.net"),
+        ("DNSR_SINKHOLE", "127.0.0.1"),
+        ("DNSR_SINKHOLE_LISTEN", &page.to_string()),
+    ]);
+    exchange(&server, &query(1, "Tracker.Ads.example.net", 1));
+
+    let response = get(page, "tracker.ads.example.net:80");
+    assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+    assert!(response.contains("<h1>tracker.ads.example.net was blocked</h1>"), "{}", response);
+    assert!(response.contains("blocked: blocklist (rule ads.example.net)"), "{}", response);
+
+    // Names not blocked recently get the page without a reason, and the
+    // name is escaped
+    let response = get(page, "<b>.example.org");
+    assert!(response.contains("&lt;b&gt;.example.org was blocked"), "{}", response);
+    assert!(!response.contains("rule"), "{}", response);
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::{answer_a, exchange, id, query, spawn_server_with, spawn_upstream};
+use std::{
+    env, fs,
+    path::Path,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+/// Reads the slow log at `path` once it holds `count` lines, which are
+/// written in the background.
+fn read_log(path: &Path, count: usize) -> String {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let log = fs::read_to_string(path).unwrap_or_default();
+        if log.lines().count() >= count {
+            return log;
+        }
+        assert!(Instant::now() < deadline, "the slow log holds {:?}", log);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn slow_queries_are_logged_with_their_trace() {
+    let path = env::temp_dir().join(format!("dnsr-slow-{}.log", process::id()));
+    let _ = fs::remove_file(&path);
+
+    let upstream = spawn_upstream(|q| {
+        thread::sleep(Duration::from_millis(50));
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+    let server = spawn_server_with(upstream, &[
+        ("DNSR_SLOW_QUERY_MS", "20"),
+        ("DNSR_SLOW_LOG", path.to_str().unwrap()),
+    ]);
+
+    // The second query is answered from the cache, below the threshold
+    exchange(&server, &query(1, "slow.example.com", 1));
+    exchange(&server, &query(2, "slow.example.com", 1));
+
+    let log = read_log(&path, 1);
+    let _ = fs::remove_file(&path);
+
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", log);
+    assert!(lines[0].contains("qname=slow.example.com"));
+    assert!(lines[0].contains(&format!("trace=slow.example.com@{}", upstream)));
+}
+
+#[test]
+fn unusual_bytes_of_names_are_escaped() {
+    let path = env::temp_dir().join(format!("dnsr-slow-escaped-{}.log", process::id()));
+    let _ = fs::remove_file(&path);
+
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server_with(upstream, &[
+        ("DNSR_SLOW_QUERY_MS", "0"),
+        ("DNSR_SLOW_LOG", path.to_str().unwrap()),
+    ]);
+
+    // A forged log line, and a byte that isn't UTF-8
+    let mut request = query(1, "evil\n1 client=x qname=forged.example.com", 1);
+    let reply       = exchange(&server, &request);
+    assert_eq!(&reply[12..request.len()], &request[12..]);
+
+    request = query(2, "x.example.com", 1);
+    request[13] = 0xFF;
+    let reply = exchange(&server, &request);
+    assert_eq!(&reply[12..request.len()], &request[12..]);
+
+    // The queries are logged once answered
+    let log = read_log(&path, 2);
+    let _ = fs::remove_file(&path);
+
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", log);
+    assert!(lines[0].contains(r"qname=evil\0101\032client=x\032qname=forged.example.com "), "{}", log);
+    assert!(lines[1].contains(r"qname=\255.example.com "), "{}", log);
+}
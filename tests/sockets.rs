@@ -0,0 +1,67 @@
+mod common;
+
+use common::{answer_a, exchange, id, query, spawn_scripted_upstream, spawn_server_with, Server};
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Starts an upstream answering every query, and recording the ports they
+/// came from.
+fn spawn_recording_upstream() -> (SocketAddr, Arc<Mutex<Vec<u16>>>) {
+    let ports    = Arc::new(Mutex::new(Vec::new()));
+    let seen     = Arc::clone(&ports);
+    let upstream = spawn_scripted_upstream(move |sock, q, peer| {
+        seen.lock().unwrap().push(peer.port());
+        sock.send_to(&answer_a(q, id(q), [192, 0, 2, 1]), peer).unwrap();
+    });
+    (upstream, ports)
+}
+
+/// Resolves `count` distinct names, waiting `pause` between them.
+fn resolve(server: &Server, count: usize, pause: Duration) {
+    for n in 0..count {
+        exchange(server, &query(n as u16, &format!("host{}.example.com", n), 1));
+        thread::sleep(pause);
+    }
+}
+
+#[test]
+fn queries_share_the_pooled_sockets() {
+    let (upstream, ports) = spawn_recording_upstream();
+    let server = spawn_server_with(upstream, &[("DNSR_OUTGOING_SOCKETS", "2")]);
+
+    resolve(&server, 10, Duration::ZERO);
+
+    let ports = ports.lock().unwrap();
+    assert_eq!(ports.len(), 10);
+    assert!(ports.iter().collect::<HashSet<_>>().len() <= 2, "{:?}", ports);
+}
+
+#[test]
+fn pooled_sockets_move_to_other_ports() {
+    let (upstream, ports) = spawn_recording_upstream();
+    let server = spawn_server_with(upstream, &[
+        ("DNSR_OUTGOING_SOCKETS", "1"),
+        ("DNSR_OUTGOING_SOCKET_LIFETIME", "1"),
+    ]);
+
+    resolve(&server, 3, Duration::from_millis(1100));
+
+    let ports = ports.lock().unwrap();
+    assert_eq!(ports.iter().collect::<HashSet<_>>().len(), 3, "{:?}", ports);
+}
+
+#[test]
+fn queries_can_have_sockets_of_their_own() {
+    let (upstream, ports) = spawn_recording_upstream();
+    let server = spawn_server_with(upstream, &[("DNSR_OUTGOING_SOCKETS", "0")]);
+
+    resolve(&server, 5, Duration::ZERO);
+
+    let ports = ports.lock().unwrap();
+    assert_eq!(ports.iter().collect::<HashSet<_>>().len(), 5, "{:?}", ports);
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{an_count, answer_a, exchange, free_addr, id, query, rcode, spawn_server, spawn_server_with, spawn_upstream};
+
+#[test]
+fn localhost_is_answered_with_loopback() {
+    // No upstream is needed, the names are answered locally
+    let server = spawn_server(free_addr());
+
+    let reply = exchange(&server, &query(1, "localhost", 1));
+    assert_eq!(id(&reply), 1);
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(&reply[reply.len() - 4..], &[127, 0, 0, 1]);
+
+    let reply = exchange(&server, &query(2, "app.LOCALHOST.", 28));
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(&reply[reply.len() - 16..], &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+}
+
+#[test]
+fn special_use_names_are_nxdomain() {
+    let server = spawn_server(free_addr());
+
+    for (i, name) in ["example.invalid", "test", "www.example.test", "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion", "printer.local"].iter().enumerate() {
+        let reply = exchange(&server, &query(i as u16, name, 1));
+        assert_eq!(rcode(&reply), 3, "{}", name);
+        assert_eq!(an_count(&reply), 0);
+    }
+
+    // The diagnostic names live under .local too
+    let reply = exchange(&server, &query(9, "whoami.resolver.local", 1));
+    assert_eq!(rcode(&reply), 0);
+}
+
+#[test]
+fn local_names_are_resolved_with_mdns() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server_with(upstream, &[("DNSR_MDNS", "true")]);
+
+    let reply = exchange(&server, &query(1, "printer.local", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 1);
+}
+
+#[test]
+fn private_reverse_lookups_stay_local() {
+    // Private reverse zones are never sent to the public DNS
+    let server = spawn_server(free_addr());
+
+    let private = [
+        "1.0.0.10.in-addr.arpa",
+        "5.1.20.172.in-addr.arpa",
+        "168.192.in-addr.arpa",
+        "7.3.254.169.in-addr.arpa",
+        "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.e.f.ip6.arpa",
+        "d.f.ip6.arpa",
+    ];
+    for (i, name) in private.iter().enumerate() {
+        let reply = exchange(&server, &query(i as u16, name, 12));
+        assert_eq!(rcode(&reply), 3, "{}", name);
+    }
+}
+
+#[test]
+fn private_reverse_lookups_use_local_records() {
+    let server = spawn_server_with(free_addr(), &[
+        ("DNSR_LOCAL_RECORDS",  "nas.lan=192.168.1.20"),
+        ("DNSR_SYNTHESIZE_PTR", "true"),
+    ]);
+
+    let reply = exchange(&server, &query(1, "20.1.168.192.in-addr.arpa", 12));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 1);
+}
+
+#[test]
+fn public_reverse_lookups_are_resolved() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server(upstream);
+
+    // 172.32.0.0 is just outside of 172.16.0.0/12
+    let reply = exchange(&server, &query(1, "1.0.32.172.in-addr.arpa", 1));
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(an_count(&reply), 1);
+}
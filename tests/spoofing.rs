@@ -0,0 +1,223 @@
+//! Off-path spoofing attempts against the upstream queries of the resolver.
+//!
+//! The mock upstreams play both the genuine server and the attacker, who is
+//! given the best odds: it learns the source port of every query, and its
+//! forged packets reach the resolver before the genuine response.
+
+mod common;
+
+use common::{
+    an_count, answer_a, answer_records, encode_name, exchange, id, qname, query, question, send, spawn_scripted_upstream,
+    spawn_server, spawn_server_with, spawn_upstream, wait_ready,
+};
+use std::{
+    net::UdpSocket,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+/// Address of the genuine records.
+const GOOD: [u8; 4] = [192, 0, 2, 1];
+
+/// Address the forged records point to.
+const EVIL: [u8; 4] = [203, 0, 113, 66];
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Appends an A record owned by `name` to the answers of `reply`, which
+/// must have no authority nor additional records.
+fn with_answer(mut reply: Vec<u8>, name: &str, ip: [u8; 4]) -> Vec<u8> {
+    let count = an_count(&reply) + 1;
+    reply[6..8].copy_from_slice(&count.to_be_bytes());
+    reply.extend(encode_name(name));
+    reply.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0x0E, 0x10, 0, 4]);
+    reply.extend_from_slice(&ip);
+    reply
+}
+
+/// Builds a referral answering `query`, delegating `zone` to `server`,
+/// whose address comes as glue.
+fn delegation(query: &[u8], zone: &str, server: &str, glue: [u8; 4]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id(query).to_be_bytes());
+    out.extend_from_slice(&0x8000u16.to_be_bytes());
+    out.extend_from_slice(&[0, 1, 0, 0, 0, 1, 0, 1]);
+    out.extend_from_slice(question(query));
+
+    let rdata = encode_name(server);
+    out.extend(encode_name(zone));
+    out.extend_from_slice(&[0, 2, 0, 1, 0, 2, 0xA3, 0]);
+    out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    out.extend(rdata);
+
+    out.extend(encode_name(server));
+    out.extend_from_slice(&[0, 1, 0, 1, 0, 2, 0xA3, 0, 0, 4]);
+    out.extend_from_slice(&glue);
+    out
+}
+
+/// Sends a query for `name` that isn't expected to be answered, leaving
+/// the server the time to resolve it.
+fn ask(server: &common::Server, name: &str) {
+    wait_ready(server);
+    send(server, &query(1, name, 1));
+    thread::sleep(Duration::from_secs(1));
+}
+
+/// Resolves `name` through a server whose upstream sends `forged` right
+/// before the genuine response, and checks that the genuine one is used.
+fn assert_forged_ignored<F>(name: &str, forged: F)
+where
+    F: Fn(&UdpSocket, &[u8], std::net::SocketAddr) + Send + 'static,
+{
+    let upstream = spawn_scripted_upstream(move |sock, q, peer| {
+        forged(sock, q, peer);
+        thread::sleep(Duration::from_millis(50));
+        let _ = sock.send_to(&answer_a(q, id(q), GOOD), peer);
+    });
+    let server = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(1, name, 1));
+
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&GOOD));
+    assert!(!contains(&reply, &EVIL));
+}
+
+#[test]
+fn responses_with_another_id_are_ignored() {
+    assert_forged_ignored("www.example.com", |sock, q, peer| {
+        let _ = sock.send_to(&answer_a(q, id(q) ^ 0x5555, EVIL), peer);
+    });
+}
+
+#[test]
+fn responses_from_another_address_are_ignored() {
+    assert_forged_ignored("www.example.com", |_, q, peer| {
+        let attacker = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let _ = attacker.send_to(&answer_a(q, id(q), EVIL), peer);
+    });
+}
+
+#[test]
+fn responses_to_another_question_are_ignored() {
+    assert_forged_ignored("www.example.com", |sock, q, peer| {
+        let other = query(id(q), "www.bank.example", 1);
+        let _ = sock.send_to(&answer_a(&other, id(q), EVIL), peer);
+    });
+}
+
+#[test]
+fn floods_of_forged_responses_are_ignored() {
+    // Kaminsky-style: a flood of guesses for every query of a random name,
+    // hoping that one of them matches
+    assert_forged_ignored("x7f3q.example.com", |sock, q, peer| {
+        let attacker = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for guess in 1..=1000u16 {
+            let forged = with_answer(answer_a(q, id(q).wrapping_add(guess), EVIL), "www.example.com", EVIL);
+            let _ = sock.send_to(&forged, peer);
+        }
+        let _ = attacker.send_to(&answer_a(q, id(q), EVIL), peer);
+    });
+}
+
+#[test]
+fn answers_for_other_names_are_ignored() {
+    let upstream = spawn_upstream(|q| with_answer(answer_a(q, id(q), GOOD), "www.bank.example", EVIL));
+    let server   = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&GOOD));
+    assert!(!contains(&reply, &EVIL));
+}
+
+#[test]
+fn delegations_within_bailiwick_are_followed() {
+    // The root delegates com, whose server delegates example.com: both
+    // are the same mock, on the port the delegations are followed to
+    let queries = Arc::new(AtomicUsize::new(0));
+    let seen    = Arc::clone(&queries);
+    let tld = spawn_upstream(move |q| match seen.fetch_add(1, Ordering::SeqCst) {
+        0 => delegation(q, "example.com", "ns.example.com", [127, 0, 0, 1]),
+        _ => answer_a(q, id(q), GOOD),
+    });
+    let root   = spawn_upstream(|q| delegation(q, "com", "a.gtld.com", [127, 0, 0, 1]));
+    let port   = tld.port().to_string();
+    let server = spawn_server_with(root, &[("DNSR_DELEGATION_PORT", &port)]);
+
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+
+    assert!(reply.ends_with(&GOOD));
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn delegations_off_the_queried_name_are_ignored() {
+    let queries = Arc::new(AtomicUsize::new(0));
+    let seen    = Arc::clone(&queries);
+    let attacker = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        answer_a(q, id(q), EVIL)
+    });
+    let root   = spawn_upstream(|q| delegation(q, "bank.example", "ns.bank.example", [127, 0, 0, 1]));
+    let port   = attacker.port().to_string();
+    let server = spawn_server_with(root, &[("DNSR_DELEGATION_PORT", &port)]);
+
+    ask(&server, "www.example.com");
+
+    assert_eq!(queries.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn glue_outside_the_zone_is_ignored() {
+    // The server of com gives the address of a name server under net,
+    // which it has no authority over
+    let queries = Arc::new(AtomicUsize::new(0));
+    let seen    = Arc::clone(&queries);
+    let tld = spawn_upstream(move |q| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        delegation(q, "example.com", "ns.example.net", [127, 0, 0, 1])
+    });
+    let root = spawn_upstream(|q| match qname(q).as_str() {
+        "www.example.com" => delegation(q, "com", "a.gtld.com", [127, 0, 0, 1]),
+        _                 => answer_records(q, id(q), &[]),
+    });
+    let port   = tld.port().to_string();
+    let server = spawn_server_with(root, &[("DNSR_DELEGATION_PORT", &port)]);
+
+    ask(&server, "www.example.com");
+
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn aliases_leaving_the_zone_are_resolved_again() {
+    // The server of example.com aliases the name to one under net, whose
+    // address it has no authority over
+    let queries = Arc::new(AtomicUsize::new(0));
+    let seen    = Arc::clone(&queries);
+    let tld = spawn_upstream(move |q| match seen.fetch_add(1, Ordering::SeqCst) {
+        0 => delegation(q, "example.com", "ns.example.com", [127, 0, 0, 1]),
+        _ => with_answer(answer_records(q, id(q), &[(5, encode_name("www.example.net"))]), "www.example.net", EVIL),
+    });
+    let root = spawn_upstream(|q| match qname(q).as_str() {
+        "www.example.net" => answer_a(q, id(q), GOOD),
+        _                 => delegation(q, "com", "a.gtld.com", [127, 0, 0, 1]),
+    });
+    let port   = tld.port().to_string();
+    let server = spawn_server_with(root, &[("DNSR_DELEGATION_PORT", &port)]);
+
+    let reply = exchange(&server, &query(1, "www.example.com", 1));
+
+    assert_eq!(an_count(&reply), 2);
+    assert!(reply.ends_with(&GOOD));
+    assert!(!contains(&reply, &EVIL));
+}
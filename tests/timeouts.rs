@@ -0,0 +1,89 @@
+mod common;
+
+use common::{an_count, answer_a, id, query, spawn_server_with, spawn_upstream, wait_ready, Server};
+use std::{
+    net::UdpSocket,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Sends `packet` to the server once, and waits up to five seconds for
+/// its reply, so that the resolution isn't started again meanwhile.
+fn exchange_once(server: &Server, packet: &[u8]) -> Option<Vec<u8>> {
+    wait_ready(server);
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    sock.send_to(packet, server.addr).unwrap();
+    let mut buf = [0u8; 4096];
+    sock.recv_from(&mut buf).ok().map(|(len, _)| buf[..len].to_vec())
+}
+
+#[test]
+fn zones_can_be_given_a_longer_timeout() {
+    // Slower than the two seconds waited by default
+    let upstream = spawn_upstream(|q| {
+        thread::sleep(Duration::from_millis(2500));
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+    let server = spawn_server_with(upstream, &[("DNSR_TIMEOUT_RULES", "slow.example=4000")]);
+
+    let reply = exchange_once(&server, &query(1, "www.slow.example", 1)).expect("no reply");
+
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+}
+
+#[test]
+fn upstreams_can_be_given_retries() {
+    // The first three queries are lost, as many as are sent by default
+    // while shrinking the advertised UDP payload size
+    let queries  = Arc::new(AtomicUsize::new(0));
+    let seen     = Arc::clone(&queries);
+    let upstream = spawn_upstream(move |q| match seen.fetch_add(1, Ordering::SeqCst) {
+        0..=2 => Vec::new(),
+        _     => answer_a(q, id(q), [192, 0, 2, 1]),
+    });
+    let rule   = format!("udp:{}=500/1", upstream);
+    let server = spawn_server_with(upstream, &[
+        ("DNSR_DOMAIN_RULES", &format!(".=udp:{}", upstream)),
+        ("DNSR_TIMEOUT_RULES", &rule),
+    ]);
+
+    let reply = exchange_once(&server, &query(1, "www.example.com", 1)).expect("no reply");
+
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(queries.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn retries_wait_longer_and_longer() {
+    // The first three queries are lost: the one advertising the smallest
+    // payload size, the same without EDNS, and the first retry
+    let arrivals = Arc::new(Mutex::new(Vec::new()));
+    let seen     = Arc::clone(&arrivals);
+    let upstream = spawn_upstream(move |q| {
+        let mut arrivals = seen.lock().unwrap();
+        arrivals.push(Instant::now());
+        match arrivals.len() {
+            1..=3 => Vec::new(),
+            _     => answer_a(q, id(q), [192, 0, 2, 1]),
+        }
+    });
+    let server = spawn_server_with(upstream, &[
+        ("DNSR_MAX_UDP_SIZE", "512"),
+        ("DNSR_QUERY_TIMEOUT", "300/2"),
+    ]);
+
+    let reply = exchange_once(&server, &query(1, "www.example.com", 1)).expect("no reply");
+    assert_eq!(an_count(&reply), 1);
+
+    let arrivals = arrivals.lock().unwrap();
+    let gaps: Vec<Duration> = arrivals.windows(2).map(|w| w[1] - w[0]).collect();
+    assert_eq!(gaps.len(), 3);
+    assert!(gaps[0] < Duration::from_millis(500), "{:?}", gaps);
+    assert!(gaps[2] >= Duration::from_millis(550), "{:?}", gaps);
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "zone-transfers")]
+
+mod common;
+
+use common::{answer_records, encode_name, id, spawn_server_with, Server};
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    process::{self, Command, Output},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Returns the data of the SOA record of `example.com`.
+fn soa(serial: u32) -> Vec<u8> {
+    let mut rdata = encode_name("ns1.example.com");
+    rdata.extend_from_slice(&encode_name("hostmaster.example.com"));
+    for value in [serial, 7200, 3600, 1209600, 300] {
+        rdata.extend_from_slice(&value.to_be_bytes());
+    }
+    rdata
+}
+
+/// Spawns a primary server answering a single zone transfer with the
+/// given messages, as (type, rdata) records owned by the zone apex.
+fn spawn_primary(messages: Vec<Vec<(u16, Vec<u8>)>>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr     = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut length = [0u8; 2];
+        stream.read_exact(&mut length).unwrap();
+        let mut query = vec![0u8; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut query).unwrap();
+
+        for records in messages {
+            let reply = answer_records(&query, id(&query), &records);
+            stream.write_all(&(reply.len() as u16).to_be_bytes()).unwrap();
+            stream.write_all(&reply).unwrap();
+        }
+    });
+    addr
+}
+
+/// Transfers `example.com` from `primary` with the `transfer` subcommand.
+fn transfer(primary: SocketAddr) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .args(["transfer", "example.com", &primary.to_string()])
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn zones_are_transferred_from_their_primary() {
+    let primary = spawn_primary(vec![
+        vec![(6, soa(42)), (2, encode_name("ns1.example.com")), (1, vec![192, 0, 2, 1])],
+        vec![(1, vec![192, 0, 2, 2]), (6, soa(42))],
+    ]);
+
+    let output = transfer(primary);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("example.com 3600 A 2 records"), "{}", stdout);
+    assert!(stdout.contains("example.com: serial 42, 4 records in 3 sets"), "{}", stdout);
+}
+
+#[test]
+fn incomplete_transfers_are_rejected() {
+    let primary = spawn_primary(vec![vec![(6, soa(42)), (1, vec![192, 0, 2, 1])]]);
+    assert!(!transfer(primary).status.success());
+
+    let primary = spawn_primary(vec![vec![(1, vec![192, 0, 2, 1]), (6, soa(42))]]);
+    assert!(!transfer(primary).status.success());
+}
+
+/// Spawns the resolver serving the transfers of a zone with many records
+/// to `allow`, and waits for its TCP socket to accept connections.
+fn spawn_transfer_server(name: &str, allow: &str) -> (Server, SocketAddr) {
+    let mut text = "@ 3600 IN SOA ns1 hostmaster 7 7200 3600 1209600 300\n@ IN NS ns1\n".to_string();
+    for n in 0..1000 {
+        text.push_str(&format!("host{} 300 IN A 192.0.2.{}\n", n, n % 256));
+    }
+    let path = env::temp_dir().join(format!("dnsr-zone-{}-{}", name, process::id()));
+    fs::write(&path, text).unwrap();
+
+    let listen = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let zones  = format!("example.com={}", path.display());
+    let server = spawn_server_with("127.0.0.1:9".parse().unwrap(), &[
+        ("DNSR_ZONES",           &zones),
+        ("DNSR_TRANSFER_LISTEN", &listen.to_string()),
+        ("DNSR_ALLOW_TRANSFER",  allow),
+    ]);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while TcpStream::connect(listen).is_err() {
+        assert!(Instant::now() < deadline, "the transfer socket never opened");
+        thread::sleep(Duration::from_millis(20));
+    }
+    (server, listen)
+}
+
+#[test]
+fn zones_are_transferred_to_allowed_secondaries() {
+    let (_server, listen) = spawn_transfer_server("served", "10.0.0.0/8, 127.0.0.0/8");
+
+    let output = transfer(listen);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("example.com 3600 NS 1 records"), "{}", stdout);
+    assert!(stdout.contains("host999.example.com 300 A 1 records"), "{}", stdout);
+    assert!(stdout.contains("example.com: serial 7, 1002 records in 1002 sets"), "{}", stdout);
+}
+
+#[test]
+fn zone_transfers_are_refused_to_other_clients() {
+    let (_server, listen) = spawn_transfer_server("refused", "192.0.2.0/24");
+
+    let output = transfer(listen);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("response code 5"));
+}
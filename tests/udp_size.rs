@@ -0,0 +1,212 @@
+mod common;
+
+use common::{
+    advertised_size, an_count, answer_a, answer_many, error_reply, exchange, exchange_tcp, id, query, spawn_server,
+    spawn_server_with, spawn_tcp_upstream, spawn_upstream, truncated, wait_ready, with_do,
+};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[test]
+fn outgoing_queries_advertise_the_configured_size() {
+    let sizes = Arc::new(Mutex::new(Vec::new()));
+    let seen  = Arc::clone(&sizes);
+    let upstream = spawn_upstream(move |q| {
+        seen.lock().unwrap().push(advertised_size(q));
+        answer_a(q, id(q), [192, 0, 2, 1])
+    });
+    let server = spawn_server_with(upstream, &[("DNSR_MAX_UDP_SIZE", "1400")]);
+
+    exchange(&server, &query(1, "www.example.com", 1));
+
+    assert_eq!(*sizes.lock().unwrap(), vec![Some(1400)]);
+}
+
+#[test]
+fn timeouts_with_large_sizes_downgrade_the_server() {
+    // The upstream never answers queries advertising more than 512 bytes,
+    // as if large fragmented responses were lost on the way back
+    let sizes = Arc::new(Mutex::new(Vec::new()));
+    let seen  = Arc::clone(&sizes);
+    let upstream = spawn_upstream(move |q| {
+        let size = advertised_size(q).unwrap_or(512);
+        seen.lock().unwrap().push(size);
+        if size > 512 { Vec::new() } else { answer_a(q, id(q), [192, 0, 2, 1]) }
+    });
+    let server = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(1, "one.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+
+    // The reduced size is remembered for the following queries
+    sizes.lock().unwrap().clear();
+    exchange(&server, &query(2, "two.example.com", 1));
+    assert_eq!(*sizes.lock().unwrap(), vec![512]);
+}
+
+#[test]
+fn oversized_responses_are_truncated() {
+    let ips: Vec<[u8; 4]> = (0..30).map(|i| [192, 0, 2, i]).collect();
+    let upstream = spawn_upstream(move |q| answer_many(q, id(q), &ips));
+    let server   = spawn_server(upstream);
+
+    // Without EDNS the client can only receive 512 bytes...
+    let reply = exchange(&server, &query(1, "big.example.com", 1));
+    assert!(truncated(&reply));
+    assert_eq!(an_count(&reply), 0);
+
+    // ...while with EDNS the whole answer fits
+    let reply = exchange(&server, &with_do(query(2, "big.example.com", 1)));
+    assert!(!truncated(&reply));
+    assert_eq!(an_count(&reply), 30);
+}
+
+#[test]
+fn truncated_responses_are_answered_whole_over_tcp() {
+    let ips: Vec<[u8; 4]> = (0..30).map(|i| [192, 0, 2, i]).collect();
+    let upstream = spawn_upstream(move |q| answer_many(q, id(q), &ips));
+    let server   = spawn_server(upstream);
+    wait_ready(&server);
+
+    let reply = exchange(&server, &query(1, "big.example.com", 1));
+    assert!(truncated(&reply));
+
+    // The client retries over TCP, on the same address
+    let reply = exchange_tcp(&server, &query(2, "big.example.com", 1));
+    assert_eq!(id(&reply), 2);
+    assert!(!truncated(&reply));
+    assert_eq!(an_count(&reply), 30);
+}
+
+#[test]
+fn truncated_upstream_responses_are_asked_again_over_tcp() {
+    // Over UDP the upstream only has room for part of the answer
+    let upstream = spawn_upstream(|q| {
+        let mut reply = answer_a(q, id(q), [192, 0, 2, 9]);
+        reply[2] |= 0x02;
+        reply
+    });
+    let ips: Vec<[u8; 4]> = (0..3).map(|i| [192, 0, 2, i]).collect();
+    spawn_tcp_upstream(upstream, move |q| answer_many(q, id(q), &ips));
+    let server = spawn_server(upstream);
+
+    let reply = exchange(&server, &with_do(query(1, "big.example.com", 1)));
+    assert!(!truncated(&reply));
+    assert_eq!(an_count(&reply), 3);
+    assert!(!reply.windows(4).any(|w| w == [192, 0, 2, 9]));
+}
+
+#[test]
+fn truncated_replies_are_relayed_whole_in_proxy_mode() {
+    let upstream = spawn_upstream(|q| {
+        let mut reply = answer_a(q, id(q), [192, 0, 2, 9]);
+        reply[2] |= 0x02;
+        reply
+    });
+    let ips: Vec<[u8; 4]> = (0..30).map(|i| [192, 0, 2, i]).collect();
+    spawn_tcp_upstream(upstream, move |q| answer_many(q, id(q), &ips));
+    let server = spawn_server_with(upstream, &[("DNSR_PROXY", &upstream.to_string())]);
+
+    // Too large for a client without EDNS, which gets it over TCP
+    let reply = exchange(&server, &query(1, "big.example.com", 1));
+    assert!(truncated(&reply));
+    assert_eq!(an_count(&reply), 0);
+    let reply = exchange_tcp(&server, &query(2, "big.example.com", 1));
+    assert!(!truncated(&reply));
+    assert_eq!(an_count(&reply), 30);
+}
+
+#[test]
+fn edns_options_are_skipped_over() {
+    let ips: Vec<[u8; 4]> = (0..30).map(|i| [192, 0, 2, i]).collect();
+    let upstream = spawn_upstream(move |q| answer_many(q, id(q), &ips));
+    let server   = spawn_server(upstream);
+
+    // A client cookie (RFC 7873) follows the advertised size of 4096 bytes
+    let mut query = query(1, "big.example.com", 1);
+    query[11] += 1;
+    query.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 12]);
+    query.extend_from_slice(&[0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+    let reply = exchange(&server, &query);
+    assert_eq!(id(&reply), 1);
+    assert!(!truncated(&reply));
+    assert_eq!(an_count(&reply), 30);
+}
+
+#[test]
+fn servers_rejecting_edns_are_queried_without_it() {
+    // The upstream predates EDNS, and answers FORMERR to queries with an
+    // OPT record
+    let sizes = Arc::new(Mutex::new(Vec::new()));
+    let seen  = Arc::clone(&sizes);
+    let upstream = spawn_upstream(move |q| {
+        seen.lock().unwrap().push(advertised_size(q));
+        match advertised_size(q) {
+            Some(_) => error_reply(q, 1),
+            None    => answer_a(q, id(q), [192, 0, 2, 1]),
+        }
+    });
+    let server = spawn_server(upstream);
+
+    let reply = exchange(&server, &query(1, "one.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+    assert_eq!(*sizes.lock().unwrap(), vec![Some(1232), None]);
+
+    // The server is remembered as not supporting EDNS
+    sizes.lock().unwrap().clear();
+    exchange(&server, &query(2, "two.example.com", 1));
+    assert_eq!(*sizes.lock().unwrap(), vec![None]);
+}
+
+#[test]
+fn servers_dropping_edns_are_queried_without_it() {
+    // The upstream, or a middlebox in front of it, drops every query with
+    // an OPT record
+    let sizes = Arc::new(Mutex::new(Vec::new()));
+    let seen  = Arc::clone(&sizes);
+    let upstream = spawn_upstream(move |q| {
+        seen.lock().unwrap().push(advertised_size(q));
+        match advertised_size(q) {
+            Some(_) => Vec::new(),
+            None    => answer_a(q, id(q), [192, 0, 2, 1]),
+        }
+    });
+    let server = spawn_server_with(upstream, &[("DNSR_TIMEOUT_RULES", ".=300")]);
+
+    let reply = exchange(&server, &query(1, "one.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+    // Only once the smallest size timed out too
+    let sent = sizes.lock().unwrap().clone();
+    assert_eq!(sent.first(), Some(&Some(1232)));
+    assert_eq!(sent.iter().position(Option::is_none), Some(sent.len() - 1), "{:?}", sent);
+    assert!(sent.contains(&Some(512)));
+
+    sizes.lock().unwrap().clear();
+    exchange(&server, &query(2, "two.example.com", 1));
+    assert_eq!(*sizes.lock().unwrap(), vec![None]);
+}
+
+#[test]
+fn tcp_clients_too_slow_to_send_a_query_are_dropped() {
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let server   = spawn_server(upstream);
+    wait_ready(&server);
+
+    // The length of a query, but never the query
+    let mut slow = TcpStream::connect(server.addr).unwrap();
+    slow.set_read_timeout(Some(Duration::from_secs(15))).unwrap();
+    slow.write_all(&[0, 40, 0, 1]).unwrap();
+
+    // The others are still answered meanwhile
+    let reply = exchange_tcp(&server, &query(1, "www.example.com", 1));
+    assert_eq!(an_count(&reply), 1);
+
+    let start = Instant::now();
+    assert_eq!(slow.read(&mut [0; 2]).unwrap(), 0);
+    assert!(start.elapsed() < Duration::from_secs(12));
+}
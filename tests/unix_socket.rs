@@ -0,0 +1,84 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{an_count, answer_a, id, query, spawn_server_with, spawn_upstream, Server};
+use std::{
+    env,
+    io::{Read, Write},
+    os::unix::{fs::MetadataExt, net::UnixStream},
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+/// Returns a path for the socket of a test.
+fn socket_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("dnsr-{}-{}.sock", name, process::id()))
+}
+
+/// Spawns the resolver, also listening on the unix domain socket at
+/// `path`, and waits for the socket to appear.
+fn spawn_unix_server(path: &Path, vars: &[(&str, &str)]) -> Server {
+    let _ = std::fs::remove_file(path);
+    let upstream = spawn_upstream(|q| answer_a(q, id(q), [192, 0, 2, 1]));
+    let listen   = path.to_str().unwrap();
+    let mut vars = vars.to_vec();
+    vars.push(("DNSR_UNIX_LISTEN", listen));
+    let server = spawn_server_with(upstream, &vars);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !path.exists() {
+        assert!(Instant::now() < deadline, "the socket never appeared");
+        thread::sleep(Duration::from_millis(20));
+    }
+    server
+}
+
+/// Sends a query over the socket, returning the reply, or `None` if the
+/// connection is closed first.
+fn exchange(path: &Path, packet: &[u8]) -> Option<Vec<u8>> {
+    let mut stream = UnixStream::connect(path).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let mut framed = (packet.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(packet);
+    stream.write_all(&framed).ok()?;
+
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length).ok()?;
+    let mut reply = vec![0u8; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut reply).ok()?;
+    Some(reply)
+}
+
+#[test]
+fn queries_are_answered_over_the_unix_socket() {
+    let path    = socket_path("unix");
+    let _server = spawn_unix_server(&path, &[]);
+
+    let reply = exchange(&path, &query(7, "www.example.com", 1)).unwrap();
+    assert_eq!(id(&reply), 7);
+    assert_eq!(an_count(&reply), 1);
+    assert!(reply.ends_with(&[192, 0, 2, 1]));
+
+    let reply = exchange(&path, &query(8, "whoami.resolver.local", 16)).unwrap();
+    assert!(reply.windows(14).any(|w| w == b"transport=unix"));
+}
+
+#[test]
+fn clients_are_admitted_by_their_credentials() {
+    let path = socket_path("unix-acl");
+    std::fs::write(&path, b"").unwrap();
+    let uid = std::fs::metadata(&path).unwrap().uid();
+    std::fs::remove_file(&path).unwrap();
+
+    let other  = format!("uid:{}", uid.wrapping_add(1));
+    let server = spawn_unix_server(&path, &[("DNSR_UNIX_ALLOW", &other)]);
+    assert!(exchange(&path, &query(1, "www.example.com", 1)).is_none());
+    drop(server);
+
+    let own     = format!("uid:{}", uid);
+    let _server = spawn_unix_server(&path, &[("DNSR_UNIX_ALLOW", &own)]);
+    assert!(exchange(&path, &query(1, "www.example.com", 1)).is_some());
+}
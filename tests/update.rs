@@ -0,0 +1,129 @@
+mod common;
+
+use common::{encode_name, exchange, id, rcode, spawn_server_with, wait_ready, Server};
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::{self, Command},
+};
+
+/// A record of an UPDATE message: name, type, class, TTL and rdata.
+type Record<'a> = (&'a str, u16, u16, u32, Vec<u8>);
+
+const ZONE: &str = "\
+$ORIGIN example.com.
+@    3600 IN SOA ns1 hostmaster 1 7200 3600 1209600 300
+@    3600 IN NS  ns1
+ns1   300 IN A   192.0.2.1
+old   300 IN A   192.0.2.2
+";
+
+/// Builds an UPDATE of `example.com` with the given prerequisites and
+/// updates.
+fn update(id: u16, prerequisites: &[Record], updates: &[Record]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&0x2800u16.to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&(prerequisites.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(updates.len() as u16).to_be_bytes());
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&encode_name("example.com"));
+    out.extend_from_slice(&[0, 6, 0, 1]);
+    for (name, rtype, class, ttl, rdata) in prerequisites.iter().chain(updates) {
+        out.extend_from_slice(&encode_name(name));
+        out.extend_from_slice(&rtype.to_be_bytes());
+        out.extend_from_slice(&class.to_be_bytes());
+        out.extend_from_slice(&ttl.to_be_bytes());
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(rdata);
+    }
+    out
+}
+
+/// Spawns a primary of `example.com` updated by the clients of `allow`,
+/// returning the path of its master file.
+fn spawn_primary(name: &str, allow: &str) -> (Server, PathBuf) {
+    let path = env::temp_dir().join(format!("dnsr-update-{}-{}", name, process::id()));
+    fs::write(&path, ZONE).unwrap();
+
+    let zones  = format!("example.com={}", path.display());
+    let server = spawn_server_with("127.0.0.1:9".parse().unwrap(), &[
+        ("DNSR_ZONES",        &zones),
+        ("DNSR_ALLOW_UPDATE", allow),
+    ]);
+    wait_ready(&server);
+    (server, path)
+}
+
+#[test]
+fn updates_are_applied_and_saved() {
+    let (server, path) = spawn_primary("applied", "127.0.0.0/8");
+
+    // Provided old.example.com exists, replace it with www.example.com
+    let reply = exchange(&server, &update(
+        0x5a01,
+        &[("old.example.com", 255, 255, 0, vec![])],
+        &[
+            ("old.example.com", 255, 255, 0, vec![]),
+            ("www.example.com", 1, 1, 600, vec![192, 0, 2, 80]),
+            ("www.example.com", 16, 1, 600, b"\x05hello\x04a \"\xff".to_vec()),
+        ],
+    ));
+    assert_eq!(id(&reply), 0x5a01);
+    assert_eq!(rcode(&reply), 0);
+
+    // The saved zone is a valid master file
+    let text   = fs::read_to_string(&path).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .arg("zone")
+        .arg("example.com")
+        .arg(&path)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&path);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("example.com: serial 2, 5 records in 5 sets"), "{}", stdout);
+    assert!(text.starts_with("example.com. 3600 IN SOA ns1.example.com. hostmaster.example.com. 2 "), "{}", text);
+    assert!(text.contains("www.example.com. 600 IN A 192.0.2.80\n"), "{}", text);
+    assert!(text.contains(r#"www.example.com. 600 IN TXT "hello" "a \"\255""#), "{}", text);
+    assert!(text.contains("ns1.example.com. 300 IN A 192.0.2.1\n"), "{}", text);
+    assert!(!text.contains("old.example.com"), "{}", text);
+}
+
+#[test]
+fn updates_with_failed_prerequisites_are_rejected() {
+    let (server, path) = spawn_primary("prerequisites", "127.0.0.0/8");
+    let adding = [("new.example.com", 1, 1, 300, vec![192, 0, 2, 3])];
+
+    // The A records of old.example.com exist
+    let reply = exchange(&server, &update(1, &[("old.example.com", 1, 254, 0, vec![])], &adding));
+    assert_eq!(rcode(&reply), 7);
+
+    // They don't hold exactly 192.0.2.9
+    let reply = exchange(&server, &update(2, &[("old.example.com", 1, 1, 0, vec![192, 0, 2, 9])], &adding));
+    assert_eq!(rcode(&reply), 8);
+
+    // No name is in use below old.example.com
+    let reply = exchange(&server, &update(3, &[("a.old.example.com", 255, 255, 0, vec![])], &adding));
+    assert_eq!(rcode(&reply), 3);
+
+    // Names outside of the zone can't be updated
+    let reply = exchange(&server, &update(4, &[], &[("www.example.org", 1, 1, 300, vec![192, 0, 2, 4])]));
+    assert_eq!(rcode(&reply), 10);
+
+    let text = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(text, ZONE);
+}
+
+#[test]
+fn updates_from_other_clients_are_refused() {
+    let (server, path) = spawn_primary("refused", "192.0.2.0/24");
+
+    let reply = exchange(&server, &update(0x5a02, &[], &[("www.example.com", 1, 1, 300, vec![192, 0, 2, 80])]));
+    let text  = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(rcode(&reply), 5);
+    assert_eq!(text, ZONE);
+}
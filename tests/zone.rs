@@ -0,0 +1,70 @@
+use std::{
+    env, fs,
+    process::{self, Command, Output},
+};
+
+/// Checks a zone with the `zone` subcommand.
+fn check_zone(name: &str, origin: &str, text: &str) -> Output {
+    let path = env::temp_dir().join(format!("dnsr-zone-{}-{}", name, process::id()));
+    fs::write(&path, text).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dns-resolver"))
+        .arg("zone")
+        .arg(origin)
+        .arg(&path)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&path);
+    output
+}
+
+#[test]
+fn master_files_are_read_into_record_sets() {
+    let output = check_zone("valid", "example.com", "\
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+            2024010101 ; serial
+            7200       ; refresh
+            3600       ; retry
+            1209600    ; expire
+            300 )      ; minimum
+    IN  NS  ns1
+    IN  NS  ns2.example.net.
+    IN  MX  10 mail
+ns1     300 A    192.0.2.53
+www         A    192.0.2.1
+            A    192.0.2.2
+            TXT  \"v=spf1 -all ; not a comment\"
+$ORIGIN sub.example.com.
+host        AAAA 2001:db8::1
+alias       CNAME host
+");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("example.com 3600 SOA 1 records"), "{}", stdout);
+    assert!(stdout.contains("example.com 3600 NS 2 records"), "{}", stdout);
+    assert!(stdout.contains("ns1.example.com 300 A 1 records"), "{}", stdout);
+    assert!(stdout.contains("www.example.com 3600 A 2 records"), "{}", stdout);
+    assert!(stdout.contains("www.example.com 3600 TXT 1 records"), "{}", stdout);
+    assert!(stdout.contains("host.sub.example.com 3600 AAAA 1 records"), "{}", stdout);
+    assert!(stdout.contains("alias.sub.example.com 3600 CNAME 1 records"), "{}", stdout);
+    assert!(stdout.contains("example.com: serial 2024010101, 10 records in 8 sets"), "{}", stdout);
+}
+
+#[test]
+fn invalid_master_files_are_rejected() {
+    let soa = "@ 3600 IN SOA ns1 hostmaster 1 7200 3600 1209600 300\n";
+    for (name, text) in [
+        ("no-soa",   "www 300 IN A 192.0.2.1\n".to_string()),
+        ("bad-data", format!("{}www 300 IN A 192.0.2.300\n", soa)),
+        ("unclosed", format!("{}www 300 IN TXT ( \"open\"\n", soa)),
+        ("include",  format!("{}$INCLUDE other.zone\n", soa)),
+    ] {
+        let output = check_zone(name, "example.com", &text);
+        assert!(!output.status.success(), "{} was accepted", name);
+    }
+
+    let output = check_zone("line", "example.com", &format!("{}\nwww 300 IN A 192.0.2.300\n", soa));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("line 3"));
+}